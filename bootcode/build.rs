@@ -12,6 +12,10 @@ fn main() {
 
 fn yarn_build() {
     println!("cargo:rerun-if-changed=js/src");
+    // The bundle also changes with its dependencies and the bundler settings
+    for file in ["package.json", "yarn.lock", "webpack.config.js", "tsconfig.json"] {
+        println!("cargo:rerun-if-changed=js/{file}");
+    }
     let mut cmd = std::process::Command::new("bash");
     cmd.arg("-c").arg("cd js && yarn && yarn build");
     cmd.status().expect("Failed to run yarn");
@@ -0,0 +1,329 @@
+// JSON-RPC helpers for Substrate nodes, over http(s) or WebSocket, and a generic JSON-RPC client.
+
+(function (g) {
+    const Sidevm = g.Sidevm;
+    let nextId = 1;
+
+    // An error object returned by the server.
+    class JsonRpcError extends Error {
+        constructor(error) {
+            super(`RPC error ${error.code}: ${error.message}`);
+            this.code = error.code;
+            this.data = error.data;
+        }
+        get name() {
+            return 'JsonRpcError';
+        }
+    }
+
+    // The request didn't get a valid JSON-RPC response, `status` is the http status if any.
+    class JsonRpcTransportError extends Error {
+        constructor(message, status) {
+            super(message);
+            this.status = status;
+        }
+        get name() {
+            return 'JsonRpcTransportError';
+        }
+    }
+
+    function rpcError(error) {
+        return new JsonRpcError(error);
+    }
+
+    function isWebSocketUrl(url) {
+        return /^wss?:\/\//i.test(url);
+    }
+
+    // Decode a hex result with the SCALE type `type` if given.
+    function decodeResult(result, type) {
+        if (type === undefined || typeof result !== 'string') {
+            return result;
+        }
+        if (!Sidevm.scaleDecode) {
+            throw new Error('Decoding RPC results requires the js-scale feature');
+        }
+        return Sidevm.scaleDecode(type, Sidevm.hexDecode(result.replace(/^0x/, '')));
+    }
+
+    function request(method, params) {
+        return { jsonrpc: '2.0', id: nextId++, method, params };
+    }
+
+    // Open a WebSocket, call `onOpen(send)` once connected and `onMessage(msg)` for each
+    // JSON message. Returns a function closing the connection.
+    function connect(url, { onOpen, onMessage, onError, signal }) {
+        let id;
+        const send = (msg) => Sidevm.websocketSend(id, JSON.stringify(msg));
+        id = Sidevm.websocketConnect(url, (cmd, data) => {
+            switch (cmd) {
+                case 'open':
+                    onOpen(send);
+                    break;
+                case 'message':
+                    try {
+                        onMessage(JSON.parse(typeof data === 'string' ? data : new TextDecoder().decode(data)));
+                    } catch (err) {
+                        onError(err);
+                    }
+                    break;
+                case 'error':
+                    onError(new Error(data));
+                    break;
+                case 'close':
+                    onError(new Error(`Connection closed: ${data.code} ${data.reason}`));
+                    break;
+            }
+        }, { signal });
+        return () => Sidevm.websocketClose(id);
+    }
+
+    /**
+     * Call `method` on the node at `url`, resolving to the result.
+     *
+     * Options:
+     * - `type`: a SCALE type to decode a hex encoded result with.
+     * - `signal`: an AbortSignal to cancel the call.
+     */
+    async function chainRpc(url, method, params = [], options = {}) {
+        if (!isWebSocketUrl(url)) {
+            const result = await jsonRpc(url, { signal: options.signal }).call(method, params);
+            return decodeResult(result, options.type);
+        }
+        if (!Sidevm.websocketConnect) {
+            throw new Error('WebSocket is not supported in this runtime');
+        }
+        const req = request(method, params);
+        const response = await new Promise((resolve, reject) => {
+            let close = () => {};
+            let done = false;
+            const finish = (fn, value) => {
+                if (!done) {
+                    done = true;
+                    close();
+                    fn(value);
+                }
+            };
+            close = connect(url, {
+                onOpen: (send) => send(req),
+                onMessage: (msg) => msg.id === req.id && finish(resolve, msg),
+                onError: (err) => finish(reject, err),
+                signal: options.signal,
+            });
+        });
+        if (response.error) {
+            throw rpcError(response.error);
+        }
+        return decodeResult(response.result, options.type);
+    }
+
+    /**
+     * Subscribe with `method` over WebSocket, calling `callback(result)` for each notification.
+     *
+     * Options:
+     * - `type`: a SCALE type to decode hex encoded results with.
+     * - `unsubscribe`: the unsubscribe method, derived from `method` by default,
+     *   e.g. `chain_subscribeNewHeads` => `chain_unsubscribeNewHeads`.
+     * - `onError(err)`: called when the subscription fails or the connection is lost.
+     * - `signal`: an AbortSignal to cancel the subscription.
+     *
+     * Returns `{ id, unsubscribe() }` where `id` is a promise of the subscription id.
+     */
+    function chainSubscribe(url, method, params, callback, options = {}) {
+        if (!Sidevm.websocketConnect) {
+            throw new Error('WebSocket is not supported in this runtime');
+        }
+        if (!isWebSocketUrl(url)) {
+            throw new TypeError('Subscriptions require a ws:// or wss:// url');
+        }
+        const unsubscribeMethod = options.unsubscribe || method.replace('_subscribe', '_unsubscribe');
+        const onError = options.onError || ((err) => console.error(`Subscription ${method} failed:`, err));
+        const req = request(method, params || []);
+        let subscription = null;
+        let closed = false;
+        let send = null;
+        let resolveId, rejectId;
+        const id = new Promise((resolve, reject) => {
+            resolveId = resolve;
+            rejectId = reject;
+        });
+        // Avoid an unhandled rejection if the caller doesn't care about the id.
+        id.catch(() => {});
+        const close = connect(url, {
+            onOpen: (sender) => {
+                send = sender;
+                send(req);
+            },
+            onMessage: (msg) => {
+                if (msg.id === req.id) {
+                    if (msg.error) {
+                        const err = rpcError(msg.error);
+                        rejectId(err);
+                        onError(err);
+                        unsubscribe();
+                    } else {
+                        subscription = msg.result;
+                        resolveId(subscription);
+                    }
+                } else if (msg.params && msg.params.subscription === subscription) {
+                    callback(decodeResult(msg.params.result, options.type));
+                }
+            },
+            onError: (err) => {
+                if (!closed) {
+                    closed = true;
+                    rejectId(err);
+                    onError(err);
+                }
+            },
+            signal: options.signal,
+        });
+        function unsubscribe() {
+            if (closed) {
+                return;
+            }
+            closed = true;
+            if (send && subscription !== null) {
+                send(request(unsubscribeMethod, [subscription]));
+            }
+            close();
+        }
+        return { id, unsubscribe };
+    }
+
+    /**
+     * A JSON-RPC 2.0 client of the http(s) endpoint `url`, backed by `Sidevm.jsonRpcCall` which
+     * assigns the ids, sends the request and matches the responses to the calls.
+     *
+     * Options:
+     * - `headers`: extra headers of the requests, e.g. for authentication.
+     * - `signal`: an AbortSignal to cancel the pending requests.
+     * - `timeoutMs`: the timeout of each request.
+     *
+     * `call(method, params)` resolves to the result, or rejects with a `JsonRpcError` for an error
+     * returned by the server, even along with a failure http status, and a `JsonRpcTransportError`
+     * for a request that got no JSON-RPC answer. `notify` sends a notification, which has no
+     * response.
+     *
+     * `batch()` collects calls to send in one request: its `call` returns a promise settled once
+     * `send()` got the response, and `send()` resolves when all the calls have been settled.
+     */
+    function jsonRpc(url, options = {}) {
+        if (!/^https?:\/\//i.test(url)) {
+            throw new TypeError('jsonRpc requires an http:// or https:// url');
+        }
+        const { signal, headers, timeoutMs } = options;
+
+        // Send `calls` of `{ method, params, notification }`, resolving to the outcomes of the
+        // calls that aren't notifications.
+        function exchange(calls, batch) {
+            const req = { url, headers: headers || {} };
+            if (timeoutMs !== undefined) {
+                req.timeoutMs = timeoutMs;
+            }
+            const hostCalls = calls.map(({ method, params, notification }) => ({
+                method: String(method),
+                params: params === undefined ? undefined : JSON.stringify(params),
+                notification,
+            }));
+            return new Promise((resolve, reject) => {
+                const callback = (cmd, data) => {
+                    switch (cmd) {
+                        case 'response':
+                            resolve(data);
+                            break;
+                        case 'transportError':
+                            reject(new JsonRpcTransportError(data.message, data.status));
+                            break;
+                        case 'rateLimited':
+                            reject(new Sidevm.RateLimitError(data));
+                            break;
+                    }
+                };
+                const start = (cb) => Sidevm.jsonRpcCall(req, hostCalls, batch, cb);
+                if (!signal) {
+                    start(callback);
+                    return;
+                }
+                const abort = () => {
+                    const err = new Error('The call was aborted');
+                    err.name = 'AbortError';
+                    return err;
+                };
+                if (signal.aborted) {
+                    reject(abort());
+                    return;
+                }
+                let id;
+                const onAbort = () => {
+                    Sidevm.close(id);
+                    reject(abort());
+                };
+                id = start((cmd, data) => {
+                    signal.removeEventListener('abort', onAbort);
+                    callback(cmd, data);
+                });
+                signal.addEventListener('abort', onAbort);
+            });
+        }
+
+        function settle(outcome, { resolve, reject }) {
+            if (outcome.error) {
+                const { data, ...error } = outcome.error;
+                reject(new JsonRpcError(data == null ? error : { ...error, data: JSON.parse(data) }));
+            } else if (outcome.result === undefined || outcome.result === null) {
+                reject(new JsonRpcTransportError('Missing JSON-RPC response'));
+            } else {
+                resolve(JSON.parse(outcome.result));
+            }
+        }
+
+        async function call(method, params = []) {
+            const [outcome] = await exchange([{ method, params }], false);
+            return new Promise((resolve, reject) => settle(outcome, { resolve, reject }));
+        }
+
+        async function notify(method, params = []) {
+            await exchange([{ method, params, notification: true }], false);
+        }
+
+        function batch() {
+            const pending = [];
+            let sent = false;
+            return {
+                call(method, params = []) {
+                    if (sent) {
+                        throw new Error('The batch has already been sent');
+                    }
+                    return new Promise((resolve, reject) => pending.push({ method, params, resolve, reject }));
+                },
+                async send() {
+                    if (sent) {
+                        throw new Error('The batch has already been sent');
+                    }
+                    sent = true;
+                    if (pending.length === 0) {
+                        return;
+                    }
+                    let outcomes;
+                    try {
+                        outcomes = await exchange(pending, true);
+                    } catch (err) {
+                        pending.forEach(({ reject }) => reject(err));
+                        return;
+                    }
+                    pending.forEach((entry, i) => settle(outcomes[i], entry));
+                },
+            };
+        }
+
+        return { url, call, notify, batch };
+    }
+
+    Sidevm.chainRpc = chainRpc;
+    Sidevm.chainSubscribe = chainSubscribe;
+    Sidevm.jsonRpc = jsonRpc;
+    Sidevm.JsonRpcError = JsonRpcError;
+    Sidevm.JsonRpcTransportError = JsonRpcTransportError;
+})(globalThis);
+export default {};
@@ -0,0 +1,173 @@
+// Ethereum RPC helpers, EIP-1559 transaction signing and the ABI codec, see host_functions/eth.rs.
+
+(function (g) {
+    const Sidevm = g.Sidevm;
+    const { ethSignTransaction, abiEncodeJson, abiDecodeJson } = Sidevm;
+
+    function bytesToHex(bytes) {
+        return '0x' + Array.from(bytes, (b) => b.toString(16).padStart(2, '0')).join('');
+    }
+
+    function hexToBytes(hex) {
+        return Sidevm.hexDecode(hex.replace(/^0x/i, ''));
+    }
+
+    // Bytes as Uint8Arrays or hex strings.
+    function toData(value) {
+        if (value instanceof Uint8Array) {
+            return bytesToHex(value);
+        }
+        if (typeof value === 'string' && /^0x[0-9a-f]*$/i.test(value)) {
+            return value;
+        }
+        throw new TypeError(`Expected bytes or a 0x prefixed hex string, got ${value}`);
+    }
+
+    // Quantities as numbers, BigInts, decimal strings or hex strings.
+    function toQuantity(value) {
+        const n = BigInt(value);
+        if (n < 0n) {
+            throw new RangeError(`Quantities can not be negative, got ${value}`);
+        }
+        return '0x' + n.toString(16);
+    }
+
+    function toBlock(block) {
+        return typeof block === 'string' && !/^(0x)?[0-9a-f]+$/i.test(block) ? block : toQuantity(block);
+    }
+
+    // Copy the fields of `obj` found in `convert`, converted.
+    function convertFields(obj, convert) {
+        const out = {};
+        for (const [key, f] of Object.entries(convert)) {
+            if (obj[key] !== undefined && obj[key] !== null) {
+                out[key] = f(obj[key]);
+            }
+        }
+        return out;
+    }
+
+    const callFields = {
+        from: toData,
+        to: toData,
+        gas: toQuantity,
+        gasPrice: toQuantity,
+        maxFeePerGas: toQuantity,
+        maxPriorityFeePerGas: toQuantity,
+        value: toQuantity,
+        data: toData,
+    };
+
+    /**
+     * Execute `tx` ({ to, data, from, value, gas, ... }) against the state at `block` of the node
+     * at `url` without sending a transaction, resolving to the return data as a Uint8Array.
+     *
+     * `options` are the options of `Sidevm.jsonRpc`.
+     */
+    async function ethCall(url, tx, block = 'latest', options = {}) {
+        const result = await Sidevm.jsonRpc(url, options).call('eth_call', [convertFields(tx, callFields), toBlock(block)]);
+        return hexToBytes(result);
+    }
+
+    /**
+     * The logs matching `filter` ({ address, topics, fromBlock, toBlock, blockHash }) on the node at
+     * `url`. `blockNumber`, `logIndex` and `transactionIndex` of the logs are converted to numbers.
+     */
+    async function ethGetLogs(url, filter = {}, options = {}) {
+        const params = convertFields(filter, {
+            address: (address) => Array.isArray(address) ? address.map(toData) : toData(address),
+            topics: (topics) => topics.map((topic) =>
+                topic === null ? null : Array.isArray(topic) ? topic.map(toData) : toData(topic)),
+            fromBlock: toBlock,
+            toBlock: toBlock,
+            blockHash: toData,
+        });
+        const logs = await Sidevm.jsonRpc(url, options).call('eth_getLogs', [params]);
+        return logs.map((log) => ({
+            ...log,
+            blockNumber: log.blockNumber == null ? null : Number(log.blockNumber),
+            logIndex: log.logIndex == null ? null : Number(log.logIndex),
+            transactionIndex: log.transactionIndex == null ? null : Number(log.transactionIndex),
+        }));
+    }
+
+    Sidevm.ethCall = ethCall;
+    Sidevm.ethGetLogs = ethGetLogs;
+
+    if (ethSignTransaction) {
+        /**
+         * Sign the EIP-1559 transaction `tx` with the 32-byte secp256k1 `key`, returning
+         * `{ raw, hash, from }` as Uint8Arrays, `raw` being ready for `eth_sendRawTransaction`.
+         *
+         * `tx` has `chainId`, `nonce`, `maxPriorityFeePerGas`, `maxFeePerGas`, `gasLimit`, and
+         * optionally `to`, `value`, `data` and `accessList` ([{ address, storageKeys }]).
+         */
+        Sidevm.ethSignTransaction = function (tx, key) {
+            const fields = convertFields(tx, {
+                chainId: toQuantity,
+                nonce: toQuantity,
+                maxPriorityFeePerGas: toQuantity,
+                maxFeePerGas: toQuantity,
+                gasLimit: toQuantity,
+                to: toData,
+                value: toQuantity,
+                data: toData,
+            });
+            if (tx.accessList) {
+                fields.accessList = tx.accessList.map(({ address, storageKeys = [] }) => ({
+                    address: toData(address),
+                    storageKeys: storageKeys.map(toData),
+                }));
+            }
+            return ethSignTransaction(fields, key);
+        };
+    }
+
+    if (abiEncodeJson) {
+        // BigInts travel as strings and bytes as hex, see host_functions/eth/abi.rs.
+        const replacer = (key, value) => {
+            if (typeof value === 'bigint') {
+                return value.toString();
+            }
+            if (value instanceof Uint8Array) {
+                return bytesToHex(value);
+            }
+            return value;
+        };
+        const reviver = (key, value) => {
+            if (value && typeof value === 'object' && !Array.isArray(value)) {
+                if ('$bigint' in value) {
+                    return BigInt(value.$bigint);
+                }
+                if ('$bytes' in value) {
+                    return hexToBytes(value.$bytes);
+                }
+            }
+            return value;
+        };
+        const typeList = (types) => Array.isArray(types) ? `(${types.join(',')})`
+            : types.trim().startsWith('(') ? types : `(${types})`;
+
+        /**
+         * Encode `values` as the parameters of the function `signature`, e.g.
+         * `transfer(address,uint256)`, prefixed with its selector. A signature without a name,
+         * e.g. `(address,uint256)`, encodes the parameters only.
+         *
+         * Integers are numbers, BigInts or strings, bytes are Uint8Arrays or hex strings,
+         * addresses are hex strings and tuples are arrays.
+         */
+        Sidevm.abiEncode = function (signature, values) {
+            return abiEncodeJson(String(signature), JSON.stringify(values, replacer));
+        };
+
+        /**
+         * Decode `bytes` as `types`, an array such as `['uint256', '(address,bytes)[]']` or a
+         * parenthesized list, to an array of values. Integers are BigInts, bytes are Uint8Arrays
+         * and addresses are lowercase hex strings.
+         */
+        Sidevm.abiDecode = function (types, bytes) {
+            return JSON.parse(abiDecodeJson(typeList(types), bytes), reviver);
+        };
+    }
+})(globalThis);
+export default {};
@@ -0,0 +1,54 @@
+// The event target of the global scope, e.g. `addEventListener("fetch", ...)`.
+
+const listeners = {};
+const activators = {};
+
+/**
+ * Call `activate` once the first listener of `type` is added, so that the host sources of the events
+ * are only set up when needed.
+ */
+export function onFirstListener(type, activate) {
+    activators[type] = activate;
+}
+
+export function dispatchGlobalEvent(type, event) {
+    const handler = globalThis[`on${type}`];
+    if (typeof handler === 'function') {
+        handler.call(globalThis, event);
+    }
+    for (const listener of (listeners[type] || []).slice()) {
+        listener.call(globalThis, event);
+    }
+}
+
+export function hasGlobalListener(type) {
+    return (listeners[type] || []).length > 0 || typeof globalThis[`on${type}`] === 'function';
+}
+
+globalThis.addEventListener = function (type, listener) {
+    type = String(type);
+    const list = listeners[type] = listeners[type] || [];
+    if (list.includes(listener)) {
+        return;
+    }
+    list.push(listener);
+    const activate = activators[type];
+    if (activate) {
+        delete activators[type];
+        activate();
+    }
+};
+
+globalThis.removeEventListener = function (type, listener) {
+    const list = listeners[String(type)] || [];
+    const index = list.indexOf(listener);
+    if (index >= 0) {
+        list.splice(index, 1);
+    }
+};
+
+// `Service::shutdown` calls the `beforeunload` listeners before the tasks are cancelled.
+if (globalThis.Sidevm && Sidevm.registerShutdownHandler) {
+    Sidevm.registerShutdownHandler(() => dispatchGlobalEvent('beforeunload', { type: 'beforeunload' }));
+    Sidevm.onShutdown = (listener) => globalThis.addEventListener('beforeunload', listener);
+}
@@ -1,10 +1,20 @@
+import "./polyfill-events";
 import "./polyfill-textencoding";
+import "./polyfill-crypto";
 import "./polyfill-streams";
+import "./polyfill-headers";
+import "./polyfill-formdata";
+import "./polyfill-encoding-streams";
 import "./polyfill-fetch";
 import "./polyfill-url";
 import "./polyfill-xhr";
 import "./sidevm";
+import "./polyfill-codec";
+import "./polyfill-performance";
+import "./polyfill-intl";
+import "./polyfill-wasm";
+import "./chain-rpc";
+import "./eth";
+import "./polyfill-worker";
+import "./polyfill-messagechannel";
 import "./polyfill-abortcontroller";
-
-import { Headers } from "headers-polyfill";
-globalThis.Headers = Headers;
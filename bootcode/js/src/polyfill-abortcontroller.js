@@ -1 +1,73 @@
-import 'abortcontroller-polyfill/dist/polyfill-patch-fetch';
\ No newline at end of file
+((g) => {
+    function abortError() {
+        const err = new Error("This operation was aborted");
+        err.name = "AbortError";
+        return err;
+    }
+
+    function timeoutError() {
+        const err = new Error("The operation timed out");
+        err.name = "TimeoutError";
+        return err;
+    }
+
+    class AbortSignal extends EventTarget {
+        constructor() {
+            super();
+            this.aborted = false;
+            this.reason = undefined;
+            this.onabort = null;
+        }
+        throwIfAborted() {
+            if (this.aborted) {
+                throw this.reason;
+            }
+        }
+        _abort(reason) {
+            if (this.aborted) {
+                return;
+            }
+            this.aborted = true;
+            this.reason = reason === undefined ? abortError() : reason;
+            this.dispatchEvent(new Event("abort"));
+        }
+        static abort(reason) {
+            const controller = new AbortController();
+            controller.abort(reason);
+            return controller.signal;
+        }
+        static timeout(ms) {
+            const signal = new AbortSignal();
+            // Armed right away so that `aborted` turns true even if nobody listens.
+            setTimeout(() => signal._abort(timeoutError()), ms);
+            return signal;
+        }
+        static any(signals) {
+            const signal = new AbortSignal();
+            for (const source of signals) {
+                if (source.aborted) {
+                    signal._abort(source.reason);
+                    return signal;
+                }
+            }
+            const onAbort = function () {
+                signal._abort(this.reason);
+                signals.forEach((source) => source.removeEventListener("abort", onAbort));
+            };
+            signals.forEach((source) => source.addEventListener("abort", onAbort));
+            return signal;
+        }
+    }
+
+    class AbortController {
+        constructor() {
+            this.signal = new AbortSignal();
+        }
+        abort(reason) {
+            this.signal._abort(reason);
+        }
+    }
+
+    g.AbortSignal = AbortSignal;
+    g.AbortController = AbortController;
+})(globalThis)
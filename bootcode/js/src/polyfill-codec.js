@@ -0,0 +1,43 @@
+(function (g) {
+    const { atob, btoa, Codec } = g.Sidevm;
+    if (!Codec) {
+        return;
+    }
+    g.atob = (data) => atob(String(data));
+    g.btoa = (data) => btoa(String(data));
+
+    function define(target, name, value) {
+        if (!(name in target)) {
+            Object.defineProperty(target, name, { value, writable: true, configurable: true });
+        }
+    }
+
+    function base64Codec(options) {
+        const alphabet = (options && options.alphabet) || 'base64';
+        if (alphabet !== 'base64' && alphabet !== 'base64url') {
+            throw new TypeError(`Invalid alphabet: ${alphabet}`);
+        }
+        return Codec[alphabet];
+    }
+
+    // The methods of the TC39 Uint8Array to/from base64 and hex proposal.
+    define(Uint8Array.prototype, 'toHex', function () {
+        return Codec.hex.encode(this);
+    });
+    define(Uint8Array.prototype, 'toBase64', function (options) {
+        return base64Codec(options).encode(this, !(options && options.omitPadding));
+    });
+    define(Uint8Array, 'fromHex', function (s) {
+        if (typeof s !== 'string' || s.length % 2 !== 0) {
+            throw new SyntaxError('Invalid hex string');
+        }
+        return Codec.hex.decode(s);
+    });
+    define(Uint8Array, 'fromBase64', function (s, options) {
+        if (typeof s !== 'string') {
+            throw new TypeError('Expected a string');
+        }
+        return base64Codec(options).decode(s.replace(/[\t\n\f\r ]/g, ''));
+    });
+})(globalThis);
+export default {};
@@ -0,0 +1,168 @@
+(function (g) {
+    const Sidevm = g.Sidevm;
+    if (!Sidevm.randomBytes) {
+        return;
+    }
+
+    const INTEGER_ARRAYS = [
+        Int8Array, Uint8Array, Uint8ClampedArray, Int16Array, Uint16Array,
+        Int32Array, Uint32Array, BigInt64Array, BigUint64Array,
+    ];
+
+    function toBytes(data) {
+        if (data instanceof ArrayBuffer) {
+            return new Uint8Array(data);
+        }
+        if (ArrayBuffer.isView(data)) {
+            return new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+        }
+        throw new TypeError("The provided value is not of type '(ArrayBuffer or ArrayBufferView)'");
+    }
+
+    function getRandomValues(array) {
+        if (!INTEGER_ARRAYS.some((T) => array instanceof T)) {
+            throw new TypeError('The provided ArrayBufferView is not an integer array type');
+        }
+        const bytes = toBytes(array);
+        bytes.set(Sidevm.randomBytes(bytes.length));
+        return array;
+    }
+
+    function randomUUID() {
+        const bytes = Sidevm.randomBytes(16);
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        const hex = Array.from(bytes, (b) => b.toString(16).padStart(2, '0')).join('');
+        return `${hex.slice(0, 8)}-${hex.slice(8, 12)}-${hex.slice(12, 16)}-${hex.slice(16, 20)}-${hex.slice(20)}`;
+    }
+
+    function normalizeAlgorithm(algorithm) {
+        const alg = typeof algorithm === 'string' ? { name: algorithm } : { ...algorithm };
+        alg.name = String(alg.name).toUpperCase();
+        if (alg.hash !== undefined) {
+            alg.hash = normalizeAlgorithm(alg.hash);
+        }
+        return alg;
+    }
+
+    function toBuffer(bytes) {
+        return bytes.buffer.slice(bytes.byteOffset, bytes.byteOffset + bytes.byteLength);
+    }
+
+    const KEY_USAGES = {
+        'HMAC': ['sign', 'verify'],
+        'AES-GCM': ['encrypt', 'decrypt', 'wrapKey', 'unwrapKey'],
+        'AES-CTR': ['encrypt', 'decrypt', 'wrapKey', 'unwrapKey'],
+    };
+
+    class CryptoKey {
+        constructor(algorithm, extractable, usages, raw) {
+            this.type = 'secret';
+            this.algorithm = algorithm;
+            this.extractable = extractable;
+            this.usages = usages;
+            Object.defineProperty(this, '_raw', { value: raw });
+        }
+    }
+
+    function checkKey(key, name, usage) {
+        if (!(key instanceof CryptoKey)) {
+            throw new TypeError('The provided key is not a CryptoKey');
+        }
+        if (key.algorithm.name !== name) {
+            throw new Error(`InvalidAccessError: The key is not a ${name} key`);
+        }
+        if (!key.usages.includes(usage)) {
+            throw new Error(`InvalidAccessError: The key does not support the '${usage}' operation`);
+        }
+    }
+
+    const subtle = {
+        async digest(algorithm, data) {
+            const alg = normalizeAlgorithm(algorithm);
+            return toBuffer(Sidevm.digest(alg.name, toBytes(data)));
+        },
+        async importKey(format, keyData, algorithm, extractable, usages) {
+            if (format !== 'raw') {
+                throw new Error(`NotSupportedError: Unsupported key format '${format}'`);
+            }
+            const alg = normalizeAlgorithm(algorithm);
+            const allowed = KEY_USAGES[alg.name];
+            if (!allowed) {
+                throw new Error(`NotSupportedError: Unsupported algorithm '${alg.name}'`);
+            }
+            if (usages.some((usage) => !allowed.includes(usage))) {
+                throw new SyntaxError(`Invalid key usages for ${alg.name}`);
+            }
+            const raw = toBytes(keyData).slice();
+            if (alg.name === 'HMAC') {
+                if (!alg.hash) {
+                    throw new TypeError('HMAC requires a hash algorithm');
+                }
+                alg.length = raw.length * 8;
+            } else {
+                if (![16, 24, 32].includes(raw.length)) {
+                    throw new Error('DataError: AES key data must be 128, 192 or 256 bits');
+                }
+                alg.length = raw.length * 8;
+            }
+            return new CryptoKey(alg, !!extractable, Array.from(usages), raw);
+        },
+        async exportKey(format, key) {
+            if (format !== 'raw') {
+                throw new Error(`NotSupportedError: Unsupported key format '${format}'`);
+            }
+            if (!key.extractable) {
+                throw new Error('InvalidAccessError: The key is not extractable');
+            }
+            return toBuffer(key._raw);
+        },
+        async sign(algorithm, key, data) {
+            const alg = normalizeAlgorithm(algorithm);
+            checkKey(key, alg.name, 'sign');
+            return toBuffer(Sidevm.hmacSign(key.algorithm.hash.name, key._raw, toBytes(data)));
+        },
+        async verify(algorithm, key, signature, data) {
+            const alg = normalizeAlgorithm(algorithm);
+            checkKey(key, alg.name, 'verify');
+            return Sidevm.hmacVerify(key.algorithm.hash.name, key._raw, toBytes(data), toBytes(signature));
+        },
+        async encrypt(algorithm, key, data) {
+            return aes(algorithm, key, data, 'encrypt');
+        },
+        async decrypt(algorithm, key, data) {
+            return aes(algorithm, key, data, 'decrypt');
+        },
+    };
+
+    function aes(algorithm, key, data, usage) {
+        const alg = normalizeAlgorithm(algorithm);
+        checkKey(key, alg.name, usage);
+        let output;
+        switch (alg.name) {
+            case 'AES-GCM': {
+                if (alg.tagLength !== undefined && alg.tagLength !== 128) {
+                    throw new Error('NotSupportedError: Only 128-bit tags are supported');
+                }
+                const aad = alg.additionalData === undefined ? undefined : toBytes(alg.additionalData);
+                const f = usage === 'encrypt' ? Sidevm.aesGcmEncrypt : Sidevm.aesGcmDecrypt;
+                try {
+                    output = f(key._raw, toBytes(alg.iv), toBytes(data), aad);
+                } catch (err) {
+                    throw new Error(`OperationError: ${err}`);
+                }
+                break;
+            }
+            case 'AES-CTR':
+                output = Sidevm.aesCtr(key._raw, toBytes(alg.counter), alg.length, toBytes(data));
+                break;
+            default:
+                throw new Error(`NotSupportedError: Unsupported algorithm '${alg.name}'`);
+        }
+        return toBuffer(output);
+    }
+
+    g.CryptoKey = CryptoKey;
+    g.crypto = { getRandomValues, randomUUID, subtle };
+})(globalThis);
+export default {};
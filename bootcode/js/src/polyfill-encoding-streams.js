@@ -0,0 +1,145 @@
+// Streams built on top of the WHATWG streams polyfill.
+(function (g) {
+    class TextDecoderStream extends TransformStream {
+        constructor(encoding = 'utf-8', options = {}) {
+            const decoder = new TextDecoder(encoding, options);
+            super({
+                transform(chunk, controller) {
+                    const text = decoder.decode(chunk, { stream: true });
+                    if (text) {
+                        controller.enqueue(text);
+                    }
+                },
+                flush(controller) {
+                    const text = decoder.decode();
+                    if (text) {
+                        controller.enqueue(text);
+                    }
+                },
+            });
+            this._decoder = decoder;
+        }
+        get encoding() {
+            return this._decoder.encoding;
+        }
+        get fatal() {
+            return this._decoder.fatal;
+        }
+        get ignoreBOM() {
+            return this._decoder.ignoreBOM;
+        }
+    }
+
+    class TextEncoderStream extends TransformStream {
+        constructor() {
+            const encoder = new TextEncoder();
+            // A lone leading surrogate at the end of a chunk waits for its trailing half.
+            let pending = '';
+            super({
+                transform(chunk, controller) {
+                    let text = pending + String(chunk);
+                    pending = '';
+                    const last = text.charCodeAt(text.length - 1);
+                    if (last >= 0xD800 && last <= 0xDBFF) {
+                        pending = text.slice(-1);
+                        text = text.slice(0, -1);
+                    }
+                    if (text) {
+                        controller.enqueue(encoder.encode(text));
+                    }
+                },
+                flush(controller) {
+                    if (pending) {
+                        controller.enqueue(encoder.encode(pending));
+                    }
+                },
+            });
+        }
+        get encoding() {
+            return 'utf-8';
+        }
+    }
+
+    /**
+     * Split a stream of text into lines, without the line terminators. Handy for NDJSON or
+     * server-sent events:
+     *
+     *     response.body
+     *         .pipeThrough(new TextDecoderStream())
+     *         .pipeThrough(new Sidevm.LineSplitStream())
+     */
+    class LineSplitStream extends TransformStream {
+        constructor() {
+            let buffer = '';
+            super({
+                transform(chunk, controller) {
+                    buffer += chunk;
+                    const lines = buffer.split(/\r?\n/);
+                    buffer = lines.pop();
+                    for (const line of lines) {
+                        controller.enqueue(line);
+                    }
+                },
+                flush(controller) {
+                    if (buffer) {
+                        controller.enqueue(buffer);
+                    }
+                },
+            });
+        }
+    }
+
+    /**
+     * Issue a `Sidevm.httpRequest`, resolving to the response head with the body as a
+     * ReadableStream once the head arrives. Cancelling the body cancels the request.
+     */
+    function httpRequestStream(req) {
+        return new Promise((resolve, reject) => {
+            let controller = null;
+            let head = null;
+            const body = new ReadableStream({
+                start(c) {
+                    controller = c;
+                },
+                cancel() {
+                    Sidevm.cancelHttpRequest(id);
+                },
+            });
+            const id = Sidevm.httpRequest(req, (cmd, data) => {
+                switch (cmd) {
+                    case 'head':
+                        head = { ...data, body, trailers: null };
+                        resolve(head);
+                        break;
+                    case 'trailers':
+                        head.trailers = data;
+                        break;
+                    case 'data':
+                        controller.enqueue(data);
+                        break;
+                    case 'end':
+                        controller.close();
+                        break;
+                    case 'abort':
+                    case 'error': {
+                        const err = cmd === 'error'
+                            ? (data instanceof Error ? data : new TypeError(data))
+                            : new Error('The operation was aborted');
+                        if (cmd === 'abort') {
+                            err.name = 'AbortError';
+                        }
+                        controller.error(err);
+                        reject(err);
+                        break;
+                    }
+                }
+            });
+        });
+    }
+
+    g.TextDecoderStream = TextDecoderStream;
+    g.TextEncoderStream = TextEncoderStream;
+    g.Sidevm.LineSplitStream = LineSplitStream;
+    g.Sidevm.httpRequestStream = httpRequestStream;
+})(globalThis);
+export default {};
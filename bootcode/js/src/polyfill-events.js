@@ -0,0 +1,163 @@
+(function (g) {
+    class Event {
+        constructor(type, init = {}) {
+            if (arguments.length === 0) {
+                throw new TypeError("Failed to construct 'Event': 1 argument required");
+            }
+            this.type = String(type);
+            this.bubbles = !!init.bubbles;
+            this.cancelable = !!init.cancelable;
+            this.composed = !!init.composed;
+            this.defaultPrevented = false;
+            this.target = null;
+            this.currentTarget = null;
+            this.eventPhase = Event.NONE;
+            this.isTrusted = false;
+            this.timeStamp = Date.now();
+            this._stop = false;
+            this._stopImmediate = false;
+        }
+        preventDefault() {
+            if (this.cancelable) {
+                this.defaultPrevented = true;
+            }
+        }
+        stopPropagation() {
+            this._stop = true;
+        }
+        stopImmediatePropagation() {
+            this._stop = true;
+            this._stopImmediate = true;
+        }
+        composedPath() {
+            return this.currentTarget ? [this.currentTarget] : [];
+        }
+        get [Symbol.toStringTag]() {
+            return 'Event';
+        }
+    }
+    Event.NONE = 0;
+    Event.CAPTURING_PHASE = 1;
+    Event.AT_TARGET = 2;
+    Event.BUBBLING_PHASE = 3;
+
+    class CustomEvent extends Event {
+        constructor(type, init = {}) {
+            super(type, init);
+            this.detail = init.detail === undefined ? null : init.detail;
+        }
+        get [Symbol.toStringTag]() {
+            return 'CustomEvent';
+        }
+    }
+
+    class MessageEvent extends Event {
+        constructor(type, init = {}) {
+            super(type, init);
+            this.data = init.data === undefined ? null : init.data;
+            this.origin = init.origin || '';
+            this.lastEventId = init.lastEventId || '';
+            this.source = init.source || null;
+            this.ports = init.ports || [];
+        }
+        get [Symbol.toStringTag]() {
+            return 'MessageEvent';
+        }
+    }
+
+    function flag(options, name) {
+        return typeof options === 'object' && options !== null ? !!options[name] : name === 'capture' && !!options;
+    }
+
+    /**
+     * Listener management shared by the objects emitting events, such as AbortSignal, MessagePort
+     * and Worker.
+     *
+     * There is no event tree, so events are only dispatched at the target. The `on<type>` handler
+     * of the target, if any, is called before the listeners.
+     */
+    class EventTarget {
+        constructor() {
+            // type => [{ listener, capture, once }] in the order they were added
+            Object.defineProperty(this, '_eventListeners', { value: {}, writable: true });
+        }
+        addEventListener(type, listener, options) {
+            if (listener == null) {
+                return;
+            }
+            type = String(type);
+            const capture = flag(options, 'capture');
+            const list = this._eventListeners[type] = this._eventListeners[type] || [];
+            if (list.some((l) => l.listener === listener && l.capture === capture)) {
+                return;
+            }
+            const signal = options && options.signal;
+            if (signal && signal.aborted) {
+                return;
+            }
+            list.push({ listener, capture, once: flag(options, 'once') });
+            if (signal) {
+                signal.addEventListener('abort', () => this.removeEventListener(type, listener, { capture }), { once: true });
+            }
+        }
+        removeEventListener(type, listener, options) {
+            const list = this._eventListeners[String(type)] || [];
+            const capture = flag(options, 'capture');
+            const index = list.findIndex((l) => l.listener === listener && l.capture === capture);
+            if (index >= 0) {
+                list[index].removed = true;
+                list.splice(index, 1);
+            }
+        }
+        dispatchEvent(event) {
+            if (event instanceof Event) {
+                event.target = this;
+                event.currentTarget = this;
+                event.eventPhase = Event.AT_TARGET;
+            } else if (event.target === undefined) {
+                event.target = this;
+            }
+            const handler = this[`on${event.type}`];
+            if (typeof handler === 'function') {
+                this._invoke(event, handler);
+            }
+            for (const entry of (this._eventListeners[event.type] || []).slice()) {
+                if (event._stopImmediate) {
+                    break;
+                }
+                if (entry.removed) {
+                    continue;
+                }
+                if (entry.once) {
+                    this.removeEventListener(event.type, entry.listener, { capture: entry.capture });
+                }
+                this._invoke(event, entry.listener);
+            }
+            if (event instanceof Event) {
+                event.currentTarget = null;
+                event.eventPhase = Event.NONE;
+            }
+            return !event.defaultPrevented;
+        }
+        _invoke(event, listener) {
+            try {
+                if (typeof listener === 'function') {
+                    listener.call(this, event);
+                } else {
+                    listener.handleEvent(event);
+                }
+            } catch (err) {
+                console.error(`Uncaught error in ${event.type} listener:`, err);
+            }
+        }
+        get [Symbol.toStringTag]() {
+            return 'EventTarget';
+        }
+    }
+
+    g.Event = Event;
+    g.CustomEvent = CustomEvent;
+    g.MessageEvent = MessageEvent;
+    g.EventTarget = EventTarget;
+})(globalThis);
+export default {};
@@ -1,132 +1,414 @@
+import { dispatchGlobalEvent, onFirstListener } from "./global-events";
+
 ((g) => {
-    class Request {
-        constructor(input, init = {}) {
-            if (input instanceof Request) {
-                this.url = input.url;
-                this.method = input.method;
-                this.headers = new Headers(input.headers);
-                this.body = input.body;
-            } else {
-                this.url = input;
-                this.method = init.method || 'GET';
-                this.headers = new Headers(init.headers);
-                this.body = init.body || null;
-            }
-            this.cache = init.cache || 'default';
-            this.redirect = init.redirect || 'follow';
-            this.referrer = init.referrer || 'about:client';
+    function abortError() {
+        const err = new Error("The operation was aborted");
+        err.name = "AbortError";
+        return err;
+    }
+
+    function toBytes(body) {
+        if (body instanceof Blob) {
+            return body._bytes;
+        }
+        if (body instanceof Uint8Array) {
+            return body;
+        }
+        if (body instanceof ArrayBuffer) {
+            return new Uint8Array(body);
         }
+        if (ArrayBuffer.isView(body)) {
+            return new Uint8Array(body.buffer, body.byteOffset, body.byteLength);
+        }
+        return new TextEncoder().encode(String(body));
     }
 
-    class Response {
-        constructor(id, receiver, head) {
-            this.id = id;
-            this.ok = ((head.status / 100) | 0) == 2;
-            this.statusText = head.statusText;
-            this.status = head.status;
-            this.url = head["Location"] || head["location"] || "";
-            this.headers = new Headers(head.headers);
-            this.receiver = receiver;
-            this.bodyUsed = false;
-            this.type = "default";
-            const chunks = [];
-            receiver.recv = (cmd, data) => {
-                if (cmd == "data") {
-                    chunks.push(data);
-                } else if (cmd == "end") {
-                    const body = Sidevm.concatU8a(chunks);
-                    receiver.resolve(body);
-                } else {
-                    receiver.reject(data);
-                }
+    function headerPairs(headers) {
+        const pairs = [];
+        headers.forEach((value, name) => pairs.push([name, value]));
+        return pairs;
+    }
+
+    async function readAll(stream) {
+        const reader = stream.getReader();
+        const chunks = [];
+        for (;;) {
+            const { done, value } = await reader.read();
+            if (done) {
+                return Sidevm.concatU8a(chunks);
             }
+            chunks.push(toBytes(value));
         }
-        async text() {
-            return new TextDecoder().decode(await this.bytes());
+    }
+
+    // Closes the request of a BodyReceiver the scripts dropped without consuming its body.
+    const abandoned = typeof FinalizationRegistry === "function"
+        ? new FinalizationRegistry((reqId) => Sidevm.close(reqId))
+        : null;
+
+    // Buffers the events of an in-flight http request until someone starts consuming the body.
+    //
+    // The request callback only holds the receiver through `handle`, weakly until the body is
+    // attached, so a response dropped unread doesn't pin its buffered body until the request ends.
+    class BodyReceiver {
+        constructor(reqId) {
+            this.reqId = reqId;
+            this.events = [];
+            this.sink = null;
+            this.handle = abandoned
+                ? { weak: new WeakRef(this), strong: null }
+                : { weak: null, strong: this };
+            if (abandoned) {
+                abandoned.register(this, reqId, this.handle);
+            }
         }
-        async json() {
-            return JSON.parse(await this.text());
+        // The receiver of `handle`, or null once it has been collected.
+        static from(handle) {
+            return handle.strong || handle.weak.deref() || null;
         }
-        async blob() {
-            return new Blob([await this.bytes()]);
+        recv(cmd, data) {
+            if (abandoned && cmd != "data") {
+                // The request is over, nothing is left to close.
+                abandoned.unregister(this.handle);
+            }
+            if (this.sink) {
+                this.sink(cmd, data);
+            } else {
+                this.events.push([cmd, data]);
+            }
         }
-        async arrayBuffer() {
-            return (await this.bytes()).buffer;
+        attach(sink) {
+            this.sink = sink;
+            // Someone waits for the body now, keep the receiver alive until the request ends.
+            this.handle.strong = this;
+            if (abandoned) {
+                abandoned.unregister(this.handle);
+            }
+            const events = this.events;
+            this.events = [];
+            for (const [cmd, data] of events) {
+                sink(cmd, data);
+            }
         }
-        bytes() {
-            const r = this.receiver;
-            return new Promise((resolve, reject) => {
-                r.resolve = resolve
-                r.reject = reject;
-            });
+    }
+
+    class Body {
+        _initBody(body) {
+            // null, an Uint8Array, a ReadableStream or a BodyReceiver of a pending message.
+            this._body = body;
+            this._stream = null;
+            this.bodyUsed = false;
         }
         get body() {
-            const r = this.receiver;
-            const reqId = this.id;
-            return new ReadableStream({
+            if (this._body == null) {
+                return null;
+            }
+            if (this._stream) {
+                return this._stream;
+            }
+            if (this._body instanceof ReadableStream) {
+                this.bodyUsed = true;
+                this._stream = this._body;
+                return this._stream;
+            }
+            const self = this;
+            const body = this._body;
+            this._stream = new ReadableStream({
                 start(controller) {
-                    r.recv = (cmd, data) => {
+                    self.bodyUsed = true;
+                    if (!(body instanceof BodyReceiver)) {
+                        controller.enqueue(body);
+                        controller.close();
+                        return;
+                    }
+                    body.attach((cmd, data) => {
                         if (cmd == "data") {
                             controller.enqueue(data);
                         } else if (cmd == "end") {
                             controller.close();
+                        } else if (cmd == "abort") {
+                            controller.error(abortError());
                         } else {
-                            controller.error(data);
+                            controller.error(new TypeError(data));
                         }
-                    }
+                    });
                 },
                 cancel() {
-                    Sidevm.close(reqId);
+                    if (body instanceof BodyReceiver) {
+                        Sidevm.close(body.reqId);
+                    }
                 }
             });
+            return this._stream;
+        }
+        bytes() {
+            if (this.bodyUsed) {
+                return Promise.reject(new TypeError("Body has already been consumed"));
+            }
+            this.bodyUsed = true;
+            const body = this._body;
+            if (body instanceof ReadableStream) {
+                return readAll(body);
+            }
+            if (!(body instanceof BodyReceiver)) {
+                return Promise.resolve(body || new Uint8Array(0));
+            }
+            return new Promise((resolve, reject) => {
+                const chunks = [];
+                body.attach((cmd, data) => {
+                    if (cmd == "data") {
+                        chunks.push(data);
+                    } else if (cmd == "end") {
+                        resolve(Sidevm.concatU8a(chunks));
+                    } else if (cmd == "abort") {
+                        reject(abortError());
+                    } else {
+                        reject(new TypeError(data));
+                    }
+                });
+            });
+        }
+        async text() {
+            return new TextDecoder().decode(await this.bytes());
+        }
+        async json() {
+            return JSON.parse(await this.text());
+        }
+        async blob() {
+            return new Blob([await this.bytes()]);
+        }
+        async arrayBuffer() {
+            const bytes = await this.bytes();
+            return bytes.buffer.slice(bytes.byteOffset, bytes.byteOffset + bytes.byteLength);
         }
     }
 
-    g.fetch = (resource, options) => {
-        var url;
-        if (typeof resource == "string") {
-            url = resource;
-        } else if (resource instanceof Request) {
-            url = resource.url;
-            options = {
-                ...resource,
-                ...options || {},
-            }
-        } else {
-            url = resource.toString();
-        }
-        options = options || {};
-        const redirect = options.redirect || "follow";
+    class Request extends Body {
+        constructor(input, init = {}) {
+            super();
+            const base = input instanceof Request ? input : {};
+            this.url = input instanceof Request ? input.url : String(input);
+            this.method = (init.method || base.method || 'GET').toUpperCase();
+            this.headers = new Headers(init.headers || base.headers);
+            this.cache = init.cache || base.cache || 'default';
+            this.redirect = init.redirect || base.redirect || 'follow';
+            this.referrer = init.referrer || base.referrer || 'about:client';
+            this.signal = init.signal || base.signal || null;
+            // Non-standard: timeout of the whole request in milliseconds.
+            this.timeout = init.timeout || base.timeout;
+            // Non-standard: maximum number of response body bytes to receive.
+            this.maxResponseSize = init.maxResponseSize || base.maxResponseSize;
+            // Non-standard: per attempt timeouts and retries, see `HttpRequest` in http_request.rs.
+            this.connectTimeout = init.connectTimeout || base.connectTimeout;
+            this.readTimeout = init.readTimeout || base.readTimeout;
+            this.retries = init.retries || base.retries;
+            this.retryOn = init.retryOn || base.retryOn;
+            this.retryNonIdempotent = init.retryNonIdempotent || base.retryNonIdempotent;
+            this.retryDelay = init.retryDelay || base.retryDelay;
+            // Non-standard: proxy url, an empty string bypasses the default proxy.
+            this.proxy = init.proxy !== undefined ? init.proxy : base.proxy;
+            // Non-standard: path of a Unix domain socket to send the request to.
+            this.unixSocketPath = init.unixSocketPath || base.unixSocketPath;
+            // Non-standard: `onProgress({ sent, received, total })` called every `progressInterval` ms.
+            this.onProgress = init.onProgress || base.onProgress;
+            this.progressInterval = init.progressInterval || base.progressInterval || 100;
+            const body = init.body !== undefined ? init.body : (base._formData || base._body);
+            if (body != null && (this.method == 'GET' || this.method == 'HEAD')) {
+                throw new TypeError("Request with GET/HEAD method cannot have body");
+            }
+            // FormData is serialized by the host, which sets the multipart Content-Type.
+            this._formData = body instanceof FormData ? body : null;
+            this._initBody(body == null || this._formData ? null : toBytes(body));
+        }
+        clone() {
+            return new Request(this);
+        }
+    }
+
+    class Response extends Body {
+        constructor(body = null, init = {}) {
+            super();
+            this.status = init.status === undefined ? 200 : init.status;
+            this.statusText = init.statusText || "";
+            this.ok = this.status >= 200 && this.status < 300;
+            this.headers = new Headers(init.headers);
+            this.url = "";
+            this.type = "default";
+            this.redirected = false;
+            // Non-standard: the trailer headers, filled once the body has been read to the end.
+            this.trailers = new Headers();
+            const keep = body == null || body instanceof BodyReceiver || body instanceof ReadableStream;
+            this._initBody(keep ? body : toBytes(body));
+        }
+        static json(data, init = {}) {
+            const headers = new Headers(init.headers);
+            if (!headers.has("content-type")) {
+                headers.set("content-type", "application/json");
+            }
+            return new Response(JSON.stringify(data), { ...init, headers });
+        }
+        static error() {
+            const response = new Response(null, { status: 0 });
+            response.type = "error";
+            return response;
+        }
+    }
+
+    function doFetch(request) {
         return new Promise((resolve, reject) => {
-            const receiver = {
-                recv: (cmd, data) => {
-                    if (cmd == "head") {
-                        if (redirect == "follow" && [301, 302, 307, 308].includes(data.status)) {
-                            const location = data.headers['Location'];
-                            if (location) {
-                                g.fetch(location, options).then(resolve).catch(reject);
-                                return;
-                            }
-                        }
-                        resolve(new Response(reqId, receiver, data));
-                    } else {
-                        reject(data);
-                    }
-                },
-                resolve: () => { },
-                reject: () => { },
+            const abortReason = () => request.signal ? request.signal.reason : abortError();
+            if (request.signal && request.signal.aborted) {
+                reject(abortReason());
+                return;
             }
+            let handle = null;
             const reqId = Sidevm.httpRequest({
-                    url,
-                    method: options.method || "GET",
-                    headers: options.headers || {},
-                    timeout: options.timeout || 10000,
-                    body: options.body || "",
+                    url: request.url,
+                    method: request.method,
+                    headers: headerPairs(request.headers),
+                    body: request._body || new Uint8Array(0),
+                    formData: request._formData || undefined,
+                    redirect: request.redirect,
+                    // Builds without decompression pass the encoded body through
+                    decompress: Sidevm.httpDecompress,
+                    timeoutMs: request.timeout,
+                    maxResponseSize: request.maxResponseSize,
+                    connectTimeoutMs: request.connectTimeout,
+                    readTimeoutMs: request.readTimeout,
+                    retries: request.retries,
+                    retryOn: request.retryOn,
+                    retryNonIdempotent: request.retryNonIdempotent,
+                    retryDelayMs: request.retryDelay,
+                    proxy: request.proxy,
+                    unixSocketPath: request.unixSocketPath,
+                    progressIntervalMs: request.onProgress ? request.progressInterval : undefined,
+                    abortSignal: request.signal || undefined,
+                    cache: request.cache,
+                },
+                (cmd, data) => {
+                    if (cmd == "progress") {
+                        request.onProgress(data);
+                        return;
+                    }
+                    if (handle) {
+                        // Null once the response was dropped unread, the finalizer closes the request.
+                        const receiver = BodyReceiver.from(handle);
+                        if (!receiver) {
+                            return;
+                        }
+                        if (cmd == "trailers") {
+                            receiver.response.trailers = new Headers(data);
+                        } else {
+                            receiver.recv(cmd, data);
+                        }
+                    } else if (cmd == "head") {
+                        const receiver = new BodyReceiver(reqId);
+                        handle = receiver.handle;
+                        const response = new Response(receiver, {
+                            status: data.status,
+                            statusText: data.statusText,
+                            headers: data.headers,
+                        });
+                        response.url = data.url;
+                        response.redirected = data.url != new URL(request.url).href;
+                        receiver.response = response;
+                        resolve(response);
+                    } else if (cmd == "abort") {
+                        reject(abortReason());
+                    } else if (cmd == "error") {
+                        reject(data instanceof Error ? data : new TypeError(data));
+                    } else if (cmd == "warning") {
+                        console.warn(`fetch ${request.url}: ${data}`);
+                    }
                 },
-                (cmd, data) => receiver.recv(cmd, data),
             );
         });
+    }
+
+    g.fetch = (resource, options) => {
+        try {
+            return doFetch(new Request(resource, options));
+        } catch (err) {
+            return Promise.reject(err);
+        }
     };
     g.Response = Response;
     g.Request = Request;
+
+    if (!Sidevm.httpListen) {
+        return;
+    }
+
+    function writeChunk(writer, chunk) {
+        return new Promise((resolve, reject) => {
+            Sidevm.httpWriteChunk(writer, chunk, (ok, err) => ok ? resolve() : reject(new TypeError(err)));
+        });
+    }
+
+    async function sendResponse(req, response) {
+        if (!(response instanceof Response)) {
+            // Accept a plain `{status, headers, body}` object as well.
+            response = new Response(response.body, response);
+        }
+        Sidevm.httpSendResponseHead(req.opaqueResponseTx, {
+            status: response.status,
+            headers: headerPairs(response.headers),
+        });
+        const writer = Sidevm.httpMakeWriter(req.opaqueOutputStream);
+        try {
+            const body = response.body;
+            if (body) {
+                const reader = body.getReader();
+                for (;;) {
+                    const { done, value } = await reader.read();
+                    if (done) {
+                        break;
+                    }
+                    await writeChunk(writer, toBytes(value));
+                }
+            }
+        } finally {
+            Sidevm.httpCloseWriter(writer);
+        }
+    }
+
+    function incomingRequest(req) {
+        const request = new Request(req.url, { method: req.method, headers: req.headers });
+        const receiver = new BodyReceiver(null);
+        receiver.reqId = Sidevm.httpReceiveBody(req.opaqueInputStream,
+            (cmd, data) => receiver.recv(cmd, data));
+        request._initBody(receiver);
+        return request;
+    }
+
+    class FetchEvent {
+        constructor(req) {
+            this.type = 'fetch';
+            this.request = incomingRequest(req);
+            this._req = req;
+            this._responded = false;
+        }
+        respondWith(response) {
+            if (this._responded) {
+                throw new Error("respondWith() has already been called");
+            }
+            this._responded = true;
+            Promise.resolve(response)
+                .then((response) => sendResponse(this._req, response))
+                .catch((err) => {
+                    console.error('Failed to respond to', this.request.url, err);
+                    Sidevm.httpSendResponseHead(this._req.opaqueResponseTx, { status: 500, headers: [] });
+                });
+        }
+        waitUntil(promise) {
+            Promise.resolve(promise).catch((err) => console.error('waitUntil:', err));
+        }
+    }
+
+    onFirstListener('fetch', () => {
+        Sidevm.httpListen((req) => {
+            dispatchGlobalEvent('fetch', new FetchEvent(req));
+        });
+    });
+    g.FetchEvent = FetchEvent;
 })(globalThis)
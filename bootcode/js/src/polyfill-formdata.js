@@ -0,0 +1,155 @@
+(function (g) {
+    function toBytes(part) {
+        if (part instanceof Blob) {
+            return part._bytes;
+        }
+        if (part instanceof ArrayBuffer) {
+            return new Uint8Array(part);
+        }
+        if (ArrayBuffer.isView(part)) {
+            return new Uint8Array(part.buffer, part.byteOffset, part.byteLength);
+        }
+        return new TextEncoder().encode(String(part));
+    }
+
+    class Blob {
+        constructor(parts = [], options = {}) {
+            this._bytes = Sidevm.concatU8a(Array.from(parts, toBytes));
+            this.type = options.type ? String(options.type).toLowerCase() : '';
+        }
+        get size() {
+            return this._bytes.length;
+        }
+        slice(start = 0, end = this.size, type = '') {
+            return new Blob([this._bytes.slice(start, end)], { type });
+        }
+        async bytes() {
+            return this._bytes.slice();
+        }
+        async arrayBuffer() {
+            return this._bytes.slice().buffer;
+        }
+        async text() {
+            return new TextDecoder().decode(this._bytes);
+        }
+        stream() {
+            const bytes = this._bytes.slice();
+            return new ReadableStream({
+                start(controller) {
+                    controller.enqueue(bytes);
+                    controller.close();
+                }
+            });
+        }
+        get [Symbol.toStringTag]() {
+            return 'Blob';
+        }
+    }
+
+    class File extends Blob {
+        constructor(parts, name, options = {}) {
+            super(parts, options);
+            this.name = String(name);
+            this.lastModified = options.lastModified === undefined ? Date.now() : Number(options.lastModified);
+        }
+        get [Symbol.toStringTag]() {
+            return 'File';
+        }
+    }
+
+    // Strings are kept as is, Blobs become Files named `blob` unless a filename is given.
+    function toEntryValue(value, filename) {
+        if (!(value instanceof Blob)) {
+            return String(value);
+        }
+        if (value instanceof File && filename === undefined) {
+            return value;
+        }
+        const name = filename !== undefined ? filename : 'blob';
+        return new File([value], name, { type: value.type });
+    }
+
+    class FormData {
+        constructor() {
+            // [name, value] pairs in insertion order, values are strings or Files.
+            this._list = [];
+        }
+        append(name, value, filename) {
+            this._list.push([String(name), toEntryValue(value, filename)]);
+        }
+        delete(name) {
+            name = String(name);
+            this._list = this._list.filter(([n]) => n !== name);
+        }
+        get(name) {
+            name = String(name);
+            const entry = this._list.find(([n]) => n === name);
+            return entry ? entry[1] : null;
+        }
+        getAll(name) {
+            name = String(name);
+            return this._list.filter(([n]) => n === name).map(([, v]) => v);
+        }
+        has(name) {
+            name = String(name);
+            return this._list.some(([n]) => n === name);
+        }
+        set(name, value, filename) {
+            name = String(name);
+            const entry = [name, toEntryValue(value, filename)];
+            const index = this._list.findIndex(([n]) => n === name);
+            if (index < 0) {
+                this._list.push(entry);
+            } else {
+                this._list[index] = entry;
+                this._list = this._list.filter(([n], i) => n !== name || i <= index);
+            }
+        }
+        forEach(callback, thisArg) {
+            for (const [name, value] of this) {
+                callback.call(thisArg, value, name, this);
+            }
+        }
+        *entries() {
+            for (const [name, value] of this._list) {
+                yield [name, value];
+            }
+        }
+        *keys() {
+            for (const [name] of this._list) {
+                yield name;
+            }
+        }
+        *values() {
+            for (const [, value] of this._list) {
+                yield value;
+            }
+        }
+        [Symbol.iterator]() {
+            return this.entries();
+        }
+        // The fields in the shape of `formData` of `Sidevm.httpRequest`, see `FormField` in
+        // multipart.rs.
+        _fields() {
+            return this._list.map(([name, value]) => {
+                if (typeof value === 'string') {
+                    return { name, value };
+                }
+                return {
+                    name,
+                    data: value._bytes,
+                    filename: value.name,
+                    contentType: value.type || undefined,
+                };
+            });
+        }
+        get [Symbol.toStringTag]() {
+            return 'FormData';
+        }
+    }
+
+    g.Blob = Blob;
+    g.File = File;
+    g.FormData = FormData;
+})(globalThis);
+export default {};
@@ -0,0 +1,118 @@
+(function (g) {
+    const TOKEN = /^[!#$%&'*+\-.^_`|~0-9A-Za-z]+$/;
+
+    function normalizeName(name) {
+        name = String(name);
+        if (!TOKEN.test(name)) {
+            throw new TypeError(`Invalid header name: ${name}`);
+        }
+        return name.toLowerCase();
+    }
+
+    function normalizeValue(value) {
+        value = String(value).replace(/^[\t\n\r ]+|[\t\n\r ]+$/g, '');
+        if (/[\0\r\n]/.test(value)) {
+            throw new TypeError(`Invalid header value: ${value}`);
+        }
+        return value;
+    }
+
+    class Headers {
+        constructor(init) {
+            // [lowercase name, value] pairs in insertion order, repeated names are kept apart.
+            this._list = [];
+            if (init == null) {
+                return;
+            }
+            if (init instanceof Headers) {
+                this._list = init._list.map(([name, value]) => [name, value]);
+            } else if (typeof init[Symbol.iterator] === 'function') {
+                for (const entry of init) {
+                    const pair = Array.from(entry);
+                    if (pair.length !== 2) {
+                        throw new TypeError('Each header pair must be an iterable [name, value] tuple');
+                    }
+                    this.append(pair[0], pair[1]);
+                }
+            } else if (typeof init === 'object') {
+                for (const name of Object.keys(init)) {
+                    this.append(name, init[name]);
+                }
+            } else {
+                throw new TypeError('Headers must be constructed from an object or iterable');
+            }
+        }
+        append(name, value) {
+            this._list.push([normalizeName(name), normalizeValue(value)]);
+        }
+        delete(name) {
+            name = normalizeName(name);
+            this._list = this._list.filter(([n]) => n !== name);
+        }
+        get(name) {
+            const values = this.getAll(name);
+            return values.length > 0 ? values.join(', ') : null;
+        }
+        // Non-standard: every value of `name` in the order they were added, without combining.
+        getAll(name) {
+            name = normalizeName(name);
+            return this._list.filter(([n]) => n === name).map(([, v]) => v);
+        }
+        getSetCookie() {
+            return this.getAll('set-cookie');
+        }
+        has(name) {
+            name = normalizeName(name);
+            return this._list.some(([n]) => n === name);
+        }
+        set(name, value) {
+            name = normalizeName(name);
+            value = normalizeValue(value);
+            const index = this._list.findIndex(([n]) => n === name);
+            if (index < 0) {
+                this._list.push([name, value]);
+            } else {
+                this._list[index][1] = value;
+                this._list = this._list.filter(([n], i) => n !== name || i <= index);
+            }
+        }
+        forEach(callback, thisArg) {
+            for (const [name, value] of this) {
+                callback.call(thisArg, value, name, this);
+            }
+        }
+        // Sorted by name with the values of a name combined, except for Set-Cookie whose values
+        // are yielded one by one, as the Fetch spec defines.
+        *entries() {
+            const names = Array.from(new Set(this._list.map(([n]) => n))).sort();
+            for (const name of names) {
+                if (name === 'set-cookie') {
+                    for (const value of this.getAll(name)) {
+                        yield [name, value];
+                    }
+                } else {
+                    yield [name, this.get(name)];
+                }
+            }
+        }
+        *keys() {
+            for (const [name] of this) {
+                yield name;
+            }
+        }
+        *values() {
+            for (const [, value] of this) {
+                yield value;
+            }
+        }
+        [Symbol.iterator]() {
+            return this.entries();
+        }
+        get [Symbol.toStringTag]() {
+            return 'Headers';
+        }
+    }
+
+    g.Headers = Headers;
+})(globalThis);
+export default {};
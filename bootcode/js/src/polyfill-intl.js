@@ -0,0 +1,66 @@
+(function (g) {
+    const { formatNumber, formatDate, resolveLocale } = g.Sidevm;
+    if (!formatNumber || g.Intl) {
+        return;
+    }
+
+    function firstLocale(locales) {
+        return Array.isArray(locales) ? locales[0] : locales;
+    }
+
+    function toTime(date) {
+        return date === undefined ? Date.now() : Number(date instanceof Date ? date.getTime() : date);
+    }
+
+    // A subset of the ECMA-402 formatters backed by the host, see intl.rs for what's covered.
+    class NumberFormat {
+        constructor(locales, options = {}) {
+            this._locale = resolveLocale(firstLocale(locales));
+            this._options = { ...options };
+        }
+        format(value) {
+            return formatNumber(Number(value), this._locale, this._options);
+        }
+        resolvedOptions() {
+            return { locale: this._locale, ...this._options };
+        }
+    }
+
+    class DateTimeFormat {
+        constructor(locales, options = {}) {
+            this._locale = resolveLocale(firstLocale(locales));
+            this._options = { ...options };
+        }
+        format(date) {
+            return formatDate(toTime(date), this._locale, this._options);
+        }
+        resolvedOptions() {
+            return { locale: this._locale, timeZone: this._options.timeZone || 'UTC', ...this._options };
+        }
+    }
+
+    g.Intl = { NumberFormat, DateTimeFormat };
+
+    Number.prototype.toLocaleString = function (locales, options) {
+        return new NumberFormat(locales, options).format(this);
+    };
+    const dateDefaults = { year: 'numeric', month: 'numeric', day: 'numeric' };
+    const timeDefaults = { hour: 'numeric', minute: '2-digit', second: '2-digit' };
+    function hasAny(options, keys) {
+        return options !== undefined && Object.keys(keys).some((key) => options[key] !== undefined);
+    }
+    Date.prototype.toLocaleDateString = function (locales, options) {
+        const defaults = hasAny(options, dateDefaults) ? {} : dateDefaults;
+        return new DateTimeFormat(locales, { ...defaults, ...options }).format(this);
+    };
+    Date.prototype.toLocaleTimeString = function (locales, options) {
+        const defaults = hasAny(options, timeDefaults) ? {} : timeDefaults;
+        return new DateTimeFormat(locales, { ...defaults, ...options }).format(this);
+    };
+    Date.prototype.toLocaleString = function (locales, options) {
+        const all = { ...dateDefaults, ...timeDefaults };
+        const defaults = hasAny(options, all) ? {} : all;
+        return new DateTimeFormat(locales, { ...defaults, ...options }).format(this);
+    };
+})(globalThis);
+export default {};
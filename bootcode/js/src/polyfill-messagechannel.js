@@ -0,0 +1,64 @@
+import { structuredClone } from "./structured-clone";
+
+(function (g) {
+    class MessagePort extends EventTarget {
+        constructor() {
+            super();
+            this.onmessage = null;
+            this.onmessageerror = null;
+            this._other = null;
+            this._queue = [];
+            this._started = false;
+            this._closed = false;
+        }
+        postMessage(message, transfer) {
+            if (this._closed) {
+                return;
+            }
+            const options = Array.isArray(transfer) ? { transfer } : transfer;
+            const data = structuredClone(message, options);
+            const other = this._other;
+            if (other && !other._closed) {
+                other._queue.push(data);
+                other._schedule();
+            }
+        }
+        start() {
+            this._started = true;
+            this._schedule();
+        }
+        close() {
+            this._closed = true;
+            this._queue = [];
+            if (this._other) {
+                this._other._closed = true;
+            }
+        }
+        _schedule() {
+            // Setting onmessage implicitly starts the port as on the web.
+            if (!(this._started || typeof this.onmessage === 'function') || this._queue.length === 0) {
+                return;
+            }
+            setTimeout(() => this._deliver(), 0);
+        }
+        _deliver() {
+            while (this._queue.length > 0 && !this._closed) {
+                this.dispatchEvent(new MessageEvent('message', { data: this._queue.shift() }));
+            }
+        }
+    }
+
+    class MessageChannel {
+        constructor() {
+            this.port1 = new MessagePort();
+            this.port2 = new MessagePort();
+            this.port1._other = this.port2;
+            this.port2._other = this.port1;
+        }
+    }
+
+    g.structuredClone = structuredClone;
+    g.MessagePort = MessagePort;
+    g.MessageChannel = MessageChannel;
+})(globalThis);
+export default {};
@@ -0,0 +1,105 @@
+(function (g) {
+    const now = g.Sidevm.performanceNow;
+
+    class PerformanceEntry {
+        constructor(name, entryType, startTime, duration, detail) {
+            this.name = name;
+            this.entryType = entryType;
+            this.startTime = startTime;
+            this.duration = duration;
+            this.detail = detail === undefined ? null : detail;
+        }
+        toJSON() {
+            const { name, entryType, startTime, duration, detail } = this;
+            return { name, entryType, startTime, duration, detail };
+        }
+    }
+
+    class PerformanceMark extends PerformanceEntry {
+        constructor(name, options = {}) {
+            const startTime = options.startTime === undefined ? now() : Number(options.startTime);
+            super(String(name), 'mark', startTime, 0, options.detail);
+        }
+    }
+
+    class PerformanceMeasure extends PerformanceEntry {
+    }
+
+    class Performance {
+        constructor() {
+            this._entries = [];
+            this.timeOrigin = Date.now() - now();
+        }
+        // Milliseconds since the service was created, with sub-millisecond precision unless the
+        // embedder coarsened the clock.
+        now() {
+            return now();
+        }
+        mark(name, options) {
+            const mark = new PerformanceMark(name, options);
+            this._entries.push(mark);
+            return mark;
+        }
+        measure(name, startOrOptions, endMark) {
+            let start, end, detail;
+            if (typeof startOrOptions === 'object' && startOrOptions !== null) {
+                start = startOrOptions.start;
+                end = startOrOptions.end;
+                detail = startOrOptions.detail;
+                if (startOrOptions.duration !== undefined) {
+                    if (start === undefined) {
+                        start = this._time(end) - startOrOptions.duration;
+                    } else {
+                        end = this._time(start) + startOrOptions.duration;
+                    }
+                }
+            } else {
+                start = startOrOptions;
+                end = endMark;
+            }
+            const startTime = start === undefined ? 0 : this._time(start);
+            const endTime = end === undefined ? now() : this._time(end);
+            const measure = new PerformanceMeasure(String(name), 'measure', startTime, endTime - startTime, detail);
+            this._entries.push(measure);
+            return measure;
+        }
+        // The time of the last mark named `markOrTime`, or the time itself if it's a number.
+        _time(markOrTime) {
+            if (typeof markOrTime === 'number') {
+                return markOrTime;
+            }
+            const marks = this.getEntriesByName(String(markOrTime), 'mark');
+            if (marks.length === 0) {
+                throw new SyntaxError(`The mark '${markOrTime}' does not exist`);
+            }
+            return marks[marks.length - 1].startTime;
+        }
+        getEntries() {
+            return this._entries.slice().sort((a, b) => a.startTime - b.startTime);
+        }
+        getEntriesByName(name, type) {
+            return this.getEntries().filter((e) => e.name === name && (type === undefined || e.entryType === type));
+        }
+        getEntriesByType(type) {
+            return this.getEntries().filter((e) => e.entryType === type);
+        }
+        clearMarks(name) {
+            this._clear('mark', name);
+        }
+        clearMeasures(name) {
+            this._clear('measure', name);
+        }
+        _clear(type, name) {
+            this._entries = this._entries.filter((e) => e.entryType !== type || (name !== undefined && e.name !== name));
+        }
+        toJSON() {
+            return { timeOrigin: this.timeOrigin };
+        }
+    }
+
+    g.PerformanceEntry = PerformanceEntry;
+    g.PerformanceMark = PerformanceMark;
+    g.PerformanceMeasure = PerformanceMeasure;
+    g.performance = new Performance();
+})(globalThis);
+export default {};
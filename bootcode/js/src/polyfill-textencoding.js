@@ -1,39 +1,92 @@
-'use strict'; (function (g) {
-    function m(b) { var a = b.charCodeAt(0) | 0; if (55296 <= a) if (56320 > a) if (b = b.charCodeAt(1) | 0, 56320 <= b && 57343 >= b) { if (a = (a << 10) + b - 56613888 | 0, 65535 < a) return k(240 | a >>> 18, 128 | a >>> 12 & 63, 128 | a >>> 6 & 63, 128 | a & 63) } else a = 65533; else 57343 >= a && (a = 65533); return 2047 >= a ? k(192 | a >>> 6, 128 | a & 63) : k(224 | a >>> 12, 128 | a >>> 6 & 63, 128 | a & 63) } function n() { } function p(b, a) {
-        var f = void 0 === b ? "" : ("" + b).replace(/[\x80-\uD7ff\uDC00-\uFFFF]|[\uD800-\uDBFF][\uDC00-\uDFFF]?/g, m), d = f.length | 0, c = 0, e = 0, h = a.length | 0, q = b.length |
-            0; h < d && (d = h); a: for (; c < d; c = c + 1 | 0) { b = f.charCodeAt(c) | 0; switch (b >>> 4) { case 0: case 1: case 2: case 3: case 4: case 5: case 6: case 7: e = e + 1 | 0; case 8: case 9: case 10: case 11: break; case 12: case 13: if ((c + 1 | 0) < h) { e = e + 1 | 0; break } case 14: if ((c + 2 | 0) < h) { e = e + 1 | 0; break } case 15: if ((c + 3 | 0) < h) { e = e + 1 | 0; break } default: break a }a[c] = b } return { written: c, read: q < e ? q : e }
-    } var k = String.fromCharCode, t = g.Uint8Array || Array, r = n.prototype, l = g.TextEncoder; r.encode = function (b) {
-        b = void 0 === b ? "" : ("" + b).replace(/[\x80-\uD7ff\uDC00-\uFFFF]|[\uD800-\uDBFF][\uDC00-\uDFFF]?/g,
-            m); for (var a = b.length | 0, f = new t(a), d = 0; d < a; d = d + 1 | 0)f[d] = b.charCodeAt(d) | 0; return f
-    }; r.encodeInto = p; if (!l) g.TextEncoder = n; else if (!(g = l.prototype).encodeInto) { var u = new l; g.encodeInto = function (b, a) { var f = b.length | 0, d = a.length | 0; if (f < ((d >> 1) + 3 | 0)) { var c = u.encode(b); if ((c.length | 0) < d) return a.set(c), { read: f, written: c.length | 0 } } return p(b, a) } }
-})(globalThis);
 (function (g) {
+    const Sidevm = g.Sidevm;
+
+    function toBytes(input) {
+        if (input === undefined) {
+            return new Uint8Array(0);
+        }
+        if (input instanceof Uint8Array) {
+            return input;
+        }
+        if (input instanceof ArrayBuffer) {
+            return new Uint8Array(input);
+        }
+        if (ArrayBuffer.isView(input)) {
+            return new Uint8Array(input.buffer, input.byteOffset, input.byteLength);
+        }
+        throw new TypeError("The provided value is not of type '(ArrayBuffer or ArrayBufferView)'");
+    }
+
+    function canonicalEncoding(label) {
+        try {
+            return Sidevm.textEncoding(String(label));
+        } catch (err) {
+            throw new RangeError(`The encoding label provided ('${label}') is invalid.`);
+        }
+    }
+
+    class TextEncoder {
+        get encoding() {
+            return 'utf-8';
+        }
+        encode(input = '') {
+            return Sidevm.textEncode('utf-8', String(input));
+        }
+        encodeInto(input, dest) {
+            const { bytes, read } = Sidevm.textEncodeInto(String(input), dest.length);
+            dest.set(bytes);
+            return { read, written: bytes.length };
+        }
+    }
+
     class TextDecoder {
-        constructor(encoding = 'utf-8') {
-            if (encoding !== 'utf-8') {
-                throw new TypeError('Only utf-8 encoding is supported');
-            }
+        constructor(encoding = 'utf-8', options = {}) {
+            this._encoding = canonicalEncoding(encoding);
+            this._fatal = !!options.fatal;
+            this._ignoreBOM = !!options.ignoreBOM;
+            this._pending = null;
+            this._bomSeen = false;
+        }
+        get encoding() {
+            return this._encoding;
+        }
+        get fatal() {
+            return this._fatal;
         }
-        decode(bytes) {
-            let result = '';
-            let i = 0;
-            while (i < bytes.length) {
-                let codePoint = bytes[i++];
-                if (codePoint >= 0xC0) {
-                    if (codePoint < 0xE0) {
-                        codePoint = ((codePoint & 0x1F) << 6) | (bytes[i++] & 0x3F);
-                    } else if (codePoint < 0xF0) {
-                        codePoint = ((codePoint & 0x0F) << 12) | ((bytes[i++] & 0x3F) << 6) | (bytes[i++] & 0x3F);
-                    } else {
-                        codePoint = ((codePoint & 0x07) << 18) | ((bytes[i++] & 0x3F) << 12) | ((bytes[i++] & 0x3F) << 6) | (bytes[i++] & 0x3F);
-                    }
-                }
-                result += String.fromCharCode(codePoint);
+        get ignoreBOM() {
+            return this._ignoreBOM;
+        }
+        decode(input, options = {}) {
+            const stream = !!options.stream;
+            let bytes = toBytes(input);
+            if (this._pending) {
+                const joined = new Uint8Array(this._pending.length + bytes.length);
+                joined.set(this._pending);
+                joined.set(bytes, this._pending.length);
+                bytes = joined;
+                this._pending = null;
+            }
+            const stripBOM = !this._ignoreBOM && !this._bomSeen;
+            let result;
+            try {
+                result = Sidevm.textDecode(this._encoding, bytes, this._fatal, stream, stripBOM);
+            } catch (err) {
+                this._bomSeen = false;
+                throw new TypeError(String(err));
+            }
+            if (result.rest > 0) {
+                this._pending = bytes.slice(bytes.length - result.rest);
             }
-            return result;
+            if (stream) {
+                this._bomSeen = this._bomSeen || bytes.length > result.rest;
+            } else {
+                this._bomSeen = false;
+            }
+            return result.text;
         }
     }
+
+    g.TextEncoder = TextEncoder;
     g.TextDecoder = TextDecoder;
-}
-)(globalThis);
-export default {};
\ No newline at end of file
+})(globalThis);
+export default {};
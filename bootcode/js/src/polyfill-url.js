@@ -1,3 +1,186 @@
-require('core-js/full/url');
-require('core-js/full/url/can-parse');
-require('core-js/full/url-search-params');
\ No newline at end of file
+(function (g) {
+    const Sidevm = g.Sidevm;
+    if (!Sidevm.setURLComponent) {
+        // Built without the `js-url` feature, fall back to the pure JS implementation.
+        require('core-js/full/url');
+        require('core-js/full/url/can-parse');
+        require('core-js/full/url-search-params');
+        return;
+    }
+
+    const COMPONENTS = [
+        'protocol', 'username', 'password', 'host', 'hostname', 'port', 'pathname', 'hash',
+    ];
+
+    class URLSearchParams {
+        constructor(init = '') {
+            this._pairs = [];
+            this._url = null;
+            if (init instanceof URLSearchParams) {
+                this._pairs = init._pairs.map(([k, v]) => [k, v]);
+            } else if (typeof init === 'object' && init !== null) {
+                const entries = typeof init[Symbol.iterator] === 'function'
+                    ? Array.from(init)
+                    : Object.entries(init);
+                for (const entry of entries) {
+                    const pair = Array.from(entry);
+                    if (pair.length !== 2) {
+                        throw new TypeError('Each query pair must be an iterable [name, value] tuple');
+                    }
+                    this._pairs.push([String(pair[0]), String(pair[1])]);
+                }
+            } else {
+                this._pairs = Sidevm.parseURLSearchPairs(String(init));
+            }
+        }
+        _update() {
+            if (this._url) {
+                // An empty query drops the `?` from the url.
+                this._url._setComponent('search', this.toString());
+            }
+        }
+        get size() {
+            return this._pairs.length;
+        }
+        append(name, value) {
+            this._pairs.push([String(name), String(value)]);
+            this._update();
+        }
+        delete(name, value) {
+            name = String(name);
+            this._pairs = this._pairs.filter(([k, v]) =>
+                k !== name || (value !== undefined && v !== String(value)));
+            this._update();
+        }
+        get(name) {
+            name = String(name);
+            const pair = this._pairs.find(([k]) => k === name);
+            return pair ? pair[1] : null;
+        }
+        getAll(name) {
+            name = String(name);
+            return this._pairs.filter(([k]) => k === name).map(([, v]) => v);
+        }
+        has(name, value) {
+            name = String(name);
+            return this._pairs.some(([k, v]) =>
+                k === name && (value === undefined || v === String(value)));
+        }
+        set(name, value) {
+            name = String(name);
+            value = String(value);
+            const index = this._pairs.findIndex(([k]) => k === name);
+            if (index < 0) {
+                this._pairs.push([name, value]);
+            } else {
+                this._pairs[index][1] = value;
+                this._pairs = this._pairs.filter(([k], i) => k !== name || i <= index);
+            }
+            this._update();
+        }
+        sort() {
+            // Array.prototype.sort is stable, which the spec requires.
+            this._pairs.sort(([a], [b]) => (a < b ? -1 : a > b ? 1 : 0));
+            this._update();
+        }
+        forEach(callback, thisArg) {
+            for (const [k, v] of this._pairs) {
+                callback.call(thisArg, v, k, this);
+            }
+        }
+        *entries() {
+            for (const [k, v] of this._pairs) {
+                yield [k, v];
+            }
+        }
+        *keys() {
+            for (const [k] of this._pairs) {
+                yield k;
+            }
+        }
+        *values() {
+            for (const [, v] of this._pairs) {
+                yield v;
+            }
+        }
+        [Symbol.iterator]() {
+            return this.entries();
+        }
+        toString() {
+            return Sidevm.serializeURLSearchPairs(this._pairs);
+        }
+    }
+
+    class URL {
+        constructor(url, base) {
+            const args = [String(url)];
+            if (base !== undefined) {
+                args.push(String(base));
+            }
+            try {
+                this._record = Sidevm.parseURLRecord(...args);
+            } catch (err) {
+                throw new TypeError(`Invalid URL: ${url}`);
+            }
+            this._searchParams = new URLSearchParams(this._record.search);
+            this._searchParams._url = this;
+        }
+        static canParse(url, base) {
+            try {
+                new URL(url, base);
+                return true;
+            } catch (err) {
+                return false;
+            }
+        }
+        _setComponent(name, value) {
+            this._record = Sidevm.setURLComponent(this._record.href, name, String(value));
+        }
+        get href() {
+            return this._record.href;
+        }
+        set href(value) {
+            try {
+                this._setComponent('href', value);
+            } catch (err) {
+                throw new TypeError(`Invalid URL: ${value}`);
+            }
+            this._searchParams._pairs = Sidevm.parseURLSearchPairs(this._record.search);
+        }
+        get origin() {
+            return this._record.origin;
+        }
+        get search() {
+            return this._record.search;
+        }
+        set search(value) {
+            this._setComponent('search', value);
+            this._searchParams._pairs = Sidevm.parseURLSearchPairs(this._record.search);
+        }
+        get searchParams() {
+            return this._searchParams;
+        }
+        toString() {
+            return this.href;
+        }
+        toJSON() {
+            return this.href;
+        }
+    }
+
+    for (const name of COMPONENTS) {
+        Object.defineProperty(URL.prototype, name, {
+            get() {
+                return this._record[name];
+            },
+            set(value) {
+                this._setComponent(name, value);
+            },
+            enumerable: true,
+            configurable: true,
+        });
+    }
+
+    g.URL = URL;
+    g.URLSearchParams = URLSearchParams;
+})(globalThis);
@@ -0,0 +1,218 @@
+(function (g) {
+    const {
+        wasmCompile, wasmModuleImports, wasmModuleExports, wasmInstantiate, wasmCall,
+        wasmMemoryRead, wasmMemoryWrite, wasmMemorySize, wasmMemoryGrow, wasmGlobalGet, wasmGlobalSet,
+    } = g.Sidevm;
+    if (!wasmCompile || g.WebAssembly) {
+        return;
+    }
+
+    const PAGE_SIZE = 65536;
+
+    class CompileError extends Error {
+        get name() {
+            return 'CompileError';
+        }
+    }
+    class LinkError extends Error {
+        get name() {
+            return 'LinkError';
+        }
+    }
+    class RuntimeError extends Error {
+        get name() {
+            return 'RuntimeError';
+        }
+    }
+
+    function rethrowAs(ErrorClass, f) {
+        try {
+            return f();
+        } catch (err) {
+            throw new ErrorClass(err && err.message !== undefined ? err.message : String(err));
+        }
+    }
+
+    function toBytes(source) {
+        if (source instanceof ArrayBuffer) {
+            return new Uint8Array(source);
+        }
+        if (ArrayBuffer.isView(source)) {
+            return new Uint8Array(source.buffer, source.byteOffset, source.byteLength);
+        }
+        throw new TypeError('WebAssembly source must be an ArrayBuffer or a typed array');
+    }
+
+    // The host takes numbers, and i64 as decimal strings.
+    function toWire(type, value) {
+        switch (type) {
+            case 'i32': return Number(value) | 0;
+            case 'i64': return BigInt.asIntN(64, BigInt(value)).toString();
+            case 'f32': return Math.fround(Number(value));
+            default: return Number(value);
+        }
+    }
+
+    function fromWire(type, value) {
+        return type === 'i64' ? BigInt(value) : value;
+    }
+
+    // Zero results give undefined, one gives the value and more give an array.
+    function unpackResults(types, values) {
+        const results = values.map((value, i) => fromWire(types[i], value));
+        return results.length === 0 ? undefined : results.length === 1 ? results[0] : results;
+    }
+
+    class Module {
+        constructor(bytes) {
+            this._handle = rethrowAs(CompileError, () => wasmCompile(toBytes(bytes)));
+        }
+        static imports(module) {
+            return wasmModuleImports(module._handle).map(({ module, name, kind }) => ({ module, name, kind }));
+        }
+        static exports(module) {
+            return wasmModuleExports(module._handle).map(({ name, kind }) => ({ name, kind }));
+        }
+        get [Symbol.toStringTag]() {
+            return 'WebAssembly.Module';
+        }
+    }
+
+    // A live view of an exported memory. `buffer` is a copy of the memory at the time of the
+    // access, use `read` and `write` to exchange data with the instance.
+    class Memory {
+        constructor(instance, name) {
+            this._instance = instance;
+            this._name = name;
+        }
+        get buffer() {
+            return this.read(0, wasmMemorySize(this._instance, this._name) * PAGE_SIZE).buffer;
+        }
+        grow(delta) {
+            return rethrowAs(RangeError, () => wasmMemoryGrow(this._instance, this._name, delta));
+        }
+        read(offset, length) {
+            return rethrowAs(RangeError, () => wasmMemoryRead(this._instance, this._name, offset, length));
+        }
+        write(offset, bytes) {
+            rethrowAs(RangeError, () => wasmMemoryWrite(this._instance, this._name, offset, toBytes(bytes)));
+        }
+        get [Symbol.toStringTag]() {
+            return 'WebAssembly.Memory';
+        }
+    }
+
+    class Global {
+        constructor(instance, name, type) {
+            this._instance = instance;
+            this._name = name;
+            this._type = type;
+        }
+        get value() {
+            return fromWire(this._type, wasmGlobalGet(this._instance, this._name));
+        }
+        set value(value) {
+            rethrowAs(TypeError, () => wasmGlobalSet(this._instance, this._name, toWire(this._type, value)));
+        }
+        valueOf() {
+            return this.value;
+        }
+        get [Symbol.toStringTag]() {
+            return 'WebAssembly.Global';
+        }
+    }
+
+    class Instance {
+        constructor(module, importObject = {}) {
+            if (!(module instanceof Module)) {
+                throw new TypeError('Argument 0 must be a WebAssembly.Module');
+            }
+            const imports = wasmModuleImports(module._handle);
+            const funcs = imports.map(({ module, name, kind }) => {
+                const func = (importObject[module] || {})[name];
+                if (kind !== 'function' || typeof func !== 'function') {
+                    throw new LinkError(`Import ${module}.${name} must be a function`);
+                }
+                return func;
+            });
+            // Imported functions are dispatched by their index in the import list.
+            const handler = (index, args) => {
+                const { params, results } = imports[index];
+                const ret = funcs[index](...args.map((arg, i) => fromWire(params[i], arg)));
+                const values = results.length === 1 ? [ret] : results.length === 0 ? [] : Array.from(ret);
+                return values.map((value, i) => toWire(results[i], value));
+            };
+            const handle = rethrowAs(LinkError, () => wasmInstantiate(module._handle, handler));
+            const exports = {};
+            for (const { name, kind, params, results } of wasmModuleExports(module._handle)) {
+                switch (kind) {
+                    case 'function':
+                        exports[name] = function (...args) {
+                            const wire = params.map((type, i) => toWire(type, args[i] === undefined ? 0 : args[i]));
+                            const values = rethrowAs(RuntimeError, () => wasmCall(handle, name, wire, handler));
+                            return unpackResults(results, values);
+                        };
+                        break;
+                    case 'memory':
+                        exports[name] = new Memory(handle, name);
+                        break;
+                    case 'global':
+                        exports[name] = new Global(handle, name, params[0]);
+                        break;
+                }
+            }
+            this.exports = Object.freeze(exports);
+        }
+        get [Symbol.toStringTag]() {
+            return 'WebAssembly.Instance';
+        }
+    }
+
+    async function compile(bytes) {
+        return new Module(bytes);
+    }
+
+    async function instantiate(source, importObject) {
+        if (source instanceof Module) {
+            return new Instance(source, importObject);
+        }
+        const module = new Module(source);
+        return { module, instance: new Instance(module, importObject) };
+    }
+
+    function validate(bytes) {
+        try {
+            wasmCompile(toBytes(bytes));
+            return true;
+        } catch (err) {
+            if (err instanceof TypeError) {
+                throw err;
+            }
+            return false;
+        }
+    }
+
+    async function compileStreaming(response) {
+        return compile(await (await response).arrayBuffer());
+    }
+
+    async function instantiateStreaming(response, importObject) {
+        return instantiate(await (await response).arrayBuffer(), importObject);
+    }
+
+    g.WebAssembly = {
+        Module,
+        Instance,
+        Memory,
+        Global,
+        CompileError,
+        LinkError,
+        RuntimeError,
+        compile,
+        compileStreaming,
+        instantiate,
+        instantiateStreaming,
+        validate,
+    };
+})(globalThis);
+export default {};
@@ -0,0 +1,59 @@
+import { serialize, deserialize } from "./structured-clone";
+import { dispatchGlobalEvent } from "./global-events";
+
+(function (g) {
+    const Sidevm = g.Sidevm;
+    if (!Sidevm.workerCreate) {
+        return;
+    }
+
+    /**
+     * Runs `source` in a separate context. `source` is the code of the worker, or a function
+     * whose body is run as the worker.
+     *
+     * Workers run until `terminate()` is called or the worker calls `close()`.
+     */
+    class Worker extends EventTarget {
+        constructor(source) {
+            super();
+            if (typeof source === 'function') {
+                source = `(${source})();`;
+            }
+            this.onmessage = null;
+            this.onerror = null;
+            this._id = Sidevm.workerCreate(String(source), (type, data) => {
+                switch (type) {
+                    case 'message':
+                        this.dispatchEvent(new MessageEvent(type, { data: deserialize(data) }));
+                        break;
+                    case 'error':
+                        this.dispatchEvent(Object.assign(new Event(type), { message: data, error: new Error(data) }));
+                        break;
+                }
+            });
+        }
+        // Messages are serialized across contexts, so transferring is the same as copying.
+        postMessage(message, _transfer) {
+            Sidevm.workerPostMessage(this._id, serialize(message));
+        }
+        terminate() {
+            Sidevm.close(this._id);
+        }
+    }
+    g.Worker = Worker;
+
+    // The worker side. These are harmless in the main context, where `postMessage` throws.
+    Sidevm.dispatchWorkerMessage = function (data) {
+        dispatchGlobalEvent('message', { type: 'message', data: deserialize(data) });
+    };
+    g.postMessage = function (message, _transfer) {
+        Sidevm.workerPostToParent(serialize(message));
+    };
+    g.close = function () {
+        Sidevm.workerClose();
+    };
+    if (g.self === undefined) {
+        g.self = g;
+    }
+})(globalThis);
+export default {};
@@ -239,7 +239,6 @@
                 return void 0;
             }
             _sendHxxpRequest() {
-                var agent = "sidejs/1.0.0";
                 const request = {};
                 const response = {
                     headers: {},
@@ -248,10 +247,7 @@
                 const reqId = Sidevm.httpRequest({
                     url: this.url,
                     method: this._method,
-                    headers: {
-                        "User-Agent": agent,
-                        ...this._headers,
-                    },
+                    headers: this._headers,
                     timeout: this.timeout || 60000,
                     body: this.upload._body,
                 },
@@ -293,12 +289,10 @@
             }
 
             _finalizeHeaders() {
-                var base;
                 this._headers['Connection'] = 'keep-alive';
                 if (this._anonymous) {
                     this._headers['Referer'] = 'about:blank';
                 }
-                (base = this._headers)['User-Agent'] || (base['User-Agent'] = this._userAgent);
                 this.upload._finalizeHeaders(this._headers, this._loweredHeaders);
                 return void 0;
             }
@@ -554,7 +548,6 @@
             'set-cookie': true,
             'set-cookie2': true
         };
-        XMLHttpRequest.prototype._userAgent = `Sidevm HTTP Client`;
 
         return XMLHttpRequest;
 
@@ -1,7 +1,7 @@
 (function (g) {
     function timerFn(hostFn) {
         return function (f, t) {
-            t = t || 0;
+            t = Math.max(0, Math.floor(Number(t) || 0));
             if (typeof f == 'string') {
                 return hostFn(() => eval(f), t);
             }
@@ -20,16 +20,339 @@
         return merged;
     }
     g.Sidevm.concatU8a = concatU8a;
+    // The JSON.stringify replacer of the script outputs, tagging BigInts as `{"$bigint": "<decimal>"}`.
+    g.Sidevm.jsonReplacer = (_key, value) => typeof value == 'bigint' ? { $bigint: value.toString() } : value;
+    // Cancel the resource created by `start` when `signal` aborts. The listener is detached once
+    // the callback receives one of the `finalEvents`.
+    function abortable(signal, start, cancel, callback, finalEvents) {
+        if (!signal) {
+            return start(callback);
+        }
+        let id;
+        const onAbort = () => cancel(id);
+        const wrapped = (cmd, data) => {
+            if (finalEvents.includes(cmd)) {
+                signal.removeEventListener("abort", onAbort);
+            }
+            return callback(cmd, data);
+        };
+        id = start(wrapped);
+        if (signal.aborted) {
+            cancel(id);
+        } else {
+            signal.addEventListener("abort", onAbort);
+        }
+        return id;
+    }
+    const { httpRequest, grpcWebCall, websocketConnect, tcpConnect, tlsConnect } = g.Sidevm;
+    // A request refused by the rate limit the embedder set on the service. It's a TypeError as
+    // the other network errors of fetch.
+    class RateLimitError extends TypeError {
+        get name() {
+            return 'RateLimitError';
+        }
+    }
+    g.Sidevm.RateLimitError = RateLimitError;
+    g.Sidevm.httpRequest = function (req, callback) {
+        if (req.formData instanceof FormData || req.body instanceof FormData) {
+            req = { ...req, formData: (req.formData || req.body)._fields(), body: undefined };
+        }
+        // Rate limiting is reported as an error carrying the RateLimitError.
+        const onEvent = (cmd, data) => cmd == "rateLimited"
+            ? callback("error", new RateLimitError(data))
+            : callback(cmd, data);
+        return abortable(req.abortSignal, (cb) => httpRequest(req, cb),
+            Sidevm.cancelHttpRequest, onEvent, ["end", "error", "abort", "rateLimited"]);
+    }
+    // A call that completed with a non-OK gRPC status.
+    class GrpcError extends Error {
+        constructor(code, details, metadata) {
+            super(`gRPC status ${code}: ${details}`);
+            this.code = code;
+            this.details = details;
+            this.metadata = metadata;
+        }
+        get name() {
+            return 'GrpcError';
+        }
+    }
+    g.Sidevm.GrpcError = GrpcError;
+    // Call `/{service}/{method}` of the gRPC-web server at `url` with the protobuf encoded
+    // `message`, resolving to `{ message, messages, headers, trailers }` with the response
+    // messages as Uint8Arrays. `options` takes the httpRequest options, e.g. `headers` for the
+    // metadata, `timeoutMs` and `signal`.
+    g.Sidevm.grpcWebCall = function (url, service, method, message, options = {}) {
+        const { signal, ...req } = options;
+        return new Promise((resolve, reject) => {
+            const callback = (cmd, data) => {
+                switch (cmd) {
+                    case "response":
+                        if (data.status === 0) {
+                            resolve({
+                                message: data.messages[0],
+                                messages: data.messages,
+                                headers: data.headers,
+                                trailers: data.trailers,
+                            });
+                        } else {
+                            reject(new GrpcError(data.status, data.statusMessage, data.trailers));
+                        }
+                        break;
+                    case "rateLimited":
+                        reject(new RateLimitError(data));
+                        break;
+                    case "error":
+                        reject(new TypeError(data));
+                        break;
+                }
+            };
+            const cancel = (id) => {
+                Sidevm.close(id);
+                const err = new Error("The call was aborted");
+                err.name = "AbortError";
+                reject(err);
+            };
+            abortable(signal, (cb) => grpcWebCall({ ...req, url }, String(service), String(method),
+                message, cb), cancel, callback, ["response", "rateLimited", "error"]);
+        });
+    }
+    if (websocketConnect) {
+        g.Sidevm.websocketConnect = function (url, callback, options) {
+            const signal = options && options.signal;
+            return abortable(signal, (cb) => websocketConnect(url, cb),
+                Sidevm.close, callback, ["close", "error"]);
+        }
+    }
+    if (tcpConnect) {
+        g.Sidevm.tcpConnect = function (host, port, callback, options) {
+            const signal = options && options.signal;
+            return abortable(signal, (cb) => tcpConnect(host, port, cb),
+                Sidevm.close, callback, ["end", "error"]);
+        }
+    }
+    if (tlsConnect) {
+        g.Sidevm.tlsConnect = function (host, port, callback, options) {
+            const signal = options && options.signal;
+            return abortable(signal, (cb) => tlsConnect(host, port, cb, options),
+                Sidevm.close, callback, ["end", "error"]);
+        }
+    }
+    const { dnsResolve } = g.Sidevm;
+    if (dnsResolve) {
+        // Resolves to an array of strings, or of `{priority, exchange}` for MX records.
+        g.Sidevm.dnsResolve = function (hostname, recordType = 'A') {
+            return new Promise((resolve, reject) => {
+                dnsResolve(hostname, recordType, (cmd, data) => {
+                    if (cmd === "result") {
+                        resolve(data);
+                    } else {
+                        reject(new Error(data));
+                    }
+                });
+            });
+        }
+    }
+    if (Sidevm.scaleEncodeJson) {
+        // BigInts travel as decimal strings and byte arrays as hex, see host_functions/scale.rs.
+        const replacer = (key, value) => {
+            if (typeof value === 'bigint') {
+                return value.toString();
+            }
+            if (value instanceof Uint8Array) {
+                return '0x' + Array.from(value, (b) => b.toString(16).padStart(2, '0')).join('');
+            }
+            return value;
+        };
+        g.Sidevm.scaleEncode = function (typeDef, value) {
+            return Sidevm.scaleEncodeJson(typeDef, JSON.stringify(value === undefined ? null : value, replacer));
+        };
+        g.Sidevm.scaleDecode = function (typeDef, bytes) {
+            return JSON.parse(Sidevm.scaleDecodeJson(typeDef, bytes));
+        };
+    }
+    const { verifyMerkleProof, verifyMerkleMultiproof, verifyTrieProof } = g.Sidevm;
+    if (verifyMerkleProof) {
+        // Hashes and keys are Uint8Arrays or hex strings, as returned by the RPCs.
+        const toBytes = (value) => typeof value == 'string'
+            ? Sidevm.hexDecode(value.replace(/^0x/i, ''))
+            : value;
+        // Whether `leaf` is in the tree of `root`. `hasher` is a `Sidevm.hash` algorithm for
+        // binary trees, whose pairs are sorted unless `options.index` gives the leaf position.
+        // For Substrate storage proofs it's 'substrate', or 'substrate-<algorithm>' for another
+        // trie hasher than blake2b256, and `leaf` is `{ key, value }`, a null value proving the
+        // absence of the key.
+        g.Sidevm.verifyMerkleProof = function (root, proof, leaf, hasher = 'keccak256', options = {}) {
+            proof = proof.map(toBytes);
+            if (hasher == 'substrate' || hasher.startsWith('substrate-')) {
+                const trieHasher = hasher == 'substrate' ? 'blake2b256' : hasher.slice('substrate-'.length);
+                const value = leaf.value == null ? null : toBytes(leaf.value);
+                return verifyTrieProof(toBytes(root), proof, toBytes(leaf.key), value, trieHasher);
+            }
+            return verifyMerkleProof(toBytes(root), proof, toBytes(leaf), hasher, options.index);
+        };
+        // Whether all the `leaves` are in the tree of `root`, as OpenZeppelin's multiProofVerify.
+        g.Sidevm.verifyMerkleMultiproof = function (root, proof, flags, leaves, hasher = 'keccak256') {
+            return verifyMerkleMultiproof(toBytes(root), proof.map(toBytes), flags.map(Boolean),
+                leaves.map(toBytes), hasher);
+        };
+    }
+    const { registerQueryHandler, registerMessageHandler, replyQuery } = g.Sidevm;
+    // `handler(payload, origin)` may return a promise, its result is the reply of the query.
+    const { fetchModule } = g.Sidevm;
+    if (fetchModule) {
+        const fetchOne = (url, integrity) => new Promise((resolve, reject) => {
+            fetchModule(url, integrity, (cmd, data) => {
+                if (cmd === "result") {
+                    resolve();
+                } else {
+                    reject(new Error(data));
+                }
+            });
+        });
+        // Fetches the module, verified with its subresource integrity, then imports it.
+        // `dependencies` maps the modules it imports, relative to `url` or absolute, to their
+        // integrity, e.g. `{ "./util.js": "sha384-..." }`.
+        g.Sidevm.importRemote = async function (url, integrity, dependencies = {}) {
+            await Promise.all(Object.entries(dependencies).map(
+                ([specifier, integrity]) => fetchOne(new URL(specifier, url).href, integrity)
+            ));
+            await fetchOne(url, integrity);
+            return import(/* webpackIgnore: true */ url);
+        }
+    }
+    const { getAttestationQuote } = g.Sidevm;
+    if (getAttestationQuote) {
+        // Resolves to a quote of the TEE worker binding the running script and `userData`, such
+        // as a hash of the output of the script. The report data of the quote is
+        // blake2b512("sidevm-quickjs:attestation:v1\0" ‖ codeHash ‖ userData).
+        g.Sidevm.getAttestationQuote = function (userData) {
+            return new Promise((resolve, reject) => {
+                getAttestationQuote(userData, (cmd, data) => {
+                    if (cmd === "result") {
+                        resolve(data);
+                    } else {
+                        reject(new Error(data));
+                    }
+                });
+            });
+        }
+    }
+    g.Sidevm.registerQueryHandler = function (handler) {
+        registerQueryHandler((id, payload, origin) => {
+            new Promise((resolve) => resolve(handler(payload, origin))).then(
+                (value) => replyQuery(id, true, value),
+                (err) => replyQuery(id, false, String(err)));
+        });
+    }
+    g.Sidevm.registerMessageHandler = function (handler) {
+        registerMessageHandler((payload) => {
+            new Promise((resolve) => resolve(handler(payload))).catch(
+                (err) => console.error('Message handler failed:', err));
+        });
+    }
     g.setTimeout = timerFn(Sidevm.setTimeout);
     g.setInterval = timerFn(Sidevm.setInterval);
-    g.clearTimeout = Sidevm.close;
-    g.clearInterval = Sidevm.close;
+    function clearTimer(id) {
+        // Clearing an invalid id is a no-op as on the web
+        if (typeof id == 'number' && id > 0) {
+            Sidevm.close(id);
+        }
+    }
+    // Encode a BigInt in `size` bytes, as few as possible if not given, little-endian unless
+    // `endianness` is 'be'. Negative values are encoded in two's complement.
+    g.Sidevm.bigintToBytes = function (value, size, endianness = 'le') {
+        value = BigInt(value);
+        const negative = value < 0n;
+        const hex = (negative ? -value : value).toString(16);
+        return Sidevm.bigintHexToBytes(hex, negative, size, endianness == 'le');
+    };
+    // Decode a BigInt, in two's complement if `signed`.
+    g.Sidevm.bytesToBigint = function (bytes, endianness = 'le', signed = false) {
+        const { hex, negative } = Sidevm.bytesToBigintHex(bytes, endianness == 'le', signed);
+        const value = BigInt('0x' + hex);
+        return negative ? -value : value;
+    };
+    // Like JSON.parse, with the integers beyond Number.MAX_SAFE_INTEGER parsed as BigInts rather
+    // than rounded, e.g. the balances in RPC responses.
+    JSON.parseBig = function (text, reviver) {
+        const { text: quoted, marker } = Sidevm.jsonQuoteBigInts(String(text));
+        if (!marker) {
+            return JSON.parse(quoted, reviver);
+        }
+        return JSON.parse(quoted, function (key, value) {
+            if (typeof value == 'string' && value.startsWith(marker)) {
+                value = BigInt(value.slice(marker.length));
+            }
+            return reviver ? reviver.call(this, key, value) : value;
+        });
+    };
+    g.clearTimeout = clearTimer;
+    g.clearInterval = clearTimer;
+    // Immediates run after the promise jobs, in the order they were set.
+    g.setImmediate = function (f) {
+        const args = Array.prototype.slice.call(arguments, 1);
+        return Sidevm.setImmediate(() => { f.apply(null, args); });
+    };
+    g.clearImmediate = clearTimer;
+    // Run `handler({ scheduledTime, cron })` on the cron schedule `spec`, in UTC. The runs missed
+    // while the service was busy are dropped, fired once or all fired, by `options.misfire` being
+    // 'skip', 'once' (default) or 'all'. Returns an id for `cancelCron`.
+    g.registerCron = function (spec, handler, options = {}) {
+        return Sidevm.registerCron(String(spec), (scheduledTime) => {
+            new Promise((resolve) => resolve(handler({ scheduledTime, cron: spec }))).catch(
+                (err) => console.error('Cron handler failed:', err));
+        }, options.misfire);
+    };
+    g.cancelCron = clearTimer;
+    // Jobs of the QuickJS job queue, run before the next host event is delivered. An exception is
+    // reported as an unhandled rejection.
+    g.queueMicrotask = function (callback) {
+        if (typeof callback != 'function') {
+            throw new TypeError('The callback provided as parameter 1 is not a function');
+        }
+        Promise.resolve().then(() => callback());
+    };
     g.Sidevm.inspect = function (...obj) {
         return Sidevm.print(2, obj, {
             indent: '  ',
             depth: 5,
         });
     }
+    // Render the rows of `data` as a text table like console.table in browsers.
+    function renderTable(data, columns) {
+        const rows = data instanceof Map ? Array.from(data) : Object.entries(data);
+        const isObject = (v) => v !== null && typeof v === 'object';
+        const fmt = (v) => typeof v === 'string' ? `'${v}'` : String(v);
+        if (!columns) {
+            const keys = new Set();
+            rows.forEach(([, v]) => isObject(v) && Object.keys(v).forEach((k) => keys.add(k)));
+            columns = Array.from(keys);
+        }
+        const hasValues = rows.some(([, v]) => !isObject(v));
+        const header = ['(index)', ...columns, ...(hasValues ? ['Values'] : [])];
+        const body = rows.map(([k, v]) => [
+            String(k),
+            ...columns.map((c) => isObject(v) && c in v ? fmt(v[c]) : ''),
+            ...(hasValues ? [isObject(v) ? '' : fmt(v)] : []),
+        ]);
+        const widths = header.map((h, i) => Math.max(h.length, ...body.map((r) => r[i].length)));
+        const line = (cells) => '| ' + cells.map((c, i) => c.padEnd(widths[i])).join(' | ') + ' |';
+        const sep = '|' + widths.map((w) => '-'.repeat(w + 2)).join('|') + '|';
+        return [line(header), sep, ...body.map(line)].join('\n');
+    }
+    const timers = new Map();
+    const counters = new Map();
+    // The polyfill of `performance` is installed after this module, it is looked up on use.
+    const now = () => (g.performance ? g.performance.now() : Date.now());
+    function timeLog(label, args) {
+        if (!timers.has(label)) {
+            Sidevm.print(3, [`Timer '${label}' does not exist`]);
+            return false;
+        }
+        Sidevm.print(2, [`${label}: ${(now() - timers.get(label)).toFixed(3)}ms`, ...args]);
+        return true;
+    }
+    // Levels are trace(0), debug(1), info(2), warn(3) and error(4), see `Service::js_log`.
     g.console = {
         log(...args) {
             return Sidevm.print(2, args);
@@ -37,17 +360,74 @@
         info(...args) {
             return Sidevm.print(2, args);
         },
+        debug(...args) {
+            return Sidevm.print(1, args);
+        },
         warn(...args) {
             return Sidevm.print(3, args);
         },
         error(...args) {
             return Sidevm.print(4, args);
+        },
+        trace(...args) {
+            const stack = new Error().stack.split('\n').slice(1).join('\n');
+            return Sidevm.print(1, ['Trace:', ...args, '\n' + stack]);
+        },
+        assert(condition, ...args) {
+            if (!condition) {
+                Sidevm.print(4, ['Assertion failed' + (args.length > 0 ? ':' : ''), ...args]);
+            }
+        },
+        dir(obj, options) {
+            return Sidevm.print(2, [obj], { indent: '  ', depth: (options && options.depth) || 5 });
+        },
+        table(data, columns) {
+            if (data === null || typeof data !== 'object') {
+                return Sidevm.print(2, [data]);
+            }
+            return Sidevm.print(2, [renderTable(data, columns)]);
+        },
+        time(label = 'default') {
+            if (timers.has(label)) {
+                Sidevm.print(3, [`Timer '${label}' already exists`]);
+                return;
+            }
+            timers.set(label, now());
+        },
+        timeLog(label = 'default', ...args) {
+            timeLog(label, args);
+        },
+        timeEnd(label = 'default') {
+            if (timeLog(label, [])) {
+                timers.delete(label);
+            }
+        },
+        count(label = 'default') {
+            const count = (counters.get(label) || 0) + 1;
+            counters.set(label, count);
+            Sidevm.print(2, [`${label}: ${count}`]);
+        },
+        countReset(label = 'default') {
+            counters.delete(label);
+        },
+    }
+    /** Thrown by the host functions of a capability the service doesn't allow. */
+    class PermissionDenied extends Error {
+        constructor(capability) {
+            super(`Permission denied: ${capability}`);
+            this.name = 'PermissionDenied';
+            this.capability = capability;
         }
     }
+    g.PermissionDenied = PermissionDenied;
     g.print = g.console.log;
     g.global = g;
     g.window = g;
     g.SCALE = Sidevm.SCALE;
+    g.__memoryUsage = Sidevm.memoryUsage;
+    if (Sidevm.Codec) {
+        g.Codec = Sidevm.Codec;
+    }
 }(globalThis))
 
 export default {};
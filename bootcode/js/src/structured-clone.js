@@ -0,0 +1,225 @@
+// A subset of the structured clone algorithm, both in the same context and serialized for
+// passing between contexts. Serialized values are encoded into JSON with tagged objects for the
+// types JSON can't represent, the contents of the ArrayBuffers and typed arrays being carried
+// aside as bytes.
+
+const TAG = '$sc';
+const TYPED_ARRAYS = {
+    Int8Array, Uint8Array, Uint8ClampedArray, Int16Array, Uint16Array, Int32Array, Uint32Array,
+    Float32Array, Float64Array, BigInt64Array, BigUint64Array,
+};
+
+// The ArrayBuffer holding exactly `bytes`, without a copy if possible.
+function ownBuffer(bytes) {
+    if (bytes.byteOffset === 0 && bytes.byteLength === bytes.buffer.byteLength) {
+        return bytes.buffer;
+    }
+    return bytes.slice().buffer;
+}
+
+function dataCloneError(what) {
+    const err = new Error(`${what} could not be cloned`);
+    err.name = 'DataCloneError';
+    return err;
+}
+
+function toWire(value, seen, buffers) {
+    switch (typeof value) {
+        case 'undefined':
+            return { [TAG]: 'undefined' };
+        case 'bigint':
+            return { [TAG]: 'bigint', v: value.toString() };
+        case 'number':
+            return Number.isFinite(value) ? value : { [TAG]: 'number', v: String(value) };
+        case 'string':
+        case 'boolean':
+            return value;
+        case 'function':
+        case 'symbol':
+            throw dataCloneError(String(value));
+    }
+    if (value === null) {
+        return null;
+    }
+    if (seen.has(value)) {
+        throw dataCloneError('Circular reference');
+    }
+    seen.add(value);
+    try {
+        if (Array.isArray(value)) {
+            return value.map((item) => toWire(item, seen, buffers));
+        }
+        if (value instanceof Date) {
+            return { [TAG]: 'Date', v: value.getTime() };
+        }
+        if (value instanceof RegExp) {
+            return { [TAG]: 'RegExp', v: value.source, flags: value.flags };
+        }
+        if (value instanceof ArrayBuffer) {
+            buffers.push(new Uint8Array(value));
+            return { [TAG]: 'ArrayBuffer', i: buffers.length - 1 };
+        }
+        if (ArrayBuffer.isView(value)) {
+            buffers.push(new Uint8Array(value.buffer, value.byteOffset, value.byteLength));
+            const type = value instanceof DataView ? 'DataView' : value.constructor.name;
+            return { [TAG]: type, i: buffers.length - 1 };
+        }
+        if (value instanceof Map) {
+            return {
+                [TAG]: 'Map',
+                v: Array.from(value, ([k, v]) => [toWire(k, seen, buffers), toWire(v, seen, buffers)]),
+            };
+        }
+        if (value instanceof Set) {
+            return { [TAG]: 'Set', v: Array.from(value, (item) => toWire(item, seen, buffers)) };
+        }
+        if (value instanceof Error) {
+            return { [TAG]: 'Error', name: value.name, v: value.message, stack: value.stack };
+        }
+        const obj = {};
+        for (const key of Object.keys(value)) {
+            obj[key] = toWire(value[key], seen, buffers);
+        }
+        return { [TAG]: 'Object', v: obj };
+    } finally {
+        seen.delete(value);
+    }
+}
+
+function fromWire(wire, buffers) {
+    if (wire === null || typeof wire !== 'object') {
+        return wire;
+    }
+    if (Array.isArray(wire)) {
+        return wire.map((item) => fromWire(item, buffers));
+    }
+    const v = wire.v;
+    switch (wire[TAG]) {
+        case 'undefined':
+            return undefined;
+        case 'bigint':
+            return BigInt(v);
+        case 'number':
+            return Number(v);
+        case 'Date':
+            return new Date(v);
+        case 'RegExp':
+            return new RegExp(v, wire.flags);
+        case 'ArrayBuffer':
+            return ownBuffer(buffers[wire.i]);
+        case 'DataView':
+            return new DataView(ownBuffer(buffers[wire.i]));
+        case 'Map':
+            return new Map(v.map(([k, val]) => [fromWire(k, buffers), fromWire(val, buffers)]));
+        case 'Set':
+            return new Set(v.map((item) => fromWire(item, buffers)));
+        case 'Error': {
+            const Ctor = globalThis[wire.name];
+            const err = typeof Ctor === 'function' && Ctor.prototype instanceof Error
+                ? new Ctor(v) : new Error(v);
+            err.name = wire.name;
+            err.stack = wire.stack;
+            return err;
+        }
+        case 'Object': {
+            const obj = {};
+            for (const key of Object.keys(v)) {
+                obj[key] = fromWire(v[key], buffers);
+            }
+            return obj;
+        }
+        default: {
+            const TypedArray = TYPED_ARRAYS[wire[TAG]];
+            if (TypedArray) {
+                return new TypedArray(ownBuffer(buffers[wire.i]));
+            }
+            throw new TypeError(`Unknown serialized type ${wire[TAG]}`);
+        }
+    }
+}
+
+// Returns `{ data, buffers }`, `data` being the JSON of `value` and `buffers` the Uint8Arrays it
+// refers to by index.
+export function serialize(value) {
+    const buffers = [];
+    const data = JSON.stringify(toWire(value, new Set(), buffers));
+    return { data, buffers };
+}
+
+export function deserialize({ data, buffers }) {
+    return fromWire(JSON.parse(data), buffers);
+}
+
+function cloneValue(value, memory) {
+    switch (typeof value) {
+        case 'function':
+        case 'symbol':
+            throw dataCloneError(String(value));
+        case 'object':
+            break;
+        default:
+            return value;
+    }
+    if (value === null) {
+        return null;
+    }
+    if (memory.has(value)) {
+        return memory.get(value);
+    }
+    let out;
+    if (value instanceof Date) {
+        out = new Date(value.getTime());
+    } else if (value instanceof RegExp) {
+        out = new RegExp(value.source, value.flags);
+    } else if (value instanceof ArrayBuffer) {
+        out = value.slice(0);
+    } else if (value instanceof DataView) {
+        out = new DataView(cloneValue(value.buffer, memory), value.byteOffset, value.byteLength);
+    } else if (ArrayBuffer.isView(value)) {
+        out = new value.constructor(cloneValue(value.buffer, memory), value.byteOffset, value.length);
+    } else if (value instanceof Boolean || value instanceof Number || value instanceof String) {
+        out = Object(value.valueOf());
+    } else if (value instanceof Map) {
+        out = new Map();
+        memory.set(value, out);
+        value.forEach((v, k) => out.set(cloneValue(k, memory), cloneValue(v, memory)));
+    } else if (value instanceof Set) {
+        out = new Set();
+        memory.set(value, out);
+        value.forEach((v) => out.add(cloneValue(v, memory)));
+    } else if (value instanceof Error) {
+        const Ctor = globalThis[value.name];
+        out = typeof Ctor === 'function' && Ctor.prototype instanceof Error
+            ? new Ctor(value.message) : new Error(value.message);
+        out.name = value.name;
+        out.stack = value.stack;
+    } else {
+        out = Array.isArray(value) ? new Array(value.length) : {};
+        memory.set(value, out);
+        for (const key of Object.keys(value)) {
+            out[key] = cloneValue(value[key], memory);
+        }
+    }
+    memory.set(value, out);
+    return out;
+}
+
+/**
+ * Deep clone `value`, keeping shared and circular references.
+ *
+ * The ArrayBuffers in `options.transfer` are moved into the clone instead of copied. They are
+ * detached from the original where the engine supports `ArrayBuffer.prototype.transfer`.
+ */
+export function structuredClone(value, options = {}) {
+    const memory = new Map();
+    for (const buffer of options.transfer || []) {
+        if (!(buffer instanceof ArrayBuffer)) {
+            throw dataCloneError('Only ArrayBuffers can be transferred, the value');
+        }
+        if (memory.has(buffer)) {
+            throw dataCloneError('An ArrayBuffer transferred twice');
+        }
+        memory.set(buffer, typeof buffer.transfer === 'function' ? buffer.transfer() : buffer);
+    }
+    return cloneValue(value, memory);
+}
@@ -1,2 +1,4 @@
 #![no_std]
+/// The polyfills and the `Sidevm` wrappers, bundled and compiled to QuickJS bytecode by the build
+/// script so that services don't parse them on startup.
 pub const BOOT_CODE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bootcode.jsc"));
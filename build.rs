@@ -0,0 +1,25 @@
+//! Identifies the QuickJS engine built into the binary, for the bytecode header of
+//! `src/bytecode.rs`.
+
+use std::{path::Path, process::Command};
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+fn main() {
+    // The checked out revision of the engine sources, or the one recorded by the superproject.
+    let revision = git(&["-C", "qjs-sys", "rev-parse", "HEAD"])
+        .or_else(|| git(&["rev-parse", "HEAD:qjs-sys"]))
+        .unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=QJS_SYS_REVISION={revision}");
+    for head in [".git/modules/qjs-sys/HEAD", ".git/HEAD"] {
+        if Path::new(head).exists() {
+            println!("cargo:rerun-if-changed={head}");
+        }
+    }
+}
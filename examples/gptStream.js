@@ -15,9 +15,13 @@ async function main() {
     })
   });
 
-  // TODO: this should be wrapped in a line-based reader
-  for await (const chunk of response.body) {
-    console.log('chunk:', new TextDecoder().decode(chunk));
+  const lines = response.body
+    .pipeThrough(new TextDecoderStream())
+    .pipeThrough(new Sidevm.LineSplitStream());
+  for await (const line of lines) {
+    if (line.startsWith('data: ')) {
+      console.log('event:', line.slice(6));
+    }
   }
 }
 
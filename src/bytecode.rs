@@ -0,0 +1,93 @@
+//! Versioned QuickJS bytecode.
+//!
+//! QuickJS bytecode is only valid for the engine build that produced it, and reading bytecode from
+//! another build is undefined behavior. Bytecode produced by [`compile`] starts with a header
+//! identifying the engine, which [`check`] verifies before the bytecode is passed to QuickJS.
+//!
+//! The header is the magic, the header version, the bytecode version byte of QuickJS, the byte
+//! order flags, and the length prefixed [`ENGINE_VERSION`] string.
+
+use std::sync::OnceLock;
+
+const MAGIC: &[u8; 4] = b"QJBC";
+const HEADER_VERSION: u8 = 2;
+/// The bytecode was written on a big-endian machine.
+const FLAG_BIG_ENDIAN: u8 = 1;
+
+/// Identifies the engine sources, as the version byte doesn't change with every engine release.
+pub const ENGINE_VERSION: &str = concat!("qjs-sys ", env!("QJS_SYS_REVISION"));
+
+/// The version byte QuickJS writes at the start of its bytecode, which changes with the format
+/// and the build options of the engine.
+fn engine_version() -> u8 {
+    static VERSION: OnceLock<u8> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let bytecode = js::compile("0", "<version>").expect("Failed to compile the probe script");
+        bytecode[0]
+    })
+}
+
+fn flags() -> u8 {
+    if cfg!(target_endian = "big") {
+        FLAG_BIG_ENDIAN
+    } else {
+        0
+    }
+}
+
+/// Compile `source` into bytecode with the version header.
+pub fn compile(source: &str, name: &str) -> Result<Vec<u8>, String> {
+    let bytecode = js::compile(source, name)?;
+    Ok(with_header(&bytecode))
+}
+
+/// Prefix `bytecode` written by this engine with the version header.
+pub(crate) fn with_header(bytecode: &[u8]) -> Vec<u8> {
+    let engine = ENGINE_VERSION.as_bytes();
+    let mut output = Vec::with_capacity(MAGIC.len() + 4 + engine.len() + bytecode.len());
+    output.extend_from_slice(MAGIC);
+    output.extend_from_slice(&[HEADER_VERSION, engine_version(), flags()]);
+    output.push(engine.len() as u8);
+    output.extend_from_slice(engine);
+    output.extend_from_slice(bytecode);
+    output
+}
+
+/// Verify that `bytecode` was compiled for this engine and return the bytecode after the header.
+pub fn check(bytecode: &[u8]) -> Result<&[u8], String> {
+    let Some(rest) = bytecode.strip_prefix(MAGIC) else {
+        return Err("Bytecode without version header, recompile it with `phatjs --compile`".into());
+    };
+    let [header_version, version, bytecode_flags, ref body @ ..] = *rest else {
+        return Err("Truncated bytecode header".into());
+    };
+    if header_version != HEADER_VERSION {
+        return Err(format!(
+            "Unsupported bytecode header version {header_version}"
+        ));
+    }
+    if version != engine_version() {
+        return Err(format!(
+            "Incompatible bytecode: compiled for QuickJS bytecode version {version}, \
+            this engine reads version {}",
+            engine_version()
+        ));
+    }
+    if bytecode_flags != flags() {
+        return Err("Incompatible bytecode: compiled for a different byte order".into());
+    }
+    let Some((&len, body)) = body.split_first() else {
+        return Err("Truncated bytecode header".into());
+    };
+    if body.len() < len as usize {
+        return Err("Truncated bytecode header".into());
+    }
+    let (engine, body) = body.split_at(len as usize);
+    if engine != ENGINE_VERSION.as_bytes() {
+        return Err(format!(
+            "Incompatible bytecode: compiled by {}, this engine is {ENGINE_VERSION}",
+            String::from_utf8_lossy(engine)
+        ));
+    }
+    Ok(body)
+}
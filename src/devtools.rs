@@ -0,0 +1,384 @@
+//! A Chrome DevTools Protocol endpoint attached to a service.
+//!
+//! Only evaluating expressions in the console, the console messages of the scripts and CPU
+//! profiles are implemented. The `Debugger` domain isn't supported: there are no breakpoints,
+//! stepping or scope inspection, and its commands fail with an error saying so.
+//!
+//! One session is served at a time, as it takes over the log sink of the service.
+
+use anyhow::Result;
+use core::time::Duration;
+use futures::{SinkExt, StreamExt};
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    upgrade::Upgraded,
+    Body, Request, Response, StatusCode,
+};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::net::{IpAddr, SocketAddr};
+use std::{cell::Cell, rc::Rc};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+
+use crate::{runtime::HyperExecutor, service::ServiceWeakRef, Service};
+
+/// The only execution context, the global scope of the service.
+const CONTEXT_ID: u32 = 1;
+
+/// How often `Profiler.start` samples the JS stack.
+const PROFILE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Marks the session as active until dropped.
+struct SessionGuard(Rc<Cell<bool>>);
+
+impl SessionGuard {
+    /// Start a session unless one is already active.
+    fn acquire(active: &Rc<Cell<bool>>) -> Option<Self> {
+        if active.replace(true) {
+            return None;
+        }
+        Some(Self(active.clone()))
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// Serve the DevTools endpoints on `addr` until the server fails.
+///
+/// Chrome lists the service under `chrome://inspect` once `addr` is added to the network
+/// targets. The WebSocket is served under a random path, listed by `/json`, and only requests
+/// naming the endpoint by IP or as localhost are accepted, so web pages can't reach it. A session
+/// replaces the log sink of the service until it ends, the logs keep going to the `log` crate as
+/// well. A second session is refused with 409 Conflict while one is active.
+pub async fn serve(service: &Service, addr: SocketAddr) -> Result<()> {
+    let weak_service = service.weak_self();
+    let path: Rc<str> = format!("/{}", random_uuid()).into();
+    let active = Rc::new(Cell::new(false));
+    info!("DevTools listening on ws://{addr}{path}");
+    let make_service = make_service_fn(move |_conn| {
+        let weak_service = weak_service.clone();
+        let path = path.clone();
+        let active = active.clone();
+        async move {
+            Ok::<_, core::convert::Infallible>(service_fn(move |req| {
+                handle_request(
+                    weak_service.clone(),
+                    addr,
+                    path.clone(),
+                    active.clone(),
+                    req,
+                )
+            }))
+        }
+    });
+    hyper::Server::try_bind(&addr)?
+        .executor(HyperExecutor)
+        .serve(make_service)
+        .await?;
+    Ok(())
+}
+
+/// A random version 4 UUID.
+fn random_uuid() -> String {
+    let mut bytes = [0u8; 16];
+    crate::runtime::getrandom(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Whether the Host header names the endpoint by IP or as localhost, so that a web page can't
+/// reach it through a domain rebound to a local address.
+fn is_local_host(req: &Request<Body>) -> bool {
+    let Some(host) = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+    else {
+        return false;
+    };
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok()
+}
+
+/// Whether the request comes from the DevTools frontend or a tool rather than from a web page.
+fn is_allowed_origin(req: &Request<Body>) -> bool {
+    match req.headers().get(header::ORIGIN) {
+        None => true,
+        Some(origin) => origin.to_str().map_or(false, |origin| {
+            origin.starts_with("devtools://") || origin.starts_with("chrome-devtools://")
+        }),
+    }
+}
+
+async fn handle_request(
+    weak_service: ServiceWeakRef,
+    addr: SocketAddr,
+    path: Rc<str>,
+    active: Rc<Cell<bool>>,
+    mut req: Request<Body>,
+) -> Result<Response<Body>> {
+    if !is_local_host(&req) || !is_allowed_origin(&req) {
+        return Ok(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::empty())?);
+    }
+    let key = req.headers().get(header::SEC_WEBSOCKET_KEY).cloned();
+    if let Some(key) = key.filter(|_| req.uri().path() == &*path) {
+        let Some(session) = SessionGuard::acquire(&active) else {
+            return Ok(Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body("Another DevTools session is active".into())?);
+        };
+        let accept = derive_accept_key(key.as_bytes());
+        let upgrade = hyper::upgrade::on(&mut req);
+        crate::runtime::spawn(async move {
+            let _session = session;
+            let stream = match upgrade.await {
+                Ok(upgraded) => {
+                    WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await
+                }
+                Err(err) => {
+                    warn!("DevTools upgrade failed: {err}");
+                    return;
+                }
+            };
+            if let Err(err) = run_session(weak_service, stream).await {
+                warn!("DevTools session failed: {err:?}");
+            }
+        });
+        return Ok(Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+            .body(Body::empty())?);
+    }
+    let body = match req.uri().path() {
+        "/json" | "/json/list" => json!([{
+            "id": "sidevm",
+            "type": "node",
+            "title": "sidevm-quickjs",
+            "description": "sidevm-quickjs service",
+            "url": "",
+            "devtoolsFrontendUrl": format!("devtools://devtools/bundled/js_app.html?ws={addr}{path}"),
+            "webSocketDebuggerUrl": format!("ws://{addr}{path}"),
+        }]),
+        "/json/version" => json!({
+            "Browser": concat!("sidevm-quickjs/", env!("CARGO_PKG_VERSION")),
+            "Protocol-Version": "1.3",
+        }),
+        _ => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())?)
+        }
+    };
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.to_string().into())?)
+}
+
+async fn run_session(
+    weak_service: ServiceWeakRef,
+    stream: WebSocketStream<Upgraded>,
+) -> Result<()> {
+    let (event_tx, event_rx) = unbounded_channel::<Value>();
+    let Some(service) = weak_service.upgrade() else {
+        return Ok(());
+    };
+    let previous_sink = service.replace_log_sink(Some(Rc::new(move |level, msg| {
+        log::log!(level, "JS: {}", msg);
+        _ = event_tx.send(console_event(level, msg));
+    })));
+    drop(service);
+    let result = relay_messages(&weak_service, stream, event_rx).await;
+    if let Some(service) = weak_service.upgrade() {
+        service.replace_log_sink(previous_sink);
+    }
+    result
+}
+
+/// Answer the commands of the session and forward the console events until it closes.
+async fn relay_messages(
+    weak_service: &ServiceWeakRef,
+    stream: WebSocketStream<Upgraded>,
+    mut event_rx: UnboundedReceiver<Value>,
+) -> Result<()> {
+    let (mut sink, mut stream) = stream.split();
+    loop {
+        let outgoing = tokio::select! {
+            message = stream.next() => {
+                let text = match message.transpose()? {
+                    Some(Message::Text(text)) => text,
+                    Some(Message::Close(_)) | None => break,
+                    Some(_) => continue,
+                };
+                let Some(service) = weak_service.upgrade() else {
+                    break;
+                };
+                handle_message(&service, &text)
+            }
+            Some(event) = event_rx.recv() => vec![event],
+        };
+        for message in outgoing {
+            sink.send(Message::Text(message.to_string())).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Handle a command, returning the reply followed by the events it triggers.
+fn handle_message(service: &Service, text: &str) -> Vec<Value> {
+    let Ok(command) = serde_json::from_str::<Value>(text) else {
+        return vec![json!({ "error": { "code": -32700, "message": "Invalid JSON" } })];
+    };
+    let id = command["id"].clone();
+    let method = command["method"].as_str().unwrap_or_default();
+    let params = &command["params"];
+    let mut events = vec![];
+    let result = match method {
+        "Runtime.enable" => {
+            events.push(json!({
+                "method": "Runtime.executionContextCreated",
+                "params": {
+                    "context": { "id": CONTEXT_ID, "origin": "", "name": "sidevm" },
+                },
+            }));
+            Ok(json!({}))
+        }
+        "Runtime.evaluate" => Ok(evaluate(
+            service,
+            params["expression"].as_str().unwrap_or_default(),
+        )),
+        "Profiler.start" => {
+            service.start_profiling(PROFILE_INTERVAL);
+            Ok(json!({}))
+        }
+        "Profiler.stop" => match service.stop_profiling() {
+            Some(profile) => serde_json::from_str::<Value>(&profile)
+                .map(|profile| json!({ "profile": profile }))
+                .map_err(|err| (-32000, err.to_string())),
+            None => Err((-32000, "Profiler is not started".into())),
+        },
+        method if method.starts_with("Debugger.") => Err((
+            -32601,
+            "The Debugger domain is not supported, breakpoints and stepping are unavailable".into(),
+        )),
+        method if is_toggle(method) || method == "Runtime.runIfWaitingForDebugger" => Ok(json!({})),
+        method => Err((-32601, format!("'{method}' wasn't found"))),
+    };
+    let reply = match result {
+        Ok(result) => json!({ "id": id, "result": result }),
+        Err((code, message)) => json!({ "id": id, "error": { "code": code, "message": message } }),
+    };
+    core::iter::once(reply).chain(events).collect()
+}
+
+/// Whether `method` only turns a domain on or off, which is accepted for the supported domains.
+fn is_toggle(method: &str) -> bool {
+    method.ends_with(".enable") || method.ends_with(".disable")
+}
+
+fn evaluate(service: &Service, expression: &str) -> Value {
+    let value = service
+        .exec_script(expression)
+        .map_err(|err| err.to_string())
+        .and_then(|value| {
+            crate::js_eval::convert(service, service.to_js_value(&value))
+                .map_err(|err| err.to_string())
+        });
+    match value {
+        Ok(value) => json!({ "result": remote_object(&value) }),
+        Err(err) => json!({
+            "result": { "type": "object", "subtype": "error", "description": err },
+            "exceptionDetails": {
+                "exceptionId": 1,
+                "text": err,
+                "lineNumber": 0,
+                "columnNumber": 0,
+            },
+        }),
+    }
+}
+
+/// Describe the value as a `Runtime.RemoteObject`.
+fn remote_object(value: &pink_types::js::JsValue) -> Value {
+    use pink_types::js::JsValue;
+    match value {
+        JsValue::Undefined => json!({ "type": "undefined" }),
+        JsValue::Null => json!({ "type": "object", "subtype": "null", "value": null }),
+        JsValue::String(s) => json!({ "type": "string", "value": s }),
+        JsValue::Bytes(bytes) => json!({
+            "type": "object",
+            "subtype": "typedarray",
+            "description": format!("Uint8Array({})", bytes.len()),
+        }),
+        JsValue::Other(s) => match serde_json::from_str::<Value>(s) {
+            Ok(value @ Value::Number(_)) => json!({ "type": "number", "value": value }),
+            Ok(value @ Value::Bool(_)) => json!({ "type": "boolean", "value": value }),
+            _ => json!({ "type": "object", "description": s }),
+        },
+        JsValue::Exception(err) => {
+            json!({ "type": "object", "subtype": "error", "description": err })
+        }
+    }
+}
+
+fn console_event(level: log::Level, msg: &str) -> Value {
+    let kind = match level {
+        log::Level::Error => "error",
+        log::Level::Warn => "warning",
+        log::Level::Info => "log",
+        log::Level::Debug => "debug",
+        log::Level::Trace => "trace",
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+        * 1000.0;
+    json!({
+        "method": "Runtime.consoleAPICalled",
+        "params": {
+            "type": kind,
+            "args": [{ "type": "string", "value": msg }],
+            "executionContextId": CONTEXT_ID,
+            "timestamp": timestamp,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_session_at_a_time() {
+        let active = Rc::new(Cell::new(false));
+        let session = SessionGuard::acquire(&active).unwrap();
+        assert!(SessionGuard::acquire(&active).is_none());
+        drop(session);
+        assert!(SessionGuard::acquire(&active).is_some());
+    }
+}
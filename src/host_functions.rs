@@ -1,46 +1,167 @@
 use alloc::rc::Weak;
 use anyhow::Result;
-use log::error;
+use log::{error, info};
 
 use crate::service::{Service, ServiceRef, ServiceWeakRef};
 use crate::traits::ResultExt;
 
+#[cfg(not(feature = "web"))]
+pub(crate) use cookie::CookieJar;
+pub(crate) use debug::{setup_gc, setup_metrics};
+#[cfg(feature = "js-dns")]
+pub use dns::DnsConfig;
+#[cfg(feature = "js-dns")]
+pub(crate) use dns::{DnsResolver, SharedResolver};
+pub use egress::EgressFilter;
+pub(crate) use egress::{check_egress, check_egress_url, SharedEgressFilter};
+#[cfg(feature = "native")]
+pub(crate) use egress::{connect_checked, EgressConnector};
+pub(crate) use entry::{dispatch_message, dispatch_query};
+pub(crate) use fs::DEFAULT_MEMORY_QUOTA as DEFAULT_FS_QUOTA;
+pub use fs::{DirFs, FileStat, FileSystem, MemoryFs};
 #[cfg(feature = "js-http-listen")]
 pub(crate) use http_listen::try_accept_http_request;
+#[cfg(feature = "js-http-proxy")]
+pub(crate) use http_request::ProxyClient;
+pub(crate) use info::code_hash;
+pub use interceptor::{HttpInterceptor, RequestParts, ResponseParts};
+pub(crate) use permission::own_property_names;
+pub use permission::{Capability, Permissions};
+pub use rate_limit::RateLimit;
+#[cfg(feature = "js-remote-module")]
+pub(crate) use remote_module::RemoteFetch;
+#[cfg(feature = "js-scale")]
+pub(crate) use scale::TypeDef as ScaleType;
+#[cfg(feature = "js-secret")]
+pub(crate) use secret::set_master_secret;
+pub(crate) use worker::Message as WorkerMessage;
 
+#[cfg(feature = "js-hash")]
+mod attestation;
+mod bigint;
+mod buffer;
+#[cfg(feature = "js-cache")]
+mod cache;
+mod calendar;
+#[cfg(feature = "js-codec")]
+mod codec;
+#[cfg(not(feature = "web"))]
+mod cookie;
+mod cron;
+#[cfg(feature = "js-crypto")]
+mod crypto;
 mod debug;
+#[cfg(feature = "js-dns")]
+mod dns;
+mod egress;
+mod entry;
+#[cfg(feature = "js-eth")]
+mod eth;
+mod fs;
 #[cfg(feature = "js-http-listen")]
 mod http_listen;
 mod http_request;
+mod info;
+mod interceptor;
+#[cfg(feature = "js-intl")]
+mod intl;
 #[cfg(feature = "mem-stats")]
 mod mem_stats;
+#[cfg(feature = "js-hash")]
+mod merkle;
+mod permission;
 mod print;
+mod rate_limit;
+#[cfg(feature = "js-remote-module")]
+mod remote_module;
+#[cfg(feature = "js-scale")]
+mod scale;
+#[cfg(feature = "js-secret")]
+mod secret;
+#[cfg(feature = "js-sign")]
+mod sign;
+#[cfg(feature = "js-tcp")]
+mod tcp;
+mod text;
 mod timer;
+#[cfg(feature = "js-tls")]
+mod tls;
 #[cfg(feature = "js-url")]
 mod url;
+#[cfg(feature = "js-wasm")]
+mod wasm;
+#[cfg(feature = "js-websocket")]
+mod websocket;
+mod worker;
 
 #[cfg(feature = "js-hash")]
 mod hash;
 
-pub(crate) fn setup_host_functions(ctx: &js::Context) -> Result<()> {
+pub(crate) fn setup_host_functions(ctx: &js::Context, permissions: &Permissions) -> Result<()> {
+    use permission::setup_guarded as guarded;
+    use Capability::*;
+
     let ns = js::Value::new_object(ctx);
     let version = env!("CARGO_PKG_VERSION");
     let version = ctx.new_string(version);
     ns.set_property("version", &version)?;
     set_extensions(&ns, ctx)?;
     print::setup(&ns)?;
-    timer::setup(&ns)?;
-    http_request::setup(&ns)?;
+    guarded(&ns, ctx, permissions, Timers, timer::setup)?;
+    guarded(&ns, ctx, permissions, Timers, cron::setup)?;
+    text::setup(&ns)?;
+    bigint::setup(&ns)?;
+    guarded(&ns, ctx, permissions, Network, |ns| {
+        http_request::setup(ns, ctx)
+    })?;
     debug::setup(&ns)?;
+    entry::setup(&ns)?;
+    info::setup(&ns)?;
+    guarded(&ns, ctx, permissions, Workers, worker::setup)?;
+    #[cfg(not(feature = "web"))]
+    guarded(&ns, ctx, permissions, Network, |ns| cookie::setup(ns, ctx))?;
     ns.define_property_fn("close", close_res)?;
     ns.define_property_fn("exit", exit)?;
 
     #[cfg(feature = "js-url")]
     url::setup(&ns)?;
     #[cfg(feature = "js-http-listen")]
-    http_listen::setup(&ns)?;
+    guarded(&ns, ctx, permissions, Network, http_listen::setup)?;
+    #[cfg(feature = "js-hash")]
+    guarded(&ns, ctx, permissions, Crypto, hash::setup)?;
     #[cfg(feature = "js-hash")]
-    hash::setup(&ns)?;
+    guarded(&ns, ctx, permissions, Crypto, merkle::setup)?;
+    #[cfg(feature = "js-dns")]
+    guarded(&ns, ctx, permissions, Network, dns::setup)?;
+    #[cfg(feature = "js-remote-module")]
+    guarded(&ns, ctx, permissions, Network, remote_module::setup)?;
+    #[cfg(feature = "js-cache")]
+    guarded(&ns, ctx, permissions, Cache, |ns| cache::setup(ns, ctx))?;
+    guarded(&ns, ctx, permissions, FileSystem, |ns| fs::setup(ns, ctx))?;
+    #[cfg(feature = "js-codec")]
+    codec::setup(&ns, ctx)?;
+    #[cfg(feature = "js-intl")]
+    intl::setup(&ns)?;
+    #[cfg(feature = "js-wasm")]
+    guarded(&ns, ctx, permissions, WebAssembly, wasm::setup)?;
+    #[cfg(feature = "js-crypto")]
+    guarded(&ns, ctx, permissions, Crypto, crypto::setup)?;
+    #[cfg(feature = "js-scale")]
+    scale::setup(&ns)?;
+    #[cfg(feature = "js-secret")]
+    guarded(&ns, ctx, permissions, Crypto, secret::setup)?;
+    #[cfg(feature = "js-sign")]
+    guarded(&ns, ctx, permissions, Crypto, sign::setup)?;
+    #[cfg(feature = "js-hash")]
+    guarded(&ns, ctx, permissions, Crypto, attestation::setup)?;
+    #[cfg(feature = "js-eth")]
+    guarded(&ns, ctx, permissions, Crypto, eth::setup)?;
+    #[cfg(feature = "js-websocket")]
+    guarded(&ns, ctx, permissions, Network, websocket::setup)?;
+    #[cfg(feature = "js-tcp")]
+    guarded(&ns, ctx, permissions, Network, tcp::setup)?;
+    #[cfg(feature = "js-tls")]
+    guarded(&ns, ctx, permissions, Network, tls::setup)?;
     #[cfg(feature = "mem-stats")]
     mem_stats::setup(&ns)?;
 
@@ -82,7 +203,126 @@ fn close_res(service: ServiceRef, _this: js::Value, res_id: u64) {
     service.remove_resource(res_id);
 }
 
+/// Close all the resources, `code` becomes the exit code of phatjs.
 #[js::host_call(with_context)]
-fn exit(service: ServiceRef, _this: js::Value) {
+fn exit(service: ServiceRef, _this: js::Value, code: Option<i32>) {
+    if let Some(code) = code {
+        service.set_exit_code(code);
+    }
     service.close_all();
 }
+
+/// Report the event `name` with `data` to the JS callback of the task `id`, unless the service or
+/// the task has gone.
+pub(crate) fn invoke_callback(
+    weak_service: &Weak<Service>,
+    id: u64,
+    name: &str,
+    data: &dyn js::ToJsValue,
+) {
+    let Some(service) = weak_service.upgrade() else {
+        info!("task {id} exited because the service has been dropped");
+        return;
+    };
+    let Some(callback) = service.get_resource_value(id) else {
+        info!("task {id} exited because the resource has been dropped");
+        return;
+    };
+    if let Err(err) = service.call_function(callback, (name, data)) {
+        error!("[{id}] Failed to report event {name}: {err:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::Resource;
+    use js::FromJsValue;
+
+    /// A service with a global `log` array and a task whose callback is `callback`.
+    fn service_with_task(callback: &str) -> (ServiceRef, u64) {
+        let service = Service::new_ref();
+        service.exec_script("globalThis.log = []").unwrap();
+        let callback = service.exec_script(callback).unwrap();
+        let id = service.push_resource(Resource::new(callback, None));
+        (service, id)
+    }
+
+    fn log(service: &Service) -> String {
+        let value = service.exec_script("log.join(',')").unwrap();
+        String::from_js_value(service.to_js_value(&value)).unwrap()
+    }
+
+    #[test]
+    fn events_are_delivered_in_order() {
+        let (service, id) = service_with_task("(name) => log.push(name)");
+        let weak_service = service.weak_self();
+        for name in ["head", "data", "end"] {
+            invoke_callback(&weak_service, id, name, &());
+        }
+        assert_eq!(log(&service), "head,data,end");
+    }
+
+    #[test]
+    fn jobs_of_an_event_run_before_the_next_event() {
+        let (service, id) = service_with_task(
+            "(name) => {
+                log.push(name);
+                Promise.resolve().then(() => log.push(name + ':then'));
+            }",
+        );
+        let weak_service = service.weak_self();
+        invoke_callback(&weak_service, id, "a", &());
+        invoke_callback(&weak_service, id, "b", &());
+        assert_eq!(log(&service), "a,a:then,b,b:then");
+    }
+
+    #[test]
+    fn jobs_run_after_a_throwing_callback() {
+        let (service, id) = service_with_task(
+            "(name) => {
+                log.push(name);
+                Promise.resolve().then(() => log.push(name + ':then'));
+                throw new Error(name);
+            }",
+        );
+        let weak_service = service.weak_self();
+        invoke_callback(&weak_service, id, "a", &());
+        invoke_callback(&weak_service, id, "b", &());
+        assert_eq!(log(&service), "a,a:then,b,b:then");
+    }
+
+    #[test]
+    fn nested_events_leave_the_jobs_to_the_outermost_call() {
+        let (service, id) = service_with_task(
+            "(name) => {
+                log.push(name);
+                Promise.resolve().then(() => log.push(name + ':then'));
+                if (name === 'outer') {
+                    deliver('inner');
+                    log.push('outer:end');
+                }
+            }",
+        );
+        let weak_service = service.weak_self();
+        service
+            .define_fn("deliver", move |service, args| {
+                let name = String::from_js_value(args[0].clone())?;
+                invoke_callback(&service.weak_self(), id, &name, &());
+                Ok(())
+            })
+            .unwrap();
+        invoke_callback(&weak_service, id, "outer", &());
+        assert_eq!(log(&service), "outer,inner,outer:end,outer:then,inner:then");
+    }
+
+    #[test]
+    fn events_after_the_task_is_dropped_are_ignored() {
+        let (service, id) = service_with_task("(name) => log.push(name)");
+        let weak_service = service.weak_self();
+        invoke_callback(&weak_service, id, "a", &());
+        service.remove_resource(id);
+        invoke_callback(&weak_service, id, "b", &());
+        assert_eq!(log(&service), "a");
+    }
+}
@@ -0,0 +1,50 @@
+//! Attestation quotes of the TEE worker, see `getAttestationQuote` in sidevm.js.
+//!
+//! The report data of the quote is `blake2b512(DOMAIN ‖ code_hash ‖ user_data)`, so a quote
+//! produced for one script can't be passed off as coming from another one, nor reused for
+//! another purpose than these quotes. `code_hash` is the hash the service reports as `codeHash`.
+
+use anyhow::Context;
+use blake2::Blake2b512;
+use js::AsBytes;
+
+use super::*;
+use crate::service::OwnedJsValue;
+
+/// Separates the report data of these quotes from the other uses of the quotes of the worker.
+const DOMAIN: &[u8] = b"sidevm-quickjs:attestation:v1\0";
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("getAttestationQuote", get_attestation_quote)?;
+    Ok(())
+}
+
+/// Produce a quote of the TEE worker binding the running script and `user_data`, e.g. a hash of
+/// the script output.
+///
+/// The quote is requested from the contract that deployed the sidevm, other runtimes fail. The
+/// callback receives a `result` event with the quote bytes or an `error` event.
+#[js::host_call(with_context)]
+fn get_attestation_quote(
+    service: ServiceRef,
+    _this: js::Value,
+    user_data: AsBytes<Vec<u8>>,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    let code_hash = service
+        .code_hash()
+        .context("No code hash recorded for the running script")?;
+    let report_data = report_data(&code_hash, &user_data.0);
+    service.spawn(callback, do_get_attestation_quote, report_data)
+}
+
+fn report_data(code_hash: &[u8], user_data: &[u8]) -> Vec<u8> {
+    super::hash::do_hash::<Blake2b512>(&[DOMAIN, code_hash, user_data].concat())
+}
+
+async fn do_get_attestation_quote(weak_service: ServiceWeakRef, id: u64, report_data: Vec<u8>) {
+    match crate::runtime::attestation_quote(report_data).await {
+        Ok(quote) => invoke_callback(&weak_service, id, "result", &AsBytes(quote)),
+        Err(err) => invoke_callback(&weak_service, id, "error", &format!("{err:#}")),
+    }
+}
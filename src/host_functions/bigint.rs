@@ -0,0 +1,102 @@
+//! Conversions between BigInts and their fixed width byte encodings.
+//!
+//! The BigInts cross the host boundary as hex strings, which QuickJS converts natively with
+//! `toString(16)` and `BigInt('0x..')`, so the arithmetic stays out of JS.
+
+use anyhow::{bail, Context};
+use js::{AsBytes, ToJsValue};
+
+use super::Result;
+
+#[derive(ToJsValue, Debug)]
+struct BigIntHex {
+    /// The magnitude in hex, without prefix.
+    hex: String,
+    negative: bool,
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("bigintHexToBytes", bigint_hex_to_bytes)?;
+    ns.define_property_fn("bytesToBigintHex", bytes_to_bigint_hex)?;
+    Ok(())
+}
+
+/// Encode the integer of magnitude `hex` in `size` bytes, or as few as possible if not given.
+///
+/// Negative values are encoded in two's complement.
+#[js::host_call]
+fn bigint_hex_to_bytes(
+    hex: String,
+    negative: bool,
+    size: Option<u32>,
+    little_endian: bool,
+) -> Result<AsBytes<Vec<u8>>> {
+    let hex = hex.trim_start_matches('0');
+    let padded = if hex.len() % 2 == 1 {
+        format!("0{hex}")
+    } else {
+        hex.to_string()
+    };
+    let magnitude = hex::decode(padded).context("Invalid hex digits")?;
+    let negative = negative && !magnitude.is_empty();
+    let size = size.map(|size| size as usize);
+    let mut width = size.unwrap_or(magnitude.len().max(1));
+    let mut bytes = loop {
+        if magnitude.len() > width {
+            match size {
+                Some(size) => bail!("The value doesn't fit in {size} bytes"),
+                None => {
+                    width = magnitude.len();
+                    continue;
+                }
+            }
+        }
+        let mut bytes = vec![0u8; width - magnitude.len()];
+        bytes.extend_from_slice(&magnitude);
+        if !negative {
+            break bytes;
+        }
+        negate(&mut bytes);
+        // The sign bit must be set, else the magnitude needs a wider encoding
+        if bytes[0] & 0x80 != 0 {
+            break bytes;
+        }
+        match size {
+            Some(size) => bail!("The value doesn't fit in {size} bytes"),
+            None => width += 1,
+        }
+    };
+    if little_endian {
+        bytes.reverse();
+    }
+    Ok(AsBytes(bytes))
+}
+
+/// Decode an integer, in two's complement if `signed`.
+#[js::host_call]
+fn bytes_to_bigint_hex(bytes: AsBytes<Vec<u8>>, little_endian: bool, signed: bool) -> BigIntHex {
+    let mut bytes = bytes.0;
+    if little_endian {
+        bytes.reverse();
+    }
+    let negative = signed && bytes.first().map_or(false, |b| b & 0x80 != 0);
+    if negative {
+        negate(&mut bytes);
+    }
+    let hex = hex::encode(&bytes);
+    let hex = match hex.trim_start_matches('0') {
+        "" => "0".into(),
+        hex => hex.into(),
+    };
+    BigIntHex { hex, negative }
+}
+
+/// Two's complement negation of a big-endian integer, in place.
+fn negate(bytes: &mut [u8]) {
+    let mut carry = true;
+    for byte in bytes.iter_mut().rev() {
+        let (value, overflow) = (!*byte).overflowing_add(carry as u8);
+        *byte = value;
+        carry = overflow;
+    }
+}
@@ -0,0 +1,54 @@
+use core::{cell::Cell, ffi::c_void};
+use js::{c, Error as ValueError, ToJsValue};
+
+/// Bytes handed over to JS as a `Uint8Array` without copying.
+///
+/// The `Vec` becomes the backing store of the ArrayBuffer and is freed by the JS garbage collector.
+/// It can only be converted once, later conversions fail.
+pub(crate) struct ExternalBuffer(Cell<Option<Vec<u8>>>);
+
+impl ExternalBuffer {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self(Cell::new(Some(data)))
+    }
+}
+
+unsafe extern "C" fn free_buffer(_rt: *mut c::JSRuntime, opaque: *mut c_void, _ptr: *mut c_void) {
+    drop(Box::from_raw(opaque as *mut Vec<u8>));
+}
+
+impl ToJsValue for ExternalBuffer {
+    fn to_js_value(&self, ctx: &js::Context) -> Result<js::Value, ValueError> {
+        let Some(data) = self.0.take() else {
+            return Err(ValueError::Static(
+                "The buffer has already been moved to JS",
+            ));
+        };
+        let mut data = Box::new(data);
+        let ptr = data.as_mut_ptr();
+        let len = data.len();
+        let opaque = Box::into_raw(data);
+        let js_ctx = ctx;
+        let ctx = ctx.as_ptr();
+        unsafe {
+            let buffer =
+                c::JS_NewArrayBuffer(ctx, ptr, len as _, Some(free_buffer), opaque as _, 0);
+            if c::is_exception(buffer) {
+                // QuickJS doesn't call the free function if it fails to create the buffer
+                drop(Box::from_raw(opaque));
+                return Err(ValueError::Static("Failed to create ArrayBuffer"));
+            }
+            let global = c::JS_GetGlobalObject(ctx);
+            let ctor = c::JS_GetPropertyStr(ctx, global, b"Uint8Array\0".as_ptr() as _);
+            c::JS_FreeValue(ctx, global);
+            let mut args = [buffer];
+            let array = c::JS_CallConstructor(ctx, ctor, 1, args.as_mut_ptr());
+            c::JS_FreeValue(ctx, ctor);
+            c::JS_FreeValue(ctx, buffer);
+            if c::is_exception(array) {
+                return Err(ValueError::Static("Failed to create Uint8Array"));
+            }
+            Ok(js::Value::new_moved(js_ctx, array))
+        }
+    }
+}
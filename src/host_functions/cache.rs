@@ -0,0 +1,182 @@
+use anyhow::Context;
+use js::{AsBytes, BytesOrString};
+
+use super::Result;
+
+type Bytes = AsBytes<Vec<u8>>;
+
+/// The prefix of the keys set by the scripts. The host keeps its own entries, such as the http
+/// cache, under other prefixes, out of reach of the scripts.
+const SCRIPT_KEY_PREFIX: &[u8] = b"js\0";
+
+fn script_key(key: &BytesOrString) -> Vec<u8> {
+    let key: &[u8] = key.as_ref();
+    [SCRIPT_KEY_PREFIX, key].concat()
+}
+
+/// Set up the `cache` namespace, a key-value store local to the worker.
+///
+/// The keys of the scripts are kept apart from the entries of the host, see `SCRIPT_KEY_PREFIX`.
+///
+/// Values survive across script runs in the same worker but may be evicted at any time.
+///
+/// `cas` and `increment` read and write the entry within one host call, nothing else runs on the
+/// worker in between, so they are atomic for the scripts sharing the worker.
+pub(crate) fn setup(ns: &js::Value, ctx: &js::Context) -> Result<()> {
+    let cache = js::Value::new_object(ctx);
+    cache.define_property_fn("set", cache_set)?;
+    cache.define_property_fn("get", cache_get)?;
+    cache.define_property_fn("getString", cache_get_string)?;
+    cache.define_property_fn("remove", cache_remove)?;
+    cache.define_property_fn("cas", cache_cas)?;
+    cache.define_property_fn("increment", cache_increment)?;
+    ns.set_property("cache", &cache)?;
+    Ok(())
+}
+
+/// Store `value` under `key`, expiring after `ttl` seconds if given.
+#[js::host_call]
+fn cache_set(key: BytesOrString, value: BytesOrString, ttl: Option<u64>) -> Result<()> {
+    let key = script_key(&key);
+    backend::set(&key, value.as_ref(), ttl)
+}
+
+#[js::host_call]
+fn cache_get(key: BytesOrString) -> Result<Option<Bytes>> {
+    let key = script_key(&key);
+    Ok(backend::get(&key)?.map(AsBytes))
+}
+
+/// Like `get` but decodes the value as utf-8.
+#[js::host_call]
+fn cache_get_string(key: BytesOrString) -> Result<Option<String>> {
+    let key = script_key(&key);
+    let Some(value) = backend::get(&key)? else {
+        return Ok(None);
+    };
+    Ok(Some(String::from_utf8(value)?))
+}
+
+/// Remove `key`, returning the value it held.
+#[js::host_call]
+fn cache_remove(key: BytesOrString) -> Result<Option<Bytes>> {
+    let key = script_key(&key);
+    Ok(backend::remove(&key)?.map(AsBytes))
+}
+
+/// Replace the value of `key` with `new` if it currently is `expected`, returning whether it did.
+///
+/// A null `expected` matches a missing key and a null `new` removes the key.
+#[js::host_call]
+fn cache_cas(
+    key: BytesOrString,
+    expected: Option<BytesOrString>,
+    new: Option<BytesOrString>,
+    ttl: Option<u64>,
+) -> Result<bool> {
+    let key = script_key(&key);
+    let current = backend::get(&key)?;
+    if current.as_deref() != expected.as_ref().map(|v| v.as_ref()) {
+        return Ok(false);
+    }
+    match new {
+        Some(new) => backend::set(&key, new.as_ref(), ttl)?,
+        None => {
+            backend::remove(&key)?;
+        }
+    }
+    Ok(true)
+}
+
+/// Add `delta` to the integer stored in decimal under `key`, a missing key counting as 0.
+/// Returns the new value.
+#[js::host_call]
+fn cache_increment(key: BytesOrString, delta: i64, ttl: Option<u64>) -> Result<i64> {
+    let key = script_key(&key);
+    let current = match backend::get(&key)? {
+        Some(value) => core::str::from_utf8(&value)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .context("The cached value is not an integer")?,
+        None => 0,
+    };
+    let value = current
+        .checked_add(delta)
+        .context("Integer overflow in increment")?;
+    backend::set(&key, value.to_string().as_bytes(), ttl)?;
+    Ok(value)
+}
+
+#[cfg(feature = "sidevm")]
+pub(super) mod backend {
+    use anyhow::anyhow;
+    use sidevm::ocall;
+
+    use super::Result;
+
+    pub fn set(key: &[u8], value: &[u8], ttl: Option<u64>) -> Result<()> {
+        ocall::local_cache_set(key, value).map_err(|err| anyhow!("Cache error: {err:?}"))?;
+        if let Some(ttl) = ttl {
+            ocall::local_cache_set_expiration(key, ttl)
+                .map_err(|err| anyhow!("Cache error: {err:?}"))?;
+        }
+        Ok(())
+    }
+
+    pub fn get(key: &[u8]) -> Result<Option<Vec<u8>>> {
+        ocall::local_cache_get(key).map_err(|err| anyhow!("Cache error: {err:?}"))
+    }
+
+    pub fn remove(key: &[u8]) -> Result<Option<Vec<u8>>> {
+        ocall::local_cache_remove(key).map_err(|err| anyhow!("Cache error: {err:?}"))
+    }
+}
+
+/// Outside of sidevm there is no worker cache, keep the entries in memory instead.
+#[cfg(not(feature = "sidevm"))]
+pub(super) mod backend {
+    use core::{cell::RefCell, time::Duration};
+    use std::collections::BTreeMap;
+
+    use super::Result;
+    use crate::runtime::monotonic_now;
+
+    struct Entry {
+        value: Vec<u8>,
+        expires_at: Option<Duration>,
+    }
+
+    thread_local! {
+        static CACHE: RefCell<BTreeMap<Vec<u8>, Entry>> = RefCell::new(BTreeMap::new());
+    }
+
+    pub fn set(key: &[u8], value: &[u8], ttl: Option<u64>) -> Result<()> {
+        let entry = Entry {
+            value: value.to_vec(),
+            expires_at: ttl.map(|ttl| monotonic_now() + Duration::from_secs(ttl)),
+        };
+        CACHE.with(|cache| cache.borrow_mut().insert(key.to_vec(), entry));
+        Ok(())
+    }
+
+    pub fn get(key: &[u8]) -> Result<Option<Vec<u8>>> {
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            let expired = match cache.get(key) {
+                None => return Ok(None),
+                Some(entry) => entry.expires_at.is_some_and(|at| at <= monotonic_now()),
+            };
+            if expired {
+                cache.remove(key);
+                return Ok(None);
+            }
+            Ok(cache.get(key).map(|entry| entry.value.clone()))
+        })
+    }
+
+    pub fn remove(key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let value = get(key)?;
+        CACHE.with(|cache| cache.borrow_mut().remove(key));
+        Ok(value)
+    }
+}
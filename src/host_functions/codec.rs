@@ -0,0 +1,223 @@
+use anyhow::{anyhow, bail, Context};
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use blake2::{digest::typenum::U64, Blake2b};
+use js::{AsBytes, ToJsValue};
+
+use super::{hash::do_hash, Result};
+
+type Bytes = AsBytes<Vec<u8>>;
+
+const SS58_PREFIX: &[u8] = b"SS58PRE";
+/// The generic Substrate address format.
+const SS58_DEFAULT_FORMAT: u16 = 42;
+
+#[derive(ToJsValue, Debug)]
+#[qjsbind(rename_all = "camelCase")]
+struct Ss58Address {
+    public_key: Bytes,
+    format: u16,
+}
+
+/// Set up the `Codec` namespace with a sub-namespace for each encoding.
+pub(crate) fn setup(ns: &js::Value, ctx: &js::Context) -> Result<()> {
+    let codec = js::Value::new_object(ctx);
+
+    let hex = js::Value::new_object(ctx);
+    hex.define_property_fn("encode", hex_encode)?;
+    hex.define_property_fn("decode", hex_decode)?;
+    codec.set_property("hex", &hex)?;
+
+    let base58 = js::Value::new_object(ctx);
+    base58.define_property_fn("encode", base58_encode)?;
+    base58.define_property_fn("decode", base58_decode)?;
+    codec.set_property("base58", &base58)?;
+
+    let base64 = js::Value::new_object(ctx);
+    base64.define_property_fn("encode", base64_encode)?;
+    base64.define_property_fn("decode", base64_decode)?;
+    codec.set_property("base64", &base64)?;
+
+    let base64url = js::Value::new_object(ctx);
+    base64url.define_property_fn("encode", base64url_encode)?;
+    base64url.define_property_fn("decode", base64url_decode)?;
+    codec.set_property("base64url", &base64url)?;
+
+    let ss58 = js::Value::new_object(ctx);
+    ss58.define_property_fn("encode", ss58_encode)?;
+    ss58.define_property_fn("decode", ss58_decode)?;
+    codec.set_property("ss58", &ss58)?;
+
+    ns.set_property("Codec", &codec)?;
+    ns.define_property_fn("atob", atob)?;
+    ns.define_property_fn("btoa", btoa)?;
+    Ok(())
+}
+
+#[js::host_call]
+fn hex_encode(data: Bytes, with_prefix: Option<bool>) -> String {
+    let encoded = hex::encode(data.0);
+    if with_prefix.unwrap_or(false) {
+        format!("0x{encoded}")
+    } else {
+        encoded
+    }
+}
+
+/// Decode a hex string, with or without the `0x` prefix.
+#[js::host_call]
+fn hex_decode(s: String) -> Result<Bytes> {
+    let s = s.strip_prefix("0x").unwrap_or(&s);
+    Ok(hex::decode(s).context("Invalid hex string")?.into())
+}
+
+#[js::host_call]
+fn base58_encode(data: Bytes) -> String {
+    bs58::encode(data.0).into_string()
+}
+
+#[js::host_call]
+fn base58_decode(s: String) -> Result<Bytes> {
+    Ok(bs58::decode(s)
+        .into_vec()
+        .context("Invalid base58 string")?
+        .into())
+}
+
+fn base64_engine(alphabet: &alphabet::Alphabet, pad: bool) -> GeneralPurpose {
+    let config = GeneralPurposeConfig::new()
+        .with_encode_padding(pad)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent);
+    GeneralPurpose::new(alphabet, config)
+}
+
+/// Encode with the standard alphabet, padded unless `pad` is false.
+#[js::host_call]
+fn base64_encode(data: Bytes, pad: Option<bool>) -> String {
+    base64_engine(&alphabet::STANDARD, pad.unwrap_or(true)).encode(data.0)
+}
+
+/// Decode with the standard alphabet. Padding is optional.
+#[js::host_call]
+fn base64_decode(s: String) -> Result<Bytes> {
+    let engine = base64_engine(&alphabet::STANDARD, true);
+    Ok(engine.decode(s).context("Invalid base64 string")?.into())
+}
+
+/// Encode with the URL-safe alphabet, unpadded unless `pad` is true.
+#[js::host_call]
+fn base64url_encode(data: Bytes, pad: Option<bool>) -> String {
+    base64_engine(&alphabet::URL_SAFE, pad.unwrap_or(false)).encode(data.0)
+}
+
+#[js::host_call]
+fn base64url_decode(s: String) -> Result<Bytes> {
+    let engine = base64_engine(&alphabet::URL_SAFE, false);
+    Ok(engine.decode(s).context("Invalid base64url string")?.into())
+}
+
+/// Decode base64 to a binary string, each char holding a byte, as `atob` of the web platform.
+///
+/// ASCII whitespace is ignored and padding is optional.
+#[js::host_call]
+fn atob(s: String) -> Result<String> {
+    let s: String = s.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let engine = base64_engine(&alphabet::STANDARD, true);
+    let bytes = engine
+        .decode(s)
+        .context("InvalidCharacterError: The string to be decoded is not correctly encoded")?;
+    Ok(bytes.into_iter().map(char::from).collect())
+}
+
+/// Encode a binary string, each char holding a byte, to base64, as `btoa` of the web platform.
+#[js::host_call]
+fn btoa(s: String) -> Result<String> {
+    let bytes = s
+        .chars()
+        .map(|c| u8::try_from(u32::from(c)))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| {
+            anyhow!(
+                "InvalidCharacterError: The string contains characters outside of the Latin1 range"
+            )
+        })?;
+    Ok(base64_engine(&alphabet::STANDARD, true).encode(bytes))
+}
+
+fn ss58_checksum(data: &[u8]) -> Vec<u8> {
+    do_hash::<Blake2b<U64>>([SS58_PREFIX, data].concat())
+}
+
+/// The checksum length by the length of the encoded account, see the SS58 specification.
+fn ss58_checksum_len(account_len: usize) -> Option<usize> {
+    match account_len {
+        1 | 2 | 4 | 8 => Some(1),
+        32 | 33 => Some(2),
+        _ => None,
+    }
+}
+
+/// Encode a public key (or account index) as an SS58 address. `format` defaults to 42.
+#[js::host_call]
+fn ss58_encode(public_key: Bytes, format: Option<u16>) -> Result<String> {
+    let format = format.unwrap_or(SS58_DEFAULT_FORMAT);
+    let public_key = public_key.0;
+    let Some(checksum_len) = ss58_checksum_len(public_key.len()) else {
+        bail!("Invalid public key length {}", public_key.len());
+    };
+    let mut data = match format {
+        0..=63 => vec![format as u8],
+        64..=16383 => {
+            let first = ((format & 0b0000_0000_1111_1100) as u8) >> 2;
+            let second = ((format >> 8) as u8) | ((format & 0b0000_0000_0000_0011) as u8) << 6;
+            vec![first | 0b0100_0000, second]
+        }
+        _ => bail!("Invalid SS58 format {format}"),
+    };
+    data.extend_from_slice(&public_key);
+    let checksum = ss58_checksum(&data);
+    data.extend_from_slice(&checksum[..checksum_len]);
+    Ok(bs58::encode(data).into_string())
+}
+
+/// Decode an SS58 address, validating its checksum.
+#[js::host_call]
+fn ss58_decode(address: String) -> Result<Ss58Address> {
+    let data = bs58::decode(&address)
+        .into_vec()
+        .context("Invalid base58 string")?;
+    let (format, prefix_len) = match data.first() {
+        Some(&first @ 0..=63) => (first as u16, 1),
+        Some(&first @ 64..=127) => {
+            let second = *data.get(1).ok_or_else(|| anyhow!("Address too short"))?;
+            let lower = (first << 2) | (second >> 6);
+            let upper = second & 0b0011_1111;
+            (lower as u16 | (upper as u16) << 8, 2)
+        }
+        Some(_) => bail!("Invalid SS58 prefix"),
+        None => bail!("Empty address"),
+    };
+    let body_len = data.len() - prefix_len;
+    let Some((account_len, checksum_len)) = [1, 2]
+        .into_iter()
+        .filter_map(|checksum_len| {
+            let account_len = body_len.checked_sub(checksum_len)?;
+            (ss58_checksum_len(account_len) == Some(checksum_len))
+                .then_some((account_len, checksum_len))
+        })
+        .next()
+    else {
+        bail!("Invalid address length");
+    };
+    let (payload, checksum) = data.split_at(prefix_len + account_len);
+    if ss58_checksum(payload)[..checksum_len] != *checksum {
+        bail!("Invalid address checksum");
+    }
+    Ok(Ss58Address {
+        public_key: payload[prefix_len..].to_vec().into(),
+        format,
+    })
+}
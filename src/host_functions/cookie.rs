@@ -0,0 +1,287 @@
+use js::ToJsValue;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::*;
+
+/// A cookie stored by the jar, following the storage model of RFC 6265.
+#[derive(Debug, Clone)]
+struct Cookie {
+    name: String,
+    value: String,
+    /// Lowercase, without a leading dot.
+    domain: String,
+    /// Only sent to `domain` itself, not its subdomains, if the cookie has no Domain attribute.
+    host_only: bool,
+    path: String,
+    secure: bool,
+    expires: Option<SystemTime>,
+}
+
+#[derive(ToJsValue, Debug)]
+#[qjsbind(rename_all = "camelCase")]
+struct CookieInfo {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    secure: bool,
+    /// Milliseconds since the unix epoch, or None for session cookies.
+    expires: Option<u64>,
+}
+
+impl From<&Cookie> for CookieInfo {
+    fn from(cookie: &Cookie) -> Self {
+        Self {
+            name: cookie.name.clone(),
+            value: cookie.value.clone(),
+            domain: cookie.domain.clone(),
+            host_only: cookie.host_only,
+            path: cookie.path.clone(),
+            secure: cookie.secure,
+            expires: cookie.expires.map(|expires| {
+                expires
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64
+            }),
+        }
+    }
+}
+
+impl Cookie {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, url: &::url::Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_match = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_match
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The directory of the request path, used when a cookie has no Path attribute.
+fn default_path(url: &::url::Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".into(),
+        Some(i) => path[..i].into(),
+    }
+}
+
+/// Whether `domain` is a public suffix such as `com` or `github.io`, under which anyone can
+/// register a domain.
+fn is_public_suffix(domain: &str) -> bool {
+    psl::suffix_str(domain) == Some(domain)
+}
+
+/// The longest lifetime of a cookie, 400 days as RFC 6265bis caps `Max-Age`.
+const MAX_AGE_SECS: u64 = 400 * 24 * 3600;
+
+/// Stores the cookies set by responses and supplies them to the following requests.
+#[derive(Default)]
+pub(crate) struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// Store the cookie of a `Set-Cookie` header received from `url`.
+    ///
+    /// Invalid cookies, cookies for domains other than the one of `url` or for a public suffix, and
+    /// secure cookies set over plain http are ignored.
+    pub fn store(&mut self, url: &::url::Url, set_cookie: &str) {
+        let Some(host) = url.host_str() else {
+            return;
+        };
+        let host = host.to_ascii_lowercase();
+        let mut parts = set_cookie.split(';');
+        let Some((name, value)) = parts.next().and_then(|pair| pair.split_once('=')) else {
+            return;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+        let now = SystemTime::now();
+        let mut cookie = Cookie {
+            name: name.into(),
+            value: value.trim().into(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attr in parts {
+            let (key, value) = attr.split_once('=').unwrap_or((attr, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain_matches(&host, &domain) {
+                        return;
+                    }
+                    // A public suffix would share the cookie with every site under it, it's only
+                    // allowed as the host itself, for which the cookie is host-only.
+                    if is_public_suffix(&domain) {
+                        if domain != host {
+                            return;
+                        }
+                        continue;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.into(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "expires" => {
+                    if let Ok(expires) = httpdate::parse_http_date(value) {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let secure_url = url.scheme() == "https";
+        if cookie.secure && !secure_url {
+            return;
+        }
+        // Plain http responses can't replace the secure cookies, as RFC 6265bis requires.
+        if !secure_url
+            && self.cookies.iter().any(|c| {
+                c.secure
+                    && c.name == cookie.name
+                    && (domain_matches(&c.domain, &cookie.domain)
+                        || domain_matches(&cookie.domain, &c.domain))
+                    && path_matches(&cookie.path, &c.path)
+            })
+        {
+            return;
+        }
+        // Max-Age takes precedence over Expires
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(if max_age <= 0 {
+                UNIX_EPOCH
+            } else {
+                let max_age = Duration::from_secs((max_age as u64).min(MAX_AGE_SECS));
+                now.checked_add(max_age).unwrap_or(now)
+            });
+        }
+        self.cookies.retain(|c| {
+            !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+        });
+        if !cookie.is_expired(now) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// The value of the `Cookie` header to send to `url`, None if no cookie matches.
+    pub fn header_for(&mut self, url: &::url::Url) -> Option<String> {
+        let mut cookies = self.matching(url);
+        if cookies.is_empty() {
+            return None;
+        }
+        // Longer paths first, as RFC 6265 recommends
+        cookies.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+        let pairs: Vec<_> = cookies
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    fn matching(&mut self, url: &::url::Url) -> Vec<&Cookie> {
+        self.cookies().iter().filter(|c| c.matches(url)).collect()
+    }
+
+    /// The cookies that have not expired yet.
+    fn cookies(&mut self) -> &[Cookie] {
+        let now = SystemTime::now();
+        self.cookies.retain(|c| !c.is_expired(now));
+        &self.cookies
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+}
+
+pub(crate) fn setup(ns: &js::Value, ctx: &js::Context) -> Result<()> {
+    let cookies = js::Value::new_object(ctx);
+    cookies.define_property_fn("enable", cookies_enable)?;
+    cookies.define_property_fn("list", cookies_list)?;
+    cookies.define_property_fn("clear", cookies_clear)?;
+    ns.set_property("cookies", &cookies)?;
+    Ok(())
+}
+
+/// Start storing the cookies of the responses and sending them with the following requests.
+#[js::host_call(with_context)]
+fn cookies_enable(service: ServiceRef, _this: js::Value) {
+    service.enable_cookie_jar();
+}
+
+/// List the stored cookies, only those that would be sent to `url` if given.
+#[js::host_call(with_context)]
+fn cookies_list(
+    service: ServiceRef,
+    _this: js::Value,
+    url: Option<String>,
+) -> Result<Vec<CookieInfo>> {
+    let url = match url {
+        Some(url) => Some(url.parse::<::url::Url>()?),
+        None => None,
+    };
+    let cookies = service.with_cookie_jar(|jar| match &url {
+        Some(url) => jar
+            .matching(url)
+            .into_iter()
+            .map(CookieInfo::from)
+            .collect(),
+        None => jar.cookies().iter().map(CookieInfo::from).collect(),
+    });
+    Ok(cookies.unwrap_or_default())
+}
+
+#[js::host_call(with_context)]
+fn cookies_clear(service: ServiceRef, _this: js::Value) {
+    service.with_cookie_jar(|jar| jar.clear());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_max_age_is_capped() {
+        let url: ::url::Url = "https://example.com/".parse().unwrap();
+        let mut jar = CookieJar::default();
+        jar.store(&url, "a=b; Max-Age=9223372036854775807");
+        assert_eq!(jar.header_for(&url).as_deref(), Some("a=b"));
+    }
+}
@@ -0,0 +1,282 @@
+//! Cron schedules run by the host, see `registerCron` in sidevm.js.
+//!
+//! Schedules are evaluated in UTC against the wall clock. The sleeps are capped at
+//! `MAX_SLEEP` so that changes of the wall clock are picked up.
+
+use anyhow::{bail, Context};
+use core::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::calendar::{civil_from_days, days_from_civil};
+use super::*;
+use crate::{runtime::time::sleep, service::OwnedJsValue};
+
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+/// A run later than this is a misfire.
+const MISFIRE_THRESHOLD_SECS: i64 = 1;
+/// The most missed runs fired by the `all` policy after a pause.
+const MAX_CATCH_UP: usize = 64;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    // Cancelled by `close` on the guest side
+    ns.define_property_fn("registerCron", register_cron)?;
+    Ok(())
+}
+
+/// What to do with the runs missed while the service was busy or suspended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MisfirePolicy {
+    /// Drop the missed runs and wait for the next one.
+    Skip,
+    /// Fire once for all the missed runs, with the time of the last of them.
+    Once,
+    /// Fire each missed run, up to `MAX_CATCH_UP`.
+    All,
+}
+
+impl FromStr for MisfirePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "once" => Ok(Self::Once),
+            "all" => Ok(Self::All),
+            _ => bail!("Unknown misfire policy {s:?}, expected skip, once or all"),
+        }
+    }
+}
+
+/// Call `handler(scheduledTimeMs)` on the schedule given by `spec`, returning the resource id.
+///
+/// `spec` has the five fields `minute hour day-of-month month day-of-week`, optionally preceded
+/// by seconds, or is one of `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly`.
+#[js::host_call(with_context)]
+fn register_cron(
+    service: ServiceRef,
+    _this: js::Value,
+    spec: String,
+    handler: OwnedJsValue,
+    misfire: Option<String>,
+) -> Result<u64> {
+    let schedule: Schedule = spec.parse()?;
+    let policy = match misfire {
+        Some(policy) => policy.parse()?,
+        None => MisfirePolicy::Once,
+    };
+    if schedule.next_after(wall_clock_secs()).is_none() {
+        bail!("The cron schedule {spec:?} never fires");
+    }
+    service.spawn(handler, run_schedule, (schedule, policy))
+}
+
+async fn run_schedule(
+    service: ServiceWeakRef,
+    id: u64,
+    (schedule, policy): (Schedule, MisfirePolicy),
+) {
+    let Some(mut next) = schedule.next_after(wall_clock_secs()) else {
+        return;
+    };
+    loop {
+        let mut now = wall_clock_secs();
+        while now < next {
+            sleep(Duration::from_secs((next - now) as u64).min(MAX_SLEEP)).await;
+            now = wall_clock_secs();
+        }
+        let mut missed = vec![next];
+        while let Some(time) = schedule.next_after(*missed.last().unwrap_or(&next)) {
+            if time > now || missed.len() >= MAX_CATCH_UP {
+                break;
+            }
+            missed.push(time);
+        }
+        let runs = match policy {
+            MisfirePolicy::Skip if now - next > MISFIRE_THRESHOLD_SECS => vec![],
+            MisfirePolicy::Skip => vec![next],
+            MisfirePolicy::Once => missed.split_off(missed.len() - 1),
+            MisfirePolicy::All => missed,
+        };
+        for time in runs {
+            if fire(&service, id, time).log_err().is_err() {
+                return;
+            }
+        }
+        let Some(time) = schedule.next_after(now) else {
+            return;
+        };
+        next = time;
+    }
+}
+
+fn fire(service: &ServiceWeakRef, id: u64, time: i64) -> Result<()> {
+    let Some(service) = service.upgrade() else {
+        bail!("Cron {id} exited because the service has been dropped");
+    };
+    let Some(handler) = service.get_resource_value(id) else {
+        bail!("Cron {id} exited because the resource has been dropped");
+    };
+    if let Err(err) = service.call_function(handler, (time as f64 * 1000.0,)) {
+        error!("Failed to run cron {id}: {err}");
+    }
+    Ok(())
+}
+
+fn wall_clock_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// The allowed values of each field, as bit sets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Schedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month or the day of week starts with `*`. If both are restricted, a day
+    /// matching either of them matches as in the classic cron.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let spec = match spec.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            spec => spec,
+        };
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let (seconds, fields) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            _ => bail!("Invalid cron schedule {spec:?}, expected 5 or 6 fields"),
+        };
+        let weekdays = parse_field(fields[4], 0, 7, &WEEKDAYS).context("Invalid day of week")?;
+        Ok(Self {
+            seconds: parse_field(seconds, 0, 59, &[]).context("Invalid seconds")?,
+            minutes: parse_field(fields[0], 0, 59, &[]).context("Invalid minutes")?,
+            hours: parse_field(fields[1], 0, 23, &[]).context("Invalid hours")?,
+            days: parse_field(fields[2], 1, 31, &[]).context("Invalid day of month")?,
+            months: parse_field(fields[3], 1, 12, &MONTHS).context("Invalid month")?,
+            // 7 is Sunday as well
+            weekdays: (weekdays | (weekdays >> 7)) & 0x7f,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+}
+
+impl Schedule {
+    /// The first time strictly after `time` that matches, in seconds since the unix epoch.
+    fn next_after(&self, time: i64) -> Option<i64> {
+        const DAY: i64 = 86400;
+        let mut t = time + 1;
+        // Any satisfiable schedule fires within 8 years, the longest gap between two Feb 29.
+        let limit = t + 8 * 366 * DAY;
+        while t < limit {
+            let days = t.div_euclid(DAY);
+            let secs = t.rem_euclid(DAY);
+            let (year, month, day) = civil_from_days(days);
+            if !has(self.months, month) {
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                t = days_from_civil(year, month, 1) * DAY;
+                continue;
+            }
+            // The unix epoch was a Thursday
+            let weekday = (days + 4).rem_euclid(7) as u32;
+            if !self.day_matches(day, weekday) {
+                t = (days + 1) * DAY;
+                continue;
+            }
+            let (hour, minute, second) = (secs / 3600, secs % 3600 / 60, secs % 60);
+            if !has(self.hours, hour as u32) {
+                t = days * DAY + (hour + 1) * 3600;
+                continue;
+            }
+            if !has(self.minutes, minute as u32) {
+                t = days * DAY + hour * 3600 + (minute + 1) * 60;
+                continue;
+            }
+            if !has(self.seconds, second as u32) {
+                t += 1;
+                continue;
+            }
+            return Some(t);
+        }
+        None
+    }
+
+    fn day_matches(&self, day: u32, weekday: u32) -> bool {
+        let day_ok = has(self.days, day);
+        let weekday_ok = has(self.weekdays, weekday);
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day_ok || weekday_ok,
+            _ => day_ok && weekday_ok,
+        }
+    }
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parse a comma-separated list of `*`, `n`, `a-b`, optionally stepped by `/step`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let value = |s: &str| -> Result<u32> {
+        let lower = s.to_ascii_lowercase();
+        let value = match names.iter().position(|name| *name == lower) {
+            // Names start at 1 for months and 0 for weekdays, as the ranges do.
+            Some(index) => index as u32 + min,
+            None => s.parse().with_context(|| format!("Invalid value {s:?}"))?,
+        };
+        if value < min || value > max {
+            bail!("{value} is out of the range {min}-{max}");
+        }
+        Ok(value)
+    };
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().context("Invalid step")?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("The step can not be 0");
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `n/step` runs from n to the end of the range
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            bail!("Invalid range {range}");
+        }
+        for value in (start..=end).step_by(step) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
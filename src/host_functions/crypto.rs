@@ -0,0 +1,187 @@
+use super::{hash::do_hash, Result};
+use aes::{Aes128, Aes192, Aes256};
+use aes_gcm::{
+    aead::{consts::U12, Aead, KeyInit, Payload},
+    AesGcm,
+};
+use anyhow::{anyhow, bail};
+use blake2::{
+    digest::typenum::{U32, U64},
+    Blake2b,
+};
+use ctr::{
+    cipher::{KeyIvInit, StreamCipher},
+    Ctr128BE, Ctr32BE, Ctr64BE,
+};
+use hmac::{Hmac, Mac};
+use js::AsBytes;
+
+type Bytes = AsBytes<Vec<u8>>;
+
+/// The quota of `crypto.getRandomValues` defined by the Web Crypto spec.
+const MAX_RANDOM_BYTES: u32 = 65536;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("randomBytes", random_bytes)?;
+    ns.define_property_fn("digest", digest)?;
+    ns.define_property_fn("hmacSign", hmac_sign)?;
+    ns.define_property_fn("hmacVerify", hmac_verify)?;
+    ns.define_property_fn("aesGcmEncrypt", aes_gcm_encrypt)?;
+    ns.define_property_fn("aesGcmDecrypt", aes_gcm_decrypt)?;
+    ns.define_property_fn("aesCtr", aes_ctr)?;
+    Ok(())
+}
+
+/// Generate `len` cryptographically secure random bytes.
+#[js::host_call]
+fn random_bytes(len: u32) -> Result<Bytes> {
+    if len > MAX_RANDOM_BYTES {
+        bail!("The requested length ({len}) exceeds the quota of {MAX_RANDOM_BYTES} bytes");
+    }
+    let mut buf = vec![0u8; len as usize];
+    crate::runtime::getrandom(&mut buf).expect("Failed to get random bytes");
+    Ok(buf.into())
+}
+
+/// Digest `data` with one of the algorithms named the way `crypto.subtle.digest` does.
+#[js::host_call]
+fn digest(algorithm: String, data: Bytes) -> Result<Bytes> {
+    let data = data.0;
+    let hash = match algorithm.to_ascii_uppercase().as_str() {
+        "SHA-256" => do_hash::<sha2::Sha256>(data),
+        "SHA-384" => do_hash::<sha2::Sha384>(data),
+        "SHA-512" => do_hash::<sha2::Sha512>(data),
+        "KECCAK-256" => do_hash::<sha3::Keccak256>(data),
+        "SHA3-256" => do_hash::<sha3::Sha3_256>(data),
+        "BLAKE2B-256" => do_hash::<Blake2b<U32>>(data),
+        "BLAKE2B-512" => do_hash::<Blake2b<U64>>(data),
+        _ => bail!("Unsupported digest algorithm: {algorithm}"),
+    };
+    Ok(hash.into())
+}
+
+fn new_hmac<M: Mac + KeyInit>(key: &[u8], data: &[u8]) -> M {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac
+}
+
+fn do_hmac_sign<M: Mac + KeyInit>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    new_hmac::<M>(key, data).finalize().into_bytes().to_vec()
+}
+
+fn do_hmac_verify<M: Mac + KeyInit>(key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    new_hmac::<M>(key, data).verify_slice(signature).is_ok()
+}
+
+#[js::host_call]
+fn hmac_sign(hash: String, key: Bytes, data: Bytes) -> Result<Bytes> {
+    let (key, data) = (&key.0, &data.0);
+    let signature = match hash.to_ascii_uppercase().as_str() {
+        "SHA-256" => do_hmac_sign::<Hmac<sha2::Sha256>>(key, data),
+        "SHA-384" => do_hmac_sign::<Hmac<sha2::Sha384>>(key, data),
+        "SHA-512" => do_hmac_sign::<Hmac<sha2::Sha512>>(key, data),
+        _ => bail!("Unsupported HMAC hash: {hash}"),
+    };
+    Ok(signature.into())
+}
+
+/// Verify an HMAC signature in constant time.
+#[js::host_call]
+fn hmac_verify(hash: String, key: Bytes, data: Bytes, signature: Bytes) -> Result<bool> {
+    let (key, data, signature) = (&key.0, &data.0, &signature.0);
+    let valid = match hash.to_ascii_uppercase().as_str() {
+        "SHA-256" => do_hmac_verify::<Hmac<sha2::Sha256>>(key, data, signature),
+        "SHA-384" => do_hmac_verify::<Hmac<sha2::Sha384>>(key, data, signature),
+        "SHA-512" => do_hmac_verify::<Hmac<sha2::Sha512>>(key, data, signature),
+        _ => bail!("Unsupported HMAC hash: {hash}"),
+    };
+    Ok(valid)
+}
+
+fn do_aes_gcm<C: Aead + KeyInit>(
+    key: &[u8],
+    iv: &[u8],
+    data: &[u8],
+    aad: &[u8],
+    encrypt: bool,
+) -> Result<Vec<u8>> {
+    let cipher = C::new_from_slice(key).map_err(|_| anyhow!("Invalid AES key length"))?;
+    let nonce = aes_gcm::aead::Nonce::<C>::from_slice(iv);
+    let payload = Payload { msg: data, aad };
+    let result = if encrypt {
+        cipher.encrypt(nonce, payload)
+    } else {
+        cipher.decrypt(nonce, payload)
+    };
+    result.map_err(|_| anyhow!("AES-GCM operation failed"))
+}
+
+fn aes_gcm(
+    key: &[u8],
+    iv: &[u8],
+    data: &[u8],
+    additional_data: Option<Bytes>,
+    encrypt: bool,
+) -> Result<Bytes> {
+    if iv.len() != 12 {
+        bail!("AES-GCM only supports 96-bit IVs");
+    }
+    let aad = additional_data.map(|aad| aad.0).unwrap_or_default();
+    let output = match key.len() {
+        16 => do_aes_gcm::<AesGcm<Aes128, U12>>(key, iv, data, &aad, encrypt)?,
+        24 => do_aes_gcm::<AesGcm<Aes192, U12>>(key, iv, data, &aad, encrypt)?,
+        32 => do_aes_gcm::<AesGcm<Aes256, U12>>(key, iv, data, &aad, encrypt)?,
+        len => bail!("Invalid AES key length: {len}"),
+    };
+    Ok(output.into())
+}
+
+/// Encrypt with AES-GCM. The 128-bit authentication tag is appended to the ciphertext.
+#[js::host_call]
+fn aes_gcm_encrypt(
+    key: Bytes,
+    iv: Bytes,
+    data: Bytes,
+    additional_data: Option<Bytes>,
+) -> Result<Bytes> {
+    aes_gcm(&key.0, &iv.0, &data.0, additional_data, true)
+}
+
+#[js::host_call]
+fn aes_gcm_decrypt(
+    key: Bytes,
+    iv: Bytes,
+    data: Bytes,
+    additional_data: Option<Bytes>,
+) -> Result<Bytes> {
+    aes_gcm(&key.0, &iv.0, &data.0, additional_data, false)
+}
+
+fn do_aes_ctr<C: KeyIvInit + StreamCipher>(key: &[u8], counter: &[u8], data: &mut [u8]) {
+    let mut cipher = C::new_from_slices(key, counter).expect("Lengths checked by the caller");
+    cipher.apply_keystream(data);
+}
+
+/// Encrypt or decrypt with AES-CTR. The rightmost `length` bits of `counter` are incremented.
+#[js::host_call]
+fn aes_ctr(key: Bytes, counter: Bytes, length: u32, data: Bytes) -> Result<Bytes> {
+    let (key, counter, mut data) = (key.0, counter.0, data.0);
+    if counter.len() != 16 {
+        bail!("AES-CTR counter must be 16 bytes");
+    }
+    match (key.len(), length) {
+        (16, 32) => do_aes_ctr::<Ctr32BE<Aes128>>(&key, &counter, &mut data),
+        (16, 64) => do_aes_ctr::<Ctr64BE<Aes128>>(&key, &counter, &mut data),
+        (16, 128) => do_aes_ctr::<Ctr128BE<Aes128>>(&key, &counter, &mut data),
+        (24, 32) => do_aes_ctr::<Ctr32BE<Aes192>>(&key, &counter, &mut data),
+        (24, 64) => do_aes_ctr::<Ctr64BE<Aes192>>(&key, &counter, &mut data),
+        (24, 128) => do_aes_ctr::<Ctr128BE<Aes192>>(&key, &counter, &mut data),
+        (32, 32) => do_aes_ctr::<Ctr32BE<Aes256>>(&key, &counter, &mut data),
+        (32, 64) => do_aes_ctr::<Ctr64BE<Aes256>>(&key, &counter, &mut data),
+        (32, 128) => do_aes_ctr::<Ctr128BE<Aes256>>(&key, &counter, &mut data),
+        (16 | 24 | 32, _) => bail!("Unsupported AES-CTR counter length: {length}"),
+        (len, _) => bail!("Invalid AES key length: {len}"),
+    }
+    Ok(data.into())
+}
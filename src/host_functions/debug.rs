@@ -3,6 +3,20 @@ use log::info;
 
 pub(crate) fn setup(ns: &js::Value) -> Result<()> {
     ns.define_property_fn("marker", marker)?;
+    ns.define_property_fn("memoryUsage", memory_usage)?;
+    ns.define_property_fn("performanceNow", performance_now)?;
+    Ok(())
+}
+
+/// Define `runGc`, only when the embedder exposes the garbage collector.
+pub(crate) fn setup_gc(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("runGc", run_gc)?;
+    Ok(())
+}
+
+/// Define `metrics`, only when the service counts its host calls.
+pub(crate) fn setup_metrics(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("metrics", metrics)?;
     Ok(())
 }
 
@@ -19,3 +33,24 @@ impl Drop for Marker {
 fn marker(service: ServiceRef, _this: js::Value, tag: String) -> js::Value {
     js::Value::new_opaque_object(service.context(), Marker { tag })
 }
+
+#[js::host_call(with_context)]
+fn memory_usage(service: ServiceRef, _this: js::Value) -> crate::MemoryUsage {
+    service.memory_usage()
+}
+
+#[js::host_call(with_context)]
+fn run_gc(service: ServiceRef, _this: js::Value) {
+    service.run_gc();
+}
+
+#[js::host_call(with_context)]
+fn metrics(service: ServiceRef, _this: js::Value) -> crate::Metrics {
+    service.metrics()
+}
+
+/// The milliseconds since the service was created, backing `performance.now()`.
+#[js::host_call(with_context)]
+fn performance_now(service: ServiceRef, _this: js::Value) -> f64 {
+    service.performance_now()
+}
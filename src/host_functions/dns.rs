@@ -0,0 +1,263 @@
+use anyhow::{anyhow, Context};
+use core::{
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    task::{Context as TaskContext, Poll},
+};
+use hickory_resolver::{
+    config::{NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts},
+    proto::rr::{RData, RecordType},
+    TokioAsyncResolver,
+};
+use hyper::{
+    client::{connect::dns::Name, HttpConnector},
+    service::Service as HyperService,
+};
+use hyper_rustls::HttpsConnector;
+use js::ToJsValue;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use super::*;
+use crate::{
+    runtime::{https_connector, HyperExecutor},
+    service::OwnedJsValue,
+};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A resolver shared by the lookups of a service.
+pub(crate) type SharedResolver = Arc<TokioAsyncResolver>;
+
+pub(crate) type DnsHttpClient =
+    hyper::Client<HttpsConnector<EgressConnector<HttpConnector<HickoryResolver>>>, hyper::Body>;
+
+/// The DNS servers to resolve host names with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsConfig {
+    /// The servers configured in the operating system.
+    System,
+    /// Plain DNS to these servers over UDP, falling back to TCP.
+    Servers(Vec<SocketAddr>),
+    /// DNS over HTTPS to the servers at `addrs`, whose certificate must be valid for `name`,
+    /// e.g. `1.1.1.1` as `cloudflare-dns.com`.
+    Https { addrs: Vec<IpAddr>, name: String },
+}
+
+impl FromStr for DnsConfig {
+    type Err = anyhow::Error;
+
+    /// Parse `system`, `<ip>[:port],..` or `https://<name>@<ip>,..`.
+    fn from_str(spec: &str) -> Result<Self> {
+        if spec == "system" {
+            return Ok(Self::System);
+        }
+        if let Some(rest) = spec.strip_prefix("https://") {
+            let (name, addrs) = rest
+                .split_once('@')
+                .context("DNS over HTTPS expects https://<name>@<ip>[,<ip>..]")?;
+            let addrs = addrs
+                .split(',')
+                .map(|addr| addr.parse().context("Invalid DNS server address"))
+                .collect::<Result<_>>()?;
+            return Ok(Self::Https {
+                addrs,
+                name: name.into(),
+            });
+        }
+        let servers = spec
+            .split(',')
+            .map(|addr| match addr.parse::<SocketAddr>() {
+                Ok(addr) => Ok(addr),
+                Err(_) => addr
+                    .parse::<IpAddr>()
+                    .map(|ip| SocketAddr::new(ip, 53))
+                    .with_context(|| format!("Invalid DNS server address: {addr}")),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::Servers(servers))
+    }
+}
+
+/// A configured resolver along with the http client resolving through it.
+#[derive(Clone)]
+pub(crate) struct DnsResolver {
+    resolver: SharedResolver,
+    http_client: DnsHttpClient,
+}
+
+impl DnsResolver {
+    pub fn new(config: &DnsConfig, egress_filter: SharedEgressFilter) -> Result<Self> {
+        let resolver = match config {
+            DnsConfig::System => {
+                TokioAsyncResolver::tokio_from_system_conf().context("Failed to read DNS config")?
+            }
+            DnsConfig::Servers(servers) => {
+                let mut group = NameServerConfigGroup::new();
+                for addr in servers {
+                    group.push(NameServerConfig::new(*addr, Protocol::Udp));
+                    group.push(NameServerConfig::new(*addr, Protocol::Tcp));
+                }
+                let config = ResolverConfig::from_parts(None, vec![], group);
+                TokioAsyncResolver::tokio(config, ResolverOpts::default())
+            }
+            DnsConfig::Https { addrs, name } => {
+                let group = NameServerConfigGroup::from_ips_https(addrs, 443, name.clone(), true);
+                let config = ResolverConfig::from_parts(None, vec![], group);
+                TokioAsyncResolver::tokio(config, ResolverOpts::default())
+            }
+        };
+        let resolver = Arc::new(resolver);
+        let mut http = HttpConnector::new_with_resolver(HickoryResolver(resolver.clone()));
+        http.enforce_http(false);
+        let connector = https_connector(EgressConnector::new(http, egress_filter));
+        let http_client = hyper::Client::builder()
+            .executor(HyperExecutor)
+            .build(connector);
+        Ok(Self {
+            resolver,
+            http_client,
+        })
+    }
+
+    pub fn http_client(&self) -> DnsHttpClient {
+        self.http_client.clone()
+    }
+
+    /// The resolver of the system config, read once and kept by the service.
+    pub fn system(service: &Service) -> Result<SharedResolver> {
+        if let Some(resolver) = service.system_dns_resolver() {
+            return Ok(resolver);
+        }
+        let resolver = Arc::new(
+            TokioAsyncResolver::tokio_from_system_conf().context("Failed to read DNS config")?,
+        );
+        service.set_system_dns_resolver(resolver.clone());
+        Ok(resolver)
+    }
+}
+
+/// Resolves the host names for hyper's `HttpConnector`.
+#[derive(Clone)]
+pub(crate) struct HickoryResolver(Arc<TokioAsyncResolver>);
+
+impl HyperService<Name> for HickoryResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let ips = resolver.lookup_ip(name.as_str()).await?;
+            // The connector fills in the port
+            let addrs: Vec<_> = ips.iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+#[derive(ToJsValue, Debug)]
+struct MxRecord {
+    priority: u16,
+    exchange: String,
+}
+
+/// The records of a lookup, MX records are objects and the others strings.
+enum Records {
+    Text(Vec<String>),
+    Mx(Vec<MxRecord>),
+}
+
+impl ToJsValue for Records {
+    fn to_js_value(&self, ctx: &js::Context) -> Result<js::Value, js::Error> {
+        match self {
+            Self::Text(records) => records.to_js_value(ctx),
+            Self::Mx(records) => records.to_js_value(ctx),
+        }
+    }
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("dnsResolve", dns_resolve)?;
+    Ok(())
+}
+
+/// Look up the `record_type` records of `hostname`, `A` by default.
+///
+/// The callback receives a `result` event with the records or an `error` event.
+#[js::host_call(with_context)]
+fn dns_resolve(
+    service: ServiceRef,
+    _this: js::Value,
+    hostname: String,
+    record_type: Option<String>,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    let record_type = record_type.as_deref().unwrap_or("A").to_ascii_uppercase();
+    let record_type = RecordType::from_str(&record_type)
+        .map_err(|_| anyhow!("Unsupported DNS record type: {record_type}"))?;
+    let resolver = match service.dns_resolver() {
+        Some(resolver) => resolver.resolver,
+        None => DnsResolver::system(&service)?,
+    };
+    service.spawn(callback, do_dns_resolve, (resolver, hostname, record_type))
+}
+
+async fn do_dns_resolve(
+    weak_service: ServiceWeakRef,
+    id: u64,
+    (resolver, hostname, record_type): (SharedResolver, String, RecordType),
+) {
+    match lookup(&resolver, &hostname, record_type).await {
+        Ok(records) => invoke_callback(&weak_service, id, "result", &records),
+        Err(err) => invoke_callback(
+            &weak_service,
+            id,
+            "error",
+            &format!("Failed to resolve {record_type} records of {hostname}: {err}"),
+        ),
+    }
+}
+
+async fn lookup(
+    resolver: &TokioAsyncResolver,
+    hostname: &str,
+    record_type: RecordType,
+) -> Result<Records> {
+    let lookup = resolver.lookup(hostname, record_type).await?;
+    let records = lookup
+        .iter()
+        .filter(|rdata| rdata.record_type() == record_type);
+    if record_type == RecordType::MX {
+        let records = records
+            .filter_map(|rdata| match rdata {
+                RData::MX(mx) => Some(MxRecord {
+                    priority: mx.preference(),
+                    exchange: mx.exchange().to_string(),
+                }),
+                _ => None,
+            })
+            .collect();
+        return Ok(Records::Mx(records));
+    }
+    let records = records
+        .map(|rdata| match rdata {
+            // A TXT record may be split into several strings
+            RData::TXT(txt) => txt
+                .txt_data()
+                .iter()
+                .map(|data| String::from_utf8_lossy(data))
+                .collect(),
+            rdata => rdata.to_string(),
+        })
+        .collect();
+    Ok(Records::Text(records))
+}
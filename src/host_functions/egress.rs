@@ -0,0 +1,404 @@
+use anyhow::{bail, Context, Result};
+use core::str::FromStr;
+#[cfg(feature = "native")]
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+};
+#[cfg(feature = "native")]
+use hyper::{service::Service as HyperService, Uri};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::service::ServiceWeakRef;
+
+#[cfg(feature = "native")]
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A rule matching the destinations of outgoing connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    /// A host name pattern where `*` matches any characters, e.g. `*.example.com`.
+    Host(String),
+    /// IP addresses in a CIDR block, e.g. `10.0.0.0/8`.
+    Cidr { network: IpAddr, prefix: u8 },
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(rule: &str) -> Result<Self> {
+        let rule = rule.trim();
+        if rule.is_empty() {
+            bail!("Empty egress rule");
+        }
+        if let Some((network, prefix)) = rule.split_once('/') {
+            let network: IpAddr = network
+                .parse()
+                .with_context(|| format!("Invalid network in egress rule: {rule}"))?;
+            let max = if network.is_ipv4() { 32 } else { 128 };
+            let prefix = prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .with_context(|| format!("Invalid prefix length in egress rule: {rule}"))?;
+            return Ok(Self::Cidr { network, prefix });
+        }
+        if let Ok(ip) = rule.parse::<IpAddr>() {
+            let prefix = if ip.is_ipv4() { 32 } else { 128 };
+            return Ok(Self::Cidr {
+                network: ip,
+                prefix,
+            });
+        }
+        Ok(Self::Host(rule.to_ascii_lowercase()))
+    }
+}
+
+impl Rule {
+    fn matches_host(&self, host: &str) -> bool {
+        match self {
+            Self::Host(pattern) => glob_matches(pattern.as_bytes(), host.as_bytes()),
+            Self::Cidr { .. } => false,
+        }
+    }
+
+    fn matches_ip(&self, ip: IpAddr) -> bool {
+        let Self::Cidr { network, prefix } = *self else {
+            return false;
+        };
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full = prefix as usize / 8;
+    if network[..full] != ip[..full] {
+        return false;
+    }
+    let rest = prefix % 8;
+    if rest == 0 {
+        return true;
+    }
+    let mask = 0xff_u8 << (8 - rest);
+    network[full] & mask == ip[full] & mask
+}
+
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob_matches(rest, &text[i..])),
+        Some((c, rest)) => text.first() == Some(c) && glob_matches(rest, &text[1..]),
+    }
+}
+
+/// Restricts the hosts the network host functions of a service may connect to.
+///
+/// A destination is refused if it matches a deny rule, or if there are allow rules and it
+/// matches none of them. Host name rules apply to the name given by the script, CIDR rules to
+/// IP literals and to the addresses a host name resolves to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EgressFilter {
+    allow: Vec<Rule>,
+    deny: Vec<Rule>,
+}
+
+impl EgressFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow a host pattern like `*.example.com` or a CIDR block like `10.0.0.0/8`.
+    pub fn allow(mut self, rule: &str) -> Result<Self> {
+        self.allow.push(rule.parse()?);
+        Ok(self)
+    }
+
+    /// Deny a host pattern like `*.example.com` or a CIDR block like `10.0.0.0/8`.
+    pub fn deny(mut self, rule: &str) -> Result<Self> {
+        self.deny.push(rule.parse()?);
+        Ok(self)
+    }
+
+    /// Check that `host:port` may be reached, resolving `host` if some rule needs its addresses.
+    ///
+    /// The connection resolves `host` again, so the connected address must be checked as well
+    /// with [`EgressFilter::check_addr`].
+    pub async fn check(&self, host: &str, port: u16) -> Result<()> {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return self.check_one(&host, |rule| rule.matches_ip(ip));
+        }
+        let has_cidr = self
+            .allow
+            .iter()
+            .chain(&self.deny)
+            .any(|rule| matches!(rule, Rule::Cidr { .. }));
+        if !has_cidr {
+            return self.check_one(&host, |rule| rule.matches_host(&host));
+        }
+        let ips = resolve(&host, port).await?;
+        if ips.is_empty() {
+            return self.check_one(&host, |rule| rule.matches_host(&host));
+        }
+        for ip in ips {
+            self.check_one(&host, |rule| {
+                rule.matches_host(&host) || rule.matches_ip(ip)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check the address a connection to `host` has actually been established with.
+    pub fn check_addr(&self, host: &str, ip: IpAddr) -> Result<()> {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        self.check_one(&host, |rule| {
+            rule.matches_host(&host) || rule.matches_ip(ip)
+        })
+    }
+
+    fn check_one(&self, host: &str, matches: impl Fn(&Rule) -> bool) -> Result<()> {
+        let denied = self.deny.iter().any(&matches);
+        let allowed = self.allow.is_empty() || self.allow.iter().any(&matches);
+        if denied || !allowed {
+            bail!("Connection to {host} is not allowed");
+        }
+        Ok(())
+    }
+
+    /// Check the host of `url`, see [`EgressFilter::check`].
+    pub async fn check_url(&self, url: &::url::Url) -> Result<()> {
+        let host = url.host_str().context("Missing host in url")?;
+        let port = url.port_or_known_default().unwrap_or(0);
+        self.check(host, port).await
+    }
+}
+
+#[cfg(feature = "native")]
+async fn resolve(host: &str, port: u16) -> Result<Vec<IpAddr>> {
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Failed to resolve {host}"))?;
+    Ok(addrs.map(|addr| addr.ip()).collect())
+}
+
+/// The browser or the sidevm host resolves the host names, only the host name rules can apply.
+#[cfg(not(feature = "native"))]
+async fn resolve(_host: &str, _port: u16) -> Result<Vec<IpAddr>> {
+    Ok(vec![])
+}
+
+/// The egress filter of a service, shared with the connectors of its http clients.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedEgressFilter(Arc<Mutex<Option<EgressFilter>>>);
+
+impl SharedEgressFilter {
+    pub fn get(&self) -> Option<EgressFilter> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    pub fn set(&self, filter: Option<EgressFilter>) {
+        *self.0.lock().unwrap_or_else(|err| err.into_inner()) = filter;
+    }
+}
+
+/// Wraps the connector of an http client to check the address each connection ends up with.
+#[cfg(feature = "native")]
+#[derive(Clone)]
+pub(crate) struct EgressConnector<C> {
+    inner: C,
+    filter: SharedEgressFilter,
+}
+
+#[cfg(feature = "native")]
+impl<C> EgressConnector<C> {
+    pub fn new(inner: C, filter: SharedEgressFilter) -> Self {
+        Self { inner, filter }
+    }
+}
+
+#[cfg(feature = "native")]
+impl<C> HyperService<Uri> for EgressConnector<C>
+where
+    C: HyperService<Uri, Response = tokio::net::TcpStream>,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = tokio::net::TcpStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let host = uri.host().unwrap_or_default().to_string();
+        let filter = self.filter.get();
+        let connecting = self.inner.call(uri);
+        Box::pin(async move {
+            let stream = connecting.await.map_err(Into::into)?;
+            if let Some(filter) = filter {
+                filter.check_addr(&host, stream.peer_addr()?.ip())?;
+            }
+            Ok(stream)
+        })
+    }
+}
+
+/// Connect to `host:port`, checking both the host and the address actually connected to.
+#[cfg(feature = "native")]
+pub(crate) async fn connect_checked(
+    weak_service: &ServiceWeakRef,
+    host: &str,
+    port: u16,
+) -> Result<tokio::net::TcpStream> {
+    check_egress(weak_service, host, port).await?;
+    let ip_or_name = host.trim_start_matches('[').trim_end_matches(']');
+    let stream = tokio::net::TcpStream::connect((ip_or_name, port))
+        .await
+        .context("Failed to connect")?;
+    let filter = weak_service
+        .upgrade()
+        .and_then(|service| service.egress_filter());
+    if let Some(filter) = filter {
+        filter.check_addr(host, stream.peer_addr()?.ip())?;
+    }
+    Ok(stream)
+}
+
+/// Check `host:port` against the egress filter of the service, if any.
+pub(crate) async fn check_egress(
+    weak_service: &ServiceWeakRef,
+    host: &str,
+    port: u16,
+) -> Result<()> {
+    let filter = weak_service
+        .upgrade()
+        .and_then(|service| service.egress_filter());
+    match filter {
+        Some(filter) => filter.check(host, port).await,
+        None => Ok(()),
+    }
+}
+
+/// Check the host of `url` against the egress filter of the service, if any.
+pub(crate) async fn check_egress_url(
+    weak_service: &ServiceWeakRef,
+    url: &::url::Url,
+) -> Result<()> {
+    let filter = weak_service
+        .upgrade()
+        .and_then(|service| service.egress_filter());
+    match filter {
+        Some(filter) => filter.check_url(url).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(
+            "*.Example.com".parse::<Rule>().unwrap(),
+            Rule::Host("*.example.com".into())
+        );
+        assert_eq!(
+            "10.0.0.0/8".parse::<Rule>().unwrap(),
+            Rule::Cidr {
+                network: ip("10.0.0.0"),
+                prefix: 8
+            }
+        );
+        assert_eq!(
+            "::1".parse::<Rule>().unwrap(),
+            Rule::Cidr {
+                network: ip("::1"),
+                prefix: 128
+            }
+        );
+        assert!("".parse::<Rule>().is_err());
+        assert!("10.0.0.0/33".parse::<Rule>().is_err());
+        assert!("example.com/8".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn prefixes() {
+        assert!(prefix_matches(&[10, 1, 2, 3], &[10, 200, 0, 1], 8));
+        assert!(!prefix_matches(&[10, 1, 2, 3], &[11, 1, 2, 3], 8));
+        assert!(prefix_matches(&[192, 168, 16, 0], &[192, 168, 31, 255], 20));
+        assert!(!prefix_matches(&[192, 168, 16, 0], &[192, 168, 32, 0], 20));
+        assert!(prefix_matches(&[1, 2, 3, 4], &[5, 6, 7, 8], 0));
+        assert!(prefix_matches(&[1, 2, 3, 4], &[1, 2, 3, 4], 32));
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches(b"*.example.com", b"api.example.com"));
+        assert!(glob_matches(b"*.example.com", b"a.b.example.com"));
+        assert!(!glob_matches(b"*.example.com", b"example.com"));
+        assert!(glob_matches(b"api-*.test", b"api-1.test"));
+        assert!(!glob_matches(b"example.com", b"example.com.evil"));
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let filter = EgressFilter::new()
+            .allow("*.example.com")
+            .unwrap()
+            .allow("10.0.0.0/8")
+            .unwrap()
+            .deny("admin.example.com")
+            .unwrap()
+            .deny("10.0.0.1")
+            .unwrap();
+        assert!(filter.check_addr("api.example.com", ip("1.1.1.1")).is_ok());
+        assert!(filter
+            .check_addr("admin.example.com", ip("1.1.1.1"))
+            .is_err());
+        assert!(filter.check_addr("10.1.2.3", ip("10.1.2.3")).is_ok());
+        assert!(filter.check_addr("10.0.0.1", ip("10.0.0.1")).is_err());
+        assert!(filter.check_addr("other.org", ip("1.1.1.1")).is_err());
+        // An IPv4-mapped IPv6 address matches the IPv4 rules
+        assert!(filter
+            .check_addr("::ffff:10.0.0.1", ip("::ffff:10.0.0.1"))
+            .is_err());
+    }
+
+    #[test]
+    fn no_allow_rules_allows_all_but_denied() {
+        let filter = EgressFilter::new().deny("169.254.0.0/16").unwrap();
+        assert!(filter
+            .check_addr("example.com", ip("93.184.216.34"))
+            .is_ok());
+        assert!(filter
+            .check_addr("metadata", ip("169.254.169.254"))
+            .is_err());
+    }
+}
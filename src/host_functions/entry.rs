@@ -0,0 +1,98 @@
+use anyhow::bail;
+use js::AsBytes;
+use pink_types::js::JsValue;
+use tokio::sync::oneshot;
+
+use super::*;
+use crate::runtime::AccountId;
+use crate::service::{OwnedJsValue, Resource};
+
+type ReplySender = oneshot::Sender<JsValue>;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("registerQueryHandler", register_query_handler)?;
+    ns.define_property_fn("registerMessageHandler", register_message_handler)?;
+    ns.define_property_fn("replyQuery", reply_query)?;
+    ns.define_property_fn("registerShutdownHandler", register_shutdown_handler)?;
+    Ok(())
+}
+
+/// Register `handler(id, payload, origin)` for incoming queries, which answers by calling
+/// `replyQuery(id, ok, value)`. The bootcode wraps it to take a plain `handler(payload, origin)`.
+#[js::host_call(with_context)]
+fn register_query_handler(service: ServiceRef, _this: js::Value, handler: OwnedJsValue) {
+    service.set_query_handler(handler)
+}
+
+#[js::host_call(with_context)]
+fn register_message_handler(service: ServiceRef, _this: js::Value, handler: OwnedJsValue) {
+    service.set_message_handler(handler)
+}
+
+/// Register the `handler()` called by `Service::shutdown`, which dispatches `beforeunload`.
+#[js::host_call(with_context)]
+fn register_shutdown_handler(service: ServiceRef, _this: js::Value, handler: OwnedJsValue) {
+    service.set_shutdown_handler(handler)
+}
+
+/// Reply to the pending query `id`. The value is converted the same way as `scriptOutput`,
+/// or reported as an exception if `ok` is false.
+#[js::host_call(with_context)]
+fn reply_query(
+    service: ServiceRef,
+    _this: js::Value,
+    id: u64,
+    ok: bool,
+    value: js::Value,
+) -> Result<()> {
+    let Some(res) = service.remove_resource(id) else {
+        bail!("Query {id} not found");
+    };
+    let Some(Ok(reply_tx)) = res.data.map(|data| data.downcast::<ReplySender>()) else {
+        bail!("Resource {id} is not a query");
+    };
+    let reply = if ok {
+        crate::js_eval::convert(&service, value)?
+    } else {
+        JsValue::Exception(value.to_string())
+    };
+    _ = reply_tx.send(reply);
+    Ok(())
+}
+
+/// Pass the query to the handler registered by the service and wait for its reply.
+pub(crate) async fn dispatch_query(
+    service: ServiceRef,
+    origin: Option<AccountId>,
+    payload: Vec<u8>,
+) -> JsValue {
+    let Some(Ok(handler)) = service.query_handler().map(TryInto::try_into) else {
+        return JsValue::Exception("No query handler registered".into());
+    };
+    let (reply_tx, reply_rx) = oneshot::channel::<JsValue>();
+    let id = service.push_resource(Resource::new(OwnedJsValue::Null, None));
+    service.set_resource_data(id, reply_tx);
+    let origin = origin.map(|origin| AsBytes(origin.to_vec()));
+    if let Err(err) = service.call_function(handler, (id, AsBytes(payload), origin)) {
+        service.remove_resource(id);
+        return JsValue::Exception(format!("Query handler failed: {err}"));
+    }
+    // Don't keep the service alive while waiting, dropping it cancels the query.
+    drop(service);
+    reply_rx
+        .await
+        .unwrap_or_else(|_| JsValue::Exception("The query was dropped without reply".into()))
+}
+
+/// Pass the message to the handler registered by the service.
+///
+/// Returns false if the service has no message handler.
+pub(crate) fn dispatch_message(service: ServiceRef, payload: Vec<u8>) -> Result<bool> {
+    let Some(Ok(handler)) = service.message_handler().map(TryInto::try_into) else {
+        return Ok(false);
+    };
+    if let Err(err) = service.call_function(handler, (AsBytes(payload),)) {
+        bail!("Message handler failed: {err}");
+    }
+    Ok(true)
+}
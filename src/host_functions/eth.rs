@@ -0,0 +1,335 @@
+//! EIP-1559 transaction signing and the ABI codec, see eth.js for the JS side and the RPC
+//! helpers.
+
+use super::{hash::do_hash, Result};
+use anyhow::{bail, Context};
+use js::{AsBytes, FromJsValue, ToJsValue};
+use sha3::Keccak256;
+
+mod abi;
+
+type Bytes = AsBytes<Vec<u8>>;
+
+/// The EIP-2718 type of EIP-1559 transactions.
+const TX_TYPE_EIP1559: u8 = 0x02;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("ethSignTransaction", eth_sign_transaction)?;
+    ns.define_property_fn("ethAddressFromKey", eth_address_from_key)?;
+    abi::setup(ns)?;
+    Ok(())
+}
+
+/// The fields of an EIP-1559 transaction, the quantities as `0x` prefixed hex strings.
+#[derive(FromJsValue, Debug)]
+#[qjsbind(rename_all = "camelCase")]
+struct Transaction {
+    chain_id: String,
+    nonce: String,
+    max_priority_fee_per_gas: String,
+    max_fee_per_gas: String,
+    gas_limit: String,
+    /// The recipient, none to deploy a contract.
+    to: Option<String>,
+    value: Option<String>,
+    data: Option<String>,
+    #[qjsbind(default)]
+    access_list: Vec<AccessListItem>,
+}
+
+#[derive(FromJsValue, Debug)]
+#[qjsbind(rename_all = "camelCase")]
+struct AccessListItem {
+    address: String,
+    #[qjsbind(default)]
+    storage_keys: Vec<String>,
+}
+
+#[derive(ToJsValue)]
+struct SignedTransaction {
+    /// The raw transaction for `eth_sendRawTransaction`.
+    raw: Bytes,
+    /// The transaction hash.
+    hash: Bytes,
+    /// The address of the signer.
+    from: Bytes,
+}
+
+/// An RLP item.
+#[derive(Clone)]
+enum Rlp {
+    Bytes(Vec<u8>),
+    List(Vec<Rlp>),
+}
+
+impl Rlp {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Rlp::Bytes(bytes) if bytes.len() == 1 && bytes[0] < 0x80 => out.push(bytes[0]),
+            Rlp::Bytes(bytes) => {
+                encode_length(bytes.len(), 0x80, out);
+                out.extend_from_slice(bytes);
+            }
+            Rlp::List(items) => {
+                let mut payload = vec![];
+                for item in items {
+                    item.encode(&mut payload);
+                }
+                encode_length(payload.len(), 0xc0, out);
+                out.extend_from_slice(&payload);
+            }
+        }
+    }
+}
+
+fn encode_length(len: usize, offset: u8, out: &mut Vec<u8>) {
+    if len <= 55 {
+        out.push(offset + len as u8);
+    } else {
+        let len = strip_zeros(&len.to_be_bytes());
+        out.push(offset + 55 + len.len() as u8);
+        out.extend_from_slice(&len);
+    }
+}
+
+fn strip_zeros(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+fn strip_hex_prefix(value: &str) -> &str {
+    value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value)
+}
+
+/// Decode hex bytes, an odd number of digits being an error.
+fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    hex::decode(strip_hex_prefix(value)).with_context(|| format!("Invalid hex {value:?}"))
+}
+
+/// A quantity as its minimal big endian bytes, zero being empty.
+///
+/// Quantities are numbers, so they may have an odd number of digits, e.g. `0x1`.
+fn quantity(value: &str, name: &str) -> Result<Rlp> {
+    let digits = strip_hex_prefix(value);
+    let padded = if digits.len() % 2 == 1 {
+        format!("0{digits}")
+    } else {
+        digits.into()
+    };
+    let bytes = strip_zeros(&decode_hex(&padded).with_context(|| format!("Invalid {name}"))?);
+    if bytes.len() > 32 {
+        bail!("{name} exceeds 256 bits");
+    }
+    Ok(Rlp::Bytes(bytes))
+}
+
+fn fixed_bytes(value: &str, len: usize, name: &str) -> Result<Vec<u8>> {
+    let bytes = decode_hex(value).with_context(|| format!("Invalid {name}"))?;
+    if bytes.len() != len {
+        bail!("{name} must be {len} bytes, got {}", bytes.len());
+    }
+    Ok(bytes)
+}
+
+fn signing_key(key: &[u8]) -> Result<k256::ecdsa::SigningKey> {
+    k256::ecdsa::SigningKey::from_slice(key).context("Invalid secp256k1 key")
+}
+
+/// The address is the last 20 bytes of the keccak256 hash of the uncompressed public key.
+fn address_of(key: &k256::ecdsa::SigningKey) -> Vec<u8> {
+    let public = key.verifying_key().to_encoded_point(false);
+    do_hash::<Keccak256>(&public.as_bytes()[1..])[12..].to_vec()
+}
+
+/// The Ethereum address of the secp256k1 key, e.g. one from `deriveSecret`.
+#[js::host_call]
+fn eth_address_from_key(key: Bytes) -> Result<Bytes> {
+    Ok(address_of(&signing_key(&key.0)?).into())
+}
+
+/// Sign the EIP-1559 transaction `tx` with the 32-byte secp256k1 `key`.
+#[js::host_call]
+fn eth_sign_transaction(tx: Transaction, key: Bytes) -> Result<SignedTransaction> {
+    sign_transaction(&tx, &key.0)
+}
+
+fn sign_transaction(tx: &Transaction, key: &[u8]) -> Result<SignedTransaction> {
+    let key = signing_key(key)?;
+    let to = match &tx.to {
+        Some(to) => fixed_bytes(to, 20, "to")?,
+        None => vec![],
+    };
+    let data = match &tx.data {
+        Some(data) => decode_hex(data).context("Invalid data")?,
+        None => vec![],
+    };
+    let access_list = tx
+        .access_list
+        .iter()
+        .map(|item| {
+            let keys = item
+                .storage_keys
+                .iter()
+                .map(|key| Ok(Rlp::Bytes(fixed_bytes(key, 32, "storage key")?)))
+                .collect::<Result<_>>()?;
+            Ok(Rlp::List(vec![
+                Rlp::Bytes(fixed_bytes(&item.address, 20, "access list address")?),
+                Rlp::List(keys),
+            ]))
+        })
+        .collect::<Result<_>>()?;
+    let mut fields = vec![
+        quantity(&tx.chain_id, "chainId")?,
+        quantity(&tx.nonce, "nonce")?,
+        quantity(&tx.max_priority_fee_per_gas, "maxPriorityFeePerGas")?,
+        quantity(&tx.max_fee_per_gas, "maxFeePerGas")?,
+        quantity(&tx.gas_limit, "gasLimit")?,
+        Rlp::Bytes(to),
+        quantity(tx.value.as_deref().unwrap_or("0x0"), "value")?,
+        Rlp::Bytes(data),
+        Rlp::List(access_list),
+    ];
+    let mut payload = vec![TX_TYPE_EIP1559];
+    Rlp::List(fields.clone()).encode(&mut payload);
+    let (signature, recovery_id) = key
+        .sign_prehash_recoverable(&do_hash::<Keccak256>(&payload))
+        .context("Failed to sign")?;
+    let (r, s) = signature.split_bytes();
+    fields.push(Rlp::Bytes(strip_zeros(&[recovery_id.is_y_odd() as u8])));
+    fields.push(Rlp::Bytes(strip_zeros(&r)));
+    fields.push(Rlp::Bytes(strip_zeros(&s)));
+    let mut raw = vec![TX_TYPE_EIP1559];
+    Rlp::List(fields).encode(&mut raw);
+    Ok(SignedTransaction {
+        hash: do_hash::<Keccak256>(&raw).into(),
+        raw: raw.into(),
+        from: address_of(&key).into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [0x46; 32];
+
+    fn transaction() -> Transaction {
+        Transaction {
+            chain_id: "0x1".into(),
+            nonce: "0x9".into(),
+            max_priority_fee_per_gas: "0x77359400".into(),
+            max_fee_per_gas: "0x174876e800".into(),
+            gas_limit: "0x5208".into(),
+            to: Some(format!("0x{}", "35".repeat(20))),
+            value: Some("0xde0b6b3a7640000".into()),
+            data: None,
+            access_list: vec![AccessListItem {
+                address: format!("0x{}", "35".repeat(20)),
+                storage_keys: vec![format!("0x{:064x}", 1)],
+            }],
+        }
+    }
+
+    /// The example of EIP-155, which shares the RLP encoding, the hashing and the deterministic
+    /// signatures with the EIP-1559 transactions.
+    #[test]
+    fn eip155_example() {
+        let key = signing_key(&KEY).unwrap();
+        assert_eq!(
+            hex::encode(address_of(&key)),
+            "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+        let mut payload = vec![];
+        Rlp::List(vec![
+            quantity("0x9", "nonce").unwrap(),
+            quantity("0x4a817c800", "gasPrice").unwrap(),
+            quantity("0x5208", "gasLimit").unwrap(),
+            Rlp::Bytes(vec![0x35; 20]),
+            quantity("0xde0b6b3a7640000", "value").unwrap(),
+            Rlp::Bytes(vec![]),
+            quantity("0x1", "chainId").unwrap(),
+            Rlp::Bytes(vec![]),
+            Rlp::Bytes(vec![]),
+        ])
+        .encode(&mut payload);
+        assert_eq!(
+            hex::encode(&payload),
+            "ec098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080018080"
+        );
+        let hash = do_hash::<Keccak256>(&payload);
+        assert_eq!(
+            hex::encode(&hash),
+            "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53"
+        );
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        let (r, s) = signature.split_bytes();
+        assert!(!recovery_id.is_y_odd());
+        assert_eq!(
+            hex::encode(r),
+            "28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276"
+        );
+        assert_eq!(
+            hex::encode(s),
+            "67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+    }
+
+    #[test]
+    fn sign_eip1559_transaction() {
+        let signed = sign_transaction(&transaction(), &KEY).unwrap();
+        assert_eq!(
+            hex::encode(&signed.raw.0),
+            "02f8ac0109847735940085174876e800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             80f838f7943535353535353535353535353535353535353535e1a000000000000000000000000000000000000000000000\
+             0000000000000000000101a0dd2fbb2fa4c992ba00a65fa03d2e6b997f07cf06a3d5fe00117f5c02e005c8eaa03df660f0\
+             d9428ed616bf0359fc159b466d9c5fc479344ac26d3208b699348917"
+        );
+        assert_eq!(
+            hex::encode(&signed.hash.0),
+            "ee5daa1e382ef5e988c01fff6fcc526750ad00f47f424a04cb3709de768d07c4"
+        );
+        assert_eq!(
+            hex::encode(&signed.from.0),
+            "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+    }
+
+    #[test]
+    fn sign_contract_creation() {
+        let tx = Transaction {
+            chain_id: "0x5".into(),
+            nonce: "0x0".into(),
+            max_priority_fee_per_gas: "0x1".into(),
+            max_fee_per_gas: "0x3b9aca00".into(),
+            gas_limit: "0x186a0".into(),
+            to: None,
+            value: None,
+            data: Some("0x6080604052".into()),
+            access_list: vec![],
+        };
+        let signed = sign_transaction(&tx, &KEY).unwrap();
+        assert_eq!(
+            hex::encode(&signed.raw.0),
+            "02f858058001843b9aca00830186a08080856080604052c001a0c05cf43a5242fc59d977ab6d74c79d584c112f57c122\
+             a5e807f46b774415e1aba0509f6888ebf9202a389d6a1937e615528a3e241c3b236bddf73606afd9c52137"
+        );
+        assert_eq!(
+            hex::encode(&signed.hash.0),
+            "9d6b110cece85e6246e8f665fa27be64ced475308e9077a66667bbd594b3bd23"
+        );
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let mut tx = transaction();
+        tx.to = Some("0x3535".into());
+        assert!(sign_transaction(&tx, &KEY).is_err());
+        let mut tx = transaction();
+        tx.nonce = format!("0x1{}", "0".repeat(64));
+        assert!(sign_transaction(&tx, &KEY).is_err());
+        assert!(sign_transaction(&transaction(), &[0; 32]).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+//! EVM ABI encoding, see `abiEncode` and `abiDecode` in eth.js.
+//!
+//! Values cross as JSON: integers as decimal or `0x` hex strings (or numbers when encoding),
+//! bytes and addresses as `0x` hex strings, tuples and arrays as arrays. The decoded integers
+//! and bytes are tagged as `{"$bigint": "..."}` and `{"$bytes": "0x..."}` for the JS side to
+//! turn them into BigInts and Uint8Arrays.
+
+use super::*;
+use anyhow::anyhow;
+use ethabi::{param_type::Reader, param_type::Writer, ParamType, Token, Uint};
+use serde_json::{json, Value as JsonValue};
+
+pub(super) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("abiEncodeJson", abi_encode_json)?;
+    ns.define_property_fn("abiDecodeJson", abi_decode_json)?;
+    Ok(())
+}
+
+/// Parse a parenthesized list of types such as `(uint256,(address,bytes)[])`.
+fn parse_types(types: &str) -> Result<Vec<ParamType>> {
+    let types = types.trim();
+    if types.replace(char::is_whitespace, "") == "()" {
+        return Ok(vec![]);
+    }
+    match Reader::read(types).map_err(|err| anyhow!("Invalid types {types:?}: {err}"))? {
+        ParamType::Tuple(types) => Ok(types),
+        _ => bail!("Expected a parenthesized list of types, got {types:?}"),
+    }
+}
+
+/// Encode `values` for the function `signature`, e.g. `transfer(address,uint256)`, prefixed
+/// with its selector. A signature without a name such as `(address,uint256)` encodes the
+/// parameters only.
+#[js::host_call]
+fn abi_encode_json(signature: String, values: String) -> Result<Bytes> {
+    Ok(encode_json(&signature, &values)?.into())
+}
+
+fn encode_json(signature: &str, values: &str) -> Result<Vec<u8>> {
+    let (name, types) = match signature.find('(') {
+        Some(pos) => signature.split_at(pos),
+        None => bail!("Invalid signature {signature:?}, expected name(type,...)"),
+    };
+    let types = parse_types(types)?;
+    let values: JsonValue = serde_json::from_str(values).context("Invalid values")?;
+    let JsonValue::Array(values) = values else {
+        bail!("The values must be an array");
+    };
+    if values.len() != types.len() {
+        bail!(
+            "Expected {} values for {signature}, got {}",
+            types.len(),
+            values.len()
+        );
+    }
+    let tokens = types
+        .iter()
+        .zip(&values)
+        .enumerate()
+        .map(|(i, (ty, value))| to_token(ty, value).with_context(|| format!("Invalid value {i}")))
+        .collect::<Result<Vec<_>>>()?;
+    let mut out = vec![];
+    let name = name.trim();
+    if !name.is_empty() {
+        out.extend_from_slice(&ethabi::short_signature(name, &types));
+    }
+    out.extend_from_slice(&ethabi::encode(&tokens));
+    Ok(out)
+}
+
+/// Decode `bytes` as the parenthesized list of `types`, returning the values as JSON.
+#[js::host_call]
+fn abi_decode_json(types: String, bytes: Bytes) -> Result<String> {
+    decode_json(&types, &bytes.0)
+}
+
+fn decode_json(types: &str, bytes: &[u8]) -> Result<String> {
+    let types = parse_types(types)?;
+    let tokens = ethabi::decode(&types, bytes).map_err(|err| anyhow!("Failed to decode: {err}"))?;
+    let values = tokens.into_iter().map(from_token).collect();
+    Ok(JsonValue::Array(values).to_string())
+}
+
+/// The canonical name of a type, e.g. in error messages.
+fn type_name(ty: &ParamType) -> String {
+    Writer::write(ty)
+}
+
+fn to_token(ty: &ParamType, value: &JsonValue) -> Result<Token> {
+    let expected = || anyhow!("Expected {}, got {value}", type_name(ty));
+    let str_value = || value.as_str().ok_or_else(expected);
+    Ok(match ty {
+        ParamType::Address => {
+            let bytes = decode_hex(str_value()?)?;
+            if bytes.len() != 20 {
+                bail!("Addresses must be 20 bytes, got {}", bytes.len());
+            }
+            Token::Address(ethabi::Address::from_slice(&bytes))
+        }
+        ParamType::Bytes => Token::Bytes(decode_hex(str_value()?)?),
+        ParamType::FixedBytes(len) => {
+            let bytes = decode_hex(str_value()?)?;
+            if bytes.len() != *len {
+                bail!("Expected {len} bytes, got {}", bytes.len());
+            }
+            Token::FixedBytes(bytes)
+        }
+        ParamType::Uint(bits) => {
+            let (negative, value) = parse_int(value).ok_or_else(expected)?;
+            if negative || value.bits() > *bits {
+                bail!("{value} is out of the range of uint{bits}");
+            }
+            Token::Uint(value)
+        }
+        ParamType::Int(bits) => {
+            let (negative, magnitude) = parse_int(value).ok_or_else(expected)?;
+            let limit = Uint::one() << (*bits - 1);
+            let in_range = if negative {
+                magnitude <= limit
+            } else {
+                magnitude < limit
+            };
+            if !in_range {
+                bail!("{value} is out of the range of int{bits}");
+            }
+            Token::Int(if negative {
+                (!magnitude).overflowing_add(Uint::one()).0
+            } else {
+                magnitude
+            })
+        }
+        ParamType::Bool => Token::Bool(value.as_bool().ok_or_else(expected)?),
+        ParamType::String => Token::String(str_value()?.into()),
+        ParamType::Array(item) => {
+            Token::Array(to_tokens(item, value.as_array().ok_or_else(expected)?)?)
+        }
+        ParamType::FixedArray(item, len) => {
+            let values = value.as_array().ok_or_else(expected)?;
+            if values.len() != *len {
+                bail!("Expected {len} items, got {}", values.len());
+            }
+            Token::FixedArray(to_tokens(item, values)?)
+        }
+        ParamType::Tuple(types) => {
+            let values = value.as_array().ok_or_else(expected)?;
+            if values.len() != types.len() {
+                bail!("Expected {} fields, got {}", types.len(), values.len());
+            }
+            let tokens = types
+                .iter()
+                .zip(values)
+                .map(|(ty, value)| to_token(ty, value))
+                .collect::<Result<_>>()?;
+            Token::Tuple(tokens)
+        }
+    })
+}
+
+fn to_tokens(ty: &ParamType, values: &[JsonValue]) -> Result<Vec<Token>> {
+    values.iter().map(|value| to_token(ty, value)).collect()
+}
+
+/// Parse an integer given as a number or a decimal or `0x` hex string, as its sign and magnitude.
+fn parse_int(value: &JsonValue) -> Option<(bool, Uint)> {
+    let text = match value {
+        JsonValue::Number(n) => n.to_string(),
+        JsonValue::String(s) => s.trim().into(),
+        _ => return None,
+    };
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.as_str()),
+    };
+    let magnitude = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => Uint::from_str_radix(hex, 16).ok()?,
+        None => Uint::from_dec_str(digits).ok()?,
+    };
+    Some((negative && !magnitude.is_zero(), magnitude))
+}
+
+/// Tokens are self-describing, the `int`s being sign-extended to 256 bits.
+fn from_token(token: Token) -> JsonValue {
+    let to_hex = |bytes: &[u8]| format!("0x{}", hex::encode(bytes));
+    match token {
+        Token::Address(address) => JsonValue::String(to_hex(address.as_bytes())),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => json!({ "$bytes": to_hex(&bytes) }),
+        Token::Uint(value) => json!({ "$bigint": value.to_string() }),
+        Token::Int(value) if value.bit(255) => {
+            let magnitude = (!value).overflowing_add(Uint::one()).0;
+            json!({ "$bigint": format!("-{magnitude}") })
+        }
+        Token::Int(value) => json!({ "$bigint": value.to_string() }),
+        Token::Bool(value) => JsonValue::Bool(value),
+        Token::String(value) => JsonValue::String(value),
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+            JsonValue::Array(tokens.into_iter().map(from_token).collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> String {
+        words.iter().map(|word| format!("{word:0>64}")).collect()
+    }
+
+    /// The examples of the Solidity ABI specification.
+    #[test]
+    fn solidity_spec_examples() {
+        let encoded = encode_json("baz(uint32,bool)", "[69, true]").unwrap();
+        assert_eq!(
+            hex::encode(encoded),
+            format!("cdcd77c0{}", words(&["45", "1"]))
+        );
+        let encoded = encode_json(
+            "sam(bytes,bool,uint256[])",
+            r#"["0x64617665", true, [1, 2, 3]]"#,
+        )
+        .unwrap();
+        let dave = format!("{:0<64}", "64617665");
+        assert_eq!(
+            hex::encode(encoded),
+            format!(
+                "a5643bf2{}",
+                words(&["60", "1", "a0", "4", &dave, "3", "1", "2", "3"])
+            )
+        );
+    }
+
+    #[test]
+    fn encode_erc20_transfer() {
+        let encoded = encode_json(
+            "transfer(address,uint256)",
+            &format!(r#"["0x{}", "0xde0b6b3a7640000"]"#, "35".repeat(20)),
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode(encoded),
+            format!("a9059cbb{}", words(&[&"35".repeat(20), "de0b6b3a7640000"]))
+        );
+    }
+
+    #[test]
+    fn encode_signed_integers() {
+        let encoded = encode_json("(int8,int256)", r#"[-1, "-0x80"]"#).unwrap();
+        assert_eq!(
+            hex::encode(encoded),
+            "ff".repeat(32) + &format!("{:f>64}", "80")
+        );
+        assert!(encode_json("(int8)", "[128]").is_err());
+        assert!(encode_json("(int8)", "[-129]").is_err());
+        assert!(encode_json("(uint8)", "[256]").is_err());
+        assert!(encode_json("(uint8)", "[-1]").is_err());
+    }
+
+    #[test]
+    fn decode_tagged_values() {
+        let bytes = hex::decode(words(&[
+            "60",
+            "1",
+            "a0",
+            "4",
+            &format!("{:0<64}", "64617665"),
+            "3",
+            "1",
+            "2",
+            "3",
+        ]))
+        .unwrap();
+        let decoded: JsonValue =
+            serde_json::from_str(&decode_json("(bytes,bool,uint256[])", &bytes).unwrap()).unwrap();
+        assert_eq!(
+            decoded,
+            json!([
+                { "$bytes": "0x64617665" },
+                true,
+                [{ "$bigint": "1" }, { "$bigint": "2" }, { "$bigint": "3" }]
+            ])
+        );
+        let decoded = decode_json(
+            "(int256,address)",
+            &hex::decode("ff".repeat(32) + &words(&[&"35".repeat(20)])).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            decoded,
+            format!(r#"[{{"$bigint":"-1"}},"0x{}"]"#, "35".repeat(20))
+        );
+    }
+}
@@ -0,0 +1,386 @@
+//! The `fs` namespace, a sandboxed filesystem the scripts can keep artifacts in.
+//!
+//! Paths are `/`-separated and resolved against the root of the filesystem of the service, `..`
+//! can't leave it. Directories are created as needed by `writeFile`.
+
+use anyhow::{bail, Context};
+use core::cell::{Cell, RefCell};
+use js::{AsBytes, BytesOrString, ToJsValue};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use super::*;
+
+/// The default quota of the in-memory filesystem of a service.
+pub(crate) const DEFAULT_MEMORY_QUOTA: u64 = 16 << 20;
+
+/// The metadata returned by `stat`.
+#[derive(ToJsValue, Debug, Clone, PartialEq, Eq)]
+#[qjsbind(rename_all = "camelCase")]
+pub struct FileStat {
+    pub is_file: bool,
+    pub is_directory: bool,
+    pub size: u64,
+    /// Milliseconds since the unix epoch, if known.
+    pub mtime_ms: Option<u64>,
+}
+
+/// The storage behind the `fs` namespace, see
+/// [`Service::set_file_system`](crate::Service::set_file_system).
+///
+/// The paths are given normalized, relative to the root and without `.` or `..` components,
+/// the empty path being the root itself.
+pub trait FileSystem {
+    fn read(&self, path: &str) -> Result<Vec<u8>>;
+
+    /// Create or replace the file at `path`, creating its parent directories.
+    fn write(&self, path: &str, data: &[u8]) -> Result<()>;
+
+    /// The names of the entries of the directory at `path`, sorted.
+    fn read_dir(&self, path: &str) -> Result<Vec<String>>;
+
+    fn stat(&self, path: &str) -> Result<FileStat>;
+
+    /// Remove the file or the directory with its content at `path`.
+    fn remove(&self, path: &str) -> Result<()>;
+}
+
+/// A filesystem kept in memory, holding up to `quota` bytes of file content.
+pub struct MemoryFs {
+    files: RefCell<BTreeMap<String, MemoryFile>>,
+    quota: u64,
+    used: Cell<u64>,
+}
+
+struct MemoryFile {
+    data: Vec<u8>,
+    mtime_ms: u64,
+}
+
+impl MemoryFs {
+    pub fn new(quota: u64) -> Self {
+        Self {
+            files: Default::default(),
+            quota,
+            used: Cell::new(0),
+        }
+    }
+
+    /// The files under the directory `path`, by their paths.
+    fn children<'a>(
+        files: &'a BTreeMap<String, MemoryFile>,
+        path: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a MemoryFile)> + 'a {
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{path}/")
+        };
+        files
+            .range(prefix.clone()..)
+            .take_while(move |(name, _)| name.starts_with(&prefix))
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        match self.files.borrow().get(path) {
+            Some(file) => Ok(file.data.clone()),
+            None => bail!("No such file: {path}"),
+        }
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        if path.is_empty() {
+            bail!("Is a directory: /");
+        }
+        let mut files = self.files.borrow_mut();
+        if Self::children(&files, path).next().is_some() {
+            bail!("Is a directory: {path}");
+        }
+        let mut parent = path;
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            if files.contains_key(dir) {
+                bail!("Not a directory: {dir}");
+            }
+            parent = dir;
+        }
+        let old_size = files.get(path).map_or(0, |file| file.data.len() as u64);
+        let used = self.used.get() - old_size + data.len() as u64;
+        if used > self.quota {
+            bail!("Filesystem quota of {} bytes exceeded", self.quota);
+        }
+        self.used.set(used);
+        let file = MemoryFile {
+            data: data.to_vec(),
+            mtime_ms: now_ms(),
+        };
+        files.insert(path.into(), file);
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<String>> {
+        let files = self.files.borrow();
+        if files.contains_key(path) {
+            bail!("Not a directory: {path}");
+        }
+        let prefix_len = if path.is_empty() { 0 } else { path.len() + 1 };
+        let mut names: Vec<String> = Self::children(&files, path)
+            .map(|(name, _)| {
+                let name = &name[prefix_len..];
+                name.split('/').next().unwrap_or(name).to_string()
+            })
+            .collect();
+        if names.is_empty() && !path.is_empty() {
+            bail!("No such directory: {path}");
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    fn stat(&self, path: &str) -> Result<FileStat> {
+        let files = self.files.borrow();
+        if let Some(file) = files.get(path) {
+            return Ok(FileStat {
+                is_file: true,
+                is_directory: false,
+                size: file.data.len() as u64,
+                mtime_ms: Some(file.mtime_ms),
+            });
+        }
+        let mtime_ms = Self::children(&files, path)
+            .map(|(_, file)| file.mtime_ms)
+            .max();
+        if mtime_ms.is_none() && !path.is_empty() {
+            bail!("No such file or directory: {path}");
+        }
+        Ok(FileStat {
+            is_file: false,
+            is_directory: true,
+            size: 0,
+            mtime_ms,
+        })
+    }
+
+    fn remove(&self, path: &str) -> Result<()> {
+        let mut files = self.files.borrow_mut();
+        let mut removed: Vec<String> = Self::children(&files, path)
+            .map(|(name, _)| name.clone())
+            .collect();
+        if files.contains_key(path) {
+            removed.push(path.into());
+        }
+        if removed.is_empty() {
+            bail!("No such file or directory: {path}");
+        }
+        for name in removed {
+            if let Some(file) = files.remove(&name) {
+                self.used.set(self.used.get() - file.data.len() as u64);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A directory of the host filesystem, optionally limited to `quota` bytes of file content.
+///
+/// Symlinks are followed only if they stay inside the directory.
+pub struct DirFs {
+    root: PathBuf,
+    quota: Option<u64>,
+    /// The size of the files in the directory, computed when the quota is set.
+    used: Cell<u64>,
+}
+
+impl DirFs {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("Invalid directory {}", root.display()))?;
+        if !root.is_dir() {
+            bail!("Not a directory: {}", root.display());
+        }
+        Ok(Self {
+            root,
+            quota: None,
+            used: Cell::new(0),
+        })
+    }
+
+    pub fn with_quota(mut self, quota: u64) -> Result<Self> {
+        self.used.set(dir_size(&self.root)?);
+        self.quota = Some(quota);
+        Ok(self)
+    }
+
+    /// The host path of `path`, checking that existing parts don't escape the root via symlinks.
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let full = self.root.join(path);
+        let mut existing = full.as_path();
+        while existing.symlink_metadata().is_err() {
+            existing = existing.parent().unwrap_or(&self.root);
+        }
+        if !existing.canonicalize()?.starts_with(&self.root) {
+            bail!("Path escapes the filesystem root: {path}");
+        }
+        Ok(full)
+    }
+}
+
+impl FileSystem for DirFs {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        std::fs::read(self.resolve(path)?).with_context(|| format!("Failed to read {path}"))
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let full = self.resolve(path)?;
+        let old_size = std::fs::metadata(&full).map_or(0, |meta| meta.len());
+        let used = self.used.get().saturating_sub(old_size) + data.len() as u64;
+        if let Some(quota) = self.quota {
+            if used > quota {
+                bail!("Filesystem quota of {quota} bytes exceeded");
+            }
+        }
+        if let Some(parent) = full.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create the parent of {path}"))?;
+        }
+        std::fs::write(&full, data).with_context(|| format!("Failed to write {path}"))?;
+        self.used.set(used);
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<String>> {
+        let entries = std::fs::read_dir(self.resolve(path)?)
+            .with_context(|| format!("Failed to read directory {path}"))?;
+        let mut names = entries
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn stat(&self, path: &str) -> Result<FileStat> {
+        let meta = std::fs::metadata(self.resolve(path)?)
+            .with_context(|| format!("Failed to stat {path}"))?;
+        let mtime_ms = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|elapsed| elapsed.as_millis() as u64);
+        Ok(FileStat {
+            is_file: meta.is_file(),
+            is_directory: meta.is_dir(),
+            size: meta.len(),
+            mtime_ms,
+        })
+    }
+
+    fn remove(&self, path: &str) -> Result<()> {
+        if path.is_empty() {
+            bail!("Can not remove the filesystem root");
+        }
+        let full = self.resolve(path)?;
+        let size = dir_size(&full)?;
+        let result = if full.is_dir() {
+            std::fs::remove_dir_all(&full)
+        } else {
+            std::fs::remove_file(&full)
+        };
+        result.with_context(|| format!("Failed to remove {path}"))?;
+        self.used.set(self.used.get().saturating_sub(size));
+        Ok(())
+    }
+}
+
+/// The total size of the files under `path`, or of `path` itself if it's a file.
+fn dir_size(path: &Path) -> Result<u64> {
+    let meta = std::fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Normalize a script given path, rejecting the ones escaping the root.
+fn normalize(path: &str) -> Result<String> {
+    let mut parts = vec![];
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    bail!("Path escapes the filesystem root: {path}");
+                }
+            }
+            part => parts.push(part),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+pub(crate) fn setup(ns: &js::Value, ctx: &js::Context) -> Result<()> {
+    let fs = js::Value::new_object(ctx);
+    fs.define_property_fn("readFile", fs_read_file)?;
+    fs.define_property_fn("readFileString", fs_read_file_string)?;
+    fs.define_property_fn("writeFile", fs_write_file)?;
+    fs.define_property_fn("readdir", fs_readdir)?;
+    fs.define_property_fn("stat", fs_stat)?;
+    fs.define_property_fn("remove", fs_remove)?;
+    ns.set_property("fs", &fs)?;
+    Ok(())
+}
+
+#[js::host_call(with_context)]
+fn fs_read_file(service: ServiceRef, _this: js::Value, path: String) -> Result<AsBytes<Vec<u8>>> {
+    Ok(AsBytes(service.file_system().read(&normalize(&path)?)?))
+}
+
+/// Like `readFile` but decodes the content as utf-8.
+#[js::host_call(with_context)]
+fn fs_read_file_string(service: ServiceRef, _this: js::Value, path: String) -> Result<String> {
+    let data = service.file_system().read(&normalize(&path)?)?;
+    String::from_utf8(data).context("File content is not utf-8")
+}
+
+#[js::host_call(with_context)]
+fn fs_write_file(
+    service: ServiceRef,
+    _this: js::Value,
+    path: String,
+    data: BytesOrString,
+) -> Result<()> {
+    service
+        .file_system()
+        .write(&normalize(&path)?, data.as_ref())
+}
+
+#[js::host_call(with_context)]
+fn fs_readdir(service: ServiceRef, _this: js::Value, path: String) -> Result<Vec<String>> {
+    service.file_system().read_dir(&normalize(&path)?)
+}
+
+#[js::host_call(with_context)]
+fn fs_stat(service: ServiceRef, _this: js::Value, path: String) -> Result<FileStat> {
+    service.file_system().stat(&normalize(&path)?)
+}
+
+#[js::host_call(with_context)]
+fn fs_remove(service: ServiceRef, _this: js::Value, path: String) -> Result<()> {
+    service.file_system().remove(&normalize(&path)?)
+}
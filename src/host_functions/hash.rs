@@ -11,21 +11,25 @@ pub(crate) fn setup(ns: &js::Value) -> Result<()> {
     Ok(())
 }
 
-fn do_hash<T: Digest>(data: impl AsRef<[u8]>) -> Vec<u8> {
+pub(crate) fn do_hash<T: Digest>(data: impl AsRef<[u8]>) -> Vec<u8> {
     let mut hasher = T::new();
     hasher.update(data.as_ref());
     hasher.finalize().to_vec()
 }
 
+/// Hash `data` with the algorithm named as in `Sidevm.hash`.
+pub(crate) fn hash_with(algorithm: &str, data: impl AsRef<[u8]>) -> Result<Vec<u8>> {
+    Ok(match algorithm {
+        "sha256" => do_hash::<sha2::Sha256>(data),
+        "keccak256" => do_hash::<sha3::Keccak256>(data),
+        "blake2b128" => do_hash::<Blake2b<U16>>(data),
+        "blake2b256" => do_hash::<Blake2b<U32>>(data),
+        "blake2b512" => do_hash::<Blake2b<U64>>(data),
+        _ => bail!("Unsupported hash algorithm: {algorithm}"),
+    })
+}
+
 #[js::host_call]
 fn hash(algorithm: js::JsString, message: js::BytesOrString) -> Result<AsBytes<Vec<u8>>> {
-    let hash = match algorithm.as_str() {
-        "sha256" => do_hash::<sha2::Sha256>(message),
-        "keccak256" => do_hash::<sha3::Keccak256>(message),
-        "blake2b128" => do_hash::<Blake2b<U16>>(message),
-        "blake2b256" => do_hash::<Blake2b<U32>>(message),
-        "blake2b512" => do_hash::<Blake2b<U64>>(message),
-        _ => bail!("Unsupported hash algorithm: {}", algorithm.as_str()),
-    };
-    Ok(hash.into())
+    Ok(hash_with(algorithm.as_str(), message)?.into())
 }
@@ -148,7 +148,7 @@ fn http_receive_body(
             }
         },
         (),
-    );
+    )?;
     Ok(id)
 }
 
@@ -184,9 +184,11 @@ fn http_make_writer(
                     warn!("Failed to report write result: {err:?}");
                 }
             }
+            // Signal the end of the response body, the read half may still be alive.
+            let _ = write_half.shutdown().await;
         },
         (),
-    );
+    )?;
     Ok(js::Value::new_opaque_object(service.context(), tx))
 }
 
@@ -1,16 +1,32 @@
 use anyhow::{anyhow, Context};
 use log::info;
-use std::{
-    collections::{BTreeMap, BTreeSet},
-    time::Duration,
-};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeMap, rc::Rc, time::Duration};
 
-use crate::{runtime::time::sleep, service::OwnedJsValue};
+use super::buffer::ExternalBuffer;
+use super::rate_limit::RateLimited;
+use crate::{
+    runtime::{monotonic_now, time::sleep},
+    service::OwnedJsValue,
+};
 use js::{AsBytes, Error as ValueError, FromJsValue, ToJsValue};
 
 use super::*;
 
-#[derive(Debug, Default)]
+#[cfg(all(feature = "js-http-decompress", not(feature = "web")))]
+mod decompress;
+mod grpc_web;
+#[cfg(feature = "js-cache")]
+mod http_cache;
+mod json_rpc;
+mod multipart;
+#[cfg(feature = "js-http-proxy")]
+mod proxy;
+#[cfg(feature = "js-http-proxy")]
+pub(crate) use proxy::ProxyClient;
+#[cfg(all(feature = "native", unix))]
+mod unix;
+
+#[derive(Debug, Default, Clone)]
 pub struct Headers {
     pairs: Vec<(String, String)>,
 }
@@ -51,6 +67,22 @@ impl From<Headers> for Vec<(String, String)> {
     }
 }
 
+impl Headers {
+    fn contains(&self, name: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k.eq_ignore_ascii_case(name))
+    }
+
+    /// Append the `defaults` not set by the request.
+    fn with_defaults(mut self, defaults: Vec<(String, String)>) -> Self {
+        let missing: Vec<_> = defaults
+            .into_iter()
+            .filter(|(k, _)| !self.contains(k))
+            .collect();
+        self.pairs.extend(missing);
+        self
+    }
+}
+
 impl FromIterator<(String, String)> for Headers {
     fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
         Self {
@@ -70,8 +102,235 @@ pub struct HttpRequest {
     #[qjsbind(default, as_bytes)]
     body: Vec<u8>,
     text_body: Option<String>,
+    /// Fields to send as a `multipart/form-data` body, overriding `body` and `textBody`.
+    form_data: Option<Vec<multipart::FormField>>,
+    /// Timeout of the whole request including the retries.
     #[qjsbind(default = "default_timeout")]
     timeout_ms: u64,
+    /// Time allowed for each attempt to connect and receive the response head. Unlimited if not
+    /// set.
+    connect_timeout_ms: Option<u64>,
+    /// Longest time to wait for the next chunk of the response body. Unlimited if not set.
+    read_timeout_ms: Option<u64>,
+    /// How many times to retry a request that fails before the response head is received or
+    /// whose status is in `retryOn`.
+    ///
+    /// Only requests with an idempotent method are retried, unless `retryNonIdempotent` is set.
+    /// At most 10 retries are made whatever the value.
+    #[qjsbind(default)]
+    retries: u32,
+    #[qjsbind(default = "default_retry_on")]
+    retry_on: Vec<u16>,
+    /// Retry the requests of methods such as POST too, which the server may have processed before
+    /// the attempt failed.
+    #[qjsbind(default)]
+    retry_non_idempotent: bool,
+    /// Delay before the first retry, doubled for each following one.
+    #[qjsbind(default = "default_retry_delay")]
+    retry_delay_ms: u64,
+    /// Maximum number of body bytes to receive. Unlimited if not set.
+    max_response_size: Option<u64>,
+    /// Emit `progress` events at most this often while the response body is received. No
+    /// `progress` events if not set.
+    progress_interval_ms: Option<u64>,
+    #[qjsbind(default)]
+    redirect: RedirectPolicy,
+    #[qjsbind(default = "default_max_redirects")]
+    max_redirects: u32,
+    /// Decompress gzip, deflate or br encoded response bodies.
+    ///
+    /// The web backend always decompresses as the browser does it by itself.
+    #[qjsbind(default)]
+    decompress: bool,
+    /// How to decode response header values that are not valid UTF-8.
+    #[qjsbind(default)]
+    header_encoding: HeaderEncoding,
+    /// Proxy url such as `http://127.0.0.1:8080` or `socks5://127.0.0.1:9050`.
+    ///
+    /// Overrides the default proxy of the service, an empty string connects directly. Only
+    /// allowed if the service lets the scripts choose the proxy, and the proxy server must pass
+    /// the egress filter.
+    proxy: Option<String>,
+    /// Send the request to the Unix domain socket at this path rather than to the host of the
+    /// url, e.g. to reach a local daemon. Native builds on Unix only, overrides `proxy`.
+    ///
+    /// Only the sockets allowed by the service can be used.
+    unix_socket_path: Option<String>,
+    /// How the request uses the http cache, if the service enabled it.
+    #[qjsbind(default)]
+    cache: CacheMode,
+}
+
+impl HttpRequest {
+    /// A GET request of `url` with the defaults of the JS API.
+    #[cfg(feature = "js-remote-module")]
+    fn get(url: String) -> Self {
+        Self {
+            url,
+            method: default_method(),
+            headers: Headers::default(),
+            body: vec![],
+            text_body: None,
+            form_data: None,
+            timeout_ms: default_timeout(),
+            connect_timeout_ms: None,
+            read_timeout_ms: None,
+            retries: 0,
+            retry_on: default_retry_on(),
+            retry_non_idempotent: false,
+            retry_delay_ms: default_retry_delay(),
+            max_response_size: None,
+            progress_interval_ms: None,
+            redirect: RedirectPolicy::default(),
+            max_redirects: default_max_redirects(),
+            decompress: cfg!(feature = "js-http-decompress"),
+            header_encoding: HeaderEncoding::default(),
+            proxy: None,
+            unix_socket_path: None,
+            cache: CacheMode::default(),
+        }
+    }
+
+    /// Take the body out of the request, serializing `formData` if given.
+    fn take_body(&mut self) -> Result<Vec<u8>> {
+        if let Some(fields) = self.form_data.take() {
+            let (content_type, body) = multipart::encode(&fields)?;
+            self.headers
+                .pairs
+                .retain(|(k, _)| !k.eq_ignore_ascii_case("Content-Type"));
+            self.headers
+                .pairs
+                .push(("Content-Type".into(), content_type));
+            return Ok(body);
+        }
+        Ok(match self.text_body.take() {
+            Some(text_body) => text_body.into_bytes(),
+            None => core::mem::take(&mut self.body),
+        })
+    }
+}
+
+/// How to handle 3xx responses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum RedirectPolicy {
+    /// Follow the redirects up to `maxRedirects` times.
+    #[default]
+    Follow,
+    /// Report the 3xx response to JS as is.
+    Manual,
+    /// Fail the request with an error event.
+    Error,
+}
+
+impl FromJsValue for RedirectPolicy {
+    fn from_js_value(value: js::Value) -> Result<Self, ValueError> {
+        match String::from_js_value(value)?.as_str() {
+            "follow" => Ok(Self::Follow),
+            "manual" => Ok(Self::Manual),
+            "error" => Ok(Self::Error),
+            _ => Err(ValueError::Static(
+                "Invalid redirect, expected one of \"follow\", \"manual\" or \"error\"",
+            )),
+        }
+    }
+}
+
+/// The `cache` modes of fetch, see `http_cache.rs`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum CacheMode {
+    /// Use a fresh cached response, revalidate a stale one.
+    #[default]
+    Default,
+    /// Bypass the cache.
+    NoStore,
+    /// Ignore the cached response but store the new one.
+    Reload,
+    /// Revalidate the cached response even if fresh.
+    NoCache,
+    /// Use the cached response even if stale.
+    ForceCache,
+    /// Use the cached response even if stale, fail if there is none.
+    OnlyIfCached,
+}
+
+impl FromJsValue for CacheMode {
+    fn from_js_value(value: js::Value) -> Result<Self, ValueError> {
+        match String::from_js_value(value)?.as_str() {
+            "default" => Ok(Self::Default),
+            "no-store" => Ok(Self::NoStore),
+            "reload" => Ok(Self::Reload),
+            "no-cache" => Ok(Self::NoCache),
+            "force-cache" => Ok(Self::ForceCache),
+            "only-if-cached" => Ok(Self::OnlyIfCached),
+            _ => Err(ValueError::Static(
+                "Invalid cache, expected one of \"default\", \"no-store\", \"reload\", \
+                \"no-cache\", \"force-cache\" or \"only-if-cached\"",
+            )),
+        }
+    }
+}
+
+/// How to decode response header values, which may be arbitrary bytes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum HeaderEncoding {
+    /// Replace invalid UTF-8 sequences with U+FFFD and emit a `warning` event.
+    #[default]
+    Utf8,
+    /// Map each byte to the code point of the same value, which never loses data.
+    Latin1,
+    /// Like `Utf8`, and additionally report the values as bytes in `rawHeaders`.
+    Bytes,
+}
+
+impl FromJsValue for HeaderEncoding {
+    fn from_js_value(value: js::Value) -> Result<Self, ValueError> {
+        match String::from_js_value(value)?.as_str() {
+            "utf8" | "utf-8" => Ok(Self::Utf8),
+            "latin1" => Ok(Self::Latin1),
+            "bytes" => Ok(Self::Bytes),
+            _ => Err(ValueError::Static(
+                "Invalid headerEncoding, expected one of \"utf8\", \"latin1\" or \"bytes\"",
+            )),
+        }
+    }
+}
+
+type RawHeaders = Vec<(String, AsBytes<Vec<u8>>)>;
+
+/// Convert the response headers as `encoding` says, warning the `sink` about lossy conversions.
+fn convert_headers<'a>(
+    pairs: impl Iterator<Item = (&'a str, &'a [u8])>,
+    encoding: HeaderEncoding,
+    sink: &mut impl ResponseSink,
+) -> (Headers, Option<RawHeaders>) {
+    let mut headers = vec![];
+    let mut raw_headers = vec![];
+    let mut lossy = vec![];
+    for (name, value) in pairs {
+        let text = match encoding {
+            HeaderEncoding::Latin1 => value.iter().map(|&b| b as char).collect(),
+            HeaderEncoding::Utf8 | HeaderEncoding::Bytes => {
+                let text = String::from_utf8_lossy(value);
+                if matches!(text, Cow::Owned(_)) {
+                    lossy.push(name.to_string());
+                }
+                text.into_owned()
+            }
+        };
+        if encoding == HeaderEncoding::Bytes {
+            raw_headers.push((name.to_string(), AsBytes(value.to_vec())));
+        }
+        headers.push((name.to_string(), text));
+    }
+    if !lossy.is_empty() && encoding == HeaderEncoding::Utf8 {
+        sink.warning(&format!(
+            "Non UTF-8 values of response headers {} were decoded lossily, \
+            use headerEncoding \"latin1\" or \"bytes\" to keep them",
+            lossy.join(", ")
+        ));
+    }
+    let raw_headers = (encoding == HeaderEncoding::Bytes).then_some(raw_headers);
+    (headers.into(), raw_headers)
 }
 
 #[derive(ToJsValue, Debug)]
@@ -79,8 +338,63 @@ pub struct HttpRequest {
 struct HttpResponseHead {
     status: u16,
     status_text: String,
+    /// The negotiated protocol, `HTTP/1.1` or `HTTP/2.0` when the server accepts h2 with ALPN.
     version: String,
+    /// The final url after following redirects.
+    url: String,
     headers: Headers,
+    /// The header values as bytes, only with `headerEncoding: "bytes"`.
+    raw_headers: Option<RawHeaders>,
+}
+
+/// The data of the `progress` events.
+#[derive(ToJsValue, Debug, Default, Clone, Copy)]
+struct Progress {
+    /// Bytes of the request body sent.
+    sent: u64,
+    /// Bytes of the response body received, before decompression unless the browser does it.
+    received: u64,
+    /// The Content-Length of the response if known.
+    total: Option<u64>,
+}
+
+/// Reports the `progress` of a request to the sink at most once per `progressIntervalMs`.
+struct ProgressReporter {
+    interval: Option<Duration>,
+    last_report: Option<Duration>,
+    progress: Progress,
+}
+
+impl ProgressReporter {
+    fn new(interval_ms: Option<u64>, sent: u64, total: Option<u64>) -> Self {
+        Self {
+            interval: interval_ms.map(Duration::from_millis),
+            last_report: None,
+            progress: Progress {
+                sent,
+                received: 0,
+                total,
+            },
+        }
+    }
+
+    fn received(&mut self, sink: &mut impl ResponseSink, len: usize) {
+        self.progress.received += len as u64;
+        self.report(sink, false);
+    }
+
+    /// Report the progress unless the last report is more recent than the interval or `force`.
+    fn report(&mut self, sink: &mut impl ResponseSink, force: bool) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let now = monotonic_now();
+        if !force && self.last_report.is_some_and(|last| now < last + interval) {
+            return;
+        }
+        self.last_report = Some(now);
+        sink.progress(&self.progress);
+    }
 }
 
 #[derive(ToJsValue, Debug)]
@@ -89,8 +403,178 @@ struct Event<'a, Data> {
     data: Data,
 }
 
-pub fn setup(ns: &js::Value) -> Result<()> {
+/// The result of one request in a `httpBatchRequest`.
+#[derive(ToJsValue)]
+#[qjsbind(rename_all = "camelCase")]
+struct BatchResponse {
+    status: u16,
+    status_text: String,
+    url: String,
+    headers: Headers,
+    raw_headers: Option<RawHeaders>,
+    body: AsBytes<Vec<u8>>,
+    /// The trailer headers sent after the body, if any.
+    trailers: Option<Headers>,
+    error: Option<String>,
+}
+
+impl BatchResponse {
+    fn from_error(error: String) -> Self {
+        Self {
+            status: 0,
+            status_text: String::new(),
+            url: String::new(),
+            headers: Headers::default(),
+            raw_headers: None,
+            body: AsBytes(Vec::new()),
+            trailers: None,
+            error: Some(error),
+        }
+    }
+
+    fn from_head(head: HttpResponseHead, body: Vec<u8>, trailers: Option<Headers>) -> Self {
+        Self {
+            status: head.status,
+            status_text: head.status_text,
+            url: head.url,
+            headers: head.headers,
+            raw_headers: head.raw_headers,
+            body: AsBytes(body),
+            trailers,
+            error: None,
+        }
+    }
+}
+
+/// Run the request hook of the service's interceptor, if any, on `req` with its final `headers`.
+fn intercept_request(
+    service: &Service,
+    req: &mut HttpRequest,
+    headers: &mut Headers,
+) -> Result<()> {
+    use core::mem::take;
+    let Some(interceptor) = service.http_interceptor() else {
+        return Ok(());
+    };
+    let mut parts = RequestParts {
+        method: take(&mut req.method),
+        url: take(&mut req.url),
+        headers: take(&mut headers.pairs),
+    };
+    let result = interceptor.request(&mut parts);
+    req.method = parts.method;
+    req.url = parts.url;
+    headers.pairs = parts.headers;
+    result
+}
+
+/// Run the response hook of the service's interceptor, if any, on the response head.
+fn intercept_response(weak_service: &ServiceWeakRef, head: &mut HttpResponseHead) -> Result<()> {
+    use core::mem::take;
+    let Some(interceptor) = weak_service
+        .upgrade()
+        .and_then(|service| service.http_interceptor())
+    else {
+        return Ok(());
+    };
+    let mut parts = ResponseParts {
+        url: take(&mut head.url),
+        status: head.status,
+        headers: take(&mut head.headers.pairs),
+    };
+    let result = interceptor.response(&mut parts);
+    head.url = parts.url;
+    head.status = parts.status;
+    head.headers.pairs = parts.headers;
+    result
+}
+
+/// Add the body bytes of a request to the metrics of the service.
+fn count_bytes(weak_service: &ServiceWeakRef, sent: u64, received: u64) {
+    if let Some(service) = weak_service.upgrade() {
+        service.count_http_bytes(sent, received);
+    }
+}
+
+/// Receives the response of a http request.
+trait ResponseSink {
+    fn head(&mut self, head: HttpResponseHead);
+    fn data(&mut self, data: Vec<u8>);
+    fn warning(&mut self, message: &str);
+    fn progress(&mut self, progress: &Progress);
+    fn trailers(&mut self, trailers: Headers);
+}
+
+/// Forwards the response to the JS callback as `head` and `data` events.
+struct CallbackSink {
+    weak_service: ServiceWeakRef,
+    id: u64,
+}
+
+impl ResponseSink for CallbackSink {
+    fn head(&mut self, head: HttpResponseHead) {
+        invoke_callback(&self.weak_service, self.id, "head", &head);
+    }
+
+    fn data(&mut self, data: Vec<u8>) {
+        let data = ExternalBuffer::new(data);
+        invoke_callback(&self.weak_service, self.id, "data", &data);
+    }
+
+    fn warning(&mut self, message: &str) {
+        invoke_callback(&self.weak_service, self.id, "warning", &message.to_owned());
+    }
+
+    fn progress(&mut self, progress: &Progress) {
+        invoke_callback(&self.weak_service, self.id, "progress", progress);
+    }
+
+    fn trailers(&mut self, trailers: Headers) {
+        invoke_callback(&self.weak_service, self.id, "trailers", &trailers);
+    }
+}
+
+/// Buffers the whole response in memory.
+#[derive(Default)]
+struct CollectSink {
+    head: Option<HttpResponseHead>,
+    body: Vec<u8>,
+    trailers: Option<Headers>,
+}
+
+impl ResponseSink for CollectSink {
+    fn head(&mut self, head: HttpResponseHead) {
+        self.head = Some(head);
+    }
+
+    fn data(&mut self, data: Vec<u8>) {
+        if self.body.is_empty() {
+            self.body = data;
+        } else {
+            self.body.extend_from_slice(&data);
+        }
+    }
+
+    fn warning(&mut self, message: &str) {
+        log::warn!("{message}");
+    }
+
+    fn progress(&mut self, _progress: &Progress) {}
+
+    fn trailers(&mut self, trailers: Headers) {
+        self.trailers = Some(trailers);
+    }
+}
+
+pub fn setup(ns: &js::Value, ctx: &js::Context) -> Result<()> {
+    // Whether the `decompress` option is available, the web backend always decompresses.
+    let decompress = cfg!(any(feature = "js-http-decompress", feature = "web"));
+    ns.set_property("httpDecompress", &decompress.to_js_value(ctx)?)?;
     ns.define_property_fn("httpRequest", http_request)?;
+    ns.define_property_fn("httpBatchRequest", http_batch_request)?;
+    ns.define_property_fn("cancelHttpRequest", cancel_http_request)?;
+    grpc_web::setup(ns)?;
+    json_rpc::setup(ns)?;
     Ok(())
 }
 
@@ -101,7 +585,62 @@ fn http_request(
     req: HttpRequest,
     callback: OwnedJsValue,
 ) -> Result<u64> {
-    Ok(service.spawn(callback, do_http_request, req))
+    parse_request_url(&req.url)?;
+    service.spawn(callback, do_http_request, req)
+}
+
+/// Issue all the requests concurrently.
+///
+/// The callback is invoked once with an array of results in the same order as the requests. Requests
+/// not finished within `timeoutMs` are reported as timed out.
+#[js::host_call(with_context)]
+fn http_batch_request(
+    service: ServiceRef,
+    _this: js::Value,
+    requests: Vec<HttpRequest>,
+    callback: OwnedJsValue,
+    timeout_ms: Option<u64>,
+) -> Result<u64> {
+    let timeout_ms = timeout_ms.unwrap_or_else(default_timeout);
+    service.spawn(callback, do_http_batch_request, (requests, timeout_ms))
+}
+
+/// Cancel an in-flight http request.
+///
+/// The callback receives an `abort` event and then the spawned task is dropped. The `abortSignal`
+/// request option is wired to this function by the bootcode. Ids of other kinds of resources are
+/// ignored.
+#[js::host_call(with_context)]
+fn cancel_http_request(service: ServiceRef, _this: js::Value, id: u64) {
+    match service.resource_kind(id) {
+        None => {
+            info!("http_request {id} has already finished");
+            return;
+        }
+        Some("http_request" | "http_batch_request") => {}
+        Some(kind) => {
+            info!("cancelHttpRequest: {id} is a {kind}, not an http request");
+            return;
+        }
+    }
+    let Some(callback) = service.get_resource_value(id) else {
+        return;
+    };
+    if let Err(err) = service.call_function(callback, ("abort", js::Value::Null)) {
+        error!("[{id}] Failed to report http_request event abort: {err:?}");
+    }
+    service.remove_resource(id);
+}
+
+/// Parse the url of a request with the same WHATWG parser that backs the `URL` global.
+fn parse_request_url(url: &str) -> Result<::url::Url> {
+    let parsed: ::url::Url = url
+        .parse()
+        .with_context(|| format!("Failed to parse url: {url}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        anyhow::bail!("Unsupported url scheme: {}", parsed.scheme());
+    }
+    Ok(parsed)
 }
 
 fn default_method() -> String {
@@ -112,74 +651,537 @@ fn default_timeout() -> u64 {
     30_000
 }
 
-async fn do_http_request(weak_service: ServiceWeakRef, id: u64, req: HttpRequest) {
-    let url = req.url.clone();
-    let result = tokio::select! {
+fn default_max_redirects() -> u32 {
+    20
+}
+
+fn default_retry_on() -> Vec<u16> {
+    vec![502, 503, 504]
+}
+
+fn default_retry_delay() -> u64 {
+    100
+}
+
+/// Upper bound of `retries`, so that a request can't keep a worker busy retrying indefinitely.
+const MAX_RETRIES: u32 = 10;
+
+/// The delay before retry number `attempt`, counting from 0.
+fn retry_delay(req: &HttpRequest, attempt: u32) -> Duration {
+    Duration::from_millis(req.retry_delay_ms.saturating_mul(1 << attempt.min(16)))
+}
+
+/// Whether repeating a request with `method` has the same effect as sending it once.
+fn is_idempotent(method: &str) -> bool {
+    ["GET", "HEAD", "OPTIONS", "TRACE", "PUT", "DELETE"]
+        .iter()
+        .any(|idempotent| method.eq_ignore_ascii_case(idempotent))
+}
+
+/// Whether to retry after an attempt that ended with `status`, or with an error if None.
+///
+/// `retries` is clamped to `MAX_RETRIES`.
+fn should_retry(req: &HttpRequest, attempt: u32, status: Option<u16>) -> bool {
+    attempt < req.retries.min(MAX_RETRIES)
+        && (req.retry_non_idempotent || is_idempotent(&req.method))
+        && status.map_or(true, |status| req.retry_on.contains(&status))
+}
+
+/// Await `fut`, failing with `what` if it doesn't finish within `timeout_ms`.
+async fn with_timeout<T>(
+    timeout_ms: Option<u64>,
+    what: &str,
+    fut: impl core::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(timeout_ms) = timeout_ms else {
+        return fut.await;
+    };
+    tokio::select! {
+        _ = sleep(Duration::from_millis(timeout_ms)) => Err(anyhow!("{what} timed out")),
+        result = fut => result,
+    }
+}
+
+fn check_response_size(received: u64, limit: Option<u64>) -> Result<()> {
+    match limit {
+        Some(limit) if received > limit => {
+            anyhow::bail!("Response body exceeds maxResponseSize ({limit} bytes)")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The response size limit is further bounded by the memory limit of the service.
+fn response_size_limit(weak_service: &ServiceWeakRef, requested: Option<u64>) -> Option<u64> {
+    let memory_limit = weak_service
+        .upgrade()
+        .and_then(|service| service.memory_limit())
+        .map(|limit| limit as u64);
+    match (requested, memory_limit) {
+        (Some(requested), Some(memory_limit)) => Some(requested.min(memory_limit)),
+        (requested, memory_limit) => requested.or(memory_limit),
+    }
+}
+
+fn report_data(
+    sink: &mut impl ResponseSink,
+    received: &mut u64,
+    limit: Option<u64>,
+    data: Vec<u8>,
+) -> Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    *received += data.len() as u64;
+    check_response_size(*received, limit)?;
+    sink.data(data);
+    Ok(())
+}
+
+async fn request_with_timeout(
+    weak_service: &ServiceWeakRef,
+    req: HttpRequest,
+    sink: &mut impl ResponseSink,
+) -> Result<()> {
+    tokio::select! {
         _ = sleep(Duration::from_millis(req.timeout_ms)) => {
             Err(anyhow!("Timed out"))
         }
-        result = do_http_request_inner(weak_service.clone(), id, req) => result,
+        result = send_request(weak_service, req, sink) => result,
+    }
+}
+
+/// Send the request, through the http cache of the service if it applies.
+///
+/// The route chosen by the request is checked and the default headers and the interceptor of the
+/// service are applied first, so that the cache sees the headers actually sent.
+async fn send_request(
+    weak_service: &ServiceWeakRef,
+    mut req: HttpRequest,
+    sink: &mut impl ResponseSink,
+) -> Result<()> {
+    let Some(service) = weak_service.upgrade() else {
+        anyhow::bail!("Service dropped");
+    };
+    check_route(&service, &req)?;
+    let mut headers = core::mem::take(&mut req.headers).with_defaults(service.default_headers());
+    intercept_request(&service, &mut req, &mut headers)?;
+    req.headers = headers;
+    drop(service);
+    #[cfg(feature = "js-cache")]
+    if http_cache::applies(weak_service, &req) {
+        return http_cache::request(weak_service, req, sink).await;
+    }
+    do_http_request_inner(weak_service, req, sink).await
+}
+
+/// Refuse the proxy or the Unix domain socket chosen by the request unless the service allows it.
+fn check_route(service: &Service, req: &HttpRequest) -> Result<()> {
+    if req.proxy.is_some() && !service.script_proxy_allowed() {
+        anyhow::bail!("The service doesn't allow the scripts to choose the proxy");
+    }
+    if let Some(path) = &req.unix_socket_path {
+        if !service.unix_socket_allowed(path) {
+            anyhow::bail!("The service doesn't allow requests to the Unix domain socket {path}");
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the rate limit of the service to let a request to `url` through.
+///
+/// Every attempt and every redirect hop takes a token, so retries and redirects can't get around
+/// the limit.
+async fn rate_limit(weak_service: &ServiceWeakRef, url: &::url::Url) -> Result<()> {
+    let Some(limit) = weak_service
+        .upgrade()
+        .and_then(|service| service.rate_limit())
+    else {
+        return Ok(());
+    };
+    limit.acquire(url.host_str().unwrap_or_default()).await
+}
+
+/// Fetch `url` for the host the way the scripts do, with the default headers, the interceptor,
+/// the rate limit, the proxy and the resolver of the service, following the redirects.
+///
+/// Returns the final url and the body of a successful response.
+#[cfg(feature = "js-remote-module")]
+pub(super) async fn fetch(
+    weak_service: &ServiceWeakRef,
+    url: &str,
+    max_size: u64,
+) -> Result<(String, Vec<u8>)> {
+    let mut req = HttpRequest::get(url.into());
+    req.max_response_size = Some(max_size);
+    let mut sink = CollectSink::default();
+    request_with_timeout(weak_service, req, &mut sink).await?;
+    let head = sink.head.context("No response")?;
+    if !(200..300).contains(&head.status) {
+        anyhow::bail!("{} {}", head.status, head.status_text);
+    }
+    Ok((head.url, sink.body))
+}
+
+async fn do_http_request(weak_service: ServiceWeakRef, id: u64, req: HttpRequest) {
+    let url = req.url.clone();
+    let mut sink = CallbackSink {
+        weak_service: weak_service.clone(),
+        id,
     };
-    if let Err(err) = result {
-        invoke_callback(
+    match request_with_timeout(&weak_service, req, &mut sink).await {
+        Ok(()) => invoke_callback(&weak_service, id, "end", &()),
+        Err(err) if err.is::<RateLimited>() => {
+            invoke_callback(&weak_service, id, "rateLimited", &err.to_string())
+        }
+        Err(err) => invoke_callback(
             &weak_service,
             id,
             "error",
             &format!("Failed to request `{url}`: {err:?}"),
-        );
+        ),
     }
 }
 
-#[cfg(not(feature = "web"))]
-async fn do_http_request_inner(
+async fn do_http_batch_request(
     weak_service: ServiceWeakRef,
     id: u64,
-    req: HttpRequest,
-) -> Result<()> {
-    use crate::runtime::{http_connector, HyperExecutor};
-    use core::pin::pin;
-    use hyper::{body::HttpBody, Body};
-    let connector = http_connector();
-    let client = hyper::Client::builder()
-        .executor(HyperExecutor)
-        .build::<_, Body>(connector);
-    let uri: hyper::Uri = req
-        .url
-        .parse()
-        .with_context(|| format!("Failed to parse url: {}", req.url))?;
-    let mut builder = hyper::Request::builder()
-        .method(req.method.as_str())
-        .uri(&uri);
-    let body: Vec<u8> = if let Some(text_body) = req.text_body {
-        text_body.into_bytes()
-    } else {
-        req.body
+    (requests, timeout_ms): (Vec<HttpRequest>, u64),
+) {
+    let results: Rc<RefCell<Vec<BatchResponse>>> = Rc::new(RefCell::new(
+        requests
+            .iter()
+            .map(|_| BatchResponse::from_error("Timed out".into()))
+            .collect(),
+    ));
+    let tasks = requests.into_iter().enumerate().map(|(index, req)| {
+        let weak_service = weak_service.clone();
+        let results = results.clone();
+        async move {
+            let url = req.url.clone();
+            let mut sink = CollectSink::default();
+            let result = request_with_timeout(&weak_service, req, &mut sink).await;
+            let response = match (result, sink.head) {
+                (Ok(()), Some(head)) => BatchResponse::from_head(head, sink.body, sink.trailers),
+                (Ok(()), None) => BatchResponse::from_error(format!("No response from `{url}`")),
+                (Err(err), _) => {
+                    BatchResponse::from_error(format!("Failed to request `{url}`: {err:?}"))
+                }
+            };
+            results.borrow_mut()[index] = response;
+        }
+    });
+    tokio::select! {
+        _ = futures::future::join_all(tasks) => {}
+        _ = sleep(Duration::from_millis(timeout_ms)) => {}
+    }
+    let results = core::mem::take(&mut *results.borrow_mut());
+    let Some(service) = weak_service.upgrade() else {
+        info!("http_batch_request {id} exited because the service has been dropped");
+        return;
+    };
+    let Some(callback) = service.get_resource_value(id) else {
+        info!("http_batch_request {id} exited because the resource has been dropped");
+        return;
     };
-    for (k, v) in req.headers.pairs.iter() {
+    if let Err(err) = service.call_function(callback, (results,)) {
+        error!("[{id}] Failed to report http_batch_request results: {err:?}");
+    }
+}
+
+/// The client to send a request with, depending on the proxy in use.
+#[cfg(not(feature = "web"))]
+enum Client {
+    Direct(crate::runtime::HttpClient),
+    #[cfg(feature = "js-dns")]
+    Resolved(super::dns::DnsHttpClient),
+    #[cfg(feature = "js-http-proxy")]
+    Proxied(proxy::ProxyClient),
+    #[cfg(all(feature = "native", unix))]
+    Unix(unix::UnixClient),
+}
+
+#[cfg(not(feature = "web"))]
+impl Client {
+    fn new(service: &Service, proxy: Option<String>, unix_socket: Option<String>) -> Result<Self> {
+        if let Some(path) = unix_socket {
+            #[cfg(all(feature = "native", unix))]
+            return Ok(Self::Unix(unix::unix_client(path)));
+            #[cfg(not(all(feature = "native", unix)))]
+            anyhow::bail!(
+                "Unix domain sockets are not supported in this build, can not use {path}"
+            );
+        }
+        let proxy_url = proxy
+            .or_else(|| service.http_proxy())
+            .filter(|url| !url.is_empty());
+        let Some(proxy_url) = proxy_url else {
+            #[cfg(feature = "js-dns")]
+            if let Some(resolver) = service.dns_resolver() {
+                return Ok(Self::Resolved(resolver.http_client()));
+            }
+            return Ok(Self::Direct(service.http_client()));
+        };
+        #[cfg(feature = "js-http-proxy")]
+        return Ok(Self::Proxied(proxy::proxy_client(service, &proxy_url)?));
+        #[cfg(not(feature = "js-http-proxy"))]
+        anyhow::bail!("Proxy support is not enabled in this build, can not use {proxy_url}");
+    }
+
+    async fn request(
+        &self,
+        request: hyper::Request<hyper::Body>,
+    ) -> hyper::Result<hyper::Response<hyper::Body>> {
+        match self {
+            Self::Direct(client) => client.request(request).await,
+            #[cfg(feature = "js-dns")]
+            Self::Resolved(client) => client.request(request).await,
+            #[cfg(feature = "js-http-proxy")]
+            Self::Proxied(client) => client.request(request).await,
+            #[cfg(all(feature = "native", unix))]
+            Self::Unix(client) => client.request(request).await,
+        }
+    }
+}
+
+#[cfg(not(feature = "web"))]
+fn build_request(
+    method: &str,
+    uri: &hyper::Uri,
+    req_headers: &Headers,
+    body: hyper::body::Bytes,
+) -> Result<hyper::Request<hyper::Body>> {
+    let mut builder = hyper::Request::builder().method(method).uri(uri);
+    for (k, v) in req_headers.pairs.iter() {
         builder = builder.header(k.as_str(), v.as_str());
     }
-    let headers: BTreeSet<&str> = req.headers.pairs.iter().map(|(k, _v)| k.as_str()).collect();
-    // Append Host, Content-Length and Content-Length if not present
-    if !headers.contains("Host") {
-        builder = builder.header("Host", uri.host().unwrap_or_default());
+    // Append Host and Content-Length if not present
+    if !req_headers.contains("Host") {
+        builder = builder.header("Host", host_header(uri));
     }
-    if !headers.contains("Content-Length") {
+    if needs_content_length(method, req_headers, &body) {
         builder = builder.header("Content-Length", body.len());
     }
-    if !headers.contains("User-Agent") {
-        builder = builder.header("User-Agent", "PhatContract/0.1.0");
+    builder
+        .body(hyper::Body::from(body))
+        .context("Failed to build request")
+}
+
+/// The Host header for `uri`, with the port unless it is the default one of the scheme.
+///
+/// IPv6 literals keep their brackets, e.g. `[::1]:8080`.
+#[cfg(not(feature = "web"))]
+fn host_header(uri: &hyper::Uri) -> String {
+    let host = uri.host().unwrap_or_default();
+    let default_port = match uri.scheme_str() {
+        Some("https") => Some(443),
+        Some("http") => Some(80),
+        _ => None,
+    };
+    match uri.port_u16() {
+        Some(port) if Some(port) != default_port => format!("{host}:{port}"),
+        _ => host.into(),
     }
-    let request = builder
-        .body(Body::from(body))
-        .context("Failed to build request")?;
-    let response = client.request(request).await?;
+}
+
+/// Whether to add the Content-Length header to a request that doesn't set it.
+///
+/// A `Transfer-Encoding: chunked` body is sent in chunks without it. An empty body only gets it
+/// for the methods that are expected to carry one, as some servers reject `GET` requests with
+/// `Content-Length: 0`.
+#[cfg(not(feature = "web"))]
+fn needs_content_length(method: &str, req_headers: &Headers, body: &[u8]) -> bool {
+    if req_headers.contains("Content-Length") || req_headers.contains("Transfer-Encoding") {
+        return false;
+    }
+    !body.is_empty()
+        || ["POST", "PUT", "PATCH"]
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method))
+}
+
+/// Add the cookies of the jar to `headers` unless the request sets its own.
+#[cfg(not(feature = "web"))]
+fn with_cookies(weak_service: &ServiceWeakRef, url: &::url::Url, headers: &Headers) -> Headers {
+    let mut headers = headers.clone();
+    if headers.contains("Cookie") {
+        return headers;
+    }
+    let cookie = weak_service
+        .upgrade()
+        .and_then(|service| service.with_cookie_jar(|jar| jar.header_for(url)))
+        .flatten();
+    if let Some(cookie) = cookie {
+        headers.pairs.push(("Cookie".into(), cookie));
+    }
+    headers
+}
+
+#[cfg(not(feature = "web"))]
+fn store_cookies(
+    weak_service: &ServiceWeakRef,
+    url: &::url::Url,
+    response: &hyper::Response<hyper::Body>,
+) {
+    let Some(service) = weak_service.upgrade() else {
+        return;
+    };
+    service.with_cookie_jar(|jar| {
+        for value in response.headers().get_all(hyper::header::SET_COOKIE) {
+            if let Ok(value) = value.to_str() {
+                jar.store(url, value);
+            }
+        }
+    });
+}
+
+/// The request headers not sent along when a redirect leads to another origin.
+#[cfg(not(feature = "web"))]
+const CROSS_ORIGIN_STRIPPED: [&str; 4] = ["Authorization", "Proxy-Authorization", "Cookie", "Host"];
+
+/// Send the request, following the redirects as the redirect policy says.
+///
+/// The `Authorization`, `Cookie` and `Host` headers of the request are dropped once a redirect
+/// leaves its origin, the cookies of the jar are still sent where they belong. Returns the final
+/// url along with the response.
+#[cfg(not(feature = "web"))]
+async fn send_following_redirects(
+    weak_service: &ServiceWeakRef,
+    client: &Client,
+    req: &HttpRequest,
+    headers: &Headers,
+    mut body: hyper::body::Bytes,
+) -> Result<(::url::Url, hyper::Response<hyper::Body>)> {
+    use hyper::StatusCode;
+    let mut url = parse_request_url(&req.url)?;
+    let mut method = req.method.clone();
+    let mut headers = Cow::Borrowed(headers);
+    let mut redirects = 0;
+    let response = loop {
+        // Checked on every hop so that a redirect can't reach a refused host
+        check_egress_url(weak_service, &url).await?;
+        rate_limit(weak_service, &url).await?;
+        let uri: hyper::Uri = url.as_str().parse().context("Invalid url")?;
+        let hop_headers = with_cookies(weak_service, &url, &headers);
+        let request = build_request(&method, &uri, &hop_headers, body.clone())?;
+        let response = client.request(request).await?;
+        store_cookies(weak_service, &url, &response);
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(hyper::header::LOCATION)
+            .and_then(|v| v.to_str().ok());
+        let Some(location) = location.filter(|_| status.is_redirection()) else {
+            break response;
+        };
+        match req.redirect {
+            RedirectPolicy::Manual => break response,
+            RedirectPolicy::Error => anyhow::bail!("Unexpected redirect to {location}"),
+            RedirectPolicy::Follow => {}
+        }
+        if redirects >= req.max_redirects {
+            anyhow::bail!("Too many redirects");
+        }
+        redirects += 1;
+        let next = url
+            .join(location)
+            .with_context(|| format!("Invalid redirect location: {location}"))?;
+        // Like browsers and curl, don't leak the credentials to another origin
+        if next.origin() != url.origin() {
+            headers.to_mut().pairs.retain(|(k, _)| {
+                !CROSS_ORIGIN_STRIPPED
+                    .iter()
+                    .any(|h| k.eq_ignore_ascii_case(h))
+            });
+        }
+        url = next;
+        let to_get = status == StatusCode::SEE_OTHER
+            || (matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND)
+                && method == "POST");
+        if to_get {
+            method = "GET".into();
+            body = Default::default();
+        }
+    };
+    Ok((url, response))
+}
+
+#[cfg(not(feature = "web"))]
+async fn do_http_request_inner(
+    weak_service: &ServiceWeakRef,
+    mut req: HttpRequest,
+    sink: &mut impl ResponseSink,
+) -> Result<()> {
+    use core::pin::pin;
+    use hyper::body::HttpBody;
+    let Some(service) = weak_service.upgrade() else {
+        anyhow::bail!("Service dropped");
+    };
+    let script_proxy = req.proxy.take();
+    #[cfg(feature = "js-http-proxy")]
+    if let Some(url) = script_proxy.as_deref().filter(|url| !url.is_empty()) {
+        proxy::Proxy::parse(url)?.check_egress(weak_service).await?;
+    }
+    let client = Client::new(&service, script_proxy, req.unix_socket_path.take())?;
+    let body: hyper::body::Bytes = req.take_body()?.into();
+    let headers = core::mem::take(&mut req.headers);
+    drop(service);
+    let max_response_size = response_size_limit(weak_service, req.max_response_size);
+    #[cfg(not(feature = "js-http-decompress"))]
+    if req.decompress {
+        anyhow::bail!("Response decompression is not enabled in this build");
+    }
+    let mut attempt = 0;
+    let (url, response) = loop {
+        let result = with_timeout(
+            req.connect_timeout_ms,
+            "Connecting",
+            send_following_redirects(weak_service, &client, &req, &headers, body.clone()),
+        )
+        .await;
+        let status = result
+            .as_ref()
+            .ok()
+            .map(|(_, response)| response.status().as_u16());
+        let rate_limited = matches!(&result, Err(err) if err.is::<RateLimited>());
+        if rate_limited || !should_retry(&req, attempt, status) {
+            break result?;
+        }
+        info!("Retrying `{}` after {status:?}", req.url);
+        sleep(retry_delay(&req, attempt)).await;
+        attempt += 1;
+    };
+    let content_length = response.body().size_hint().exact();
+    if let Some(content_length) = content_length {
+        check_response_size(content_length, max_response_size)?;
+    }
+    // The whole request body has been sent once the response arrives
+    let mut progress =
+        ProgressReporter::new(req.progress_interval_ms, body.len() as u64, content_length);
+    count_bytes(weak_service, body.len() as u64, 0);
+    #[cfg(feature = "js-http-decompress")]
+    let mut decoder = if req.decompress {
+        decompress::Decoder::from_headers(response.headers(), max_response_size)
+    } else {
+        None
+    };
+    #[cfg(feature = "js-http-decompress")]
+    let decoding = decoder.is_some();
+    #[cfg(not(feature = "js-http-decompress"))]
+    let decoding = false;
     {
-        let head = {
-            let headers = response
+        let mut head = {
+            let pairs = response
                 .headers()
                 .iter()
-                .map(|(k, v)| (k.as_str().into(), v.to_str().unwrap_or_default().into()))
-                .collect();
+                .filter(|(k, _)| {
+                    // The encoding and length no longer apply once the body is decompressed
+                    !decoding
+                        || (k != &hyper::header::CONTENT_ENCODING
+                            && k != &hyper::header::CONTENT_LENGTH)
+                })
+                .map(|(k, v)| (k.as_str(), v.as_bytes()));
+            let (headers, raw_headers) = convert_headers(pairs, req.header_encoding, sink);
             let status = response.status().as_u16();
             let status_text = response
                 .status()
@@ -191,50 +1193,133 @@ async fn do_http_request_inner(
                 status,
                 status_text,
                 version,
+                url: url.into(),
                 headers,
+                raw_headers,
             }
         };
-        invoke_callback(&weak_service, id, "head", &head);
+        intercept_response(weak_service, &mut head)?;
+        sink.head(head);
     }
+    progress.report(sink, true);
     let mut response = pin!(response);
-    while let Some(chunk) = response.data().await {
+    let mut received = 0_u64;
+    loop {
+        let next = async { Ok(response.data().await) };
+        let Some(chunk) = with_timeout(req.read_timeout_ms, "Reading", next).await? else {
+            break;
+        };
         let chunk = chunk.context("Failed to read response body")?;
-        invoke_callback(&weak_service, id, "data", &AsBytes(chunk));
+        progress.received(sink, chunk.len());
+        count_bytes(weak_service, 0, chunk.len() as u64);
+        #[cfg(feature = "js-http-decompress")]
+        let chunk = match &mut decoder {
+            Some(decoder) => decoder
+                .decode(&chunk)
+                .await
+                .context("Failed to decompress response body")?,
+            // Takes over the buffer without copying if hyper holds the only reference
+            None => Vec::from(chunk),
+        };
+        #[cfg(not(feature = "js-http-decompress"))]
+        let chunk = Vec::from(chunk);
+        report_data(sink, &mut received, max_response_size, chunk)?;
+    }
+    #[cfg(feature = "js-http-decompress")]
+    if let Some(decoder) = &mut decoder {
+        let rest = decoder
+            .finish()
+            .await
+            .context("Failed to decompress response body")?;
+        report_data(sink, &mut received, max_response_size, rest)?;
+    }
+    progress.report(sink, true);
+    let next = async { Ok(response.trailers().await) };
+    let trailers = with_timeout(req.read_timeout_ms, "Reading", next)
+        .await?
+        .context("Failed to read response trailers")?;
+    if let Some(trailers) = trailers {
+        let pairs = trailers.iter().map(|(k, v)| (k.as_str(), v.as_bytes()));
+        let (trailers, _) = convert_headers(pairs, req.header_encoding, sink);
+        sink.trailers(trailers);
     }
-    invoke_callback(&weak_service, id, "end", &());
     Ok(())
 }
 
 #[cfg(feature = "web")]
 async fn do_http_request_inner(
-    weak_service: ServiceWeakRef,
-    id: u64,
-    req: HttpRequest,
+    weak_service: &ServiceWeakRef,
+    mut req: HttpRequest,
+    sink: &mut impl ResponseSink,
 ) -> Result<()> {
+    use core::pin::pin;
+    use futures::StreamExt;
     use reqwest::{Client, Method};
+    if req.redirect == RedirectPolicy::Manual {
+        log::warn!(
+            "Manual redirect is not supported by the web backend, redirects will be followed"
+        );
+    }
+    let body = req.take_body()?;
+    let headers = core::mem::take(&mut req.headers);
     let method = Method::from_bytes(req.method.as_bytes()).context("Invalid method")?;
-    let mut builder = Client::new().request(method, req.url);
-    for (k, v) in req.headers.pairs.iter() {
-        builder = builder.header(k, v);
+    if req.unix_socket_path.is_some() {
+        anyhow::bail!("Unix domain sockets are not supported by the web backend");
     }
-    let headers: BTreeSet<&str> = req.headers.pairs.iter().map(|(k, _v)| k.as_str()).collect();
-    // Append User-Agent if not present
-    if !headers.contains("User-Agent") {
-        builder = builder.header("User-Agent", "PhatContract/0.1.0");
+    if req.proxy.as_deref().is_some_and(|proxy| !proxy.is_empty()) {
+        log::warn!("Proxies are not supported by the web backend, the browser settings apply");
     }
-    let body: Vec<u8> = if let Some(text_body) = req.text_body {
-        text_body.into_bytes()
-    } else {
-        req.body
-    };
+    let url = parse_request_url(&req.url)?;
+    check_egress_url(weak_service, &url).await?;
+    let mut builder = Client::new().request(method, url.clone());
+    for (k, v) in headers.pairs.iter() {
+        builder = builder.header(k, v);
+    }
+    let sent = body.len() as u64;
     builder = builder.body(body);
-    let response = builder.send().await?;
-    let head = {
-        let headers = response
+    let max_response_size = response_size_limit(weak_service, req.max_response_size);
+    let mut attempt = 0;
+    let response = loop {
+        // The browser follows the redirects by itself, only the attempts can take a token
+        rate_limit(weak_service, &url).await?;
+        let attempt_builder = builder.try_clone().context("Failed to clone request")?;
+        let result = with_timeout(req.connect_timeout_ms, "Connecting", async {
+            Ok(attempt_builder.send().await?)
+        })
+        .await;
+        let status = result
+            .as_ref()
+            .ok()
+            .map(|response| response.status().as_u16());
+        if !should_retry(&req, attempt, status) {
+            break result?;
+        }
+        info!("Retrying `{}` after {status:?}", req.url);
+        sleep(retry_delay(&req, attempt)).await;
+        attempt += 1;
+    };
+    // The browser follows redirects by itself, so we can only detect them afterwards.
+    let redirected = response.url() != &url;
+    if redirected && req.redirect == RedirectPolicy::Error {
+        anyhow::bail!("Unexpected redirect to {}", response.url());
+    }
+    // The browser hides the intermediate hops, the final url must pass the egress filter before
+    // the response is exposed to the script.
+    if redirected {
+        check_egress_url(weak_service, response.url()).await?;
+    }
+    let content_length = response.content_length();
+    if let Some(content_length) = content_length {
+        check_response_size(content_length, max_response_size)?;
+    }
+    let mut progress = ProgressReporter::new(req.progress_interval_ms, sent, content_length);
+    count_bytes(weak_service, sent, 0);
+    let mut head = {
+        let pairs = response
             .headers()
             .iter()
-            .map(|(k, v)| (k.as_str().into(), v.to_str().unwrap_or_default().into()))
-            .collect();
+            .map(|(k, v)| (k.as_str(), v.as_bytes()));
+        let (headers, raw_headers) = convert_headers(pairs, req.header_encoding, sink);
         let status = response.status().as_u16();
         let status_text = response
             .status()
@@ -245,26 +1330,57 @@ async fn do_http_request_inner(
             status,
             status_text,
             version: "HTTP/1.1".into(),
+            url: response.url().to_string(),
             headers,
+            raw_headers,
         }
     };
-    invoke_callback(&weak_service, id, "head", &head);
-    let body = response.bytes().await?;
-    invoke_callback(&weak_service, id, "data", &AsBytes(body));
-    invoke_callback(&weak_service, id, "end", &());
+    intercept_response(weak_service, &mut head)?;
+    sink.head(head);
+    progress.report(sink, true);
+    // Bridged from the ReadableStream of the browser response
+    let mut body = pin!(response.bytes_stream());
+    let mut received = 0_u64;
+    loop {
+        let next = async { Ok(body.next().await) };
+        let Some(chunk) = with_timeout(req.read_timeout_ms, "Reading", next).await? else {
+            break;
+        };
+        let chunk = chunk.context("Failed to read response body")?;
+        progress.received(sink, chunk.len());
+        count_bytes(weak_service, 0, chunk.len() as u64);
+        report_data(sink, &mut received, max_response_size, Vec::from(chunk))?;
+    }
+    progress.report(sink, true);
     Ok(())
 }
 
-fn invoke_callback(weak_service: &Weak<Service>, id: u64, name: &str, data: &dyn ToJsValue) {
-    let Some(service) = weak_service.upgrade() else {
-        info!("http_request {id} exited because the service has been dropped");
-        return;
-    };
-    let Some(callback) = service.get_resource_value(id) else {
-        info!("http_request {id} exited because the resource has been dropped");
-        return;
-    };
-    if let Err(err) = service.call_function(callback, (name, data)) {
-        error!("[{id}] Failed to report http_request event {name}: {err:?}");
+#[cfg(all(test, not(feature = "web")))]
+mod tests {
+    use super::host_header;
+
+    fn host(uri: &str) -> String {
+        host_header(&uri.parse().expect("Invalid uri"))
+    }
+
+    #[test]
+    fn host_header_elides_default_ports() {
+        assert_eq!(host("http://example.com/"), "example.com");
+        assert_eq!(host("http://example.com:80/"), "example.com");
+        assert_eq!(host("https://example.com:443/"), "example.com");
+    }
+
+    #[test]
+    fn host_header_keeps_other_ports() {
+        assert_eq!(host("http://example.com:443/"), "example.com:443");
+        assert_eq!(host("https://example.com:80/"), "example.com:80");
+        assert_eq!(host("https://example.com:8443/"), "example.com:8443");
+    }
+
+    #[test]
+    fn host_header_keeps_ipv6_brackets() {
+        assert_eq!(host("http://[::1]/"), "[::1]");
+        assert_eq!(host("http://[::1]:8080/"), "[::1]:8080");
+        assert_eq!(host("https://[2001:db8::1]:443/"), "[2001:db8::1]");
     }
 }
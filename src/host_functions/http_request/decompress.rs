@@ -0,0 +1,94 @@
+use async_compression::tokio::write::{BrotliDecoder, GzipDecoder, ZlibDecoder};
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use hyper::header::{HeaderMap, CONTENT_ENCODING};
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Incremental decoder for `Content-Encoding` compressed response bodies.
+pub(super) enum Decoder {
+    Gzip(GzipDecoder<Output>),
+    Deflate(ZlibDecoder<Output>),
+    Brotli(BrotliDecoder<Output>),
+}
+
+/// Collects the decoded bytes, failing once there are more than `limit` of them in total so that
+/// a small compressed body can't inflate to exhaust the memory.
+pub(super) struct Output {
+    buf: Vec<u8>,
+    total: u64,
+    limit: Option<u64>,
+}
+
+impl AsyncWrite for Output {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        this.total += data.len() as u64;
+        if let Some(limit) = this.limit.filter(|limit| this.total > *limit) {
+            return Poll::Ready(Err(Error::new(
+                ErrorKind::Other,
+                format!("Response body exceeds maxResponseSize ({limit} bytes)"),
+            )));
+        }
+        this.buf.extend_from_slice(data);
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Decoder {
+    /// Create a decoder for the encoding of the response, producing at most `limit` bytes.
+    /// Returns None if the body is not compressed or the encoding is unknown.
+    pub fn from_headers(headers: &HeaderMap, limit: Option<u64>) -> Option<Self> {
+        let encoding = headers.get(CONTENT_ENCODING)?.to_str().ok()?;
+        let output = Output {
+            buf: Vec::new(),
+            total: 0,
+            limit,
+        };
+        match encoding.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Self::Gzip(GzipDecoder::new(output))),
+            "deflate" => Some(Self::Deflate(ZlibDecoder::new(output))),
+            "br" => Some(Self::Brotli(BrotliDecoder::new(output))),
+            _ => None,
+        }
+    }
+
+    fn writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
+        match self {
+            Self::Gzip(d) => d,
+            Self::Deflate(d) => d,
+            Self::Brotli(d) => d,
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        let output = match self {
+            Self::Gzip(d) => d.get_mut(),
+            Self::Deflate(d) => d.get_mut(),
+            Self::Brotli(d) => d.get_mut(),
+        };
+        core::mem::take(&mut output.buf)
+    }
+
+    /// Feed a chunk of compressed data and return the bytes decoded so far.
+    pub async fn decode(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.writer().write_all(chunk).await?;
+        Ok(self.take_output())
+    }
+
+    /// Flush the remaining decoded bytes at the end of the body.
+    pub async fn finish(&mut self) -> Result<Vec<u8>> {
+        self.writer().shutdown().await?;
+        Ok(self.take_output())
+    }
+}
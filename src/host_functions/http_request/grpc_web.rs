@@ -0,0 +1,194 @@
+//! Unary gRPC-web calls over the http stack, see `grpcWebCall` in sidevm.js.
+//!
+//! Each message is framed as a flag byte, its length in 4 big endian bytes and the protobuf
+//! bytes. The response carries the messages in such frames followed by a trailer frame, flagged
+//! with `0x80`, holding the `grpc-status` and `grpc-message` as http headers. Servers may also
+//! send the status in the response headers when there is no message, or as http trailers.
+
+use super::*;
+
+const FLAG_COMPRESSED: u8 = 0x01;
+const FLAG_TRAILERS: u8 = 0x80;
+/// The `UNKNOWN` status code.
+const STATUS_UNKNOWN: u32 = 2;
+
+pub(super) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("grpcWebCall", grpc_web_call)?;
+    Ok(())
+}
+
+#[derive(ToJsValue, Debug)]
+#[qjsbind(rename_all = "camelCase")]
+struct GrpcWebResponse {
+    /// The gRPC status code, 0 for OK.
+    status: u32,
+    /// The `grpc-message`, percent-decoded.
+    status_message: String,
+    headers: Headers,
+    trailers: Headers,
+    messages: Vec<AsBytes<Vec<u8>>>,
+}
+
+/// Call `/{service}/{method}` of the gRPC-web server at `req.url` with the protobuf `message`.
+///
+/// `req` takes the options of `httpRequest`, its `headers` being the call metadata. The method
+/// and the body are set here. The callback receives `("response", GrpcWebResponse)` whatever
+/// the gRPC status, or `("error", message)` if the call didn't complete.
+#[js::host_call(with_context)]
+fn grpc_web_call(
+    service: ServiceRef,
+    _this: js::Value,
+    mut req: HttpRequest,
+    grpc_service: String,
+    method: String,
+    message: AsBytes<Vec<u8>>,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    req.url = format!("{}/{grpc_service}/{method}", req.url.trim_end_matches('/'));
+    parse_request_url(&req.url)?;
+    let len = u32::try_from(message.0.len()).context("The message is too large")?;
+    let mut body = Vec::with_capacity(5 + message.0.len());
+    body.push(0);
+    body.extend_from_slice(&len.to_be_bytes());
+    body.extend_from_slice(&message.0);
+    req.method = "POST".into();
+    req.body = body;
+    req.text_body = None;
+    req.form_data = None;
+    req.header_encoding = HeaderEncoding::Utf8;
+    req.redirect = RedirectPolicy::Error;
+    req.headers.pairs.retain(|(k, _)| {
+        !["content-type", "accept", "x-grpc-web"]
+            .iter()
+            .any(|name| k.eq_ignore_ascii_case(name))
+    });
+    req.headers.pairs.extend([
+        ("Content-Type".into(), "application/grpc-web+proto".into()),
+        ("Accept".into(), "application/grpc-web+proto".into()),
+        ("X-Grpc-Web".into(), "1".into()),
+    ]);
+    service.spawn(callback, do_grpc_web_call, req)
+}
+
+async fn do_grpc_web_call(weak_service: ServiceWeakRef, id: u64, req: HttpRequest) {
+    let url = req.url.clone();
+    let mut sink = CollectSink::default();
+    let result = request_with_timeout(&weak_service, req, &mut sink)
+        .await
+        .and_then(|()| {
+            let head = sink.head.context("No response")?;
+            parse_response(head, sink.body, sink.trailers)
+        });
+    match result {
+        Ok(response) => invoke_callback(&weak_service, id, "response", &response),
+        Err(err) if err.is::<RateLimited>() => {
+            invoke_callback(&weak_service, id, "rateLimited", &err.to_string())
+        }
+        Err(err) => invoke_callback(
+            &weak_service,
+            id,
+            "error",
+            &format!("Failed to call `{url}`: {err:?}"),
+        ),
+    }
+}
+
+fn parse_response(
+    head: HttpResponseHead,
+    body: Vec<u8>,
+    http_trailers: Option<Headers>,
+) -> Result<GrpcWebResponse> {
+    let mut messages = vec![];
+    let mut trailers = http_trailers.unwrap_or_default();
+    let mut rest = &body[..];
+    while !rest.is_empty() {
+        if rest.len() < 5 {
+            anyhow::bail!("Truncated gRPC-web frame header");
+        }
+        let flags = rest[0];
+        let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+        let frame = rest[5..].get(..len).context("Truncated gRPC-web frame")?;
+        rest = &rest[5 + len..];
+        if flags & FLAG_TRAILERS != 0 {
+            trailers.pairs.extend(parse_trailers(frame)?);
+        } else if flags & FLAG_COMPRESSED != 0 {
+            anyhow::bail!("Compressed gRPC-web messages are not supported");
+        } else {
+            messages.push(AsBytes(frame.to_vec()));
+        }
+    }
+    // A trailers-only response has the status in the headers.
+    let status = find(&trailers, "grpc-status")
+        .or_else(|| find(&head.headers, "grpc-status"))
+        .map(|status| status.trim().parse().unwrap_or(STATUS_UNKNOWN));
+    let status_message = find(&trailers, "grpc-message")
+        .or_else(|| find(&head.headers, "grpc-message"))
+        .map(percent_decode)
+        .unwrap_or_default();
+    let (status, status_message) = match status {
+        Some(status) => (status, status_message),
+        None if head.status != 200 => (
+            status_from_http(head.status),
+            format!("HTTP status {} {}", head.status, head.status_text),
+        ),
+        None => (STATUS_UNKNOWN, "Missing grpc-status".into()),
+    };
+    Ok(GrpcWebResponse {
+        status,
+        status_message,
+        headers: head.headers,
+        trailers,
+        messages,
+    })
+}
+
+/// The trailer frame is a block of `name: value` lines as in http/1.1.
+fn parse_trailers(frame: &[u8]) -> Result<Vec<(String, String)>> {
+    let text = core::str::from_utf8(frame).context("Invalid gRPC-web trailers")?;
+    Ok(text
+        .split("\r\n")
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect())
+}
+
+fn find<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers
+        .pairs
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// The status for a response without `grpc-status`, as mapped by the gRPC http spec.
+fn status_from_http(status: u16) -> u32 {
+    match status {
+        400 => 13,
+        401 => 16,
+        403 => 7,
+        404 => 12,
+        429 | 502 | 503 | 504 => 14,
+        _ => STATUS_UNKNOWN,
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next(), iter.next()];
+            if let [Some(hi), Some(lo)] = hex {
+                if let Ok(byte) = u8::from_str_radix(&String::from_utf8_lossy(&[hi, lo]), 16) {
+                    bytes.push(byte);
+                    continue;
+                }
+            }
+            bytes.push(b);
+            bytes.extend(hex.into_iter().flatten());
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
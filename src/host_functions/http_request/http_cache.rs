@@ -0,0 +1,344 @@
+//! The http cache of the GET requests, see `Service::set_http_cache`.
+//!
+//! The responses are kept in the worker cache, so that the scripts sharing a worker share them,
+//! keyed by the url, the proxy or Unix domain socket and the headers actually sent: the defaults
+//! and the interceptor of the service are applied before, and the cookies of the jar are
+//! included. The entries live outside of the keys reachable through `Sidevm.cache`, so the
+//! scripts can't read or forge them.
+//!
+//! As the services of the worker share the entries, a cached response is only replayed once the
+//! egress filter of the service lets through its url and the final url it was received from, and
+//! within the `maxResponseSize` of the request.
+//!
+//! A fresh response, by `max-age`, `s-maxage` or `Expires`, is replayed without a request. A
+//! stale one with an `ETag` or `Last-Modified` is revalidated with `If-None-Match` or
+//! `If-Modified-Since`. Responses marked `no-store` or `private` are never stored, nor those to
+//! requests with credentials unless marked `public`.
+//!
+//! The responses of the requests going through the cache are buffered, so they are reported to
+//! JS at once, without `progress` events.
+
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::cache::backend;
+use super::*;
+
+const KEY_PREFIX: &[u8] = b"http-cache\0";
+/// How long an entry is kept past its freshness for revalidation.
+const REVALIDATION_TTL_SECS: u64 = 3600;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    /// When the response was received or last revalidated, in seconds since the unix epoch.
+    stored_at: u64,
+    /// How long the response is fresh after `stored_at`, in seconds.
+    max_age: Option<u64>,
+    /// The response has to be revalidated before each use.
+    no_cache: bool,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    status: u16,
+    status_text: String,
+    version: String,
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+/// The caching directives of a response.
+#[derive(Default)]
+struct Directives {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    max_age: Option<u64>,
+}
+
+impl Directives {
+    fn parse(headers: &Headers, now: u64) -> Self {
+        let mut directives = Self::default();
+        let mut s_maxage = None;
+        for (_, value) in headers
+            .pairs
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case("cache-control"))
+        {
+            for directive in value.split(',') {
+                let (name, arg) = match directive.split_once('=') {
+                    Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                    None => (directive.trim(), None),
+                };
+                match name.to_ascii_lowercase().as_str() {
+                    "no-store" => directives.no_store = true,
+                    "no-cache" => directives.no_cache = true,
+                    "private" => directives.private = true,
+                    "public" => directives.public = true,
+                    "max-age" => directives.max_age = arg.and_then(|arg| arg.parse().ok()),
+                    "s-maxage" => s_maxage = arg.and_then(|arg| arg.parse().ok()),
+                    _ => {}
+                }
+            }
+        }
+        // This is a shared cache, `s-maxage` takes precedence.
+        directives.max_age = s_maxage.or(directives.max_age).or_else(|| {
+            let expires = header(headers, "expires")?;
+            let expires = httpdate::parse_http_date(expires).ok()?;
+            Some(unix_secs(expires).saturating_sub(now))
+        });
+        if let Some(age) = header(headers, "age").and_then(|age| age.parse::<u64>().ok()) {
+            directives.max_age = directives
+                .max_age
+                .map(|max_age| max_age.saturating_sub(age));
+        }
+        directives
+    }
+}
+
+impl Entry {
+    /// The entry for the response, if it can be stored.
+    fn new(head: &HttpResponseHead, with_credentials: bool, now: u64) -> Option<Self> {
+        let directives = Directives::parse(&head.headers, now);
+        let etag = header(&head.headers, "etag").map(Into::into);
+        let last_modified = header(&head.headers, "last-modified").map(Into::into);
+        let storable = head.status == 200
+            && !directives.no_store
+            && !directives.private
+            && (!with_credentials || directives.public)
+            && (directives.max_age.is_some() || etag.is_some() || last_modified.is_some());
+        if !storable {
+            return None;
+        }
+        Some(Self {
+            stored_at: now,
+            max_age: directives.max_age,
+            no_cache: directives.no_cache,
+            etag,
+            last_modified,
+            status: head.status,
+            status_text: head.status_text.clone(),
+            version: head.version.clone(),
+            url: head.url.clone(),
+            headers: head.headers.pairs.clone(),
+        })
+    }
+
+    fn is_fresh(&self, now: u64) -> bool {
+        !self.no_cache
+            && self
+                .max_age
+                .is_some_and(|max_age| now < self.stored_at.saturating_add(max_age))
+    }
+
+    /// Refresh the entry with the headers of a `304 Not Modified`.
+    fn revalidated(&mut self, headers: &Headers, now: u64) {
+        let directives = Directives::parse(headers, now);
+        self.stored_at = now;
+        if header(headers, "cache-control").is_some() || header(headers, "expires").is_some() {
+            self.max_age = directives.max_age;
+            self.no_cache = directives.no_cache;
+        }
+        if let Some(etag) = header(headers, "etag") {
+            self.etag = Some(etag.into());
+        }
+    }
+
+    fn head(&self) -> HttpResponseHead {
+        HttpResponseHead {
+            status: self.status,
+            status_text: self.status_text.clone(),
+            version: self.version.clone(),
+            url: self.url.clone(),
+            headers: self.headers.clone().into(),
+            raw_headers: None,
+        }
+    }
+}
+
+/// Whether `req` goes through the http cache.
+pub(super) fn applies(weak_service: &ServiceWeakRef, req: &HttpRequest) -> bool {
+    let conditional = ["if-none-match", "if-modified-since", "range"]
+        .iter()
+        .any(|name| req.headers.contains(name));
+    req.cache != CacheMode::NoStore
+        && req.method.eq_ignore_ascii_case("GET")
+        && req.header_encoding != HeaderEncoding::Bytes
+        && !conditional
+        && weak_service
+            .upgrade()
+            .is_some_and(|service| service.http_cache_enabled())
+}
+
+pub(super) async fn request(
+    weak_service: &ServiceWeakRef,
+    mut req: HttpRequest,
+    sink: &mut impl ResponseSink,
+) -> Result<()> {
+    let url = parse_request_url(&req.url)?;
+    check_egress_url(weak_service, &url).await?;
+    let sent_headers = sent_headers(weak_service, &req)?;
+    let key = cache_key(weak_service, &req, &sent_headers);
+    let now = unix_secs(SystemTime::now());
+    let max_response_size = response_size_limit(weak_service, req.max_response_size);
+    let cached = match req.cache {
+        CacheMode::Reload => None,
+        _ => load(&key),
+    };
+    if let Some((entry, _)) = &cached {
+        if entry.url != req.url {
+            check_egress_url(weak_service, &parse_request_url(&entry.url)?).await?;
+        }
+    }
+    if let Some((entry, body)) = &cached {
+        let usable = match req.cache {
+            CacheMode::ForceCache | CacheMode::OnlyIfCached => true,
+            CacheMode::NoCache => false,
+            _ => entry.is_fresh(now),
+        };
+        if usable {
+            return replay(sink, entry, body, max_response_size);
+        }
+    }
+    if req.cache == CacheMode::OnlyIfCached {
+        anyhow::bail!("The response is not cached");
+    }
+    if let Some((entry, _)) = &cached {
+        if let Some(etag) = &entry.etag {
+            req.headers
+                .pairs
+                .push(("If-None-Match".into(), etag.clone()));
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            req.headers
+                .pairs
+                .push(("If-Modified-Since".into(), last_modified.clone()));
+        }
+    }
+    let with_credentials =
+        sent_headers.contains("authorization") || sent_headers.contains("cookie");
+    let mut collected = CollectSink::default();
+    do_http_request_inner(weak_service, req, &mut collected).await?;
+    let head = collected.head.context("No response")?;
+    if let (304, Some((mut entry, body))) = (head.status, cached) {
+        entry.revalidated(&head.headers, now);
+        store(&key, &entry, &body);
+        return replay(sink, &entry, &body, max_response_size);
+    }
+    if let Some(entry) = Entry::new(&head, with_credentials, now) {
+        store(&key, &entry, &collected.body);
+    }
+    sink.head(head);
+    if !collected.body.is_empty() {
+        sink.data(collected.body);
+    }
+    if let Some(trailers) = collected.trailers {
+        sink.trailers(trailers);
+    }
+    Ok(())
+}
+
+fn replay(
+    sink: &mut impl ResponseSink,
+    entry: &Entry,
+    body: &[u8],
+    max_response_size: Option<u64>,
+) -> Result<()> {
+    check_response_size(body.len() as u64, max_response_size)?;
+    sink.head(entry.head());
+    if !body.is_empty() {
+        sink.data(body.to_vec());
+    }
+    Ok(())
+}
+
+/// The headers of the request along with the cookies the jar adds to it.
+fn sent_headers(weak_service: &ServiceWeakRef, req: &HttpRequest) -> Result<Headers> {
+    #[cfg(not(feature = "web"))]
+    return Ok(with_cookies(
+        weak_service,
+        &parse_request_url(&req.url)?,
+        &req.headers,
+    ));
+    #[cfg(feature = "web")]
+    {
+        let _ = weak_service;
+        Ok(req.headers.clone())
+    }
+}
+
+/// The url, the route and the headers sent, which may change the response. The body is decoded
+/// only if `decompress` is set, so it's part of the key as well.
+fn cache_key(weak_service: &ServiceWeakRef, req: &HttpRequest, sent_headers: &Headers) -> Vec<u8> {
+    // The proxy in use, which a script may set on the request or the service by default
+    let proxy = req
+        .proxy
+        .clone()
+        .or_else(|| {
+            weak_service
+                .upgrade()
+                .and_then(|service| service.http_proxy())
+        })
+        .unwrap_or_default();
+    let mut headers: Vec<_> = sent_headers
+        .pairs
+        .iter()
+        .map(|(k, v)| format!("{}:{v}", k.to_ascii_lowercase()))
+        .collect();
+    headers.sort();
+    let mut key = KEY_PREFIX.to_vec();
+    key.extend_from_slice(req.url.as_bytes());
+    key.push(0);
+    key.push(req.decompress as u8);
+    key.push(0);
+    key.extend_from_slice(
+        req.unix_socket_path
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    key.push(0);
+    key.extend_from_slice(proxy.as_bytes());
+    for header in headers {
+        key.push(0);
+        key.extend_from_slice(header.as_bytes());
+    }
+    key
+}
+
+/// Entries are stored as the length of the JSON of the `Entry` in 4 bytes, the JSON and the body.
+fn store(key: &[u8], entry: &Entry, body: &[u8]) {
+    let Ok(json) = serde_json::to_vec(entry) else {
+        return;
+    };
+    let mut value = (json.len() as u32).to_le_bytes().to_vec();
+    value.extend_from_slice(&json);
+    value.extend_from_slice(body);
+    let ttl = entry
+        .max_age
+        .unwrap_or(0)
+        .saturating_add(REVALIDATION_TTL_SECS);
+    if let Err(err) = backend::set(key, &value, Some(ttl)) {
+        log::warn!("Failed to store the http response in the cache: {err:?}");
+    }
+}
+
+fn load(key: &[u8]) -> Option<(Entry, Vec<u8>)> {
+    let value = backend::get(key).ok()??;
+    let len = u32::from_le_bytes(value.get(..4)?.try_into().ok()?) as usize;
+    let json = value.get(4..4 + len)?;
+    let entry = serde_json::from_slice(json).ok()?;
+    Some((entry, value[4 + len..].to_vec()))
+}
+
+fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers
+        .pairs
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
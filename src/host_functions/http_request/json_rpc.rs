@@ -0,0 +1,268 @@
+//! JSON-RPC 2.0 over the http stack, the host side of `Sidevm.jsonRpc` in chain-rpc.js.
+//!
+//! The host assigns the ids, sends the calls as one object or as a batch array and matches the
+//! responses back to the calls, which may come in any order. The body is parsed whatever the http
+//! status, as servers commonly answer an RPC error with a 4xx or 5xx status. Only a response that
+//! carries no JSON-RPC answer is a transport error.
+
+use serde_json::{Map, Value as Json};
+
+use super::*;
+
+pub(super) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("jsonRpcCall", json_rpc_call)?;
+    Ok(())
+}
+
+#[derive(FromJsValue, Debug)]
+struct RpcCall {
+    method: String,
+    /// The JSON of the params, omitted from the request if not set.
+    params: Option<String>,
+    /// A notification gets no response.
+    #[qjsbind(default)]
+    notification: bool,
+}
+
+/// The answer to a call, `result` being the JSON of the result.
+#[derive(ToJsValue, Debug, Default)]
+struct RpcOutcome {
+    result: Option<String>,
+    error: Option<RpcError>,
+}
+
+/// An error object returned by the server, `data` being its JSON.
+#[derive(ToJsValue, Debug, Clone)]
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<String>,
+}
+
+/// The request didn't get a JSON-RPC answer, `status` is the http status if any.
+#[derive(ToJsValue, Debug)]
+struct TransportError {
+    message: String,
+    status: Option<u16>,
+}
+
+/// Send `calls` to the JSON-RPC server at `req.url`, as a batch array if `batch` is set.
+///
+/// `req` takes the options of `httpRequest`, the method and the body are set here. The callback
+/// receives `("response", outcomes)` with an `RpcOutcome` per call that isn't a notification, in
+/// the order of the calls, `("transportError", TransportError)` or `("rateLimited", message)`.
+#[js::host_call(with_context)]
+fn json_rpc_call(
+    service: ServiceRef,
+    _this: js::Value,
+    mut req: HttpRequest,
+    calls: Vec<RpcCall>,
+    batch: bool,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    parse_request_url(&req.url)?;
+    if !batch && calls.len() != 1 {
+        anyhow::bail!("A single call is expected outside of a batch");
+    }
+    let messages = calls
+        .iter()
+        .enumerate()
+        .map(|(index, call)| request_message(index, call))
+        .collect::<Result<Vec<_>>>()?;
+    let body = if batch {
+        Json::Array(messages)
+    } else {
+        messages.into_iter().next().unwrap_or_default()
+    };
+    req.method = "POST".into();
+    req.body = serde_json::to_vec(&body)?;
+    req.text_body = None;
+    req.form_data = None;
+    req.header_encoding = HeaderEncoding::Utf8;
+    req.headers.pairs.retain(|(k, _)| {
+        !["content-type", "accept"]
+            .iter()
+            .any(|name| k.eq_ignore_ascii_case(name))
+    });
+    req.headers.pairs.extend([
+        ("Content-Type".into(), "application/json".into()),
+        ("Accept".into(), "application/json".into()),
+    ]);
+    let ids: Vec<_> = calls
+        .iter()
+        .enumerate()
+        .filter(|(_, call)| !call.notification)
+        .map(|(index, _)| index as u64)
+        .collect();
+    service.spawn(callback, do_json_rpc_call, (req, ids))
+}
+
+/// The request object of the call, its id being its index in the batch.
+fn request_message(index: usize, call: &RpcCall) -> Result<Json> {
+    let mut message = Map::new();
+    message.insert("jsonrpc".into(), "2.0".into());
+    message.insert("method".into(), call.method.clone().into());
+    if let Some(params) = &call.params {
+        let params: Json = serde_json::from_str(params).context("Invalid params")?;
+        message.insert("params".into(), params);
+    }
+    if !call.notification {
+        message.insert("id".into(), (index as u64).into());
+    }
+    Ok(Json::Object(message))
+}
+
+async fn do_json_rpc_call(
+    weak_service: ServiceWeakRef,
+    id: u64,
+    (req, ids): (HttpRequest, Vec<u64>),
+) {
+    let url = req.url.clone();
+    let mut sink = CollectSink::default();
+    let result = request_with_timeout(&weak_service, req, &mut sink).await;
+    let result = match result {
+        Ok(()) => match sink.head {
+            Some(head) => parse_response(head.status, &sink.body, &ids),
+            None => Err(TransportError {
+                message: format!("No response from `{url}`"),
+                status: None,
+            }),
+        },
+        Err(err) if err.is::<RateLimited>() => {
+            invoke_callback(&weak_service, id, "rateLimited", &err.to_string());
+            return;
+        }
+        Err(err) => Err(TransportError {
+            message: format!("Failed to call `{url}`: {err:?}"),
+            status: None,
+        }),
+    };
+    match result {
+        Ok(outcomes) => invoke_callback(&weak_service, id, "response", &outcomes),
+        Err(err) => invoke_callback(&weak_service, id, "transportError", &err),
+    }
+}
+
+/// Match the answers in `body` to the calls with `ids`.
+///
+/// An error object answering a whole batch, e.g. a parse error, is the answer to every call.
+fn parse_response(
+    status: u16,
+    body: &[u8],
+    ids: &[u64],
+) -> Result<Vec<RpcOutcome>, TransportError> {
+    let transport_error = |message: String| TransportError {
+        message,
+        status: Some(status),
+    };
+    let ok = (200..300).contains(&status);
+    let text = String::from_utf8_lossy(body);
+    if ids.is_empty() && ok {
+        return Ok(vec![]);
+    }
+    let parsed: Option<Json> = serde_json::from_str(&text).ok();
+    let answers = match parsed {
+        Some(Json::Array(answers)) => answers,
+        Some(answer @ Json::Object(_)) => vec![answer],
+        _ if !ok => {
+            return Err(transport_error(format!(
+                "RPC request failed with status {status}: {text}"
+            )))
+        }
+        _ => return Err(transport_error("Invalid JSON-RPC response".into())),
+    };
+    // The server couldn't tell the ids, its error answers all the calls
+    if let [answer] = &answers[..] {
+        if answer.get("id").map_or(true, Json::is_null) {
+            if let Some(error) = answer.get("error").filter(|error| !error.is_null()) {
+                let error = rpc_error(error);
+                return Ok(ids
+                    .iter()
+                    .map(|_| RpcOutcome {
+                        result: None,
+                        error: Some(error.clone()),
+                    })
+                    .collect());
+            }
+        }
+    }
+    let mut outcomes: BTreeMap<u64, RpcOutcome> = BTreeMap::new();
+    for answer in &answers {
+        let Some(id) = answer.get("id").and_then(Json::as_u64) else {
+            continue;
+        };
+        let outcome = match (answer.get("error"), answer.get("result")) {
+            (Some(error), _) if !error.is_null() => RpcOutcome {
+                result: None,
+                error: Some(rpc_error(error)),
+            },
+            (_, Some(result)) => RpcOutcome {
+                result: Some(result.to_string()),
+                error: None,
+            },
+            _ => continue,
+        };
+        outcomes.insert(id, outcome);
+    }
+    if outcomes.is_empty() && !ok {
+        return Err(transport_error(format!(
+            "RPC request failed with status {status}: {text}"
+        )));
+    }
+    Ok(ids
+        .iter()
+        .map(|id| outcomes.remove(id).unwrap_or_default())
+        .collect())
+}
+
+fn rpc_error(error: &Json) -> RpcError {
+    RpcError {
+        code: error.get("code").and_then(Json::as_i64).unwrap_or_default(),
+        message: error
+            .get("message")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .into(),
+        data: error.get("data").map(Json::to_string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_with_failure_status_is_an_rpc_error() {
+        let body = br#"{"jsonrpc":"2.0","id":0,"error":{"code":-32000,"message":"reverted"}}"#;
+        let outcomes = parse_response(500, body, &[0]).unwrap();
+        let error = outcomes[0].error.as_ref().unwrap();
+        assert_eq!(error.code, -32000);
+        assert_eq!(error.message, "reverted");
+    }
+
+    #[test]
+    fn failure_status_without_answer_is_a_transport_error() {
+        let err = parse_response(502, b"Bad Gateway", &[0]).unwrap_err();
+        assert_eq!(err.status, Some(502));
+    }
+
+    #[test]
+    fn batch_answers_are_matched_by_id() {
+        let body =
+            br#"[{"jsonrpc":"2.0","id":2,"result":"0x2"},{"jsonrpc":"2.0","id":0,"result":null}]"#;
+        let outcomes = parse_response(200, body, &[0, 1, 2]).unwrap();
+        assert_eq!(outcomes[0].result.as_deref(), Some("null"));
+        assert!(outcomes[1].result.is_none() && outcomes[1].error.is_none());
+        assert_eq!(outcomes[2].result.as_deref(), Some("\"0x2\""));
+    }
+
+    #[test]
+    fn batch_rejected_as_a_whole() {
+        let body =
+            br#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"Parse error"}}"#;
+        let outcomes = parse_response(400, body, &[0, 1]).unwrap();
+        assert!(outcomes
+            .iter()
+            .all(|outcome| outcome.error.as_ref().unwrap().code == -32700));
+    }
+}
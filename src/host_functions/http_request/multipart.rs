@@ -0,0 +1,70 @@
+use anyhow::{bail, Result};
+use js::FromJsValue;
+
+/// A field of a `multipart/form-data` body.
+#[derive(FromJsValue, Debug)]
+#[qjsbind(rename_all = "camelCase")]
+pub(super) struct FormField {
+    name: String,
+    /// Text value of the field, takes precedence over `data`.
+    value: Option<String>,
+    #[qjsbind(default, as_bytes)]
+    data: Vec<u8>,
+    /// Set to send the field as a file.
+    filename: Option<String>,
+    /// Defaults to `application/octet-stream` for files.
+    content_type: Option<String>,
+}
+
+/// Serialize the fields as a multipart body, returning the content type along with the body.
+pub(super) fn encode(fields: &[FormField]) -> Result<(String, Vec<u8>)> {
+    let boundary = generate_boundary()?;
+    let mut body = Vec::new();
+    for field in fields {
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        let mut disposition = format!("form-data; name=\"{}\"", escape(&field.name));
+        if let Some(filename) = &field.filename {
+            disposition.push_str(&format!("; filename=\"{}\"", escape(filename)));
+        }
+        body.extend_from_slice(format!("Content-Disposition: {disposition}\r\n").as_bytes());
+        let content_type = match (&field.content_type, &field.filename) {
+            (Some(content_type), _) => Some(content_type.as_str()),
+            (None, Some(_)) => Some("application/octet-stream"),
+            (None, None) => None,
+        };
+        if let Some(content_type) = content_type {
+            if content_type.contains(['\r', '\n']) {
+                bail!("Invalid content type of the form field {:?}", field.name);
+            }
+            body.extend_from_slice(format!("Content-Type: {content_type}\r\n").as_bytes());
+        }
+        body.extend_from_slice(b"\r\n");
+        match &field.value {
+            Some(value) => body.extend_from_slice(value.as_bytes()),
+            None => body.extend_from_slice(&field.data),
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    Ok((format!("multipart/form-data; boundary={boundary}"), body))
+}
+
+fn generate_boundary() -> Result<String> {
+    let mut nonce = [0u8; 16];
+    // The runtimes report the failure with different types, none yields a value on failure.
+    if crate::runtime::getrandom(&mut nonce)
+        .into_iter()
+        .next()
+        .is_none()
+    {
+        bail!("Failed to get random bytes for the multipart boundary");
+    }
+    Ok(format!("----SidevmFormBoundary{}", hex::encode(nonce)))
+}
+
+/// Escape a name in a quoted header parameter the way browsers do.
+fn escape(name: &str) -> String {
+    name.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
@@ -0,0 +1,274 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+};
+use hyper::{service::Service, Uri};
+use hyper_rustls::HttpsConnector;
+use std::net::IpAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::host_functions::check_egress;
+use crate::runtime::{https_connector, HyperExecutor};
+use crate::service::{Service, ServiceWeakRef};
+
+/// Longest proxy response head accepted for a CONNECT request.
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+/// The proxy clients kept by a service, one is dropped for each new url beyond.
+const MAX_PROXY_CLIENTS: usize = 16;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub(crate) type ProxyClient = hyper::Client<HttpsConnector<ProxyConnector>, hyper::Body>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    /// Tunnel through an HTTP proxy with the CONNECT method.
+    Http,
+    /// SOCKS5 with hostname resolution on the proxy side.
+    Socks5,
+}
+
+/// A proxy server given as `http://[user:pass@]host:port` or `socks5://[user:pass@]host:port`.
+#[derive(Debug, Clone)]
+pub(super) struct Proxy {
+    protocol: Protocol,
+    host: String,
+    port: u16,
+    auth: Option<(String, String)>,
+}
+
+impl Proxy {
+    pub fn parse(url: &str) -> Result<Self> {
+        let parsed: ::url::Url = url
+            .parse()
+            .with_context(|| format!("Invalid proxy url: {url}"))?;
+        let (protocol, default_port) = match parsed.scheme() {
+            "http" => (Protocol::Http, 80),
+            "socks5" | "socks5h" => (Protocol::Socks5, 1080),
+            scheme => bail!("Unsupported proxy scheme: {scheme}"),
+        };
+        let host = parsed
+            .host_str()
+            .with_context(|| format!("Missing host in proxy url: {url}"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let auth = if parsed.username().is_empty() {
+            None
+        } else {
+            Some((
+                parsed.username().to_string(),
+                parsed.password().unwrap_or_default().to_string(),
+            ))
+        };
+        Ok(Self {
+            protocol,
+            host,
+            port: parsed.port().unwrap_or(default_port),
+            auth,
+        })
+    }
+
+    /// Check the proxy server against the egress filter of the service, for the proxies chosen by
+    /// the scripts.
+    pub async fn check_egress(&self, weak_service: &ServiceWeakRef) -> Result<()> {
+        check_egress(weak_service, &self.host, self.port).await
+    }
+
+    /// Open a tunnel to `host:port` through the proxy.
+    async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("Failed to connect to proxy {}:{}", self.host, self.port))?;
+        match self.protocol {
+            Protocol::Http => self.http_connect(&mut stream, host, port).await?,
+            Protocol::Socks5 => self.socks5_connect(&mut stream, host, port).await?,
+        }
+        Ok(stream)
+    }
+
+    async fn http_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> Result<()> {
+        let target = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{host}]:{port}"),
+            _ => format!("{host}:{port}"),
+        };
+        let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+        if let Some((user, password)) = &self.auth {
+            let credentials = STANDARD.encode(format!("{user}:{password}"));
+            request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read byte by byte so that nothing after the response head is consumed.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_CONNECT_RESPONSE {
+                bail!("Proxy response head too large");
+            }
+            head.push(
+                stream
+                    .read_u8()
+                    .await
+                    .context("Proxy closed the connection")?,
+            );
+        }
+        let status_line = String::from_utf8_lossy(&head);
+        let status_line = status_line.lines().next().unwrap_or_default();
+        let status = status_line.split_whitespace().nth(1);
+        if status != Some("200") {
+            bail!("Proxy refused to connect to {target}: {status_line}");
+        }
+        Ok(())
+    }
+
+    async fn socks5_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> Result<()> {
+        const VERSION: u8 = 5;
+        const NO_AUTH: u8 = 0;
+        const USER_PASS: u8 = 2;
+
+        let methods: &[u8] = if self.auth.is_some() {
+            &[NO_AUTH, USER_PASS]
+        } else {
+            &[NO_AUTH]
+        };
+        stream.write_all(&[VERSION, methods.len() as u8]).await?;
+        stream.write_all(methods).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        match (reply, &self.auth) {
+            ([VERSION, NO_AUTH], _) => {}
+            ([VERSION, USER_PASS], Some((user, password))) => {
+                if user.len() > 255 || password.len() > 255 {
+                    bail!("SOCKS5 credentials too long");
+                }
+                let mut request = vec![1, user.len() as u8];
+                request.extend_from_slice(user.as_bytes());
+                request.push(password.len() as u8);
+                request.extend_from_slice(password.as_bytes());
+                stream.write_all(&request).await?;
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0 {
+                    bail!("SOCKS5 authentication failed");
+                }
+            }
+            _ => bail!("SOCKS5 proxy accepts none of the authentication methods"),
+        }
+
+        let mut request = vec![VERSION, 1, 0];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(1);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                if host.len() > 255 {
+                    bail!("Hostname too long for SOCKS5: {host}");
+                }
+                request.push(3);
+                request.push(host.len() as u8);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            bail!(
+                "SOCKS5 proxy refused to connect to {host}:{port}: {}",
+                socks5_error(reply[1])
+            );
+        }
+        // Skip the bound address and port
+        let addr_len = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => stream.read_u8().await? as usize,
+            atyp => bail!("Invalid SOCKS5 address type: {atyp}"),
+        };
+        let mut bound = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+}
+
+fn socks5_error(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+/// A hyper connector that reaches every host through a tunnel opened by the proxy.
+///
+/// Plain http requests are tunneled as well rather than being sent to the proxy in absolute form.
+#[derive(Clone)]
+pub(crate) struct ProxyConnector {
+    proxy: Proxy,
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Response = TcpStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<TcpStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let proxy = self.proxy.clone();
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .context("Missing host in url")?
+                .trim_start_matches('[')
+                .trim_end_matches(']');
+            let port = match (uri.port_u16(), uri.scheme_str()) {
+                (Some(port), _) => port,
+                (None, Some("https")) => 443,
+                (None, _) => 80,
+            };
+            Ok(proxy.connect(host, port).await?)
+        })
+    }
+}
+
+/// The client of the proxy at `url`, kept by the service so that the connections are reused.
+pub(super) fn proxy_client(service: &Service, url: &str) -> Result<ProxyClient> {
+    if let Some(client) = service.with_proxy_clients(|clients| clients.get(url).cloned()) {
+        return Ok(client);
+    }
+    let connector = https_connector(ProxyConnector {
+        proxy: Proxy::parse(url)?,
+    });
+    let client: ProxyClient = hyper::Client::builder()
+        .executor(HyperExecutor)
+        .build(connector);
+    service.with_proxy_clients(|clients| {
+        if clients.len() >= MAX_PROXY_CLIENTS {
+            clients.pop_first();
+        }
+        clients.insert(url.into(), client.clone());
+    });
+    Ok(client)
+}
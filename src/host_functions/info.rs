@@ -0,0 +1,60 @@
+use js::{AsBytes, ToJsValue};
+
+use super::*;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("sideVmInfo", side_vm_info)?;
+    Ok(())
+}
+
+#[derive(ToJsValue)]
+#[qjsbind(rename_all = "camelCase")]
+struct SideVmInfo {
+    /// The public key of the worker running the sidevm, if known to the runtime.
+    worker_pubkey: Option<AsBytes<Vec<u8>>>,
+    /// The id of the sidevm instance, which is the address of the contract that deployed it.
+    instance_id: Option<AsBytes<Vec<u8>>>,
+    /// The blake2b256 hash of the entry code, see `Service::set_entry_code`.
+    code_hash: Option<AsBytes<Vec<u8>>>,
+    /// The memory limit of the service in bytes, if set.
+    memory_limit: Option<u64>,
+    /// `native`, `sidevm` or `web`.
+    runtime: &'static str,
+    /// The version of this crate.
+    version: &'static str,
+}
+
+/// The hash reported as `codeHash`, unavailable without the `js-hash` feature.
+pub(crate) fn code_hash(code: &[u8]) -> Option<Vec<u8>> {
+    #[cfg(feature = "js-hash")]
+    {
+        use blake2::{digest::typenum::U32, Blake2b};
+        Some(super::hash::do_hash::<Blake2b<U32>>(code))
+    }
+    #[cfg(not(feature = "js-hash"))]
+    {
+        let _ = code;
+        None
+    }
+}
+
+/// Describe the worker and the environment the script runs in, e.g. to include the provenance
+/// of its outputs.
+#[js::host_call(with_context)]
+fn side_vm_info(service: ServiceRef, _this: js::Value) -> SideVmInfo {
+    let runtime = if cfg!(feature = "sidevm") {
+        "sidevm"
+    } else if cfg!(feature = "web") {
+        "web"
+    } else {
+        "native"
+    };
+    SideVmInfo {
+        worker_pubkey: crate::runtime::worker_pubkey().map(AsBytes),
+        instance_id: crate::runtime::instance_id().map(AsBytes),
+        code_hash: service.code_hash().map(AsBytes),
+        memory_limit: service.memory_limit().map(|limit| limit as u64),
+        runtime,
+        version: env!("CARGO_PKG_VERSION"),
+    }
+}
@@ -0,0 +1,77 @@
+use alloc::rc::Rc;
+use anyhow::Result;
+
+/// The parts of an outgoing http request that an interceptor can inspect and modify.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestParts {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+/// The head of an incoming http response that an interceptor can inspect and modify.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseParts {
+    /// The url of the request that got the response, after following redirects.
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+type RequestHook = Rc<dyn Fn(&mut RequestParts) -> Result<()>>;
+type ResponseHook = Rc<dyn Fn(&mut ResponseParts) -> Result<()>>;
+
+/// Hooks the service runs on the http requests of the script, see
+/// [`Service::set_http_interceptor`](crate::Service::set_http_interceptor).
+///
+/// A hook returning an error fails the request with it, so hooks can enforce policies as well
+/// as inject headers or record metrics.
+#[derive(Clone, Default)]
+pub struct HttpInterceptor {
+    on_request: Option<RequestHook>,
+    on_response: Option<ResponseHook>,
+}
+
+impl HttpInterceptor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `hook` before each request is sent, including the default headers of the service.
+    pub fn on_request(mut self, hook: impl Fn(&mut RequestParts) -> Result<()> + 'static) -> Self {
+        self.on_request = Some(Rc::new(hook));
+        self
+    }
+
+    /// Run `hook` on each response head before it is passed to JS.
+    pub fn on_response(
+        mut self,
+        hook: impl Fn(&mut ResponseParts) -> Result<()> + 'static,
+    ) -> Self {
+        self.on_response = Some(Rc::new(hook));
+        self
+    }
+
+    pub(crate) fn request(&self, parts: &mut RequestParts) -> Result<()> {
+        match &self.on_request {
+            Some(hook) => hook(parts),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn response(&self, parts: &mut ResponseParts) -> Result<()> {
+        match &self.on_response {
+            Some(hook) => hook(parts),
+            None => Ok(()),
+        }
+    }
+}
+
+impl core::fmt::Debug for HttpInterceptor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("HttpInterceptor")
+            .field("on_request", &self.on_request.is_some())
+            .field("on_response", &self.on_response.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,526 @@
+//! Number and date formatting for a handful of locales, backing the `Intl` shim of the bootcode.
+//!
+//! Full ICU data is too heavy for the runtime, so only the common separators, month names and
+//! field orders are covered. Unknown locales fall back to their language, then to `en-US`.
+
+use anyhow::bail;
+use js::FromJsValue;
+
+use super::calendar::civil_from_days;
+use super::Result;
+
+/// Non-breaking space, used between numbers and their unit.
+const NBSP: &str = "\u{a0}";
+/// Narrow non-breaking space, the group separator of French.
+const NNBSP: &str = "\u{202f}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateOrder {
+    Mdy,
+    Dmy,
+    Ymd,
+}
+
+struct Locale {
+    tag: &'static str,
+    decimal: &'static str,
+    group: &'static str,
+    /// Whether the currency symbol and the percent sign follow the number, after a space.
+    unit_after: bool,
+    order: DateOrder,
+    date_sep: &'static str,
+    /// Pad the numeric day and month to 2 digits.
+    pad_date: bool,
+    months: Option<[&'static str; 12]>,
+    hour12: bool,
+}
+
+const EN_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const LOCALES: &[Locale] = &[
+    Locale {
+        tag: "en-US",
+        decimal: ".",
+        group: ",",
+        unit_after: false,
+        order: DateOrder::Mdy,
+        date_sep: "/",
+        pad_date: false,
+        months: Some(EN_MONTHS),
+        hour12: true,
+    },
+    Locale {
+        tag: "en-GB",
+        decimal: ".",
+        group: ",",
+        unit_after: false,
+        order: DateOrder::Dmy,
+        date_sep: "/",
+        pad_date: true,
+        months: Some(EN_MONTHS),
+        hour12: false,
+    },
+    Locale {
+        tag: "de-DE",
+        decimal: ",",
+        group: ".",
+        unit_after: true,
+        order: DateOrder::Dmy,
+        date_sep: ".",
+        pad_date: false,
+        months: Some([
+            "Januar",
+            "Februar",
+            "März",
+            "April",
+            "Mai",
+            "Juni",
+            "Juli",
+            "August",
+            "September",
+            "Oktober",
+            "November",
+            "Dezember",
+        ]),
+        hour12: false,
+    },
+    Locale {
+        tag: "fr-FR",
+        decimal: ",",
+        group: NNBSP,
+        unit_after: true,
+        order: DateOrder::Dmy,
+        date_sep: "/",
+        pad_date: true,
+        months: Some([
+            "janvier",
+            "février",
+            "mars",
+            "avril",
+            "mai",
+            "juin",
+            "juillet",
+            "août",
+            "septembre",
+            "octobre",
+            "novembre",
+            "décembre",
+        ]),
+        hour12: false,
+    },
+    Locale {
+        tag: "es-ES",
+        decimal: ",",
+        group: ".",
+        unit_after: true,
+        order: DateOrder::Dmy,
+        date_sep: "/",
+        pad_date: false,
+        months: Some([
+            "enero",
+            "febrero",
+            "marzo",
+            "abril",
+            "mayo",
+            "junio",
+            "julio",
+            "agosto",
+            "septiembre",
+            "octubre",
+            "noviembre",
+            "diciembre",
+        ]),
+        hour12: false,
+    },
+    Locale {
+        tag: "ja-JP",
+        decimal: ".",
+        group: ",",
+        unit_after: false,
+        order: DateOrder::Ymd,
+        date_sep: "/",
+        pad_date: false,
+        months: None,
+        hour12: false,
+    },
+    Locale {
+        tag: "zh-CN",
+        decimal: ".",
+        group: ",",
+        unit_after: false,
+        order: DateOrder::Ymd,
+        date_sep: "/",
+        pad_date: false,
+        months: None,
+        hour12: false,
+    },
+];
+
+/// The best supported match of a BCP 47 tag.
+fn resolve_locale(tag: Option<&str>) -> &'static Locale {
+    let Some(tag) = tag else {
+        return &LOCALES[0];
+    };
+    let tag = tag.replace('_', "-");
+    if let Some(locale) = LOCALES.iter().find(|l| l.tag.eq_ignore_ascii_case(&tag)) {
+        return locale;
+    }
+    let language = tag.split('-').next().unwrap_or_default();
+    LOCALES
+        .iter()
+        .find(|l| l.tag.split('-').next() == Some(language.to_ascii_lowercase().as_str()))
+        .unwrap_or(&LOCALES[0])
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("resolveLocale", resolve_locale_tag)?;
+    ns.define_property_fn("formatNumber", format_number)?;
+    ns.define_property_fn("formatDate", format_date)?;
+    Ok(())
+}
+
+/// The supported locale `tag` resolves to.
+#[js::host_call]
+fn resolve_locale_tag(tag: Option<String>) -> String {
+    resolve_locale(tag.as_deref()).tag.into()
+}
+
+/// The options of `Intl.NumberFormat` that are supported.
+#[derive(FromJsValue, Debug, Default)]
+#[qjsbind(rename_all = "camelCase")]
+struct NumberOptions {
+    /// `decimal`, `percent` or `currency`.
+    style: Option<String>,
+    /// ISO 4217 code, required for the `currency` style.
+    currency: Option<String>,
+    minimum_fraction_digits: Option<u32>,
+    maximum_fraction_digits: Option<u32>,
+    use_grouping: Option<bool>,
+}
+
+#[js::host_call]
+fn format_number(
+    value: f64,
+    locale: Option<String>,
+    options: Option<NumberOptions>,
+) -> Result<String> {
+    let locale = resolve_locale(locale.as_deref());
+    let options = options.unwrap_or_default();
+    let (value, default_digits, unit) = match options.style.as_deref().unwrap_or("decimal") {
+        "decimal" => (value, (0, 3), None),
+        "percent" => (value * 100.0, (0, 0), Some("%".to_string())),
+        "currency" => {
+            let Some(code) = options.currency.as_deref() else {
+                bail!("Currency code is required with currency style");
+            };
+            let code = code.to_ascii_uppercase();
+            let digits = if matches!(code.as_str(), "JPY" | "KRW") {
+                0
+            } else {
+                2
+            };
+            (value, (digits, digits), Some(currency_symbol(&code)))
+        }
+        style => bail!("Unsupported number style: {style}"),
+    };
+    let max = options.maximum_fraction_digits.unwrap_or(
+        default_digits
+            .1
+            .max(options.minimum_fraction_digits.unwrap_or(0)),
+    );
+    let min = options
+        .minimum_fraction_digits
+        .unwrap_or(default_digits.0.min(max));
+    if min > max || max > 20 {
+        bail!("Invalid fraction digits: {min}..{max}");
+    }
+    let number = format_decimal(
+        value,
+        min,
+        max,
+        options.use_grouping.unwrap_or(true),
+        locale,
+    );
+    let Some(unit) = unit else {
+        return Ok(number);
+    };
+    let (sign, number) = match number.strip_prefix('-') {
+        Some(number) => ("-", number),
+        None => ("", number.as_str()),
+    };
+    Ok(match (unit.as_str(), locale.unit_after) {
+        ("%", false) => format!("{sign}{number}%"),
+        (_, true) => format!("{sign}{number}{NBSP}{unit}"),
+        // ISO codes standing in for a symbol are spaced from the number
+        (_, false) if unit.chars().all(|c| c.is_ascii_alphabetic()) => {
+            format!("{sign}{unit}{NBSP}{number}")
+        }
+        (_, false) => format!("{sign}{unit}{number}"),
+    })
+}
+
+fn currency_symbol(code: &str) -> String {
+    match code {
+        "USD" => "$".into(),
+        "EUR" => "€".into(),
+        "GBP" => "£".into(),
+        "JPY" | "CNY" => "¥".into(),
+        "KRW" => "₩".into(),
+        "INR" => "₹".into(),
+        _ => code.into(),
+    }
+}
+
+fn format_decimal(value: f64, min: u32, max: u32, grouping: bool, locale: &Locale) -> String {
+    if value.is_nan() {
+        return "NaN".into();
+    }
+    let sign = if value.is_sign_negative() && value != 0.0 {
+        "-"
+    } else {
+        ""
+    };
+    if value.is_infinite() {
+        return format!("{sign}∞");
+    }
+    let rounded = format!("{:.*}", max as usize, value.abs());
+    let (int, frac) = rounded.split_once('.').unwrap_or((&rounded, ""));
+    let mut frac = frac.trim_end_matches('0');
+    if frac.len() < min as usize {
+        frac = &rounded[int.len() + 1..int.len() + 1 + min as usize];
+    }
+    // Rounding may have produced zero, which has no sign
+    let sign = if int.bytes().chain(frac.bytes()).all(|b| b == b'0') {
+        ""
+    } else {
+        sign
+    };
+    let mut out = String::from(sign);
+    for (i, digit) in int.chars().enumerate() {
+        if grouping && i > 0 && (int.len() - i) % 3 == 0 {
+            out.push_str(locale.group);
+        }
+        out.push(digit);
+    }
+    if !frac.is_empty() {
+        out.push_str(locale.decimal);
+        out.push_str(frac);
+    }
+    out
+}
+
+/// The options of `Intl.DateTimeFormat` that are supported.
+#[derive(FromJsValue, Debug, Default)]
+#[qjsbind(rename_all = "camelCase")]
+struct DateOptions {
+    /// `numeric` or `2-digit`.
+    year: Option<String>,
+    /// `numeric`, `2-digit`, `short` or `long`.
+    month: Option<String>,
+    day: Option<String>,
+    hour: Option<String>,
+    minute: Option<String>,
+    second: Option<String>,
+    hour12: Option<bool>,
+    /// `UTC` or a fixed offset such as `+08:00`, there is no time zone database.
+    time_zone: Option<String>,
+}
+
+/// The largest time value of a Date, 100,000,000 days from the epoch.
+const MAX_TIME_MS: f64 = 8.64e15;
+
+#[js::host_call]
+fn format_date(
+    time_ms: f64,
+    locale: Option<String>,
+    options: Option<DateOptions>,
+) -> Result<String> {
+    // Outside of the range of ECMAScript dates, also keeps the arithmetic below from overflowing
+    if !time_ms.is_finite() || time_ms.abs() > MAX_TIME_MS {
+        return Ok("Invalid Date".into());
+    }
+    let locale = resolve_locale(locale.as_deref());
+    let mut options = options.unwrap_or_default();
+    let fields = [
+        &options.year,
+        &options.month,
+        &options.day,
+        &options.hour,
+        &options.minute,
+        &options.second,
+    ];
+    if fields.iter().all(|field| field.is_none()) {
+        options.year = Some("numeric".into());
+        options.month = Some("numeric".into());
+        options.day = Some("numeric".into());
+    }
+    let offset_minutes = match options.time_zone.as_deref() {
+        None => 0,
+        Some(tz) => parse_offset(tz)?,
+    };
+    let time_ms = time_ms.floor() as i64 + offset_minutes * 60_000;
+    let days = time_ms.div_euclid(86_400_000);
+    let ms_of_day = time_ms.rem_euclid(86_400_000);
+    let (year, month, day) = civil_from_days(days);
+    let date = format_date_fields(locale, &options, year, month, day)?;
+    let time = format_time_fields(locale, &options, ms_of_day / 1000)?;
+    Ok(match (date, time) {
+        (Some(date), Some(time)) if locale.tag.starts_with("en") => format!("{date}, {time}"),
+        (Some(date), Some(time)) => format!("{date} {time}"),
+        (Some(part), None) | (None, Some(part)) => part,
+        (None, None) => String::new(),
+    })
+}
+
+/// Minutes east of UTC.
+fn parse_offset(tz: &str) -> Result<i64> {
+    if tz.eq_ignore_ascii_case("UTC") || tz.eq_ignore_ascii_case("GMT") || tz == "Etc/UTC" {
+        return Ok(0);
+    }
+    let (sign, rest) = match (tz.strip_prefix('+'), tz.strip_prefix('-')) {
+        (Some(rest), _) => (1, rest),
+        (_, Some(rest)) => (-1, rest),
+        _ => bail!("Unsupported time zone: {tz}, only UTC and fixed offsets are available"),
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+        (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => Ok(sign * (hours * 60 + minutes)),
+        _ => bail!("Invalid time zone offset: {tz}"),
+    }
+}
+
+fn numeric(style: &str, value: i64, pad: bool) -> Result<String> {
+    match style {
+        "numeric" if pad => Ok(format!("{value:02}")),
+        "numeric" => Ok(value.to_string()),
+        "2-digit" => Ok(format!("{:02}", value.rem_euclid(100))),
+        _ => bail!("Unsupported date field style: {style}"),
+    }
+}
+
+fn format_date_fields(
+    locale: &Locale,
+    options: &DateOptions,
+    year: i64,
+    month: u32,
+    day: u32,
+) -> Result<Option<String>> {
+    let textual = matches!(options.month.as_deref(), Some("short" | "long"));
+    let year = options
+        .year
+        .as_deref()
+        .map(|style| numeric(style, year, false))
+        .transpose()?;
+    let day = options
+        .day
+        .as_deref()
+        .map(|style| numeric(style, day.into(), locale.pad_date && !textual))
+        .transpose()?;
+    if let (true, Some(months)) = (textual, locale.months) {
+        let mut name = months[month as usize - 1].to_string();
+        if options.month.as_deref() == Some("short") && name.chars().count() > 3 {
+            name = name.chars().take(3).collect();
+            if locale.tag != "en-US" && locale.tag != "en-GB" {
+                name.push('.');
+            }
+        }
+        let parts: Vec<String> = match locale.tag {
+            "en-US" => {
+                let month_day = match day {
+                    Some(day) => format!("{name} {day}"),
+                    None => name,
+                };
+                match year {
+                    Some(year) if options.day.is_some() => vec![format!("{month_day}, {year}")],
+                    Some(year) => vec![month_day, year],
+                    None => vec![month_day],
+                }
+            }
+            "de-DE" => [day.map(|d| format!("{d}.")), Some(name), year]
+                .into_iter()
+                .flatten()
+                .collect(),
+            "es-ES" => {
+                let mut parts: Vec<String> = day.into_iter().collect();
+                parts.push(if parts.is_empty() {
+                    name
+                } else {
+                    format!("de {name}")
+                });
+                parts.extend(year.map(|year| format!("de {year}")));
+                parts
+            }
+            _ => [day, Some(name), year].into_iter().flatten().collect(),
+        };
+        return Ok(Some(parts.join(" ")));
+    }
+    let month = options
+        .month
+        .as_deref()
+        .map(|style| match style {
+            "short" | "long" => Ok(month.to_string()),
+            style => numeric(style, month.into(), locale.pad_date),
+        })
+        .transpose()?;
+    if locale.months.is_none() && textual {
+        // CJK dates spell out the units
+        let parts = [(year, "年"), (month, "月"), (day, "日")];
+        let text: String = parts
+            .into_iter()
+            .filter_map(|(value, unit)| value.map(|v| format!("{v}{unit}")))
+            .collect();
+        return Ok(Some(text));
+    }
+    let ordered = match locale.order {
+        DateOrder::Mdy => [month, day, year],
+        DateOrder::Dmy => [day, month, year],
+        DateOrder::Ymd => [year, month, day],
+    };
+    let parts: Vec<String> = ordered.into_iter().flatten().collect();
+    Ok((!parts.is_empty()).then(|| parts.join(locale.date_sep)))
+}
+
+fn format_time_fields(
+    locale: &Locale,
+    options: &DateOptions,
+    seconds: i64,
+) -> Result<Option<String>> {
+    if options.hour.is_none() && options.minute.is_none() && options.second.is_none() {
+        return Ok(None);
+    }
+    let hour12 = options.hour12.unwrap_or(locale.hour12);
+    let hour = seconds / 3600;
+    let display_hour = match hour12 {
+        true if hour % 12 == 0 => 12,
+        true => hour % 12,
+        false => hour,
+    };
+    let mut parts = vec![];
+    if let Some(style) = &options.hour {
+        parts.push(numeric(style, display_hour, !hour12)?);
+    }
+    if options.minute.is_some() {
+        parts.push(format!("{:02}", seconds / 60 % 60));
+    }
+    if options.second.is_some() {
+        parts.push(format!("{:02}", seconds % 60));
+    }
+    let mut time = parts.join(":");
+    if hour12 && options.hour.is_some() {
+        time.push_str(if hour < 12 { " AM" } else { " PM" });
+    }
+    Ok(Some(time))
+}
@@ -0,0 +1,415 @@
+//! Merkle proof verification, see `verifyMerkleProof` in sidevm.js.
+//!
+//! Binary trees pair the nodes sorted as OpenZeppelin's `MerkleProof` does, or by position when
+//! the leaf index is given. Substrate storage proofs are verified against the trie node codec of
+//! `sp-trie`, which covers both state versions.
+
+use super::{hash::hash_with, Result};
+use anyhow::{bail, Context};
+use js::AsBytes;
+use scale::{Compact, Decode};
+use std::collections::BTreeMap;
+
+type Bytes = AsBytes<Vec<u8>>;
+
+const HASH_LEN: usize = 32;
+/// Keys are bounded by the storage key sizes, this bounds the nibble count of a node.
+const NIBBLE_SIZE_BOUND: usize = u16::MAX as usize;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("verifyMerkleProof", verify_merkle_proof)?;
+    ns.define_property_fn("verifyMerkleMultiproof", verify_merkle_multiproof)?;
+    ns.define_property_fn("verifyTrieProof", verify_trie_proof)?;
+    Ok(())
+}
+
+/// Hash the concatenation of `a` and `b`, the smaller first if `sorted`.
+fn hash_pair(hasher: &str, a: &[u8], b: &[u8], sorted: bool) -> Result<Vec<u8>> {
+    let (a, b) = if sorted && b < a { (b, a) } else { (a, b) };
+    hash_with(hasher, [a, b].concat())
+}
+
+/// Whether `leaf` is in the binary tree of `root` given the sibling hashes on its path.
+///
+/// The pairs are sorted before hashing, unless `index` gives the position of the leaf, in which
+/// case a node at an even position is hashed on the left.
+#[js::host_call]
+fn verify_merkle_proof(
+    root: Bytes,
+    proof: Vec<Bytes>,
+    leaf: Bytes,
+    hasher: String,
+    index: Option<u64>,
+) -> Result<bool> {
+    let mut node = leaf.0;
+    let mut position = index.unwrap_or_default();
+    for sibling in &proof {
+        node = match index {
+            None => hash_pair(&hasher, &node, &sibling.0, true)?,
+            Some(_) if position & 1 == 0 => hash_pair(&hasher, &node, &sibling.0, false)?,
+            Some(_) => hash_pair(&hasher, &sibling.0, &node, false)?,
+        };
+        position >>= 1;
+    }
+    Ok(position == 0 && node == root.0)
+}
+
+/// Whether all the `leaves` are in the tree of `root`, as OpenZeppelin's `multiProofVerify`.
+///
+/// Each of the `flags` tells whether the next pair takes its second node from the leaves and
+/// the computed hashes, or from the `proof`.
+#[js::host_call]
+fn verify_merkle_multiproof(
+    root: Bytes,
+    proof: Vec<Bytes>,
+    flags: Vec<bool>,
+    leaves: Vec<Bytes>,
+    hasher: String,
+) -> Result<bool> {
+    let proof = proof.into_iter().map(|node| node.0).collect();
+    let leaves = leaves.into_iter().map(|leaf| leaf.0).collect();
+    multiproof_verify(&root.0, proof, flags, leaves, &hasher)
+}
+
+fn multiproof_verify(
+    root: &[u8],
+    proof: Vec<Vec<u8>>,
+    flags: Vec<bool>,
+    leaves: Vec<Vec<u8>>,
+    hasher: &str,
+) -> Result<bool> {
+    if leaves.len() + proof.len() != flags.len() + 1 {
+        return Ok(false);
+    }
+    let mut leaves = leaves.into_iter();
+    let mut proof = proof.into_iter();
+    let mut hashes = vec![];
+    let mut next_hash = 0;
+    let mut next_node = |hashes: &Vec<Vec<u8>>| {
+        leaves.next().or_else(|| {
+            next_hash += 1;
+            hashes.get(next_hash - 1).cloned()
+        })
+    };
+    for flag in flags {
+        let a = next_node(&hashes);
+        let b = if flag {
+            next_node(&hashes)
+        } else {
+            proof.next()
+        };
+        let (Some(a), Some(b)) = (a, b) else {
+            return Ok(false);
+        };
+        hashes.push(hash_pair(hasher, &a, &b, true)?);
+    }
+    let computed = match hashes.pop() {
+        Some(hash) if proof.next().is_none() => hash,
+        Some(_) => return Ok(false),
+        None => match next_node(&hashes).or_else(|| proof.next()) {
+            Some(node) => node,
+            None => return Ok(false),
+        },
+    };
+    Ok(computed == root)
+}
+
+/// Whether the Substrate trie of `root` maps `key` to `value`, or has no `key` if `value` is
+/// null, given the trie nodes of a `state_getReadProof`.
+///
+/// `hasher` is the hasher of the trie, `blake2b256` for the Substrate chains.
+#[js::host_call]
+fn verify_trie_proof(
+    root: Bytes,
+    proof: Vec<Bytes>,
+    key: Bytes,
+    value: Option<Bytes>,
+    hasher: String,
+) -> Result<bool> {
+    let proof: Vec<_> = proof.into_iter().map(|node| node.0).collect();
+    let found = trie_proof_lookup(&root.0, &proof, &key.0, &hasher)?;
+    Ok(found == value.map(|value| value.0))
+}
+
+/// The value of `key` in the trie of `root` given the trie nodes in `proof`.
+fn trie_proof_lookup(
+    root: &[u8],
+    proof: &[Vec<u8>],
+    key: &[u8],
+    hasher: &str,
+) -> Result<Option<Vec<u8>>> {
+    let nodes = proof
+        .iter()
+        .map(|node| {
+            let hash = hash_with(hasher, node)?;
+            if hash.len() != HASH_LEN {
+                bail!("The trie hasher must output {HASH_LEN} bytes");
+            }
+            Ok((hash, &node[..]))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    lookup(&nodes, root, key)
+}
+
+fn lookup(nodes: &BTreeMap<Vec<u8>, &[u8]>, root: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>> {
+    let nibbles: Vec<u8> = key.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect();
+    let mut key = &nibbles[..];
+    let mut encoded = *nodes
+        .get(root)
+        .context("The proof doesn't contain the root")?;
+    loop {
+        let (partial, value, children) = match Node::decode(encoded)? {
+            Node::Empty => return Ok(None),
+            Node::Leaf { partial, value } => {
+                if partial != key {
+                    return Ok(None);
+                }
+                return value.resolve(nodes).map(Some);
+            }
+            Node::Branch {
+                partial,
+                value,
+                children,
+            } => (partial, value, children),
+        };
+        let Some(rest) = key.strip_prefix(&partial[..]) else {
+            return Ok(None);
+        };
+        let Some((&nibble, rest)) = rest.split_first() else {
+            return value.map(|value| value.resolve(nodes)).transpose();
+        };
+        let Some(child) = children[nibble as usize] else {
+            return Ok(None);
+        };
+        key = rest;
+        // Nodes shorter than a hash are inlined in their parent.
+        encoded = if child.len() == HASH_LEN {
+            *nodes.get(child).context("The proof is incomplete")?
+        } else {
+            child
+        };
+    }
+}
+
+enum Value<'a> {
+    Inline(&'a [u8]),
+    /// Large values are stored by hash in state version 1, the proof holds them as nodes.
+    Hashed(&'a [u8]),
+}
+
+impl Value<'_> {
+    fn resolve(&self, nodes: &BTreeMap<Vec<u8>, &[u8]>) -> Result<Vec<u8>> {
+        match self {
+            Value::Inline(value) => Ok(value.to_vec()),
+            Value::Hashed(hash) => Ok(nodes
+                .get(*hash)
+                .context("The proof doesn't contain the value")?
+                .to_vec()),
+        }
+    }
+}
+
+enum Node<'a> {
+    Empty,
+    Leaf {
+        partial: Vec<u8>,
+        value: Value<'a>,
+    },
+    Branch {
+        partial: Vec<u8>,
+        value: Option<Value<'a>>,
+        children: [Option<&'a [u8]>; 16],
+    },
+}
+
+impl<'a> Node<'a> {
+    /// Decode a node, which starts with a header giving its kind and the nibble count of its
+    /// partial key.
+    fn decode(mut input: &'a [u8]) -> Result<Self> {
+        let input = &mut input;
+        let first = read(input, 1)?[0];
+        if first == 0 {
+            return Ok(Node::Empty);
+        }
+        // The kind is given by the first 2 bits, or by the first 3 or 4 if they are 0.
+        let (prefix_bits, leaf, value) = match first >> 6 {
+            0b01 => (2, true, ValueKind::Inline),
+            0b10 => (2, false, ValueKind::None),
+            0b11 => (2, false, ValueKind::Inline),
+            _ if first >> 5 == 0b001 => (3, true, ValueKind::Hashed),
+            _ if first >> 4 == 0b0001 => (4, false, ValueKind::Hashed),
+            _ => bail!("Invalid trie node header {first:#04x}"),
+        };
+        let nibble_count = decode_size(first, input, prefix_bits)?;
+        let bytes = read(input, (nibble_count + 1) / 2)?;
+        let mut partial: Vec<u8> = bytes.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect();
+        // An odd partial key is padded with a 0 nibble at the start.
+        if nibble_count % 2 == 1 {
+            partial.remove(0);
+        }
+        if leaf {
+            let value = read_value(input, value)?.context("Missing leaf value")?;
+            return Ok(Node::Leaf { partial, value });
+        }
+        let bitmap = u16::from_le_bytes([read(input, 1)?[0], read(input, 1)?[0]]);
+        let value = read_value(input, value)?;
+        let mut children = [None; 16];
+        for (i, child) in children.iter_mut().enumerate() {
+            if bitmap & (1 << i) != 0 {
+                *child = Some(read_vec(input)?);
+            }
+        }
+        Ok(Node::Branch {
+            partial,
+            value,
+            children,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ValueKind {
+    None,
+    Inline,
+    Hashed,
+}
+
+fn read_value<'a>(input: &mut &'a [u8], kind: ValueKind) -> Result<Option<Value<'a>>> {
+    Ok(match kind {
+        ValueKind::None => None,
+        ValueKind::Inline => Some(Value::Inline(read_vec(input)?)),
+        ValueKind::Hashed => Some(Value::Hashed(read(input, HASH_LEN)?)),
+    })
+}
+
+/// The nibble count is in the bits of the header after the prefix, continued in the following
+/// bytes while they are all ones.
+fn decode_size(first: u8, input: &mut &[u8], prefix_bits: u32) -> Result<usize> {
+    let max = (u8::MAX >> prefix_bits) as usize;
+    let mut size = first as usize & max;
+    if size < max {
+        return Ok(size);
+    }
+    loop {
+        let n = read(input, 1)?[0] as usize;
+        size += n;
+        if n < 255 {
+            return Ok(size);
+        }
+        if size > NIBBLE_SIZE_BOUND {
+            bail!("Invalid trie node size");
+        }
+    }
+}
+
+fn read<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        bail!("Truncated trie node");
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
+
+/// A SCALE encoded `Vec<u8>`.
+fn read_vec<'a>(input: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = Compact::<u32>::decode(input)
+        .context("Invalid trie node")?
+        .0;
+    read(input, len as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn h(hex: &str) -> Vec<u8> {
+        hex::decode(hex).unwrap()
+    }
+
+    /// A trie of `0102 => "hello"`, `0103 => "world"` and `0203 => [0xab; 64]` built with the
+    /// `sp-trie` V1 layout and blake2b256: a root branch holding an inline branch of two inline
+    /// leaves and the hash of a leaf whose value is stored by hash.
+    const TRIE_ROOT: &str = "8121e83d9e16e81ca07f46eda7450ccf0ac35e3637df8a25875f5dc48112a496";
+    const TRIE_NODES: [&str; 2] = [
+        "810006005081000c001c401468656c6c6f1c4014776f726c6480e78728c1542253a2eacf099af4b34145e38e3da0328f71b7d112de635f53dbbf",
+        "2203975c2c5394fc90c9ef3a4d9f18be8f047b8df576e707b1e5ac44ba3832229f1f",
+    ];
+
+    fn trie_lookup(nodes: &[Vec<u8>], key: &str) -> Result<Option<Vec<u8>>> {
+        trie_proof_lookup(&h(TRIE_ROOT), nodes, &h(key), "blake2b256")
+    }
+
+    #[test]
+    fn trie_proof_of_inline_values() {
+        let nodes: Vec<_> = TRIE_NODES.iter().map(|node| h(node)).collect();
+        assert_eq!(
+            trie_lookup(&nodes, "0102").unwrap(),
+            Some(b"hello".to_vec())
+        );
+        assert_eq!(
+            trie_lookup(&nodes, "0103").unwrap(),
+            Some(b"world".to_vec())
+        );
+        assert_eq!(trie_lookup(&nodes, "0104").unwrap(), None);
+        assert_eq!(trie_lookup(&nodes, "0302").unwrap(), None);
+    }
+
+    #[test]
+    fn trie_proof_of_hashed_value() {
+        let mut nodes: Vec<_> = TRIE_NODES.iter().map(|node| h(node)).collect();
+        assert!(trie_lookup(&nodes, "0203").is_err());
+        nodes.push(vec![0xab; 64]);
+        assert_eq!(trie_lookup(&nodes, "0203").unwrap(), Some(vec![0xab; 64]));
+    }
+
+    #[test]
+    fn incomplete_trie_proof() {
+        let nodes = vec![h(TRIE_NODES[0])];
+        assert!(trie_lookup(&nodes, "0203").is_err());
+        assert!(trie_lookup(&[], "0102").is_err());
+    }
+
+    /// The tree of `keccak256` of the bytes `a` to `f` and the multiproofs of
+    /// `@openzeppelin/merkle-tree`'s `getMultiProof`.
+    const OZ_ROOT: &str = "1b404f199ea828ec5771fb30139c222d8417a82175fefad5cd42bc3a189bd8d5";
+    const A: &str = "3ac225168df54212a25c1c01fd35bebfea408fdac2e31ddd6f80a4bbf9a5f1cb";
+    const B: &str = "b5553de315e0edf504d9150af82dafa5c4667fa618ed0a6f19c69b41166c5510";
+    const C: &str = "0b42b6393c1f53060fe3ddbfcd7aadcca894465a5a438f69c87d790b2299b9b2";
+    const E: &str = "a8982c89d80987fb9a510e25981ee9170206be21af3c8e0eb312ef1d3382e761";
+
+    fn multiproof(proof: &[&str], flags: &[bool], leaves: &[&str]) -> bool {
+        let hexes = |items: &[&str]| items.iter().map(|item| h(item)).collect();
+        multiproof_verify(
+            &h(OZ_ROOT),
+            hexes(proof),
+            flags.to_vec(),
+            hexes(leaves),
+            "keccak256",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn openzeppelin_multiproof() {
+        let proof = [
+            A,
+            "af46af0745b433e1d5bed9a04b1fdf4002f67a733c20db2fca5b2af6120d9bcb",
+        ];
+        let flags = [false, true, true, false];
+        assert!(multiproof(&proof, &flags, &[C, E, B]));
+        assert!(!multiproof(&proof, &flags, &[C, E, A]));
+        assert!(!multiproof(&proof, &[false, true, false, true], &[C, E, B]));
+    }
+
+    #[test]
+    fn openzeppelin_multiproof_of_one_leaf() {
+        let proof = [
+            C,
+            "434d51cfeb80272378f4c3a8fd2824561c2cad9fce556ea600d46f20550976a6",
+            "af46af0745b433e1d5bed9a04b1fdf4002f67a733c20db2fca5b2af6120d9bcb",
+        ];
+        assert!(multiproof(&proof, &[false, false, false], &[A]));
+        assert!(!multiproof(&proof[..2], &[false, false], &[A]));
+    }
+}
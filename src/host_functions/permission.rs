@@ -0,0 +1,209 @@
+use anyhow::{bail, Result};
+use core::{
+    ffi::{c_char, c_int},
+    str::FromStr,
+};
+use std::{collections::BTreeSet, ffi::CStr};
+
+use js::c;
+
+/// A group of host functions that can be denied to a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Capability {
+    /// Outgoing http requests, WebSocket, TCP, TLS, DNS and the http listener.
+    Network,
+    /// Hashing, encryption, signing and the secret derivation.
+    Crypto,
+    /// The key-value cache.
+    Cache,
+    /// The `fs` namespace.
+    FileSystem,
+    /// `setTimeout`, `setInterval`, `setImmediate` and `registerCron`.
+    Timers,
+    /// Creating workers.
+    Workers,
+    /// Compiling and running WebAssembly modules.
+    WebAssembly,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 7] = [
+        Self::Network,
+        Self::Crypto,
+        Self::Cache,
+        Self::FileSystem,
+        Self::Timers,
+        Self::Workers,
+        Self::WebAssembly,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Network => "network",
+            Self::Crypto => "crypto",
+            Self::Cache => "cache",
+            Self::FileSystem => "fs",
+            Self::Timers => "timers",
+            Self::Workers => "workers",
+            Self::WebAssembly => "wasm",
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match Self::ALL.into_iter().find(|cap| cap.name() == name) {
+            Some(cap) => Ok(cap),
+            None => bail!("Unknown capability: {name}"),
+        }
+    }
+}
+
+/// The capabilities granted to a service, all of them by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Permissions {
+    denied: BTreeSet<Capability>,
+}
+
+impl Permissions {
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    pub fn deny_all() -> Self {
+        Self {
+            denied: Capability::ALL.into_iter().collect(),
+        }
+    }
+
+    pub fn allow(mut self, cap: Capability) -> Self {
+        self.denied.remove(&cap);
+        self
+    }
+
+    pub fn deny(mut self, cap: Capability) -> Self {
+        self.denied.insert(cap);
+        self
+    }
+
+    pub fn allows(&self, cap: Capability) -> bool {
+        !self.denied.contains(&cap)
+    }
+}
+
+/// Run the `setup` of the host functions of `cap`, replacing each of them with a function throwing
+/// `PermissionDenied` if `cap` is denied.
+///
+/// The functions are kept in place so that the polyfills built on them still load.
+pub(super) fn setup_guarded(
+    ns: &js::Value,
+    ctx: &js::Context,
+    permissions: &Permissions,
+    cap: Capability,
+    setup: impl FnOnce(&js::Value) -> Result<()>,
+) -> Result<()> {
+    if permissions.allows(cap) {
+        return setup(ns);
+    }
+    let scratch = js::Value::new_object(ctx);
+    setup(&scratch)?;
+    copy_denied(&scratch, ns, ctx, cap)
+}
+
+fn copy_denied(src: &js::Value, dst: &js::Value, ctx: &js::Context, cap: Capability) -> Result<()> {
+    for name in own_property_names(src, ctx)? {
+        let value = src.get_property(&name)?;
+        let value = if value.is_function() {
+            denied_fn(ctx, cap)?
+        } else if value.is_object() {
+            let obj = js::Value::new_object(ctx);
+            copy_denied(&value, &obj, ctx, cap)?;
+            obj
+        } else {
+            value
+        };
+        dst.set_property(&name, &value)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn own_property_names(value: &js::Value, ctx: &js::Context) -> Result<Vec<String>> {
+    let ctx = ctx.as_ptr();
+    let mut tab: *mut c::JSPropertyEnum = core::ptr::null_mut();
+    let mut len: u32 = 0;
+    let flags = c::JS_GPN_STRING_MASK | c::JS_GPN_ENUM_ONLY;
+    let ret = unsafe {
+        c::JS_GetOwnPropertyNames(ctx, &mut tab, &mut len, *value.raw_value(), flags as _)
+    };
+    if ret < 0 {
+        bail!("Failed to list the host functions");
+    }
+    let mut names = Vec::with_capacity(len as usize);
+    unsafe {
+        for i in 0..len as usize {
+            let atom = (*tab.add(i)).atom;
+            let name = c::JS_AtomToCString(ctx, atom);
+            if !name.is_null() {
+                names.push(CStr::from_ptr(name).to_string_lossy().into_owned());
+                c::JS_FreeCString(ctx, name);
+            }
+            c::JS_FreeAtom(ctx, atom);
+        }
+        c::js_free(ctx, tab as _);
+    }
+    Ok(names)
+}
+
+fn denied_fn(ctx: &js::Context, cap: Capability) -> Result<js::Value> {
+    // The capability is passed back as the magic number
+    let func = unsafe {
+        c::JS_NewCFunctionData(
+            ctx.as_ptr(),
+            Some(throw_denied),
+            0,
+            cap as c_int,
+            0,
+            core::ptr::null_mut(),
+        )
+    };
+    if c::is_exception(func) {
+        bail!("Failed to create function: {}", ctx.get_exception_str());
+    }
+    Ok(js::Value::new_moved(ctx, func))
+}
+
+/// Throw a `PermissionDenied` error, defined by the bootcode, for the capability `magic`.
+unsafe extern "C" fn throw_denied(
+    ctx: *mut c::JSContext,
+    _this: c::JSValue,
+    _argc: c_int,
+    _argv: *mut c::JSValue,
+    magic: c_int,
+    _data: *mut c::JSValue,
+) -> c::JSValue {
+    let name = Capability::ALL
+        .get(magic as usize)
+        .map_or("unknown", |cap| cap.name());
+    let global = c::JS_GetGlobalObject(ctx);
+    let ctor = c::JS_GetPropertyStr(ctx, global, b"PermissionDenied\0".as_ptr() as _);
+    c::JS_FreeValue(ctx, global);
+    if c::JS_IsFunction(ctx, ctor) == 0 {
+        c::JS_FreeValue(ctx, ctor);
+        let message = format!("Permission denied: {name}\0");
+        return c::JS_ThrowInternalError(
+            ctx,
+            b"%s\0".as_ptr() as *const c_char,
+            message.as_ptr() as *const c_char,
+        );
+    }
+    let mut args = [c::JS_NewStringLen(ctx, name.as_ptr() as _, name.len() as _)];
+    let err = c::JS_CallConstructor(ctx, ctor, 1, args.as_mut_ptr());
+    c::JS_FreeValue(ctx, ctor);
+    c::JS_FreeValue(ctx, args[0]);
+    if c::is_exception(err) {
+        return err;
+    }
+    c::JS_Throw(ctx, err)
+}
@@ -0,0 +1,141 @@
+use alloc::rc::Rc;
+use anyhow::Result;
+use core::{cell::RefCell, fmt, time::Duration};
+use std::collections::BTreeMap;
+
+use crate::runtime::{monotonic_now, time::sleep};
+
+/// Per-host buckets unused for this long are dropped.
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(60);
+
+/// Limits the rate of the outgoing http requests, see
+/// [`Service::set_rate_limit`](crate::Service::set_rate_limit).
+///
+/// The limits are enforced with a token bucket per host and one for all hosts. Clones share the
+/// buckets, which is how the workers of a service share its budget.
+#[derive(Clone)]
+pub struct RateLimit {
+    global_qps: Option<f64>,
+    per_host_qps: Option<f64>,
+    burst: u32,
+    max_wait: Option<Duration>,
+    state: Rc<RefCell<Buckets>>,
+}
+
+/// The theoretical arrival time of the next request of each bucket, in `monotonic_now` time.
+#[derive(Default)]
+struct Buckets {
+    global: Duration,
+    per_host: BTreeMap<String, Duration>,
+}
+
+/// The error of a request over the limit, reported to JS as a `RateLimitError`.
+#[derive(Debug)]
+pub(crate) struct RateLimited {
+    host: String,
+    retry_after: Duration,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rate limit exceeded for {}, retry after {}ms",
+            self.host,
+            self.retry_after.as_millis()
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            global_qps: None,
+            per_host_qps: None,
+            burst: 1,
+            max_wait: None,
+            state: Default::default(),
+        }
+    }
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow up to `qps` requests per second in total.
+    pub fn global(mut self, qps: f64) -> Self {
+        self.global_qps = Some(qps);
+        self
+    }
+
+    /// Allow up to `qps` requests per second to each host.
+    pub fn per_host(mut self, qps: f64) -> Self {
+        self.per_host_qps = Some(qps);
+        self
+    }
+
+    /// Let up to `burst` requests through at once before the rate applies, 1 by default.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Delay the requests over the limit by up to `max_wait` rather than failing them.
+    pub fn queue(mut self, max_wait: Duration) -> Self {
+        self.max_wait = Some(max_wait);
+        self
+    }
+
+    /// Wait for the turn of a request to `host`, or fail with `RateLimited` if it's too far.
+    pub(crate) async fn acquire(&self, host: &str) -> Result<()> {
+        let wait = self.reserve(host)?;
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    /// Take a slot in the buckets, returning how long to wait for it.
+    fn reserve(&self, host: &str) -> Result<Duration, RateLimited> {
+        let now = monotonic_now();
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        state.per_host.retain(|_, tat| *tat + IDLE_BUCKET_TTL > now);
+        let global = self.global_qps.map(|qps| (&mut state.global, qps));
+        let per_host = self.per_host_qps.map(|qps| {
+            let tat = state.per_host.entry(host.into()).or_insert(now);
+            (tat, qps)
+        });
+        let mut buckets: Vec<_> = global.into_iter().chain(per_host).collect();
+        let wait = buckets
+            .iter()
+            .map(|(tat, qps)| self.wait_time(**tat, *qps, now))
+            .max()
+            .unwrap_or_default();
+        if wait > self.max_wait.unwrap_or_default() {
+            return Err(RateLimited {
+                host: host.into(),
+                retry_after: wait,
+            });
+        }
+        for (tat, qps) in buckets.iter_mut() {
+            **tat = (**tat).max(now) + interval(*qps);
+        }
+        Ok(wait)
+    }
+
+    /// How long a request at `now` has to wait for the bucket with the arrival time `tat`.
+    fn wait_time(&self, tat: Duration, qps: f64, now: Duration) -> Duration {
+        let tolerance = interval(qps).saturating_mul(self.burst - 1);
+        tat.saturating_sub(tolerance).saturating_sub(now)
+    }
+}
+
+/// The time between two requests at `qps`, which is taken as at least one a day.
+fn interval(qps: f64) -> Duration {
+    Duration::from_secs_f64(1.0 / qps.max(1.0 / 86400.0))
+}
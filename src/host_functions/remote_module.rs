@@ -0,0 +1,142 @@
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::path::{Path, PathBuf};
+
+use super::*;
+use crate::service::{ModuleSource, OwnedJsValue};
+
+/// Longest remote module accepted.
+const MAX_MODULE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Check `data` against a subresource integrity value like `sha384-<base64>`.
+///
+/// Several space-separated hashes may be given, any of them matching is enough.
+fn verify_integrity(data: &[u8], integrity: &str) -> Result<()> {
+    let mut supported = false;
+    for item in integrity.split_whitespace() {
+        let Some((algorithm, expected)) = item.split_once('-') else {
+            continue;
+        };
+        let digest = match algorithm {
+            "sha256" => Sha256::digest(data).to_vec(),
+            "sha384" => Sha384::digest(data).to_vec(),
+            "sha512" => Sha512::digest(data).to_vec(),
+            _ => continue,
+        };
+        supported = true;
+        // Options after `?` are reserved by the spec and ignored
+        let expected = expected.split('?').next().unwrap_or_default();
+        if STANDARD.encode(digest) == expected {
+            return Ok(());
+        }
+    }
+    if !supported {
+        bail!("No sha256, sha384 or sha512 hash in the integrity `{integrity}`");
+    }
+    bail!("The content doesn't match the integrity `{integrity}`");
+}
+
+fn cache_file(dir: &Path, url: &str) -> PathBuf {
+    dir.join(format!(
+        "{}.js",
+        hex::encode(Sha256::digest(url.as_bytes()))
+    ))
+}
+
+/// Where and how a remote module is fetched, taken from the service before fetching.
+pub(crate) struct RemoteFetch {
+    weak_service: ServiceWeakRef,
+    cache_dir: Option<PathBuf>,
+}
+
+impl RemoteFetch {
+    pub fn new(service: &Service) -> Self {
+        Self {
+            weak_service: service.weak_self(),
+            cache_dir: service.module_cache_dir(),
+        }
+    }
+
+    /// Fetch the module at `url`, or read it from the cache dir, and verify it with `integrity`.
+    pub async fn fetch(&self, url: &str, integrity: &str) -> Result<String> {
+        let cached = self
+            .cache_dir
+            .as_ref()
+            .and_then(|dir| std::fs::read(cache_file(dir, url)).ok())
+            .filter(|data| verify_integrity(data, integrity).is_ok());
+        let data = match cached {
+            Some(data) => data,
+            None => {
+                let data = self.download(url).await?;
+                verify_integrity(&data, integrity)
+                    .with_context(|| format!("Refused module {url}"))?;
+                if let Some(dir) = &self.cache_dir {
+                    if let Err(err) = std::fs::create_dir_all(dir)
+                        .and_then(|_| std::fs::write(cache_file(dir, url), &data))
+                    {
+                        log::warn!("Failed to cache module {url}: {err}");
+                    }
+                }
+                data
+            }
+        };
+        String::from_utf8(data).with_context(|| format!("Module {url} is not UTF-8"))
+    }
+
+    /// Download the module with the http stack of the scripts, so the proxy, resolver,
+    /// interceptor, rate limit and egress filter of the service apply, following the redirects.
+    async fn download(&self, url: &str) -> Result<Vec<u8>> {
+        check_https(url)?;
+        let (final_url, data) =
+            super::http_request::fetch(&self.weak_service, url, MAX_MODULE_SIZE as u64)
+                .await
+                .with_context(|| format!("Failed to fetch module {url}"))?;
+        check_https(&final_url).with_context(|| format!("Module {url} redirected"))?;
+        Ok(data)
+    }
+}
+
+fn check_https(url: &str) -> Result<()> {
+    let parsed: ::url::Url = url.parse().context("Invalid module url")?;
+    if parsed.scheme() != "https" {
+        bail!("Remote modules must be imported over https: {url}");
+    }
+    Ok(())
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("fetchModule", fetch_module)?;
+    Ok(())
+}
+
+/// Fetch the module at `url` and register it so that `import(url)` loads it.
+///
+/// The callback receives a `result` event once the module is ready or an `error` event.
+#[js::host_call(with_context)]
+fn fetch_module(
+    service: ServiceRef,
+    _this: js::Value,
+    url: String,
+    integrity: String,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    let fetch = RemoteFetch::new(&service);
+    service.spawn(callback, do_fetch_module, (fetch, url, integrity))
+}
+
+async fn do_fetch_module(
+    weak_service: ServiceWeakRef,
+    id: u64,
+    (fetch, url, integrity): (RemoteFetch, String, String),
+) {
+    match fetch.fetch(&url, &integrity).await {
+        Ok(source) => {
+            if let Some(service) = weak_service.upgrade() {
+                service.add_module(url, ModuleSource::Source(source));
+            }
+            invoke_callback(&weak_service, id, "result", &());
+        }
+        Err(err) => invoke_callback(&weak_service, id, "error", &format!("{err:#}")),
+    }
+}
@@ -0,0 +1,659 @@
+//! A SCALE codec driven by type definitions given at runtime.
+//!
+//! Type definitions are written in a Rust-like syntax:
+//!
+//! - Primitives: `bool`, `u8`..`u128`, `i8`..`i128`, `str`
+//! - Generics: `Vec<T>`, `Option<T>`, `Compact<T>`, `Result<T, E>`
+//! - Arrays and tuples: `[T; N]`, `(A, B)`, `()`
+//! - Structs: `{ name: T, .. }`
+//! - Enums: `<Variant, Variant: T, ..>`, variants are indexed in order
+//! - Names registered via `scaleRegisterTypes`
+//!
+//! Values are exchanged as JSON. Integers wider than 32 bits are represented as decimal strings,
+//! and byte sequences (`Vec<u8>` and `[u8; N]`) as 0x-prefixed hex strings.
+
+use ::scale::{Compact, Decode, Encode};
+use anyhow::{anyhow, bail, Context};
+use js::AsBytes;
+use serde_json::{Map, Value as Json};
+use std::{collections::BTreeMap, ops::Deref, rc::Rc};
+
+use super::*;
+
+const MAX_DEPTH: usize = 128;
+/// Items that take no input, e.g. of `Vec<()>`, aren't bounded by the input length.
+const MAX_ZERO_SIZED_ITEMS: usize = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TypeDef {
+    Bool,
+    Str,
+    /// Unsigned integer of the given byte width.
+    UInt(usize),
+    /// Signed integer of the given byte width.
+    Int(usize),
+    Compact(Box<TypeDef>),
+    Vec(Box<TypeDef>),
+    Array(Box<TypeDef>, usize),
+    Tuple(Vec<TypeDef>),
+    Struct(Vec<(String, TypeDef)>),
+    Enum(Vec<(String, Option<TypeDef>)>),
+    Option(Box<TypeDef>),
+    Result(Box<TypeDef>, Box<TypeDef>),
+    Named(String),
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("scaleRegisterTypes", scale_register_types)?;
+    ns.define_property_fn("scaleEncodeJson", scale_encode_json)?;
+    ns.define_property_fn("scaleDecodeJson", scale_decode_json)?;
+    Ok(())
+}
+
+/// Register named types that can be referenced by later type definitions.
+#[js::host_call(with_context)]
+fn scale_register_types(
+    service: ServiceRef,
+    _this: js::Value,
+    types: BTreeMap<String, String>,
+) -> Result<()> {
+    let types = types
+        .into_iter()
+        .map(|(name, def)| {
+            let ty =
+                parse_type(&def).with_context(|| format!("Invalid definition of type {name}"))?;
+            Ok((name, ty))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for (name, ty) in types {
+        service.register_scale_type(name, ty);
+    }
+    Ok(())
+}
+
+#[js::host_call(with_context)]
+fn scale_encode_json(
+    service: ServiceRef,
+    _this: js::Value,
+    type_def: String,
+    value: String,
+) -> Result<AsBytes<Vec<u8>>> {
+    let ty = parse_type(&type_def)?;
+    let value: Json = serde_json::from_str(&value).context("Invalid JSON value")?;
+    let mut output = vec![];
+    Codec { service: &service }.encode(&ty, &value, &mut output, 0)?;
+    Ok(AsBytes(output))
+}
+
+#[js::host_call(with_context)]
+fn scale_decode_json(
+    service: ServiceRef,
+    _this: js::Value,
+    type_def: String,
+    bytes: AsBytes<Vec<u8>>,
+) -> Result<String> {
+    let ty = parse_type(&type_def)?;
+    let mut input = &bytes.0[..];
+    let value = Codec { service: &service }.decode(&ty, &mut input, 0)?;
+    if !input.is_empty() {
+        bail!("{} trailing bytes after decoding", input.len());
+    }
+    Ok(value.to_string())
+}
+
+fn parse_type(def: &str) -> Result<TypeDef> {
+    let mut parser = Parser { input: def, pos: 0 };
+    let ty = parser.parse_type()?;
+    if parser.peek().is_some() {
+        bail!("Unexpected input at {}: {}", parser.pos, &def[parser.pos..]);
+    }
+    Ok(ty)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+        self.input[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if !self.eat(c) {
+            bail!("Expected '{c}' at {} in type `{}`", self.pos, self.input);
+        }
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<&'a str> {
+        self.peek();
+        let rest = &self.input[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            bail!(
+                "Expected identifier at {} in type `{}`",
+                self.pos,
+                self.input
+            );
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    /// Parse a comma separated list terminated by `close`. Trailing commas are allowed.
+    fn list<T>(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let mut items = vec![];
+        while !self.eat(close) {
+            items.push(item(self)?);
+            if !self.eat(',') {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn parse_type(&mut self) -> Result<TypeDef> {
+        let ty = match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                TypeDef::Tuple(self.list(')', Self::parse_type)?)
+            }
+            Some('[') => {
+                self.pos += 1;
+                let ty = self.parse_type()?;
+                self.expect(';')?;
+                let len = self.ident()?;
+                let len = len
+                    .parse()
+                    .with_context(|| format!("Invalid array length {len}"))?;
+                self.expect(']')?;
+                TypeDef::Array(Box::new(ty), len)
+            }
+            Some('{') => {
+                self.pos += 1;
+                TypeDef::Struct(self.list('}', |p| {
+                    let name = p.ident()?.to_string();
+                    p.expect(':')?;
+                    Ok((name, p.parse_type()?))
+                })?)
+            }
+            Some('<') => {
+                self.pos += 1;
+                TypeDef::Enum(self.list('>', |p| {
+                    let name = p.ident()?.to_string();
+                    let ty = if p.eat(':') {
+                        Some(p.parse_type()?)
+                    } else {
+                        None
+                    };
+                    Ok((name, ty))
+                })?)
+            }
+            Some(_) => {
+                let name = self.ident()?;
+                if self.eat('<') {
+                    let mut args = self.list('>', Self::parse_type)?;
+                    match (name, args.len()) {
+                        ("Vec", 1) => TypeDef::Vec(Box::new(args.remove(0))),
+                        ("Option", 1) => TypeDef::Option(Box::new(args.remove(0))),
+                        ("Compact", 1) => TypeDef::Compact(Box::new(args.remove(0))),
+                        ("Result", 2) => {
+                            let ok = args.remove(0);
+                            TypeDef::Result(Box::new(ok), Box::new(args.remove(0)))
+                        }
+                        _ => bail!("Unknown generic type {name} with {} arguments", args.len()),
+                    }
+                } else {
+                    match name {
+                        "bool" => TypeDef::Bool,
+                        "str" | "String" => TypeDef::Str,
+                        "u8" => TypeDef::UInt(1),
+                        "u16" => TypeDef::UInt(2),
+                        "u32" => TypeDef::UInt(4),
+                        "u64" => TypeDef::UInt(8),
+                        "u128" => TypeDef::UInt(16),
+                        "i8" => TypeDef::Int(1),
+                        "i16" => TypeDef::Int(2),
+                        "i32" => TypeDef::Int(4),
+                        "i64" => TypeDef::Int(8),
+                        "i128" => TypeDef::Int(16),
+                        _ => TypeDef::Named(name.into()),
+                    }
+                }
+            }
+            None => bail!("Unexpected end of type `{}`", self.input),
+        };
+        Ok(ty)
+    }
+}
+
+struct Codec<'a> {
+    service: &'a Service,
+}
+
+/// A type with the names resolved, either given inline or registered.
+enum Resolved<'a> {
+    Inline(&'a TypeDef),
+    Registered(Rc<TypeDef>),
+}
+
+impl Deref for Resolved<'_> {
+    type Target = TypeDef;
+
+    fn deref(&self) -> &TypeDef {
+        match self {
+            Resolved::Inline(ty) => ty,
+            Resolved::Registered(ty) => ty,
+        }
+    }
+}
+
+impl Codec<'_> {
+    fn resolve<'t>(&self, ty: &'t TypeDef) -> Result<Resolved<'t>> {
+        let TypeDef::Named(name) = ty else {
+            return Ok(Resolved::Inline(ty));
+        };
+        let mut name = name.clone();
+        // Bounded to reject aliases referring to each other.
+        for _ in 0..MAX_DEPTH {
+            let ty = self
+                .service
+                .scale_type(&name)
+                .ok_or_else(|| anyhow!("Unknown type {name}"))?;
+            match &*ty {
+                TypeDef::Named(alias) => name = alias.clone(),
+                _ => return Ok(Resolved::Registered(ty)),
+            }
+        }
+        bail!("Type aliases nested too deep")
+    }
+
+    /// Whether values of `ty` are encoded in zero bytes, e.g. `()` or `[u8; 0]`.
+    fn is_zero_sized(&self, ty: &TypeDef, depth: usize) -> Result<bool> {
+        if depth > MAX_DEPTH {
+            bail!("Type nested too deep");
+        }
+        let depth = depth + 1;
+        Ok(match &*self.resolve(ty)? {
+            TypeDef::Array(inner, len) => *len == 0 || self.is_zero_sized(inner, depth)?,
+            TypeDef::Tuple(types) => {
+                for ty in types {
+                    if !self.is_zero_sized(ty, depth)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            TypeDef::Struct(fields) => {
+                for (_, ty) in fields {
+                    if !self.is_zero_sized(ty, depth)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            _ => false,
+        })
+    }
+
+    fn encode(&self, ty: &TypeDef, value: &Json, out: &mut Vec<u8>, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            bail!("Value nested too deep");
+        }
+        let depth = depth + 1;
+        match ty {
+            TypeDef::Named(_) => self.encode(&self.resolve(ty)?, value, out, depth)?,
+            TypeDef::Bool => match value {
+                Json::Bool(b) => b.encode_to(out),
+                _ => bail!("Expected bool, got {value}"),
+            },
+            TypeDef::Str => match value {
+                Json::String(s) => s.encode_to(out),
+                _ => bail!("Expected string, got {value}"),
+            },
+            TypeDef::UInt(width) => {
+                let n = json_to_u128(value)?;
+                if *width < 16 && n >> (width * 8) != 0 {
+                    bail!("{n} overflows u{}", width * 8);
+                }
+                out.extend_from_slice(&n.to_le_bytes()[..*width]);
+            }
+            TypeDef::Int(width) => {
+                let n = json_to_i128(value)?;
+                let bits = width * 8;
+                if bits < 128 && (n < -(1i128 << (bits - 1)) || n >= 1i128 << (bits - 1)) {
+                    bail!("{n} overflows i{bits}");
+                }
+                out.extend_from_slice(&n.to_le_bytes()[..*width]);
+            }
+            TypeDef::Compact(inner) => match *self.resolve(inner)? {
+                TypeDef::UInt(width) => {
+                    let n = json_to_u128(value)?;
+                    if width < 16 && n >> (width * 8) != 0 {
+                        bail!("{n} overflows u{}", width * 8);
+                    }
+                    Compact(n).encode_to(out)
+                }
+                _ => bail!("Compact is only supported for unsigned integers"),
+            },
+            TypeDef::Vec(inner) => {
+                if let Some(bytes) = self.json_bytes(inner, value)? {
+                    bytes.encode_to(out);
+                } else {
+                    let items = json_array(value)?;
+                    let len = u32::try_from(items.len()).context("Too many items")?;
+                    Compact(len).encode_to(out);
+                    for item in items {
+                        self.encode(inner, item, out, depth)?;
+                    }
+                }
+            }
+            TypeDef::Array(inner, len) => {
+                if let Some(bytes) = self.json_bytes(inner, value)? {
+                    if bytes.len() != *len {
+                        bail!("Expected {len} bytes, got {}", bytes.len());
+                    }
+                    out.extend_from_slice(&bytes);
+                } else {
+                    let items = json_array(value)?;
+                    if items.len() != *len {
+                        bail!("Expected {len} items, got {}", items.len());
+                    }
+                    for item in items {
+                        self.encode(inner, item, out, depth)?;
+                    }
+                }
+            }
+            TypeDef::Tuple(types) => {
+                if types.is_empty() && value.is_null() {
+                    return Ok(());
+                }
+                let items = json_array(value)?;
+                if items.len() != types.len() {
+                    bail!(
+                        "Expected a tuple of {} items, got {}",
+                        types.len(),
+                        items.len()
+                    );
+                }
+                for (ty, item) in types.iter().zip(items) {
+                    self.encode(ty, item, out, depth)?;
+                }
+            }
+            TypeDef::Struct(fields) => {
+                let Json::Object(obj) = value else {
+                    bail!("Expected object, got {value}");
+                };
+                for (name, ty) in fields {
+                    let field = obj
+                        .get(name)
+                        .ok_or_else(|| anyhow!("Missing field {name}"))?;
+                    self.encode(ty, field, out, depth)
+                        .with_context(|| format!("Failed to encode field {name}"))?;
+                }
+            }
+            TypeDef::Enum(variants) => {
+                let (name, payload) = match value {
+                    Json::String(name) => (name, None),
+                    Json::Object(obj) if obj.len() == 1 => {
+                        let (name, payload) = obj.iter().next().expect("Checked above");
+                        (name, Some(payload))
+                    }
+                    _ => bail!("Expected enum variant, got {value}"),
+                };
+                let Some(index) = variants.iter().position(|(n, _)| n == name) else {
+                    bail!("Unknown enum variant {name}");
+                };
+                out.push(index as u8);
+                match (&variants[index].1, payload) {
+                    (None, None | Some(Json::Null)) => {}
+                    (Some(ty), Some(payload)) => self.encode(ty, payload, out, depth)?,
+                    (Some(_), None) => bail!("Missing payload of enum variant {name}"),
+                    (None, Some(_)) => bail!("Enum variant {name} has no payload"),
+                }
+            }
+            TypeDef::Option(inner) => {
+                let inner_ty = self.resolve(inner)?;
+                match (value, &*inner_ty) {
+                    // Option<bool> is encoded in a single byte by parity-scale-codec.
+                    (Json::Null, TypeDef::Bool) => out.push(0),
+                    (Json::Bool(b), TypeDef::Bool) => out.push(if *b { 1 } else { 2 }),
+                    (Json::Null, _) => out.push(0),
+                    (value, inner_ty) => {
+                        out.push(1);
+                        self.encode(inner_ty, value, out, depth)?;
+                    }
+                }
+            }
+            TypeDef::Result(ok, err) => {
+                let obj = match value {
+                    Json::Object(obj) if obj.len() == 1 => obj,
+                    _ => bail!("Expected {{\"Ok\": ..}} or {{\"Err\": ..}}, got {value}"),
+                };
+                if let Some(value) = obj.get("Ok") {
+                    out.push(0);
+                    self.encode(ok, value, out, depth)?;
+                } else if let Some(value) = obj.get("Err") {
+                    out.push(1);
+                    self.encode(err, value, out, depth)?;
+                } else {
+                    bail!("Expected {{\"Ok\": ..}} or {{\"Err\": ..}}, got {value}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the bytes if `value` is a hex string standing for a sequence of `u8`.
+    fn json_bytes(&self, item_ty: &TypeDef, value: &Json) -> Result<Option<Vec<u8>>> {
+        let Json::String(s) = value else {
+            return Ok(None);
+        };
+        if *self.resolve(item_ty)? != TypeDef::UInt(1) {
+            return Ok(None);
+        }
+        let hex_str = s.strip_prefix("0x").unwrap_or(s);
+        Ok(Some(hex::decode(hex_str).context("Invalid hex string")?))
+    }
+
+    fn decode(&self, ty: &TypeDef, input: &mut &[u8], depth: usize) -> Result<Json> {
+        if depth > MAX_DEPTH {
+            bail!("Value nested too deep");
+        }
+        let depth = depth + 1;
+        let value = match ty {
+            TypeDef::Named(_) => self.decode(&self.resolve(ty)?, input, depth)?,
+            TypeDef::Bool => Json::Bool(bool::decode(input)?),
+            TypeDef::Str => Json::String(String::decode(input)?),
+            TypeDef::UInt(width) => {
+                let bytes = take(input, *width)?;
+                let mut buf = [0u8; 16];
+                buf[..*width].copy_from_slice(bytes);
+                uint_to_json(u128::from_le_bytes(buf), *width)
+            }
+            TypeDef::Int(width) => {
+                let bytes = take(input, *width)?;
+                let fill = if bytes[width - 1] & 0x80 != 0 {
+                    0xff
+                } else {
+                    0
+                };
+                let mut buf = [fill; 16];
+                buf[..*width].copy_from_slice(bytes);
+                let n = i128::from_le_bytes(buf);
+                if *width <= 4 {
+                    Json::from(n as i64)
+                } else {
+                    Json::String(n.to_string())
+                }
+            }
+            TypeDef::Compact(inner) => match *self.resolve(inner)? {
+                TypeDef::UInt(width) => {
+                    let n = Compact::<u128>::decode(input)?.0;
+                    if width < 16 && n >> (width * 8) != 0 {
+                        bail!("Compact value {n} overflows u{}", width * 8);
+                    }
+                    uint_to_json(n, width)
+                }
+                _ => bail!("Compact is only supported for unsigned integers"),
+            },
+            TypeDef::Vec(inner) => {
+                let len = Compact::<u32>::decode(input)?.0 as usize;
+                self.decode_seq(inner, len, input, depth)?
+            }
+            TypeDef::Array(inner, len) => self.decode_seq(inner, *len, input, depth)?,
+            TypeDef::Tuple(types) => {
+                if types.is_empty() {
+                    return Ok(Json::Null);
+                }
+                let items = types
+                    .iter()
+                    .map(|ty| self.decode(ty, input, depth))
+                    .collect::<Result<Vec<_>>>()?;
+                Json::Array(items)
+            }
+            TypeDef::Struct(fields) => {
+                let mut obj = Map::new();
+                for (name, ty) in fields {
+                    let value = self
+                        .decode(ty, input, depth)
+                        .with_context(|| format!("Failed to decode field {name}"))?;
+                    obj.insert(name.clone(), value);
+                }
+                Json::Object(obj)
+            }
+            TypeDef::Enum(variants) => {
+                let index = u8::decode(input)? as usize;
+                let Some((name, ty)) = variants.get(index) else {
+                    bail!("Invalid enum variant index {index}");
+                };
+                match ty {
+                    None => Json::String(name.clone()),
+                    Some(ty) => {
+                        let mut obj = Map::new();
+                        obj.insert(name.clone(), self.decode(ty, input, depth)?);
+                        Json::Object(obj)
+                    }
+                }
+            }
+            TypeDef::Option(inner) => {
+                let inner_ty = self.resolve(inner)?;
+                match (u8::decode(input)?, &*inner_ty) {
+                    (0, _) => Json::Null,
+                    (1, TypeDef::Bool) => Json::Bool(true),
+                    (2, TypeDef::Bool) => Json::Bool(false),
+                    (1, inner_ty) => self.decode(inner_ty, input, depth)?,
+                    (tag, _) => bail!("Invalid Option tag {tag}"),
+                }
+            }
+            TypeDef::Result(ok, err) => {
+                let (name, ty) = match u8::decode(input)? {
+                    0 => ("Ok", ok),
+                    1 => ("Err", err),
+                    tag => bail!("Invalid Result tag {tag}"),
+                };
+                let mut obj = Map::new();
+                obj.insert(name.into(), self.decode(ty, input, depth)?);
+                Json::Object(obj)
+            }
+        };
+        Ok(value)
+    }
+
+    fn decode_seq(
+        &self,
+        item_ty: &TypeDef,
+        len: usize,
+        input: &mut &[u8],
+        depth: usize,
+    ) -> Result<Json> {
+        if *self.resolve(item_ty)? == TypeDef::UInt(1) {
+            let bytes = take(input, len)?;
+            return Ok(Json::String(format!("0x{}", hex::encode(bytes))));
+        }
+        // Don't trust the length prefix, each item takes at least a byte of the input.
+        let max_len = if self.is_zero_sized(item_ty, depth)? {
+            MAX_ZERO_SIZED_ITEMS
+        } else {
+            input.len()
+        };
+        if len > max_len {
+            bail!("Too many items: {len}");
+        }
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(self.decode(item_ty, input, depth)?);
+        }
+        Ok(Json::Array(items))
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        bail!("Not enough data to decode");
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
+
+fn uint_to_json(n: u128, width: usize) -> Json {
+    if width <= 4 {
+        Json::from(n as u64)
+    } else {
+        Json::String(n.to_string())
+    }
+}
+
+fn json_array(value: &Json) -> Result<&Vec<Json>> {
+    match value {
+        Json::Array(items) => Ok(items),
+        _ => bail!("Expected array, got {value}"),
+    }
+}
+
+fn json_to_u128(value: &Json) -> Result<u128> {
+    match value {
+        Json::Number(n) => n
+            .as_u64()
+            .map(u128::from)
+            .ok_or_else(|| anyhow!("Expected unsigned integer, got {n}")),
+        Json::String(s) => match s.strip_prefix("0x") {
+            Some(hex_str) => u128::from_str_radix(hex_str, 16),
+            None => s.parse(),
+        }
+        .with_context(|| format!("Invalid unsigned integer {s}")),
+        _ => bail!("Expected unsigned integer, got {value}"),
+    }
+}
+
+fn json_to_i128(value: &Json) -> Result<i128> {
+    match value {
+        Json::Number(n) => n
+            .as_i64()
+            .map(i128::from)
+            .ok_or_else(|| anyhow!("Expected integer, got {n}")),
+        Json::String(s) => s.parse().with_context(|| format!("Invalid integer {s}")),
+        _ => bail!("Expected integer, got {value}"),
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{bail, Context};
+use blake2::{digest::Mac, Blake2bMac512};
+use core::cell::OnceCell;
+use js::{AsBytes, BytesOrString};
+
+use super::Result;
+
+thread_local! {
+    /// The root of all derived secrets, provided by the runtime.
+    static MASTER_SECRET: OnceCell<Vec<u8>> = OnceCell::new();
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("deriveSecret", derive_secret)?;
+    Ok(())
+}
+
+/// Set the secret to derive from. Only the first call takes effect.
+///
+/// The secret is hashed so that it can be of any length.
+pub(crate) fn set_master_secret(secret: &[u8]) {
+    let secret = super::hash::do_hash::<blake2::Blake2b512>(secret);
+    MASTER_SECRET.with(|master| {
+        let _ = master.set(secret);
+    });
+}
+
+/// Derive 64 bytes of secret from the master secret and `salt`.
+///
+/// The same salt always gives the same secret in the same contract, and different salts give
+/// unrelated secrets.
+#[js::host_call]
+fn derive_secret(salt: BytesOrString) -> Result<AsBytes<Vec<u8>>> {
+    init_from_env()?;
+    let Some(master) = MASTER_SECRET.with(|master| master.get().cloned()) else {
+        bail!("deriveSecret: no master secret available in this runtime");
+    };
+    let mut mac =
+        <Blake2bMac512 as Mac>::new_from_slice(&master).context("Invalid master secret")?;
+    mac.update(salt.as_ref());
+    Ok(AsBytes(mac.finalize().into_bytes().to_vec()))
+}
+
+/// The native build takes the master secret from the `PHATJS_SECRET` environment variable in hex.
+#[cfg(feature = "native")]
+fn init_from_env() -> Result<()> {
+    if MASTER_SECRET.with(|master| master.get().is_some()) {
+        return Ok(());
+    }
+    if let Ok(secret) = std::env::var("PHATJS_SECRET") {
+        let secret = hex::decode(secret.trim_start_matches("0x"))
+            .context("PHATJS_SECRET must be hex encoded")?;
+        set_master_secret(&secret);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "native"))]
+fn init_from_env() -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use super::{hash::do_hash, Result};
+use anyhow::{anyhow, bail, Context};
+use blake2::{digest::typenum::U32, Blake2b};
+use js::{AsBytes, BytesOrString};
+
+type Bytes = AsBytes<Vec<u8>>;
+
+/// The signing context used by Substrate for sr25519 signatures.
+const SR25519_CONTEXT: &[u8] = b"substrate";
+
+#[derive(Clone, Copy, Debug)]
+enum Scheme {
+    Ed25519,
+    Sr25519,
+    Ecdsa,
+}
+
+impl Scheme {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name.to_ascii_lowercase().as_str() {
+            "ed25519" => Self::Ed25519,
+            "sr25519" => Self::Sr25519,
+            "ecdsa" | "secp256k1" => Self::Ecdsa,
+            _ => bail!("Unsupported signature scheme: {name}"),
+        })
+    }
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("signWithKey", sign_with_key)?;
+    ns.define_property_fn("verifySignature", verify_signature)?;
+    ns.define_property_fn("publicKeyFromSeed", public_key_from_seed)?;
+    Ok(())
+}
+
+fn seed_of(seed: &[u8]) -> Result<[u8; 32]> {
+    seed.try_into()
+        .map_err(|_| anyhow!("The seed must be 32 bytes, got {}", seed.len()))
+}
+
+/// Substrate signs and verifies the blake2_256 hash of the message with ecdsa.
+fn ecdsa_prehash(message: &[u8]) -> Vec<u8> {
+    do_hash::<Blake2b<U32>>(message)
+}
+
+/// Derive the public key from a 32-byte seed.
+///
+/// The keys are compatible with Substrate: sr25519 expands the seed in ed25519 mode and ecdsa
+/// public keys are returned in the 33-byte compressed form.
+#[js::host_call]
+fn public_key_from_seed(scheme: String, seed: Bytes) -> Result<Bytes> {
+    let seed = seed_of(&seed.0)?;
+    let public = match Scheme::parse(&scheme)? {
+        Scheme::Ed25519 => ed25519_dalek::SigningKey::from_bytes(&seed)
+            .verifying_key()
+            .to_bytes()
+            .to_vec(),
+        Scheme::Sr25519 => sr25519_keypair(&seed)?.public.to_bytes().to_vec(),
+        Scheme::Ecdsa => k256::ecdsa::SigningKey::from_slice(&seed)
+            .context("Invalid ecdsa seed")?
+            .verifying_key()
+            .to_encoded_point(true)
+            .as_bytes()
+            .to_vec(),
+    };
+    Ok(public.into())
+}
+
+/// Sign `message` with the key derived from `seed`.
+///
+/// ecdsa signatures are 65 bytes long with the recovery id appended, as Substrate expects.
+#[js::host_call]
+fn sign_with_key(scheme: String, seed: Bytes, message: BytesOrString) -> Result<Bytes> {
+    use ed25519_dalek::Signer;
+    let seed = seed_of(&seed.0)?;
+    let message = message.as_ref();
+    let signature = match Scheme::parse(&scheme)? {
+        Scheme::Ed25519 => ed25519_dalek::SigningKey::from_bytes(&seed)
+            .sign(message)
+            .to_bytes()
+            .to_vec(),
+        Scheme::Sr25519 => {
+            let keypair = sr25519_keypair(&seed)?;
+            let transcript = schnorrkel::signing_context(SR25519_CONTEXT).bytes(message);
+            keypair
+                .sign(schnorrkel::context::attach_rng(transcript, HostRng))
+                .to_bytes()
+                .to_vec()
+        }
+        Scheme::Ecdsa => {
+            let key = k256::ecdsa::SigningKey::from_slice(&seed).context("Invalid ecdsa seed")?;
+            let (signature, recovery_id) = key
+                .sign_prehash_recoverable(&ecdsa_prehash(message))
+                .context("Failed to sign")?;
+            let mut bytes = signature.to_bytes().to_vec();
+            bytes.push(recovery_id.to_byte());
+            bytes
+        }
+    };
+    Ok(signature.into())
+}
+
+#[js::host_call]
+fn verify_signature(
+    scheme: String,
+    public_key: Bytes,
+    message: BytesOrString,
+    signature: Bytes,
+) -> Result<bool> {
+    let (public_key, signature) = (&public_key.0, &signature.0);
+    let message = message.as_ref();
+    let valid = match Scheme::parse(&scheme)? {
+        Scheme::Ed25519 => {
+            let public_key: [u8; 32] = public_key
+                .as_slice()
+                .try_into()
+                .context("Invalid ed25519 public key")?;
+            let Ok(signature) = ed25519_dalek::Signature::from_slice(signature) else {
+                return Ok(false);
+            };
+            ed25519_dalek::VerifyingKey::from_bytes(&public_key)
+                .context("Invalid ed25519 public key")?
+                .verify_strict(message, &signature)
+                .is_ok()
+        }
+        Scheme::Sr25519 => {
+            let public_key = schnorrkel::PublicKey::from_bytes(public_key)
+                .map_err(|err| anyhow!("Invalid sr25519 public key: {err}"))?;
+            let Ok(signature) = schnorrkel::Signature::from_bytes(signature) else {
+                return Ok(false);
+            };
+            public_key
+                .verify_simple(SR25519_CONTEXT, message, &signature)
+                .is_ok()
+        }
+        Scheme::Ecdsa => {
+            use k256::ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey};
+            let public_key =
+                VerifyingKey::from_sec1_bytes(public_key).context("Invalid ecdsa public key")?;
+            // The trailing recovery id is optional.
+            let signature = match signature.len() {
+                64 | 65 => Signature::from_slice(&signature[..64]),
+                _ => return Ok(false),
+            };
+            let Ok(signature) = signature else {
+                return Ok(false);
+            };
+            public_key
+                .verify_prehash(&ecdsa_prehash(message), &signature)
+                .is_ok()
+        }
+    };
+    Ok(valid)
+}
+
+fn sr25519_keypair(seed: &[u8; 32]) -> Result<schnorrkel::Keypair> {
+    let mini = schnorrkel::MiniSecretKey::from_bytes(seed)
+        .map_err(|err| anyhow!("Invalid sr25519 seed: {err}"))?;
+    Ok(mini.expand_to_keypair(schnorrkel::ExpansionMode::Ed25519))
+}
+
+/// A CSPRNG backed by the randomness source of the runtime.
+struct HostRng;
+
+impl rand_core::RngCore for HostRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        crate::runtime::getrandom(dest).expect("Failed to get random bytes");
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl rand_core::CryptoRng for HostRng {}
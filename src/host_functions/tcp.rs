@@ -0,0 +1,128 @@
+use anyhow::Context;
+use js::AsBytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use super::*;
+use crate::service::OwnedJsValue;
+
+pub(crate) enum Command {
+    Write(Vec<u8>),
+    Close,
+}
+
+type CommandSender = UnboundedSender<Command>;
+pub(crate) type CommandReceiver = UnboundedReceiver<Command>;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("tcpConnect", tcp_connect)?;
+    ns.define_property_fn("tcpWrite", tcp_write)?;
+    ns.define_property_fn("tcpClose", tcp_close)?;
+    Ok(())
+}
+
+/// Spawn a socket task and attach the command channel to its resource.
+pub(crate) fn spawn_socket<Fut, FutGen, Args>(
+    service: &Service,
+    callback: OwnedJsValue,
+    fut_gen: FutGen,
+    args: Args,
+) -> Result<u64>
+where
+    Fut: core::future::Future<Output = ()> + 'static,
+    Args: 'static,
+    FutGen: FnOnce(ServiceWeakRef, u64, (Args, CommandReceiver)) -> Fut + 'static,
+{
+    let (tx, rx) = unbounded_channel();
+    let id = service.spawn(callback, fut_gen, (args, rx))?;
+    service.set_resource_data(id, tx);
+    Ok(id)
+}
+
+#[js::host_call(with_context)]
+fn tcp_connect(
+    service: ServiceRef,
+    _this: js::Value,
+    host: String,
+    port: u16,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    spawn_socket(&service, callback, do_tcp_connect, (host, port))
+}
+
+#[js::host_call(with_context)]
+fn tcp_write(service: ServiceRef, _this: js::Value, id: u64, data: AsBytes<Vec<u8>>) -> Result<()> {
+    send_command(&service, id, Command::Write(data.0))
+}
+
+/// Shutdown the write side of the socket. The `end` event fires once the peer closes its side.
+#[js::host_call(with_context)]
+fn tcp_close(service: ServiceRef, _this: js::Value, id: u64) -> Result<()> {
+    send_command(&service, id, Command::Close)
+}
+
+fn send_command(service: &Service, id: u64, command: Command) -> Result<()> {
+    let Some(tx) = service.resource_data::<CommandSender>(id) else {
+        anyhow::bail!("Socket {id} not found");
+    };
+    tx.send(command)
+        .map_err(|_| anyhow::anyhow!("Socket {id} has been closed"))
+}
+
+async fn do_tcp_connect(
+    weak_service: ServiceWeakRef,
+    id: u64,
+    ((host, port), rx): ((String, u16), CommandReceiver),
+) {
+    let result = async {
+        let stream = connect_checked(&weak_service, &host, port).await?;
+        run_socket(&weak_service, id, stream, rx).await
+    };
+    if let Err(err) = result.await {
+        invoke_callback(
+            &weak_service,
+            id,
+            "error",
+            &format!("Socket `{host}:{port}` error: {err:?}"),
+        );
+    }
+}
+
+/// Pump data between the JS side and an established stream until both directions are closed.
+pub(crate) async fn run_socket<S>(
+    weak_service: &ServiceWeakRef,
+    id: u64,
+    stream: S,
+    mut rx: CommandReceiver,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    invoke_callback(weak_service, id, "connect", &());
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut buf = vec![0u8; 4096];
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            command = rx.recv(), if !closing => {
+                match command {
+                    Some(Command::Write(data)) => writer.write_all(&data).await?,
+                    Some(Command::Close) | None => {
+                        closing = true;
+                        writer.shutdown().await?;
+                    }
+                }
+            }
+            result = reader.read(&mut buf) => {
+                match result? {
+                    0 => {
+                        invoke_callback(weak_service, id, "end", &());
+                        break;
+                    }
+                    n => invoke_callback(weak_service, id, "data", &AsBytes(&buf[..n])),
+                }
+            }
+        }
+    }
+    Ok(())
+}
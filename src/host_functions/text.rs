@@ -0,0 +1,269 @@
+use anyhow::bail;
+use js::{AsBytes, ToJsValue};
+
+use super::Result;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Latin1,
+}
+
+impl Encoding {
+    fn from_label(label: &str) -> Option<Self> {
+        let encoding = match label.trim().to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "unicode-1-1-utf-8" | "unicode11utf8" | "unicode20utf8" => {
+                Self::Utf8
+            }
+            "utf-16le" | "utf-16" | "ucs-2" | "unicode" | "csunicode" | "iso-10646-ucs-2" => {
+                Self::Utf16Le
+            }
+            "latin1" | "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "l1" | "ascii" | "us-ascii" => {
+                Self::Latin1
+            }
+            _ => return None,
+        };
+        Some(encoding)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Latin1 => "latin1",
+        }
+    }
+}
+
+#[derive(ToJsValue, Debug)]
+struct QuotedJson {
+    text: String,
+    /// Prefix of the strings replacing the integers, empty if there were none.
+    marker: String,
+}
+
+#[derive(ToJsValue, Debug)]
+struct Decoded {
+    text: String,
+    /// Number of trailing bytes that form an incomplete sequence and were left undecoded.
+    rest: u32,
+}
+
+#[derive(ToJsValue, Debug)]
+struct EncodedInto {
+    bytes: AsBytes<Vec<u8>>,
+    /// Number of UTF-16 code units of the input that were encoded.
+    read: u32,
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("textEncoding", text_encoding)?;
+    ns.define_property_fn("textEncode", text_encode)?;
+    ns.define_property_fn("textEncodeInto", text_encode_into)?;
+    ns.define_property_fn("textDecode", text_decode)?;
+    ns.define_property_fn("jsonQuoteBigInts", json_quote_big_ints)?;
+    Ok(())
+}
+
+fn parse_label(label: &str) -> Result<Encoding> {
+    match Encoding::from_label(label) {
+        Some(encoding) => Ok(encoding),
+        None => bail!("The encoding label provided ('{label}') is invalid"),
+    }
+}
+
+/// Returns the canonical name of the encoding `label` refers to.
+#[js::host_call]
+fn text_encoding(label: String) -> Result<String> {
+    Ok(parse_label(&label)?.name().into())
+}
+
+#[js::host_call]
+fn text_encode(encoding: String, text: String) -> Result<AsBytes<Vec<u8>>> {
+    let bytes = match parse_label(&encoding)? {
+        Encoding::Utf8 => text.into_bytes(),
+        Encoding::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        Encoding::Latin1 => text
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+            .collect(),
+    };
+    Ok(AsBytes(bytes))
+}
+
+/// Encode as utf-8 the longest prefix of `text` that fits in `capacity` bytes, without splitting
+/// a character, for `TextEncoder.encodeInto`.
+#[js::host_call]
+fn text_encode_into(text: String, capacity: u64) -> EncodedInto {
+    let mut len = 0;
+    let mut read = 0;
+    for c in text.chars() {
+        if (len + c.len_utf8()) as u64 > capacity {
+            break;
+        }
+        len += c.len_utf8();
+        read += c.len_utf16() as u32;
+    }
+    let mut bytes = text.into_bytes();
+    bytes.truncate(len);
+    EncodedInto {
+        bytes: AsBytes(bytes),
+        read,
+    }
+}
+
+/// Decode `bytes` with the given encoding.
+///
+/// In `stream` mode an incomplete sequence at the end of the input is not decoded but reported
+/// via `rest` so that the caller can prepend it to the next chunk. If `fatal` is set, malformed
+/// input throws rather than being replaced by U+FFFD.
+#[js::host_call]
+fn text_decode(
+    encoding: String,
+    bytes: AsBytes<Vec<u8>>,
+    fatal: bool,
+    stream: bool,
+    strip_bom: bool,
+) -> Result<Decoded> {
+    let (mut text, rest) = match parse_label(&encoding)? {
+        Encoding::Utf8 => decode_utf8(&bytes.0, fatal, stream)?,
+        Encoding::Utf16Le => decode_utf16le(&bytes.0, fatal, stream)?,
+        Encoding::Latin1 => (bytes.0.iter().map(|&b| char::from(b)).collect(), 0),
+    };
+    if strip_bom && text.starts_with('\u{FEFF}') {
+        text.remove(0);
+    }
+    Ok(Decoded {
+        text,
+        rest: rest as u32,
+    })
+}
+
+fn decode_utf8(mut input: &[u8], fatal: bool, stream: bool) -> Result<(String, usize)> {
+    let mut output = String::with_capacity(input.len());
+    loop {
+        match core::str::from_utf8(input) {
+            Ok(valid) => {
+                output.push_str(valid);
+                return Ok((output, 0));
+            }
+            Err(err) => {
+                let (valid, invalid) = input.split_at(err.valid_up_to());
+                output.push_str(core::str::from_utf8(valid).expect("Checked above"));
+                if fatal && (err.error_len().is_some() || !stream) {
+                    bail!("The encoded data was not valid utf-8");
+                }
+                match err.error_len() {
+                    Some(len) => {
+                        output.push(char::REPLACEMENT_CHARACTER);
+                        input = &invalid[len..];
+                    }
+                    None if stream => return Ok((output, invalid.len())),
+                    None => {
+                        output.push(char::REPLACEMENT_CHARACTER);
+                        return Ok((output, 0));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn decode_utf16le(input: &[u8], fatal: bool, stream: bool) -> Result<(String, usize)> {
+    let mut units: Vec<u16> = input
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    let truncated = input.len() % 2 != 0 && !stream;
+    let mut rest = if stream { input.len() % 2 } else { 0 };
+    if stream && matches!(units.last(), Some(0xD800..=0xDBFF)) {
+        // Keep a leading surrogate for the next chunk which may carry its trailing half.
+        units.pop();
+        rest += 2;
+    }
+    let mut output = String::with_capacity(units.len());
+    for c in char::decode_utf16(units) {
+        match c {
+            Ok(c) => output.push(c),
+            Err(_) if fatal => bail!("The encoded data was not valid utf-16le"),
+            Err(_) => output.push(char::REPLACEMENT_CHARACTER),
+        }
+    }
+    if truncated {
+        if fatal {
+            bail!("The encoded data was not valid utf-16le");
+        }
+        output.push(char::REPLACEMENT_CHARACTER);
+    }
+    Ok((output, rest))
+}
+
+/// The largest integer a JS number holds exactly, `Number.MAX_SAFE_INTEGER`.
+const MAX_SAFE_INTEGER: &str = "9007199254740991";
+
+/// Replace the integers of a JSON text that a JS number can't hold exactly with strings of their
+/// digits prefixed by a random marker, for `JSON.parseBig` to revive as BigInts.
+///
+/// The text is not validated, `JSON.parse` reports the syntax errors.
+#[js::host_call]
+fn json_quote_big_ints(text: String) -> QuotedJson {
+    let bytes = text.as_bytes();
+    let mut marker = String::new();
+    let mut output = String::new();
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            b'-' | b'0'..=b'9' => {
+                let start = i;
+                while i < bytes.len()
+                    && matches!(bytes[i], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+                {
+                    i += 1;
+                }
+                let number = &text[start..i];
+                if !is_unsafe_integer(number) {
+                    continue;
+                }
+                if marker.is_empty() {
+                    let mut nonce = [0u8; 8];
+                    crate::runtime::getrandom(&mut nonce).expect("Failed to get random bytes");
+                    marker = format!("bigint:{}:", hex::encode(nonce));
+                }
+                output.push_str(&text[copied..start]);
+                output.push('"');
+                output.push_str(&marker);
+                output.push_str(number);
+                output.push('"');
+                copied = i;
+            }
+            _ => i += 1,
+        }
+    }
+    if marker.is_empty() {
+        return QuotedJson { text, marker };
+    }
+    output.push_str(&text[copied..]);
+    QuotedJson {
+        text: output,
+        marker,
+    }
+}
+
+fn is_unsafe_integer(number: &str) -> bool {
+    let digits = number.strip_prefix('-').unwrap_or(number);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let digits = digits.trim_start_matches('0');
+    digits.len() > MAX_SAFE_INTEGER.len()
+        || (digits.len() == MAX_SAFE_INTEGER.len() && digits > MAX_SAFE_INTEGER)
+}
@@ -1,10 +1,19 @@
 use super::*;
-use crate::{runtime::time::sleep, service::OwnedJsValue};
+use crate::{
+    runtime::{monotonic_now, time::sleep},
+    service::OwnedJsValue,
+};
+use std::time::Duration;
+
+/// Timers nested deeper than this are clamped to `MIN_TIMEOUT_MS` as the HTML spec requires.
+const MAX_UNCLAMPED_NESTING_LEVEL: u32 = 5;
+const MIN_TIMEOUT_MS: u64 = 4;
 
 pub(crate) fn setup(ns: &js::Value) -> Result<()> {
     // `clearTimeout` and `clearInterval` are implemented by `close` on the guest side
     ns.define_property_fn("setTimeout", set_timeout)?;
     ns.define_property_fn("setInterval", set_interval)?;
+    ns.define_property_fn("setImmediate", set_immediate)?;
     Ok(())
 }
 
@@ -15,7 +24,8 @@ fn set_timeout(
     callback: OwnedJsValue,
     timeout_ms: u64,
 ) -> Result<u64> {
-    Ok(service.spawn(callback, do_set_timeout, timeout_ms.max(4)))
+    let level = service.timer_nesting_level() + 1;
+    service.spawn(callback, do_set_timeout, (timeout_ms, level))
 }
 
 #[js::host_call(with_context)]
@@ -25,32 +35,153 @@ fn set_interval(
     callback: OwnedJsValue,
     timeout_ms: u64,
 ) -> Result<u64> {
-    Ok(service.spawn(callback, do_set_interval, timeout_ms.max(4)))
+    let level = service.timer_nesting_level() + 1;
+    service.spawn(callback, do_set_interval, (timeout_ms, level))
+}
+
+/// Run `callback` once the tasks that are ready have had their turn, without going through the
+/// timer wheel as `setTimeout(callback, 0)` does.
+#[js::host_call(with_context)]
+fn set_immediate(service: ServiceRef, _this: js::Value, callback: OwnedJsValue) -> Result<u64> {
+    service.spawn(callback, do_set_immediate, ())
+}
+
+fn clamp_timeout(timeout_ms: u64, level: u32) -> Duration {
+    let timeout_ms = if level > MAX_UNCLAMPED_NESTING_LEVEL {
+        timeout_ms.max(MIN_TIMEOUT_MS)
+    } else {
+        timeout_ms
+    };
+    Duration::from_millis(timeout_ms)
 }
 
-fn try_fire_timer(service: &Weak<Service>, id: u64) -> Result<()> {
+fn try_fire_timer(service: &Weak<Service>, id: u64, level: u32) -> Result<()> {
     let Some(service) = service.upgrade() else {
         anyhow::bail!("Timer {id} exited because the service has been dropped");
     };
     let Some(callback) = service.get_resource_value(id) else {
         anyhow::bail!("Timer {id} exited because the resource has been dropped");
     };
-    if let Err(err) = service.call_function(callback, ()) {
+    service.set_timer_nesting_level(level);
+    let result = service.call_function(callback, ());
+    service.set_timer_nesting_level(0);
+    if let Err(err) = result {
         error!("Failed to fire timer {id}: {err}");
     }
     Ok(())
 }
 
-async fn do_set_timeout(service: ServiceWeakRef, id: u64, timeout_ms: u64) {
-    sleep(std::time::Duration::from_millis(timeout_ms)).await;
-    try_fire_timer(&service, id).ignore();
+async fn do_set_timeout(service: ServiceWeakRef, id: u64, (timeout_ms, level): (u64, u32)) {
+    sleep(clamp_timeout(timeout_ms, level)).await;
+    try_fire_timer(&service, id, level).ignore();
 }
 
-async fn do_set_interval(service: ServiceWeakRef, id: u64, timeout_ms: u64) {
+async fn do_set_immediate(service: ServiceWeakRef, id: u64, _args: ()) {
+    yield_now().await;
+    try_fire_timer(&service, id, 0).ignore();
+}
+
+async fn do_set_interval(service: ServiceWeakRef, id: u64, (timeout_ms, level): (u64, u32)) {
+    // Schedule against absolute deadlines so that the time spent in callbacks doesn't accumulate.
+    let mut deadline = monotonic_now();
+    let mut level = level;
     loop {
-        sleep(std::time::Duration::from_millis(timeout_ms)).await;
-        if try_fire_timer(&service, id).log_err().is_err() {
+        deadline += clamp_timeout(timeout_ms, level);
+        let now = monotonic_now();
+        if deadline > now {
+            sleep(deadline - now).await;
+        } else {
+            // Missed ticks are skipped rather than fired in a burst.
+            deadline = now;
+            yield_now().await;
+        }
+        if try_fire_timer(&service, id, level).log_err().is_err() {
             break;
         }
+        level = level.saturating_add(1);
+    }
+}
+
+/// Give other tasks a chance to run.
+async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if yielded {
+            return core::task::Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        core::task::Poll::Pending
+    })
+    .await
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use js::FromJsValue;
+
+    /// Run `script` until its tasks are done and return the entries it pushed to `log`.
+    fn run(script: &str) -> String {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        tokio::task::LocalSet::new().block_on(&runtime, async {
+            let service = Service::new_ref();
+            service.exec_script("globalThis.log = []").unwrap();
+            service.exec_script(script).unwrap();
+            service.wait_for_tasks().await;
+            let value = service.exec_script("log.join(',')").unwrap();
+            String::from_js_value(service.to_js_value(&value)).unwrap()
+        })
+    }
+
+    #[test]
+    fn microtasks_run_before_immediates() {
+        let log = run("
+            setImmediate(() => log.push('immediate'));
+            queueMicrotask(() => log.push('microtask'));
+            Promise.resolve().then(() => log.push('then'));
+            log.push('sync');
+        ");
+        assert_eq!(log, "sync,microtask,then,immediate");
+    }
+
+    #[test]
+    fn nested_microtasks_run_before_immediates() {
+        let log = run("
+            setImmediate(() => log.push('immediate'));
+            queueMicrotask(() => queueMicrotask(() => log.push('nested')));
+        ");
+        assert_eq!(log, "nested,immediate");
+    }
+
+    #[test]
+    fn immediates_run_in_order() {
+        let log = run("for (const i of [1, 2, 3]) setImmediate(() => log.push(i))");
+        assert_eq!(log, "1,2,3");
+    }
+
+    #[test]
+    fn microtasks_of_an_immediate_run_before_the_next_one() {
+        let log = run("
+            setImmediate(() => {
+                log.push('a');
+                queueMicrotask(() => log.push('a:microtask'));
+            });
+            setImmediate(() => log.push('b'));
+        ");
+        assert_eq!(log, "a,a:microtask,b");
+    }
+
+    #[test]
+    fn a_throwing_microtask_doesnt_stop_the_others() {
+        let log = run("
+            queueMicrotask(() => { throw new Error('boom'); });
+            queueMicrotask(() => log.push('after'));
+            setImmediate(() => log.push('immediate'));
+        ");
+        assert_eq!(log, "after,immediate");
     }
 }
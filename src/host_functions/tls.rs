@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use js::FromJsValue;
+use tokio_rustls::{
+    rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+use super::tcp::{run_socket, spawn_socket, CommandReceiver};
+use super::*;
+use crate::service::OwnedJsValue;
+
+#[derive(FromJsValue, Debug, Default)]
+#[qjsbind(rename_all = "camelCase")]
+struct TlsOptions {
+    /// The SNI hostname. Defaults to the host to connect to.
+    server_name: Option<String>,
+    /// PEM encoded root certificates to trust. The bundled webpki roots are used if empty.
+    #[qjsbind(default)]
+    root_certs: Vec<String>,
+    client_cert: Option<ClientCert>,
+}
+
+#[derive(FromJsValue, Debug)]
+#[qjsbind(rename_all = "camelCase")]
+struct ClientCert {
+    /// PEM encoded certificate chain.
+    cert_chain: String,
+    /// PEM encoded private key in PKCS#8, PKCS#1 or SEC1 format.
+    private_key: String,
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("tlsConnect", tls_connect)?;
+    Ok(())
+}
+
+/// Connect to a TLS server. The returned socket is driven by `tcpWrite` and `tcpClose`.
+#[js::host_call(with_context)]
+fn tls_connect(
+    service: ServiceRef,
+    _this: js::Value,
+    host: String,
+    port: u16,
+    callback: OwnedJsValue,
+    options: Option<TlsOptions>,
+) -> Result<u64> {
+    let options = options.unwrap_or_default();
+    let server_name = options.server_name.as_deref().unwrap_or(&host);
+    let server_name = ServerName::try_from(server_name).context("Invalid server name")?;
+    let config = client_config(&options)?;
+    spawn_socket(
+        &service,
+        callback,
+        do_tls_connect,
+        (host, port, server_name, config),
+    )
+}
+
+fn client_config(options: &TlsOptions) -> Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    if options.root_certs.is_empty() {
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+    }
+    for pem in options.root_certs.iter() {
+        for cert in parse_certs(pem)? {
+            roots.add(&cert).context("Invalid root certificate")?;
+        }
+    }
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let config = match &options.client_cert {
+        Some(client_cert) => {
+            let certs = parse_certs(&client_cert.cert_chain)?;
+            let key = parse_private_key(&client_cert.private_key)?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .context("Invalid client certificate")?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+fn parse_certs(pem: &str) -> Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut pem.as_bytes()).context("Invalid PEM certificate")?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in PEM");
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn parse_private_key(pem: &str) -> Result<PrivateKey> {
+    use rustls_pemfile::Item;
+    let mut reader = pem.as_bytes();
+    while let Some(item) = rustls_pemfile::read_one(&mut reader).context("Invalid PEM key")? {
+        match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => continue,
+        }
+    }
+    Err(anyhow!("No private key found in PEM"))
+}
+
+async fn do_tls_connect(
+    weak_service: ServiceWeakRef,
+    id: u64,
+    ((host, port, server_name, config), rx): (
+        (String, u16, ServerName, Arc<ClientConfig>),
+        CommandReceiver,
+    ),
+) {
+    let result = async {
+        let stream = connect_checked(&weak_service, &host, port).await?;
+        let stream = TlsConnector::from(config)
+            .connect(server_name, stream)
+            .await
+            .context("TLS handshake failed")?;
+        run_socket(&weak_service, id, stream, rx).await
+    };
+    if let Err(err) = result.await {
+        invoke_callback(
+            &weak_service,
+            id,
+            "error",
+            &format!("Socket `{host}:{port}` error: {err:?}"),
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use anyhow::Context;
 use js::ToJsValue;
 use std::collections::BTreeMap;
-use url::{form_urlencoded, Url};
+use url::{form_urlencoded, quirks, Url};
 
 use super::Result;
 
@@ -20,16 +20,40 @@ struct URL {
     username: String,
 }
 
-#[js::host_call]
-fn parse_url(url: String, base_url: Option<String>) -> Result<URL> {
+impl From<&Url> for URL {
+    fn from(url: &Url) -> Self {
+        Self {
+            hash: quirks::hash(url).into(),
+            host: quirks::host(url).into(),
+            hostname: quirks::hostname(url).into(),
+            href: quirks::href(url).into(),
+            origin: quirks::origin(url),
+            password: quirks::password(url).into(),
+            pathname: quirks::pathname(url).into(),
+            port: quirks::port(url).into(),
+            protocol: quirks::protocol(url).into(),
+            search: quirks::search(url).into(),
+            username: quirks::username(url).into(),
+        }
+    }
+}
+
+fn parse(url: &str, base_url: Option<&str>) -> Result<Url> {
     let url = match base_url {
         Some(base_url) => {
             let base_url: Url = base_url.parse().context("URL: Invalid base URL")?;
-            base_url.join(&url).context("URL: Invalid URL")?
+            base_url.join(url).context("URL: Invalid URL")?
         }
         None => url.parse().context("URL: Invalid URL")?,
     };
+    Ok(url)
+}
 
+/// Parse a URL into its components without the punctuation of the WHATWG URL API, e.g. a
+/// `protocol` of `https` rather than `https:`.
+#[js::host_call]
+fn parse_url(url: String, base_url: Option<String>) -> Result<URL> {
+    let url = parse(&url, base_url.as_deref())?;
     Ok(URL {
         hash: url.fragment().unwrap_or("").to_string(),
         host: url.host_str().unwrap_or("").to_string(),
@@ -45,6 +69,34 @@ fn parse_url(url: String, base_url: Option<String>) -> Result<URL> {
     })
 }
 
+/// Parse a URL into its components as exposed by the WHATWG URL API, for the URL polyfill.
+#[js::host_call]
+fn parse_url_record(url: String, base_url: Option<String>) -> Result<URL> {
+    Ok((&parse(&url, base_url.as_deref())?).into())
+}
+
+/// Set a component of the URL `href` following the setter semantics of the WHATWG URL API.
+///
+/// Invalid values are ignored, except for `href` which throws.
+#[js::host_call]
+fn set_url_component(href: String, component: String, value: String) -> Result<URL> {
+    let mut url = parse(&href, None)?;
+    match component.as_str() {
+        "href" => quirks::set_href(&mut url, &value).context("URL: Invalid URL")?,
+        "protocol" => quirks::set_protocol(&mut url, &value).unwrap_or(()),
+        "username" => quirks::set_username(&mut url, &value).unwrap_or(()),
+        "password" => quirks::set_password(&mut url, &value).unwrap_or(()),
+        "host" => quirks::set_host(&mut url, &value).unwrap_or(()),
+        "hostname" => quirks::set_hostname(&mut url, &value).unwrap_or(()),
+        "port" => quirks::set_port(&mut url, &value).unwrap_or(()),
+        "pathname" => quirks::set_pathname(&mut url, &value),
+        "search" => quirks::set_search(&mut url, &value),
+        "hash" => quirks::set_hash(&mut url, &value),
+        _ => anyhow::bail!("URL: Unknown component {component}"),
+    }
+    Ok((&url).into())
+}
+
 #[js::host_call]
 fn parse_search_params(query_str: String) -> Result<BTreeMap<String, String>> {
     Ok(form_urlencoded::parse(query_str.as_bytes())
@@ -52,8 +104,34 @@ fn parse_search_params(query_str: String) -> Result<BTreeMap<String, String>> {
         .collect())
 }
 
+/// Parse a query string into a list of `[name, value]` pairs, keeping duplicates and order.
+#[js::host_call]
+fn parse_search_pairs(query_str: String) -> Result<Vec<Vec<String>>> {
+    let query_str = query_str.strip_prefix('?').unwrap_or(&query_str);
+    Ok(form_urlencoded::parse(query_str.as_bytes())
+        .map(|(k, v)| vec![k.into_owned(), v.into_owned()])
+        .collect())
+}
+
+/// Serialize `[name, value]` pairs as `application/x-www-form-urlencoded`.
+#[js::host_call]
+fn serialize_search_pairs(pairs: Vec<Vec<String>>) -> Result<String> {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for pair in pairs.iter() {
+        let [name, value] = pair.as_slice() else {
+            anyhow::bail!("URLSearchParams: Each pair must contain exactly two items");
+        };
+        serializer.append_pair(name, value);
+    }
+    Ok(serializer.finish())
+}
+
 pub(crate) fn setup(ns: &js::Value) -> Result<()> {
     ns.define_property_fn("parseURL", parse_url)?;
+    ns.define_property_fn("parseURLRecord", parse_url_record)?;
+    ns.define_property_fn("setURLComponent", set_url_component)?;
     ns.define_property_fn("parseURLParams", parse_search_params)?;
+    ns.define_property_fn("parseURLSearchPairs", parse_search_pairs)?;
+    ns.define_property_fn("serializeURLSearchPairs", serialize_search_pairs)?;
     Ok(())
 }
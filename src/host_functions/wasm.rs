@@ -0,0 +1,569 @@
+//! WebAssembly modules run by the wasmi interpreter, the host side of the `WebAssembly` shim.
+//!
+//! Modules and instances are opaque objects owned by the JS wrappers. Values cross the boundary
+//! as numbers, except `i64` which goes as a decimal string so that the shim can map it to
+//! BigInt. Only functions can be imported, imported memories, tables and globals are rejected.
+//!
+//! The JS functions imported by an instance are not kept by the instance, they are reached
+//! through the `handler` passed to every call into it. This keeps the instance from holding
+//! references into the JS heap, which the GC couldn't see through.
+//!
+//! The interpreter doesn't check the QuickJS interrupt handler. Instead every call into an
+//! instance gets the fuel left by the gas and the deadline of the service, see
+//! `Service::wasm_fuel`, and the fuel it burns is charged back to the gas. The linear memories of
+//! all the instances share one budget, the memory limit of the service, see `MemoryBudget`.
+
+use anyhow::{anyhow, bail, Context};
+use core::cell::{Cell, RefCell};
+use js::{AsBytes, FromJsValue, ToJsValue};
+use std::rc::Rc;
+use wasmi::{
+    core::{Pages, Trap},
+    errors::{MemoryError, TableError},
+    Caller, Config, Engine, ExternType, Func, FuncType, Instance, Linker, Module, ResourceLimiter,
+    Store, StoreLimits, Value, ValueType,
+};
+
+use super::*;
+
+struct WasmModule {
+    engine: Engine,
+    module: Module,
+}
+
+struct WasmInstance {
+    store: RefCell<Store<ImportHandler>>,
+    instance: Instance,
+}
+
+/// The fuel of a call when the service has neither gas nor a deadline.
+const UNLIMITED_FUEL: u64 = u64::MAX / 2;
+
+/// The state of a store: the JS function dispatching the calls to imported functions, set while
+/// the instance runs, and the memory budget of the instance.
+#[derive(Default)]
+struct ImportHandler {
+    service: Option<ServiceWeakRef>,
+    handler: Option<js::Value>,
+    limits: MemoryBudget,
+}
+
+/// Counts the linear memories of an instance against the memory shared by all the instances of
+/// the service, releasing them when the store is dropped.
+#[derive(Default)]
+struct MemoryBudget {
+    /// The memory used by all the instances of the service.
+    in_use: Rc<Cell<usize>>,
+    limit: Option<usize>,
+    /// The memory used by this instance.
+    reserved: usize,
+    /// The growth being applied, given back if it fails.
+    growing: usize,
+    tables: StoreLimits,
+}
+
+impl MemoryBudget {
+    fn new(service: &Service) -> Self {
+        Self {
+            in_use: service.wasm_memory(),
+            limit: service.memory_limit(),
+            ..Default::default()
+        }
+    }
+
+    fn release(&mut self, bytes: usize) {
+        self.reserved -= bytes;
+        self.in_use.set(self.in_use.get() - bytes);
+    }
+}
+
+impl ResourceLimiter for MemoryBudget {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool, MemoryError> {
+        let delta = desired.saturating_sub(current);
+        let in_use = self.in_use.get();
+        if let Some(limit) = self.limit {
+            if in_use.saturating_add(delta) > limit {
+                return Ok(false);
+            }
+        }
+        self.in_use.set(in_use + delta);
+        self.reserved += delta;
+        self.growing = delta;
+        Ok(true)
+    }
+
+    fn memory_grow_failed(&mut self, _error: &MemoryError) {
+        let growing = core::mem::take(&mut self.growing);
+        self.release(growing);
+    }
+
+    fn table_growing(
+        &mut self,
+        current: u32,
+        desired: u32,
+        maximum: Option<u32>,
+    ) -> Result<bool, TableError> {
+        self.tables.table_growing(current, desired, maximum)
+    }
+}
+
+impl Drop for MemoryBudget {
+    fn drop(&mut self) {
+        self.release(self.reserved);
+    }
+}
+
+#[derive(ToJsValue, Debug)]
+struct WasmImport {
+    module: String,
+    name: String,
+    kind: &'static str,
+    params: Vec<&'static str>,
+    results: Vec<&'static str>,
+}
+
+#[derive(ToJsValue, Debug)]
+struct WasmExport {
+    name: String,
+    kind: &'static str,
+    params: Vec<&'static str>,
+    results: Vec<&'static str>,
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("wasmCompile", wasm_compile)?;
+    ns.define_property_fn("wasmModuleImports", wasm_module_imports)?;
+    ns.define_property_fn("wasmModuleExports", wasm_module_exports)?;
+    ns.define_property_fn("wasmInstantiate", wasm_instantiate)?;
+    ns.define_property_fn("wasmCall", wasm_call)?;
+    ns.define_property_fn("wasmMemoryRead", wasm_memory_read)?;
+    ns.define_property_fn("wasmMemoryWrite", wasm_memory_write)?;
+    ns.define_property_fn("wasmMemorySize", wasm_memory_size)?;
+    ns.define_property_fn("wasmMemoryGrow", wasm_memory_grow)?;
+    ns.define_property_fn("wasmGlobalGet", wasm_global_get)?;
+    ns.define_property_fn("wasmGlobalSet", wasm_global_set)?;
+    Ok(())
+}
+
+#[js::host_call(with_context)]
+fn wasm_compile(
+    service: ServiceRef,
+    _this: js::Value,
+    bytes: AsBytes<Vec<u8>>,
+) -> Result<js::Value> {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, &bytes.0[..]).map_err(|err| anyhow!("{err}"))?;
+    Ok(js::Value::new_opaque_object(
+        service.context(),
+        WasmModule { engine, module },
+    ))
+}
+
+#[js::host_call]
+fn wasm_module_imports(module: js::Value) -> Result<Vec<WasmImport>> {
+    let Some(module) = module.opaque_object_data::<WasmModule>() else {
+        bail!("Not a WebAssembly module");
+    };
+    Ok(module
+        .module
+        .imports()
+        .map(|import| {
+            let (params, results) = signature(import.ty());
+            WasmImport {
+                module: import.module().into(),
+                name: import.name().into(),
+                kind: extern_kind(import.ty()),
+                params,
+                results,
+            }
+        })
+        .collect())
+}
+
+#[js::host_call]
+fn wasm_module_exports(module: js::Value) -> Result<Vec<WasmExport>> {
+    let Some(module) = module.opaque_object_data::<WasmModule>() else {
+        bail!("Not a WebAssembly module");
+    };
+    Ok(module
+        .module
+        .exports()
+        .map(|export| {
+            let (params, results) = signature(export.ty());
+            WasmExport {
+                name: export.name().into(),
+                kind: extern_kind(export.ty()),
+                params,
+                results,
+            }
+        })
+        .collect())
+}
+
+/// Instantiate the module, running its start function.
+///
+/// The imported functions call `handler(index, args)`, `index` being the position of the import
+/// in `wasmModuleImports`.
+#[js::host_call(with_context)]
+fn wasm_instantiate(
+    service: ServiceRef,
+    _this: js::Value,
+    module: js::Value,
+    handler: js::Value,
+) -> Result<js::Value> {
+    let Some(module) = module.opaque_object_data::<WasmModule>() else {
+        bail!("Not a WebAssembly module");
+    };
+    let mut store = Store::new(
+        &module.engine,
+        ImportHandler {
+            limits: MemoryBudget::new(&service),
+            ..Default::default()
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    let mut linker = Linker::<ImportHandler>::new(&module.engine);
+    for (index, import) in module.module.imports().enumerate() {
+        let ExternType::Func(ty) = import.ty() else {
+            bail!(
+                "Unsupported import {}.{}, only functions can be imported",
+                import.module(),
+                import.name()
+            );
+        };
+        let func = import_func(&mut store, ty.clone(), index);
+        linker
+            .define(import.module(), import.name(), func)
+            .map_err(|err| anyhow!("{err}"))?;
+    }
+    let instance = with_handler(&service, &mut store, handler, |store| {
+        linker
+            .instantiate(&mut *store, &module.module)
+            .and_then(|pre| pre.start(&mut *store))
+            .map_err(|err| anyhow!("{err}"))
+    })?;
+    Ok(js::Value::new_opaque_object(
+        service.context(),
+        WasmInstance {
+            store: RefCell::new(store),
+            instance,
+        },
+    ))
+}
+
+#[js::host_call(with_context)]
+fn wasm_call(
+    service: ServiceRef,
+    _this: js::Value,
+    instance: js::Value,
+    name: String,
+    args: Vec<js::Value>,
+    handler: js::Value,
+) -> Result<Vec<js::Value>> {
+    let Some(instance) = instance.opaque_object_data::<WasmInstance>() else {
+        bail!("Not a WebAssembly instance");
+    };
+    let mut store = instance.store()?;
+    let func = instance
+        .instance
+        .get_func(&*store, &name)
+        .with_context(|| format!("No exported function {name}"))?;
+    let ty = func.ty(&*store);
+    if args.len() != ty.params().len() {
+        bail!(
+            "{name} expects {} arguments, got {}",
+            ty.params().len(),
+            args.len()
+        );
+    }
+    let args = ty
+        .params()
+        .iter()
+        .zip(args)
+        .map(|(ty, arg)| from_js(*ty, arg))
+        .collect::<Result<Vec<_>>>()?;
+    let mut results: Vec<_> = ty.results().iter().map(|ty| Value::default(*ty)).collect();
+    with_handler(&service, &mut *store, handler, |store| {
+        func.call(store, &args, &mut results)
+            .map_err(|err| anyhow!("{err}"))
+    })?;
+    results
+        .iter()
+        .map(|value| to_js(service.context(), value))
+        .collect()
+}
+
+#[js::host_call]
+fn wasm_memory_read(
+    instance: js::Value,
+    name: String,
+    offset: u32,
+    length: u32,
+) -> Result<AsBytes<Vec<u8>>> {
+    let Some(instance) = instance.opaque_object_data::<WasmInstance>() else {
+        bail!("Not a WebAssembly instance");
+    };
+    let store = instance.store()?;
+    let memory = instance
+        .instance
+        .get_memory(&*store, &name)
+        .with_context(|| format!("No exported memory {name}"))?;
+    let start = offset as usize;
+    let data = memory
+        .data(&*store)
+        .get(start..start.saturating_add(length as usize))
+        .context("Out of bounds memory access")?;
+    Ok(AsBytes(data.to_vec()))
+}
+
+#[js::host_call]
+fn wasm_memory_write(
+    instance: js::Value,
+    name: String,
+    offset: u32,
+    bytes: AsBytes<Vec<u8>>,
+) -> Result<()> {
+    let Some(instance) = instance.opaque_object_data::<WasmInstance>() else {
+        bail!("Not a WebAssembly instance");
+    };
+    let mut store = instance.store()?;
+    let memory = instance
+        .instance
+        .get_memory(&*store, &name)
+        .with_context(|| format!("No exported memory {name}"))?;
+    let start = offset as usize;
+    memory
+        .data_mut(&mut *store)
+        .get_mut(start..start.saturating_add(bytes.0.len()))
+        .context("Out of bounds memory access")?
+        .copy_from_slice(&bytes.0);
+    Ok(())
+}
+
+/// The size of the memory in 64KiB pages.
+#[js::host_call]
+fn wasm_memory_size(instance: js::Value, name: String) -> Result<u32> {
+    let Some(instance) = instance.opaque_object_data::<WasmInstance>() else {
+        bail!("Not a WebAssembly instance");
+    };
+    let store = instance.store()?;
+    let memory = instance
+        .instance
+        .get_memory(&*store, &name)
+        .with_context(|| format!("No exported memory {name}"))?;
+    Ok(memory.current_pages(&*store).into())
+}
+
+/// Grow the memory by `delta` pages, returning the previous size.
+#[js::host_call]
+fn wasm_memory_grow(instance: js::Value, name: String, delta: u32) -> Result<u32> {
+    let Some(instance) = instance.opaque_object_data::<WasmInstance>() else {
+        bail!("Not a WebAssembly instance");
+    };
+    let mut store = instance.store()?;
+    let memory = instance
+        .instance
+        .get_memory(&*store, &name)
+        .with_context(|| format!("No exported memory {name}"))?;
+    let delta = Pages::new(delta).context("Invalid number of pages")?;
+    let previous = memory
+        .grow(&mut *store, delta)
+        .map_err(|err| anyhow!("{err}"))?;
+    Ok(previous.into())
+}
+
+#[js::host_call(with_context)]
+fn wasm_global_get(
+    service: ServiceRef,
+    _this: js::Value,
+    instance: js::Value,
+    name: String,
+) -> Result<js::Value> {
+    let Some(instance) = instance.opaque_object_data::<WasmInstance>() else {
+        bail!("Not a WebAssembly instance");
+    };
+    let store = instance.store()?;
+    let global = instance
+        .instance
+        .get_global(&*store, &name)
+        .with_context(|| format!("No exported global {name}"))?;
+    to_js(service.context(), &global.get(&*store))
+}
+
+#[js::host_call]
+fn wasm_global_set(instance: js::Value, name: String, value: js::Value) -> Result<()> {
+    let Some(instance) = instance.opaque_object_data::<WasmInstance>() else {
+        bail!("Not a WebAssembly instance");
+    };
+    let mut store = instance.store()?;
+    let global = instance
+        .instance
+        .get_global(&*store, &name)
+        .with_context(|| format!("No exported global {name}"))?;
+    let value = from_js(global.ty(&*store).content(), value)?;
+    global
+        .set(&mut *store, value)
+        .map_err(|err| anyhow!("{err}"))
+}
+
+impl WasmInstance {
+    fn store(&self) -> Result<core::cell::RefMut<'_, Store<ImportHandler>>> {
+        self.store
+            .try_borrow_mut()
+            .map_err(|_| anyhow!("Reentrant call into a WebAssembly instance"))
+    }
+}
+
+/// Run `f` with the import handler installed in the store and the fuel left to the service.
+fn with_handler<R>(
+    service: &Service,
+    store: &mut Store<ImportHandler>,
+    handler: js::Value,
+    f: impl FnOnce(&mut Store<ImportHandler>) -> Result<R>,
+) -> Result<R> {
+    let fuel = service.wasm_fuel().unwrap_or(UNLIMITED_FUEL);
+    let leftover = store.consume_fuel(0).map_err(|err| anyhow!("{err}"))?;
+    store
+        .consume_fuel(leftover)
+        .and_then(|_| store.add_fuel(fuel))
+        .map_err(|err| anyhow!("{err}"))?;
+    let state = store.data_mut();
+    state.service = Some(service.weak_self());
+    state.handler = Some(handler);
+    let result = f(store);
+    let state = store.data_mut();
+    state.service = None;
+    state.handler = None;
+    let left = store.consume_fuel(0).unwrap_or(0);
+    service.charge_wasm_fuel(fuel - left, left == 0);
+    result
+}
+
+/// A function calling the import handler with the index of the import.
+fn import_func(store: &mut Store<ImportHandler>, ty: FuncType, index: usize) -> Func {
+    let results_ty = ty.results().to_vec();
+    Func::new(
+        store,
+        ty,
+        move |caller: Caller<'_, ImportHandler>, params, results| {
+            let state = caller.data();
+            let (Some(service), Some(handler)) = (
+                state.service.as_ref().and_then(|s| s.upgrade()),
+                state.handler.clone(),
+            ) else {
+                return Err(Trap::new("Import called outside of the instance"));
+            };
+            let call = || -> Result<()> {
+                let args = params
+                    .iter()
+                    .map(|value| to_js(service.context(), value))
+                    .collect::<Result<Vec<_>>>()?;
+                let ret = service.call_function(handler, (index as u32, args))?;
+                let ret = Vec::<js::Value>::from_js_value(ret)?;
+                if ret.len() != results.len() {
+                    bail!(
+                        "Import {index} returned {} values, expected {}",
+                        ret.len(),
+                        results.len()
+                    );
+                }
+                for ((slot, ty), value) in results.iter_mut().zip(&results_ty).zip(ret) {
+                    *slot = from_js(*ty, value)?;
+                }
+                Ok(())
+            };
+            call().map_err(|err| Trap::new(format!("{err:?}")))
+        },
+    )
+}
+
+fn from_js(ty: ValueType, value: js::Value) -> Result<Value> {
+    Ok(match ty {
+        ValueType::I32 => Value::I32(f64::from_js_value(value)? as i32),
+        ValueType::I64 => Value::I64(
+            String::from_js_value(value)?
+                .parse()
+                .context("Invalid i64")?,
+        ),
+        ValueType::F32 => Value::F32((f64::from_js_value(value)? as f32).into()),
+        ValueType::F64 => Value::F64(f64::from_js_value(value)?.into()),
+        ty => bail!("Unsupported value type {ty:?}"),
+    })
+}
+
+fn to_js(ctx: &js::Context, value: &Value) -> Result<js::Value> {
+    let value = match value {
+        Value::I32(v) => (*v as f64).to_js_value(ctx),
+        Value::I64(v) => v.to_string().to_js_value(ctx),
+        Value::F32(v) => (f32::from(*v) as f64).to_js_value(ctx),
+        Value::F64(v) => f64::from(*v).to_js_value(ctx),
+        value => bail!("Unsupported value type {:?}", value.ty()),
+    };
+    Ok(value?)
+}
+
+fn extern_kind(ty: &ExternType) -> &'static str {
+    match ty {
+        ExternType::Func(_) => "function",
+        ExternType::Memory(_) => "memory",
+        ExternType::Table(_) => "table",
+        ExternType::Global(_) => "global",
+    }
+}
+
+/// The parameter and result types of a function, or the value type of a global as parameter.
+fn signature(ty: &ExternType) -> (Vec<&'static str>, Vec<&'static str>) {
+    match ty {
+        ExternType::Func(ty) => (type_names(ty.params()), type_names(ty.results())),
+        ExternType::Global(ty) => (vec![type_name(ty.content())], vec![]),
+        _ => (vec![], vec![]),
+    }
+}
+
+fn type_names(types: &[ValueType]) -> Vec<&'static str> {
+    types.iter().map(|ty| type_name(*ty)).collect()
+}
+
+fn type_name(ty: ValueType) -> &'static str {
+    match ty {
+        ValueType::I32 => "i32",
+        ValueType::I64 => "i64",
+        ValueType::F32 => "f32",
+        ValueType::F64 => "f64",
+        ValueType::FuncRef => "anyfunc",
+        ValueType::ExternRef => "externref",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(in_use: &Rc<Cell<usize>>, limit: usize) -> MemoryBudget {
+        MemoryBudget {
+            in_use: in_use.clone(),
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn instances_share_the_memory_limit() {
+        let in_use = Rc::new(Cell::new(0));
+        let mut first = budget(&in_use, 100);
+        let mut second = budget(&in_use, 100);
+        assert!(first.memory_growing(0, 60, None).unwrap());
+        assert!(!second.memory_growing(0, 60, None).unwrap());
+        assert!(second.memory_growing(0, 40, None).unwrap());
+        drop(first);
+        assert_eq!(in_use.get(), 40);
+        assert!(second.memory_growing(40, 100, None).unwrap());
+    }
+}
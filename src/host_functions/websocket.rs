@@ -0,0 +1,138 @@
+use anyhow::Context;
+use futures::{SinkExt, StreamExt};
+use js::{AsBytes, ToJsValue};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message;
+
+use super::*;
+use crate::service::OwnedJsValue;
+
+#[derive(ToJsValue, Debug)]
+struct CloseEvent {
+    code: u16,
+    reason: String,
+}
+
+enum Command {
+    Send(Message),
+    Close,
+}
+
+type CommandSender = UnboundedSender<Command>;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("websocketConnect", websocket_connect)?;
+    ns.define_property_fn("websocketSend", websocket_send)?;
+    ns.define_property_fn("websocketClose", websocket_close)?;
+    Ok(())
+}
+
+#[js::host_call(with_context)]
+fn websocket_connect(
+    service: ServiceRef,
+    _this: js::Value,
+    url: String,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    let (tx, rx) = unbounded_channel();
+    let id = service.spawn(callback, do_websocket, (url, rx))?;
+    service.set_resource_data(id, tx);
+    Ok(id)
+}
+
+#[js::host_call(with_context)]
+fn websocket_send(service: ServiceRef, _this: js::Value, id: u64, data: js::Value) -> Result<()> {
+    let message = if data.is_string() {
+        Message::Text(data.decode_string()?)
+    } else {
+        Message::Binary(data.decode_bytes()?)
+    };
+    send_command(&service, id, Command::Send(message))
+}
+
+#[js::host_call(with_context)]
+fn websocket_close(service: ServiceRef, _this: js::Value, id: u64) -> Result<()> {
+    send_command(&service, id, Command::Close)
+}
+
+fn send_command(service: &Service, id: u64, command: Command) -> Result<()> {
+    let Some(tx) = service.resource_data::<CommandSender>(id) else {
+        anyhow::bail!("WebSocket {id} not found");
+    };
+    tx.send(command)
+        .map_err(|_| anyhow::anyhow!("WebSocket {id} has been closed"))
+}
+
+async fn do_websocket(
+    weak_service: ServiceWeakRef,
+    id: u64,
+    (url, rx): (String, UnboundedReceiver<Command>),
+) {
+    if let Err(err) = do_websocket_inner(&weak_service, id, &url, rx).await {
+        invoke_callback(
+            &weak_service,
+            id,
+            "error",
+            &format!("WebSocket `{url}` error: {err:?}"),
+        );
+    }
+}
+
+async fn do_websocket_inner(
+    weak_service: &ServiceWeakRef,
+    id: u64,
+    url: &str,
+    mut rx: UnboundedReceiver<Command>,
+) -> Result<()> {
+    let parsed: ::url::Url = url.parse().context("Invalid url")?;
+    let host = parsed.host_str().context("Missing host in url")?;
+    let port = parsed
+        .port_or_known_default()
+        .context("Missing port in url")?;
+    let tcp = connect_checked(weak_service, host, port).await?;
+    let (stream, _response) = tokio_tungstenite::client_async_tls(url, tcp)
+        .await
+        .context("Failed to connect")?;
+    invoke_callback(weak_service, id, "open", &());
+    let (mut sink, mut stream) = stream.split();
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            command = rx.recv(), if !closing => {
+                match command {
+                    Some(Command::Send(message)) => sink.send(message).await?,
+                    Some(Command::Close) | None => {
+                        closing = true;
+                        sink.close().await?;
+                    }
+                }
+            }
+            message = stream.next() => {
+                let Some(message) = message else {
+                    let event = CloseEvent { code: 1006, reason: String::new() };
+                    invoke_callback(weak_service, id, "close", &event);
+                    break;
+                };
+                match message? {
+                    Message::Text(text) => invoke_callback(weak_service, id, "message", &text),
+                    Message::Binary(data) => {
+                        invoke_callback(weak_service, id, "message", &AsBytes(data))
+                    }
+                    Message::Close(frame) => {
+                        let event = match frame {
+                            Some(frame) => CloseEvent {
+                                code: frame.code.into(),
+                                reason: frame.reason.into_owned(),
+                            },
+                            None => CloseEvent { code: 1005, reason: String::new() },
+                        };
+                        invoke_callback(weak_service, id, "close", &event);
+                        break;
+                    }
+                    Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
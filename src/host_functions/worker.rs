@@ -0,0 +1,236 @@
+use alloc::rc::Rc;
+use anyhow::{anyhow, bail};
+use core::cell::Cell;
+use js::{AsBytes, FromJsValue, ToJsValue};
+use log::info;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use super::*;
+use crate::service::OwnedJsValue;
+
+type MessageSender = UnboundedSender<Message>;
+
+/// The smallest memory limit a worker is started with.
+const MIN_WORKER_MEMORY: usize = 1024 * 1024;
+
+/// A message as produced by the serializer in the bootcode: the JSON of the value, and the
+/// contents of the ArrayBuffers and typed arrays it refers to by index.
+#[derive(FromJsValue, ToJsValue)]
+pub(crate) struct Message {
+    data: String,
+    buffers: Vec<AsBytes<Vec<u8>>>,
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("workerCreate", worker_create)?;
+    ns.define_property_fn("workerPostMessage", worker_post_message)?;
+    ns.define_property_fn("workerPostToParent", worker_post_to_parent)?;
+    ns.define_property_fn("workerClose", worker_close)?;
+    Ok(())
+}
+
+/// The memory limit of a worker, reserved out of the memory limit of its parent until the worker
+/// ends.
+struct MemoryShare {
+    in_use: Rc<Cell<usize>>,
+    size: usize,
+}
+
+impl MemoryShare {
+    /// Half of what the running workers of `service` left of its memory limit, so that all the
+    /// workers of a service, and theirs, stay within its limit. None if the service is unlimited.
+    fn reserve(service: &Service) -> Result<Option<Self>> {
+        let Some(limit) = service.memory_limit() else {
+            return Ok(None);
+        };
+        let in_use = service.worker_memory();
+        let size = limit.saturating_sub(in_use.get()) / 2;
+        if size < MIN_WORKER_MEMORY {
+            bail!("Not enough memory left for another worker, the limit is {limit} bytes");
+        }
+        in_use.set(in_use.get() + size);
+        Ok(Some(Self { in_use, size }))
+    }
+}
+
+impl Drop for MemoryShare {
+    fn drop(&mut self) {
+        self.in_use.set(self.in_use.get().saturating_sub(self.size));
+    }
+}
+
+/// The settings a worker inherits from the service that creates it.
+struct Inherited {
+    /// The gas, deadline and watchdog, so a busy worker can't outlive the limits of its parent.
+    exec_limits: crate::service::ExecLimits,
+    memory: Option<MemoryShare>,
+    default_headers: Vec<(String, String)>,
+    http_proxy: Option<String>,
+    script_proxy: bool,
+    unix_sockets: Vec<std::path::PathBuf>,
+    egress_filter: Option<EgressFilter>,
+    http_interceptor: Option<HttpInterceptor>,
+    /// Shares the buckets with the parent, so the limits cover the workers as well.
+    rate_limit: Option<crate::RateLimit>,
+    http_cache: bool,
+    clock_resolution: Option<core::time::Duration>,
+    /// Workers share the filesystem of their parent.
+    file_system: Rc<dyn crate::FileSystem>,
+    #[cfg(feature = "js-dns")]
+    dns_resolver: Option<crate::host_functions::DnsResolver>,
+}
+
+impl Inherited {
+    fn from_service(service: &Service) -> Result<Self> {
+        let memory = MemoryShare::reserve(service)?;
+        Ok(Self {
+            exec_limits: service.worker_limits(),
+            memory,
+            default_headers: service.default_headers(),
+            http_proxy: service.http_proxy(),
+            script_proxy: service.script_proxy_allowed(),
+            unix_sockets: service.unix_sockets(),
+            egress_filter: service.egress_filter(),
+            http_interceptor: service.http_interceptor(),
+            rate_limit: service.rate_limit(),
+            http_cache: service.http_cache_enabled(),
+            clock_resolution: service.clock_resolution(),
+            file_system: service.file_system(),
+            #[cfg(feature = "js-dns")]
+            dns_resolver: service.dns_resolver(),
+        })
+    }
+
+    /// Apply the settings to `worker`, returning the memory share to keep while it runs.
+    fn apply(self, worker: &Service) -> Option<MemoryShare> {
+        worker.set_exec_limits(self.exec_limits);
+        if let Some(memory) = &self.memory {
+            worker.set_memory_limit(memory.size);
+        }
+        worker.set_default_headers(self.default_headers);
+        worker.set_http_proxy(self.http_proxy);
+        worker.set_script_proxy(self.script_proxy);
+        worker.set_unix_sockets(self.unix_sockets);
+        worker.set_egress_filter(self.egress_filter);
+        worker.set_http_interceptor(self.http_interceptor);
+        worker.set_rate_limit(self.rate_limit);
+        worker.set_http_cache(self.http_cache);
+        worker.set_clock_resolution(self.clock_resolution);
+        worker.set_shared_file_system(self.file_system);
+        #[cfg(feature = "js-dns")]
+        if let Some(resolver) = self.dns_resolver {
+            worker.set_dns_resolver(resolver);
+        }
+        self.memory
+    }
+}
+
+/// Run `source` in a new isolated context. Events are reported to `callback` as
+/// `("message", message)` and `("error", message)`.
+///
+/// The worker lives until its resource is closed or the worker calls `workerClose`. Its memory
+/// limit is half of what the other workers left of the memory limit of the service, see
+/// `MemoryShare`.
+#[js::host_call(with_context)]
+fn worker_create(
+    service: ServiceRef,
+    _this: js::Value,
+    source: String,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    let (tx, rx) = unbounded_channel();
+    let id = service.spawn(
+        callback,
+        do_worker,
+        (
+            source,
+            Inherited::from_service(&service)?,
+            (service.permissions(), service.metrics_enabled()),
+            rx,
+        ),
+    )?;
+    service.set_resource_data(id, tx);
+    Ok(id)
+}
+
+#[js::host_call(with_context)]
+fn worker_post_message(
+    service: ServiceRef,
+    _this: js::Value,
+    id: u64,
+    message: Message,
+) -> Result<()> {
+    let Some(tx) = service.resource_data::<MessageSender>(id) else {
+        bail!("Worker {id} not found");
+    };
+    tx.send(message)
+        .map_err(|_| anyhow!("Worker {id} has been terminated"))
+}
+
+/// Post a message from inside a worker to the context that created it.
+#[js::host_call(with_context)]
+fn worker_post_to_parent(service: ServiceRef, _this: js::Value, message: Message) -> Result<()> {
+    let Some(port) = service.parent_port() else {
+        bail!("postMessage: not running in a worker");
+    };
+    port.send(message)
+        .map_err(|_| anyhow!("postMessage: the worker has been closed"))
+}
+
+#[js::host_call(with_context)]
+fn worker_close(service: ServiceRef, _this: js::Value) {
+    service.take_parent_port();
+}
+
+async fn do_worker(
+    weak_service: ServiceWeakRef,
+    id: u64,
+    (source, inherited, (permissions, metrics), mut rx): (
+        String,
+        Inherited,
+        (Permissions, bool),
+        UnboundedReceiver<Message>,
+    ),
+) {
+    // The permissions and the metrics are fixed when the host functions are set up, so not part
+    // of `Inherited`
+    let worker = Service::new_ref_with_metrics(permissions, metrics);
+    let _memory = inherited.apply(&worker);
+    let (port, mut from_worker) = unbounded_channel();
+    worker.set_parent_port(port);
+    worker.set_entry_code(source.as_bytes());
+    if let Err(err) = worker.exec_script(&source) {
+        invoke_callback(&weak_service, id, "error", &format!("Worker error: {err}"));
+        return;
+    }
+    loop {
+        tokio::select! {
+            message = rx.recv() => {
+                let Some(message) = message else {
+                    break;
+                };
+                if let Err(err) = dispatch_message(&worker, &message) {
+                    invoke_callback(&weak_service, id, "error", &format!("Worker error: {err}"));
+                }
+            }
+            message = from_worker.recv() => {
+                // The sender is only dropped when the worker closes itself.
+                let Some(message) = message else {
+                    break;
+                };
+                invoke_callback(&weak_service, id, "message", &message);
+            }
+        }
+    }
+    info!("worker {id} exited");
+}
+
+fn dispatch_message(worker: &Service, message: &Message) -> Result<()> {
+    let dispatch = worker
+        .context()
+        .get_global_object()
+        .get_property("Sidevm")?
+        .get_property("dispatchWorkerMessage")?;
+    worker.call_function(dispatch, (message,))?;
+    Ok(())
+}
@@ -1,6 +1,9 @@
+use alloc::rc::Rc;
+use core::{cell::RefCell, time::Duration};
 use js::ToJsValue;
+use std::net::SocketAddr;
 
-use crate::Service;
+use crate::{DirFs, EgressFilter, ExecError, MemoryFs, ModuleDir, Permissions, Service};
 use anyhow::{anyhow, bail, Context, Result};
 
 use pink_types::js::{JsCode, JsValue};
@@ -8,10 +11,106 @@ use pink_types::js::{JsCode, JsValue};
 struct Args {
     codes: Vec<JsCode>,
     js_args: Vec<String>,
+    interactive: bool,
+    compile: Option<Compile>,
+    max_memory: Option<usize>,
+    timeout: Option<Duration>,
+    /// Log scripts or callbacks running longer than this without yielding.
+    watchdog: Option<Duration>,
+    /// Interrupt the scripts or callbacks caught by the watchdog.
+    watchdog_interrupt: bool,
+    /// Serve incoming HTTP requests on this address after running the scripts.
+    listen: Option<SocketAddr>,
+    /// Serve the DevTools protocol on this address after running the scripts.
+    inspect: Option<SocketAddr>,
+    /// Headers added to every outgoing http request.
+    headers: Vec<(String, String)>,
+    /// Proxy for outgoing http requests, defaults to the `ALL_PROXY` environment variable.
+    proxy: Option<String>,
+    /// Let the scripts choose the proxy of their requests.
+    script_proxy: bool,
+    unix_sockets: Vec<std::path::PathBuf>,
+    /// DNS servers, see `DnsConfig` for the format.
+    dns: Option<String>,
+    /// The host functions available to the scripts.
+    permissions: Permissions,
+    /// Restricts the hosts the scripts can connect to.
+    egress_filter: Option<EgressFilter>,
+    /// The host directory backing the `fs` namespace, which is in memory if not set.
+    allow_fs: Option<String>,
+    /// The quota of the `fs` namespace, in bytes.
+    fs_quota: Option<usize>,
+    /// Cache the responses of the GET requests.
+    http_cache: bool,
+    /// Treat all the scripts as TypeScript, files named `*.ts` are always transpiled.
+    #[cfg(feature = "typescript")]
+    typescript: bool,
+    /// The directory of the packages imported by bare specifiers.
+    module_dir: Option<String>,
+    /// Remote modules to fetch before running the scripts, as `(url, integrity)`.
+    remote_modules: Vec<(String, String)>,
+    /// Where the fetched remote modules are cached.
+    module_cache: Option<String>,
+    output: OutputFormat,
+    /// Define `globalThis.gc()`.
+    expose_gc: bool,
+    /// Define `globalThis.__metrics()`.
+    expose_metrics: bool,
+    /// Where to write the CPU profile of the scripts.
+    profile: Option<String>,
+    /// The entries of `globalThis.env`.
+    env: Vec<(String, String)>,
+}
+
+/// How the result of the scripts is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// Logged in the Rust debug format.
+    Debug,
+    /// Printed on stdout as `{"type": .., "value": ..}`, see `output_json`.
+    Json,
+}
+
+/// Compile a script file to bytecode instead of executing it.
+struct Compile {
+    file: String,
+    /// Write the raw bytecode to this file. The bytecode is printed in hex if not set.
+    output: Option<String>,
 }
 
 fn parse_args(args: impl Iterator<Item = String>) -> Result<Args> {
     let mut codes = vec![];
+    let mut interactive = false;
+    let mut compile_file = None;
+    let mut compile_output = None;
+    let mut max_memory = None;
+    let mut timeout = None;
+    let mut watchdog = None;
+    let mut watchdog_interrupt = false;
+    let mut listen = None;
+    let mut inspect = None;
+    let mut headers = vec![];
+    let mut proxy = None;
+    let mut script_proxy = false;
+    let mut unix_sockets = vec![];
+    let mut dns = None;
+    let mut permissions = Permissions::default();
+    let mut egress_filter: Option<EgressFilter> = None;
+    let mut allow_fs = None;
+    let mut fs_quota = None;
+    let mut http_cache = false;
+    #[cfg(feature = "typescript")]
+    let mut typescript = false;
+    #[cfg(feature = "typescript")]
+    let mut typescript_files = vec![];
+    let mut module_dir = None;
+    let mut remote_modules = vec![];
+    let mut module_cache = None;
+    let mut output = OutputFormat::Debug;
+    let mut expose_gc = false;
+    let mut expose_metrics = false;
+    let mut profile = None;
+    let mut env = vec![];
     let mut iter = args;
     iter.next();
     while let Some(arg) = iter.next() {
@@ -29,6 +128,166 @@ fn parse_args(args: impl Iterator<Item = String>) -> Result<Args> {
                     let bytecode = hex::decode(code).context("Failed to decode bytecode")?;
                     codes.push(JsCode::Bytecode(bytecode));
                 }
+                "-" | "--stdin" => {
+                    let code = std::io::read_to_string(std::io::stdin())
+                        .context("Failed to read script from stdin")?;
+                    codes.push(JsCode::Source(code));
+                }
+                "-i" => {
+                    interactive = true;
+                }
+                "--compile" => {
+                    let file = iter.next().ok_or(anyhow!("Missing file after --compile"))?;
+                    compile_file = Some(file);
+                }
+                "-o" => {
+                    let file = iter.next().ok_or(anyhow!("Missing file after -o"))?;
+                    compile_output = Some(file);
+                }
+                "--max-memory" => {
+                    let size = iter
+                        .next()
+                        .ok_or(anyhow!("Missing size after --max-memory"))?;
+                    max_memory = Some(parse_size(&size)?);
+                }
+                "--timeout" => {
+                    let ms = iter.next().ok_or(anyhow!("Missing ms after --timeout"))?;
+                    let ms = ms.parse().context("Invalid timeout")?;
+                    timeout = Some(Duration::from_millis(ms));
+                }
+                "--watchdog" => {
+                    let ms = iter.next().ok_or(anyhow!("Missing ms after --watchdog"))?;
+                    let ms = ms.parse().context("Invalid watchdog threshold")?;
+                    watchdog = Some(Duration::from_millis(ms));
+                }
+                "--watchdog-interrupt" => {
+                    watchdog_interrupt = true;
+                }
+                "--listen" => {
+                    let addr = iter
+                        .next()
+                        .ok_or(anyhow!("Missing address after --listen"))?;
+                    listen = Some(addr.parse().context("Invalid listen address")?);
+                }
+                "--inspect" => {
+                    let addr = iter
+                        .next()
+                        .ok_or(anyhow!("Missing address after --inspect"))?;
+                    inspect = Some(addr.parse().context("Invalid inspect address")?);
+                }
+                "-H" | "--header" => {
+                    let header = iter.next().ok_or(anyhow!("Missing header after {arg}"))?;
+                    let (name, value) = header
+                        .split_once(':')
+                        .ok_or(anyhow!("Invalid header, expected `Name: value`: {header}"))?;
+                    headers.push((name.trim().into(), value.trim().into()));
+                }
+                "--proxy" => {
+                    let url = iter.next().ok_or(anyhow!("Missing url after --proxy"))?;
+                    proxy = Some(url);
+                }
+                "--script-proxy" => {
+                    script_proxy = true;
+                }
+                "--allow-unix-socket" => {
+                    let path = iter
+                        .next()
+                        .ok_or(anyhow!("Missing path after --allow-unix-socket"))?;
+                    unix_sockets.push(path.into());
+                }
+                "--dns" => {
+                    let servers = iter.next().ok_or(anyhow!("Missing servers after --dns"))?;
+                    dns = Some(servers);
+                }
+                "--allow-host" | "--deny-host" => {
+                    let rules = iter.next().ok_or(anyhow!("Missing hosts after {arg}"))?;
+                    let mut filter = egress_filter.take().unwrap_or_default();
+                    for rule in rules.split(',') {
+                        filter = if arg == "--allow-host" {
+                            filter.allow(rule)?
+                        } else {
+                            filter.deny(rule)?
+                        };
+                    }
+                    egress_filter = Some(filter);
+                }
+                #[cfg(feature = "typescript")]
+                "--typescript" => {
+                    typescript = true;
+                }
+                "--module-dir" => {
+                    let dir = iter
+                        .next()
+                        .ok_or(anyhow!("Missing directory after --module-dir"))?;
+                    module_dir = Some(dir);
+                }
+                "--remote-module" => {
+                    let spec = iter
+                        .next()
+                        .ok_or(anyhow!("Missing module after --remote-module"))?;
+                    let (url, integrity) = spec
+                        .split_once('#')
+                        .ok_or(anyhow!("Expected <url>#<integrity> after --remote-module"))?;
+                    remote_modules.push((url.into(), integrity.into()));
+                }
+                "--module-cache" => {
+                    let dir = iter
+                        .next()
+                        .ok_or(anyhow!("Missing directory after --module-cache"))?;
+                    module_cache = Some(dir);
+                }
+                "--output" => {
+                    let format = iter
+                        .next()
+                        .ok_or(anyhow!("Missing format after --output"))?;
+                    output = match format.as_str() {
+                        "debug" => OutputFormat::Debug,
+                        "json" => OutputFormat::Json,
+                        _ => bail!("Unknown output format: {format}"),
+                    };
+                }
+                "-e" | "--env" => {
+                    let entry = iter
+                        .next()
+                        .ok_or(anyhow!("Missing KEY=VALUE after {arg}"))?;
+                    let (key, value) = entry
+                        .split_once('=')
+                        .ok_or(anyhow!("Invalid env entry, expected KEY=VALUE: {entry}"))?;
+                    env.push((key.into(), value.into()));
+                }
+                "--expose-gc" => {
+                    expose_gc = true;
+                }
+                "--expose-metrics" => {
+                    expose_metrics = true;
+                }
+                "--profile" => {
+                    let path = iter.next().ok_or(anyhow!("Missing file after --profile"))?;
+                    profile = Some(path);
+                }
+                "--allow-fs" => {
+                    let dir = iter
+                        .next()
+                        .ok_or(anyhow!("Missing directory after --allow-fs"))?;
+                    allow_fs = Some(dir);
+                }
+                "--fs-quota" => {
+                    let size = iter
+                        .next()
+                        .ok_or(anyhow!("Missing size after --fs-quota"))?;
+                    fs_quota = Some(parse_size(&size)?);
+                }
+                "--http-cache" => {
+                    http_cache = true;
+                }
+                "--deny" => {
+                    let caps = iter
+                        .next()
+                        .ok_or(anyhow!("Missing capabilities after --deny"))?;
+                    for cap in caps.split(',') {
+                        permissions = permissions.deny(cap.trim().parse()?);
+                    }
+                }
                 _ => {
                     print_usage();
                     bail!("Unknown option: {}", arg);
@@ -36,31 +295,360 @@ fn parse_args(args: impl Iterator<Item = String>) -> Result<Args> {
             }
         } else {
             // File name
-            let code = std::fs::read_to_string(arg).context("Failed to read script file")?;
+            let code = std::fs::read_to_string(&arg).context("Failed to read script file")?;
+            #[cfg(feature = "typescript")]
+            if crate::typescript::is_typescript(&arg) {
+                typescript_files.push((codes.len(), arg));
+            }
             codes.push(JsCode::Source(code));
         }
     }
+    // With `--typescript` the service transpiles every script, the files must not be transpiled
+    // twice.
+    #[cfg(feature = "typescript")]
+    if !typescript {
+        for (index, name) in typescript_files {
+            if let JsCode::Source(code) = &mut codes[index] {
+                *code =
+                    crate::typescript::transpile(code, &name).map_err(|err| anyhow!("{err}"))?;
+            }
+        }
+    }
+    if let Some(file) = compile_file {
+        return Ok(Args {
+            codes,
+            js_args: vec![],
+            interactive: false,
+            compile: Some(Compile {
+                file,
+                output: compile_output,
+            }),
+            max_memory,
+            timeout,
+            watchdog,
+            watchdog_interrupt,
+            listen: None,
+            inspect: None,
+            headers,
+            proxy,
+            script_proxy,
+            unix_sockets,
+            dns,
+            permissions,
+            egress_filter,
+            allow_fs,
+            fs_quota,
+            http_cache,
+            #[cfg(feature = "typescript")]
+            typescript,
+            module_dir,
+            remote_modules,
+            module_cache,
+            output,
+            expose_gc,
+            expose_metrics,
+            profile,
+            env,
+        });
+    }
+    if compile_output.is_some() {
+        bail!("-o can only be used with --compile");
+    }
     if codes.is_empty() {
-        print_usage();
-        bail!("No script file provided");
+        if cfg!(feature = "native") {
+            interactive = true;
+        } else {
+            print_usage();
+            bail!("No script file provided");
+        }
     }
     let js_args = iter.collect();
-    Ok(Args { codes, js_args })
+    Ok(Args {
+        codes,
+        js_args,
+        interactive,
+        compile: None,
+        max_memory,
+        timeout,
+        watchdog,
+        watchdog_interrupt,
+        listen,
+        inspect,
+        headers,
+        proxy,
+        script_proxy,
+        unix_sockets,
+        dns,
+        permissions,
+        egress_filter,
+        allow_fs,
+        fs_quota,
+        http_cache,
+        #[cfg(feature = "typescript")]
+        typescript,
+        module_dir,
+        remote_modules,
+        module_cache,
+        output,
+        expose_gc,
+        expose_metrics,
+        profile,
+        env,
+    })
+}
+
+#[cfg(feature = "js-remote-module")]
+async fn import_remote_modules(
+    service: &Service,
+    cache_dir: Option<String>,
+    modules: Vec<(String, String)>,
+) -> Result<()> {
+    service.set_module_cache_dir(cache_dir.map(Into::into));
+    for (url, integrity) in modules {
+        service.import_remote(&url, &integrity).await?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "js-remote-module"))]
+async fn import_remote_modules(
+    _service: &Service,
+    _cache_dir: Option<String>,
+    modules: Vec<(String, String)>,
+) -> Result<()> {
+    if !modules.is_empty() {
+        bail!("--remote-module is not supported in this build");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "js-dns")]
+fn set_dns(service: &Service, servers: &str) -> Result<()> {
+    let config: crate::DnsConfig = servers.parse()?;
+    service.set_dns_config(&config)
+}
+
+#[cfg(not(feature = "js-dns"))]
+fn set_dns(_service: &Service, _servers: &str) -> Result<()> {
+    bail!("--dns is not supported in this build")
+}
+
+/// Parse a size in bytes with an optional K, M or G suffix.
+fn parse_size(size: &str) -> Result<usize> {
+    let (digits, unit) = match size.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&size[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    let n: usize = digits
+        .parse()
+        .with_context(|| format!("Invalid size: {size}"))?;
+    n.checked_mul(unit)
+        .ok_or_else(|| anyhow!("Size too large: {size}"))
 }
 
 fn print_usage() {
     println!("phatjs v{}", env!("CARGO_PKG_VERSION"));
     println!("Usage: phatjs [options] [script..] [-- [args]]");
+    println!("       phatjs --compile <script> [-o <output>]");
     println!("");
     println!("Options:");
     println!("  -c <code>        Execute code");
     println!("  -b <hexed code>  Execute bytecode");
+    println!("  -, --stdin       Execute the script read from stdin");
+    println!("  -i               Enter interactive mode after executing the scripts");
+    println!(
+        "  --compile <file> Compile the script to bytecode, printed in hex unless -o is given"
+    );
+    println!("  -o <file>        Write the compiled bytecode to file");
+    println!("  --max-memory <size>");
+    println!("                   Limit the memory of the script, e.g. 64M");
+    println!("  --timeout <ms>   Abort the script if it doesn't finish in time");
+    println!("  --watchdog <ms>  Log the stack of callbacks running longer without yielding");
+    println!("  --watchdog-interrupt");
+    println!("                   Interrupt the callbacks caught by --watchdog");
+    println!("  --listen <addr>  Serve incoming HTTP requests, e.g. 127.0.0.1:8000");
+    println!("  --inspect <addr> Serve the DevTools protocol for evaluation, logs and profiling");
+    println!("  -H, --header <name: value>");
+    println!("                   Add a header to every outgoing HTTP request");
+    println!("  --proxy <url>    Send HTTP requests through a http:// or socks5:// proxy");
+    println!("  --script-proxy   Let the scripts choose the proxy of their HTTP requests");
+    println!("  --allow-unix-socket <path>");
+    println!("                   Let the scripts send HTTP requests to the Unix domain socket");
+    println!(
+        "  --dns <servers>  Resolve host names with the given DNS servers, e.g. 1.1.1.1,8.8.8.8"
+    );
+    println!("                   or https://cloudflare-dns.com@1.1.1.1 for DNS over HTTPS");
+    println!("  --deny <caps>    Deny the scripts the comma-separated capabilities among network,");
+    println!("                   crypto, cache, fs, timers, workers and wasm");
+    println!("  --allow-host <rules>");
+    println!("                   Only connect to the comma-separated hosts, e.g. *.example.com");
+    println!("                   or 10.0.0.0/8");
+    println!("  --deny-host <rules>");
+    println!("                   Never connect to the comma-separated hosts");
+    println!(
+        "  --allow-fs <dir> Back the fs namespace with <dir> instead of an in-memory filesystem"
+    );
+    println!("  --fs-quota <size>");
+    println!("                   Limit the size of the files in the fs namespace, e.g. 16M");
+    println!("  --http-cache     Cache the responses of the GET requests as their headers allow");
+    println!("  --module-dir <dir>");
+    println!("                   Resolve the bare imports, e.g. import('lodash-es'), against the");
+    println!("                   packages in <dir>, laid out like node_modules");
+    println!("  --remote-module <url>#<integrity>");
+    println!("                   Fetch the module at the https url before running the scripts,");
+    println!("                   verified with a subresource integrity like sha384-<base64>");
+    println!("  --module-cache <dir>");
+    println!("                   Keep the fetched remote modules in <dir>");
+    #[cfg(feature = "typescript")]
+    println!("  --typescript     Treat all the scripts as TypeScript, e.g. those given with -c");
+    println!("  --output <fmt>   Report the result as debug (default) or json on stdout");
+    println!("  -e, --env <KEY=VALUE>");
+    println!("                   Set globalThis.env.KEY to VALUE");
+    println!("  --expose-gc      Define gc() to run a garbage collection");
+    println!("  --expose-metrics Define __metrics() returning the counters of the service");
+    println!("  --profile <file> Sample the JS stack and write a .cpuprofile for Chrome DevTools");
     println!("  --               Stop processing options");
 }
 
 pub async fn run(args: impl Iterator<Item = String>) -> Result<JsValue> {
-    let args = parse_args(args)?;
-    let service = Service::new_ref();
+    run_with_exit_code(args).await.0
+}
+
+/// How often `--profile` samples the JS stack.
+const PROFILE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Like `run`, also returning the exit code set by `process.exit(code)` or `scriptExitCode`.
+pub async fn run_with_exit_code(
+    args: impl Iterator<Item = String>,
+) -> (Result<JsValue>, Option<i32>) {
+    let mut args = match parse_args(args) {
+        Ok(args) => args,
+        Err(err) => return (Err(err), None),
+    };
+    if let Some(compile) = args.compile.take() {
+        return (compile_script(compile), None);
+    }
+    let output = args.output;
+    let permissions = core::mem::take(&mut args.permissions);
+    let service = Service::new_ref_with_metrics(permissions, args.expose_metrics);
+    let profile = args.profile.take();
+    if profile.is_some() {
+        service.start_profiling(PROFILE_INTERVAL);
+    }
+    let result = run_scripts(&service, args).await;
+    if let (Some(path), Some(cpuprofile)) = (profile, service.stop_profiling()) {
+        if let Err(err) = std::fs::write(&path, cpuprofile) {
+            log::error!("Failed to write the profile to {path}: {err}");
+        }
+    }
+    if output == OutputFormat::Json {
+        let json = match &result {
+            Ok(value) => output_json(value),
+            Err(err) => output_json(&JsValue::Exception(format!("{err:#}"))),
+        };
+        println!("{json}");
+    }
+    (result, script_exit_code(&service))
+}
+
+/// The code passed to `process.exit`, or else the value of `scriptExitCode`.
+fn script_exit_code(service: &Service) -> Option<i32> {
+    if let Some(code) = service.exit_code() {
+        return Some(code);
+    }
+    let code = service
+        .context()
+        .get_global_object()
+        .get_property("scriptExitCode")
+        .ok()?;
+    if code.is_undefined() {
+        return None;
+    }
+    js::FromJsValue::from_js_value(code).ok()
+}
+
+/// Serialize the result of the scripts as `{"type": .., "value": ..}`.
+///
+/// The types are `undefined` and `null` without value, `string`, `bytes` as a 0x-prefixed hex
+/// string, `json` for the other values serializable to JSON, `other` with the string of the value
+/// otherwise, and `exception` with the error message.
+fn output_json(value: &JsValue) -> String {
+    use serde_json::{json, Value};
+    let output = match value {
+        JsValue::Undefined => json!({ "type": "undefined" }),
+        JsValue::Null => json!({ "type": "null" }),
+        JsValue::String(s) => json!({ "type": "string", "value": s }),
+        JsValue::Bytes(bytes) => {
+            json!({ "type": "bytes", "value": format!("0x{}", hex::encode(bytes)) })
+        }
+        JsValue::Other(s) => match serde_json::from_str::<Value>(s) {
+            Ok(value) => json!({ "type": "json", "value": value }),
+            Err(_) => json!({ "type": "other", "value": s }),
+        },
+        JsValue::Exception(err) => json!({ "type": "exception", "value": err }),
+    };
+    output.to_string()
+}
+
+fn set_file_system(service: &Service, dir: Option<String>, quota: Option<usize>) -> Result<()> {
+    match (dir, quota) {
+        (Some(dir), quota) => {
+            let mut fs = DirFs::new(dir)?;
+            if let Some(quota) = quota {
+                fs = fs.with_quota(quota as u64)?;
+            }
+            service.set_file_system(fs);
+        }
+        (None, Some(quota)) => service.set_file_system(MemoryFs::new(quota as u64)),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+async fn run_scripts(service: &Service, args: Args) -> Result<JsValue> {
+    if let Some(max_memory) = args.max_memory {
+        service.set_memory_limit(max_memory);
+    }
+    service.set_watchdog(args.watchdog, args.watchdog_interrupt);
+    for (name, value) in args.headers {
+        service.set_default_header(&name, &value);
+    }
+    let proxy = args.proxy.or_else(|| {
+        std::env::var("ALL_PROXY")
+            .or_else(|_| std::env::var("all_proxy"))
+            .ok()
+    });
+    service.set_http_proxy(proxy);
+    service.set_script_proxy(args.script_proxy);
+    service.set_unix_sockets(args.unix_sockets);
+    if let Some(dns) = args.dns {
+        set_dns(service, &dns)?;
+    }
+    service.set_egress_filter(args.egress_filter);
+    set_file_system(service, args.allow_fs, args.fs_quota)?;
+    service.set_http_cache(args.http_cache);
+    #[cfg(feature = "typescript")]
+    service.set_typescript(args.typescript);
+    if let Some(dir) = args.module_dir {
+        service.set_module_resolver(ModuleDir::new(dir));
+    }
+    if args.expose_gc {
+        service.expose_gc()?;
+    }
+    if args.expose_metrics {
+        service.expose_metrics()?;
+    }
+    import_remote_modules(service, args.module_cache, args.remote_modules).await?;
+    let rejections = Rc::new(RefCell::new(Vec::new()));
+    service.set_unhandled_rejection_handler({
+        let rejections = rejections.clone();
+        move |reason: &str| {
+            log::error!("Unhandled promise rejection: {reason}");
+            rejections.borrow_mut().push(reason.to_string());
+        }
+    });
     let js_ctx = service.context();
     let js_args = args
         .js_args
@@ -70,21 +658,64 @@ pub async fn run(args: impl Iterator<Item = String>) -> Result<JsValue> {
         .get_global_object()
         .set_property("scriptArgs", &js_args)
         .context("Failed to set scriptArgs")?;
-    let mut expr_val = None;
-    for code in args.codes.into_iter() {
-        let result = match code {
-            JsCode::Source(src) => service.exec_script(&src),
-            JsCode::Bytecode(bytes) => service.exec_bytecode(&bytes),
-        };
-        match result {
-            Ok(value) => expr_val = value.to_js_value(),
-            Err(err) => {
-                bail!("Failed to execute script: {err}");
+    let env = js::Value::new_object(&js_ctx);
+    for (key, value) in &args.env {
+        env.set_property(key, &js_ctx.new_string(value))
+            .context("Failed to set env")?;
+    }
+    js_ctx
+        .get_global_object()
+        .set_property("env", &env)
+        .context("Failed to set env")?;
+    service.set_entry_code(&entry_code(&args.codes));
+    let timeout = args.timeout;
+    let execution = async {
+        let mut expr_val = None;
+        for code in args.codes.into_iter() {
+            let result = match code {
+                JsCode::Source(src) => match service.exec_script(&src) {
+                    Err(ExecError::Script(err))
+                        if is_top_level_await_error(service, &src, &err) =>
+                    {
+                        // The completion value of a module is a promise, scriptOutput has to be used
+                        let name = service.next_script_name();
+                        service.exec_module(&name, &src).map(|_| None)
+                    }
+                    result => result.map(Some),
+                },
+                JsCode::Bytecode(bytes) => service.exec_bytecode(&bytes).map(Some),
+            };
+            match result {
+                Ok(Some(value)) => expr_val = value.to_js_value(),
+                Ok(None) => {}
+                Err(err) => {
+                    bail!("Failed to execute script: {err}");
+                }
             }
         }
-    }
-    if service.number_of_tasks() > 0 {
-        service.wait_for_tasks().await;
+        if let Some(addr) = args.listen {
+            serve_http(service, addr).await?;
+        }
+        if let Some(addr) = args.inspect {
+            serve_devtools(service, addr).await?;
+        }
+        if args.interactive {
+            repl(service).await?;
+        }
+        if service.resource_count() > 0 {
+            service.wait_for_tasks().await;
+        }
+        Ok(expr_val)
+    };
+    let expr_val = match timeout {
+        Some(timeout) => service
+            .run_with_deadline(timeout, execution)
+            .await
+            .map_err(|_| anyhow!("Script timed out after {}ms", timeout.as_millis()))??,
+        None => execution.await?,
+    };
+    if let Some(reason) = rejections.borrow().first() {
+        bail!("Unhandled promise rejection: {reason}");
     }
     // If scriptOutput is set, use it as output. Otherwise, use the last expression value.
     let output = js_ctx
@@ -96,10 +727,139 @@ pub async fn run(args: impl Iterator<Item = String>) -> Result<JsValue> {
     } else {
         output
     };
-    convert(output).context("Failed to convert output")
+    convert(service, output).context("Failed to convert output")
 }
 
-fn convert(output: js::Value) -> Result<JsValue> {
+/// The entry code of the service: the code itself if there is only one, otherwise each code
+/// prefixed with its length as 4 little endian bytes.
+fn entry_code(codes: &[JsCode]) -> Vec<u8> {
+    let bytes = |code: &JsCode| -> Vec<u8> {
+        match code {
+            JsCode::Source(src) => src.as_bytes().to_vec(),
+            JsCode::Bytecode(bytecode) => bytecode.clone(),
+        }
+    };
+    if let [code] = codes {
+        return bytes(code);
+    }
+    let mut entry = vec![];
+    for code in codes {
+        let code = bytes(code);
+        entry.extend_from_slice(&(code.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&code);
+    }
+    entry
+}
+
+/// Whether the script `src` failed with `err` only because it uses `await` outside of async
+/// functions, that is it failed to compile but its source, transpiled if needed, compiles as the
+/// body of an async function.
+///
+/// Scripts are evaluated as modules in that case so that `await fetch(..)` works at the top
+/// level, at the cost of their `var`s not becoming globals. Other syntax errors are reported as
+/// the errors of the script.
+fn is_top_level_await_error(service: &Service, src: &str, err: &str) -> bool {
+    const NAME: &str = "<top-level-await>";
+    if !err.contains("SyntaxError") || !src.contains("await") {
+        return false;
+    }
+    let Ok(src) = service.prepare_source(src, NAME) else {
+        return false;
+    };
+    let body = format!("(async function () {{\n{src}\n}})");
+    js::compile(&body, NAME).is_ok()
+}
+
+fn compile_script(compile: Compile) -> Result<JsValue> {
+    let src = std::fs::read_to_string(&compile.file).context("Failed to read script file")?;
+    let bytecode = crate::bytecode::compile(&src, &compile.file)
+        .map_err(|err| anyhow!("Failed to compile: {err}"))?;
+    match compile.output {
+        Some(output) => {
+            std::fs::write(output, bytecode).context("Failed to write bytecode")?;
+        }
+        None => println!("{}", hex::encode(bytecode)),
+    }
+    Ok(JsValue::Undefined)
+}
+
+/// Read lines from stdin and evaluate them in the service until EOF.
+///
+/// Spawned tasks of the service keep running while waiting for input.
+#[cfg(feature = "native")]
+async fn repl(service: &Service) -> Result<()> {
+    use std::io::Write;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match service.exec_script(&line) {
+            Ok(value) => {
+                let Some(value) = value.to_js_value() else {
+                    continue;
+                };
+                if !value.is_undefined() {
+                    let config = Default::default();
+                    println!("{}", qjs_extensions::repr::print(&[value], &config));
+                }
+            }
+            Err(err) => println!("Uncaught {err}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "native"))]
+async fn repl(_service: &Service) -> Result<()> {
+    bail!("Interactive mode is only supported in the native build")
+}
+
+/// Pass incoming HTTP requests to the listener registered by `Sidevm.httpListen` until the
+/// server fails.
+#[cfg(all(feature = "native", feature = "js-http-listen"))]
+async fn serve_http(service: &Service, addr: SocketAddr) -> Result<()> {
+    let weak_service = service.weak_self();
+    crate::runtime::serve_http(addr, move |request| {
+        let Some(service) = weak_service.upgrade() else {
+            return;
+        };
+        if let Err(err) = crate::host_functions::try_accept_http_request(service, request) {
+            log::warn!("Failed to accept http request: {err:?}");
+        }
+    })
+    .await
+}
+
+#[cfg(not(all(feature = "native", feature = "js-http-listen")))]
+async fn serve_http(_service: &Service, _addr: SocketAddr) -> Result<()> {
+    bail!("--listen requires the native build with the js-http-listen feature")
+}
+
+#[cfg(feature = "devtools")]
+async fn serve_devtools(service: &Service, addr: SocketAddr) -> Result<()> {
+    crate::devtools::serve(service, addr).await
+}
+
+#[cfg(not(feature = "devtools"))]
+async fn serve_devtools(_service: &Service, _addr: SocketAddr) -> Result<()> {
+    bail!("--inspect requires the devtools feature")
+}
+
+/// Convert the script output to a `JsValue`.
+///
+/// `JsValue` has no variants for numbers, booleans or objects, so they are reported as
+/// `JsValue::Other` holding their JSON representation, with BigInts tagged as
+/// `{"$bigint": "<decimal>"}`.
+pub(crate) fn convert(service: &Service, output: js::Value) -> Result<JsValue> {
     if output.is_undefined() {
         return Ok(JsValue::Undefined);
     }
@@ -112,5 +872,29 @@ fn convert(output: js::Value) -> Result<JsValue> {
     if output.is_uint8_array() {
         return Ok(JsValue::Bytes(output.decode_bytes()?));
     }
-    return Ok(JsValue::Other(output.to_string()));
+    Ok(JsValue::Other(
+        to_json(service, &output).unwrap_or_else(|| output.to_string()),
+    ))
+}
+
+/// `JSON.stringify` the value with `Sidevm.jsonReplacer`, straight from the globals so that it
+/// isn't evaluated as a script of the service.
+fn to_json(service: &Service, value: &js::Value) -> Option<String> {
+    let global = service.context().get_global_object();
+    let stringify = global
+        .get_property("JSON")
+        .and_then(|json| json.get_property("stringify"))
+        .ok()?;
+    let replacer = global
+        .get_property("Sidevm")
+        .and_then(|sidevm| sidevm.get_property("jsonReplacer"))
+        .ok()?;
+    let json = service
+        .call_function(stringify, (value.clone(), replacer))
+        .ok()?;
+    if !json.is_string() {
+        // Not serializable, e.g. a function
+        return None;
+    }
+    json.decode_string().ok()
 }
@@ -1,17 +1,33 @@
 extern crate alloc;
 
-pub use service::Service;
+pub use host_functions::{
+    Capability, DirFs, EgressFilter, FileStat, FileSystem, HttpInterceptor, MemoryFs, Permissions,
+    RateLimit, RequestParts, ResponseParts,
+};
+pub use service::{
+    ExecError, MemoryUsage, Metrics, ModuleDir, ModuleResolver, ModuleSource, Service,
+    ServiceBuilder, ServiceRef, TaskInfo,
+};
 pub use service_keeper::ServiceKeeper;
 
+#[cfg(feature = "js-dns")]
+pub use host_functions::DnsConfig;
+
 mod host_functions;
 mod service;
 mod service_keeper;
 
+pub mod bytecode;
+#[cfg(feature = "devtools")]
+pub mod devtools;
 pub mod js_eval;
 mod traits;
+#[cfg(feature = "typescript")]
+pub mod typescript;
 
 #[cfg(feature = "native")]
 pub mod runtime {
+    use crate::host_functions::{EgressConnector, SharedEgressFilter};
     use hyper::client::HttpConnector;
     use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
     use js::ToJsValue;
@@ -30,18 +46,39 @@ pub mod runtime {
     use log::info;
     pub use tokio::main;
     pub use tokio::{task::spawn_local as spawn, time};
-    pub fn http_connector() -> HttpsConnector<HttpConnector> {
+    /// The connector of the http clients, checking the connected addresses against `egress`.
+    pub(crate) fn http_connector(
+        egress: SharedEgressFilter,
+    ) -> HttpsConnector<EgressConnector<HttpConnector>> {
+        let mut connector = HttpConnector::new();
+        connector.enforce_http(false);
+        https_connector(EgressConnector::new(connector, egress))
+    }
+    /// Add TLS to `connector`, negotiating HTTP/2 or HTTP/1.1 with ALPN.
+    pub fn https_connector<C>(connector: C) -> HttpsConnector<C> {
         HttpsConnectorBuilder::new()
             .with_webpki_roots()
             .https_or_http()
-            .enable_http1()
-            .build()
+            .enable_all_versions()
+            .wrap_connector(connector)
+    }
+    pub(crate) type HttpClient =
+        hyper::Client<HttpsConnector<EgressConnector<HttpConnector>>, hyper::Body>;
+    pub(crate) fn http_client(egress: SharedEgressFilter) -> HttpClient {
+        hyper::Client::builder()
+            .executor(HyperExecutor)
+            .build(http_connector(egress))
     }
     pub fn getrandom(buf: &mut [u8]) -> Option<()> {
         use rand::RngCore;
         rand::thread_rng().fill_bytes(buf);
         Some(())
     }
+    /// Monotonic time elapsed since the first call.
+    pub fn monotonic_now() -> core::time::Duration {
+        static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        START.get_or_init(std::time::Instant::now).elapsed()
+    }
     pub type AccountId = [u8; 32];
     pub struct HyperExecutor;
     impl<F: core::future::Future + 'static> hyper::rt::Executor<F> for HyperExecutor {
@@ -53,6 +90,107 @@ pub mod runtime {
         let local = tokio::task::LocalSet::new();
         local.run_until(fut).await
     }
+
+    /// Serve HTTP on `addr`, passing each incoming request to `on_request` the way the sidevm
+    /// runtime delivers them.
+    ///
+    /// Requests whose response head is never sent are answered with 500.
+    pub async fn serve_http(
+        addr: std::net::SocketAddr,
+        on_request: impl Fn(HttpRequest) + 'static,
+    ) -> anyhow::Result<()> {
+        use hyper::service::{make_service_fn, service_fn};
+        use std::rc::Rc;
+
+        let on_request: Rc<dyn Fn(HttpRequest)> = Rc::new(on_request);
+        let make_service = make_service_fn(move |_conn| {
+            let on_request = on_request.clone();
+            async move {
+                Ok::<_, core::convert::Infallible>(service_fn(move |req| {
+                    accept_http_request(req, addr, on_request.clone())
+                }))
+            }
+        });
+        info!("Listening on http://{addr}");
+        hyper::Server::try_bind(&addr)?
+            .executor(HyperExecutor)
+            .serve(make_service)
+            .await?;
+        Ok(())
+    }
+
+    async fn accept_http_request(
+        req: hyper::Request<hyper::Body>,
+        addr: std::net::SocketAddr,
+        on_request: std::rc::Rc<dyn Fn(HttpRequest)>,
+    ) -> anyhow::Result<hyper::Response<hyper::Body>> {
+        use hyper::body::{Bytes, HttpBody};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (parts, mut body) = req.into_parts();
+        let host = parts
+            .headers
+            .get(hyper::header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(String::from)
+            .unwrap_or_else(|| addr.to_string());
+        let head = HttpHead {
+            method: parts.method.to_string(),
+            url: format!("http://{host}{}", parts.uri),
+            headers: parts
+                .headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                    (name.to_string(), value)
+                })
+                .collect(),
+        };
+        let (io_stream, host_stream) = tokio::io::duplex(16 * 1024);
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        on_request(HttpRequest {
+            head,
+            io_stream,
+            response_tx,
+        });
+        let (mut reader, mut writer) = tokio::io::split(host_stream);
+        spawn(async move {
+            while let Some(Ok(chunk)) = body.data().await {
+                if writer.write_all(&chunk).await.is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown().await;
+        });
+        let Ok(head) = response_rx.await else {
+            return Ok(hyper::Response::builder()
+                .status(500)
+                .body(hyper::Body::empty())?);
+        };
+        let mut response = hyper::Response::builder().status(head.status);
+        for (name, value) in head.headers {
+            response = response.header(name, value);
+        }
+        let (mut sender, response_body) = hyper::Body::channel();
+        spawn(async move {
+            let mut buf = vec![0u8; 8192];
+            loop {
+                let n = match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if sender
+                    .send_data(Bytes::copy_from_slice(&buf[..n]))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Ok(response.body(response_body)?)
+    }
+
     pub async fn main_loop() {
         let args: Vec<_> = std::env::args().collect();
         let script_file = args
@@ -74,6 +212,7 @@ pub mod runtime {
             .get_global_object()
             .set_property("scriptArgs", &js_args)
             .expect("Failed to set scriptArgs");
+        service.set_entry_code(script.as_bytes());
         let output = service.exec_script(&script);
         match output {
             Ok(value) if value.is_undefined() => {}
@@ -81,16 +220,26 @@ pub mod runtime {
                 info!("Script output: {:?}", output);
             }
         }
-        if service.number_of_tasks() > 0 {
+        if service.resource_count() > 0 {
             service.wait_for_tasks().await;
         }
     }
     pub use tracing_subscriber::fmt::init as init_logger;
     pub fn set_output(_output: Vec<u8>) {}
+    pub fn instance_id() -> Option<Vec<u8>> {
+        None
+    }
+    pub fn worker_pubkey() -> Option<Vec<u8>> {
+        None
+    }
+    pub async fn attestation_quote(_report_data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!("Attestation quotes are only available inside the TEE worker")
+    }
 }
 
 #[cfg(feature = "sidevm")]
 pub mod runtime {
+    use crate::host_functions::SharedEgressFilter;
     use anyhow::{anyhow, Context, Result};
     use log::{error, info, warn};
     use scale::Decode;
@@ -104,9 +253,22 @@ pub mod runtime {
 
     pub use sidevm::main;
 
+    /// Monotonic time elapsed since the first call.
+    pub fn monotonic_now() -> core::time::Duration {
+        static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        START.get_or_init(std::time::Instant::now).elapsed()
+    }
+
     pub fn http_connector() -> HttpConnector {
         HttpConnector::new()
     }
+    pub type HttpClient = hyper::Client<HttpConnector, hyper::Body>;
+    /// The host makes the connections, only the checks made before connecting apply.
+    pub(crate) fn http_client(_egress: SharedEgressFilter) -> HttpClient {
+        hyper::Client::builder()
+            .executor(HyperExecutor)
+            .build(http_connector())
+    }
 
     async fn get_init_script() -> Result<String> {
         type LangError = u8;
@@ -121,7 +283,73 @@ pub mod runtime {
         Ok(script)
     }
 
+    /// Query the contract for the secret that `deriveSecret` derives from.
+    #[cfg(feature = "js-secret")]
+    async fn get_master_secret() -> Result<Vec<u8>> {
+        type LangError = u8;
+        let myid = sidevm::ocall::vmid()?;
+        let selector = ink_macro::selector_bytes!("sidevm_master_secret");
+        let response = query_pink(myid, selector.to_vec())
+            .await
+            .map_err(|err| anyhow!("Failed to query master secret: {err:?}"))?;
+        let secret = Result::<Vec<u8>, LangError>::decode(&mut &response[..])
+            .context("Failed to decode Result::<Vec<u8>, LangError>")?
+            .map_err(|err| anyhow!("LangError({err})"))?;
+        Ok(secret)
+    }
+
+    static WORKER_PUBKEY: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+
+    /// Query the contract for the public key of the worker running the sidevm.
+    async fn get_worker_pubkey() -> Result<Vec<u8>> {
+        type LangError = u8;
+        let myid = sidevm::ocall::vmid()?;
+        let selector = ink_macro::selector_bytes!("sidevm_worker_pubkey");
+        let response = query_pink(myid, selector.to_vec())
+            .await
+            .map_err(|err| anyhow!("Failed to query worker pubkey: {err:?}"))?;
+        let pubkey = Result::<Vec<u8>, LangError>::decode(&mut &response[..])
+            .context("Failed to decode Result::<Vec<u8>, LangError>")?
+            .map_err(|err| anyhow!("LangError({err})"))?;
+        Ok(pubkey)
+    }
+
+    /// The id of the sidevm instance.
+    pub fn instance_id() -> Option<Vec<u8>> {
+        sidevm::ocall::vmid().ok().map(|id| id.to_vec())
+    }
+
+    pub fn worker_pubkey() -> Option<Vec<u8>> {
+        WORKER_PUBKEY.get().cloned()
+    }
+
+    /// Query the contract for a quote of the TEE worker binding `report_data`.
+    pub async fn attestation_quote(report_data: Vec<u8>) -> Result<Vec<u8>> {
+        type LangError = u8;
+        let myid = sidevm::ocall::vmid()?;
+        let mut input = ink_macro::selector_bytes!("sidevm_attestation_quote").to_vec();
+        scale::Encode::encode_to(&report_data, &mut input);
+        let response = query_pink(myid, input)
+            .await
+            .map_err(|err| anyhow!("Failed to query attestation quote: {err:?}"))?;
+        let quote = Result::<Vec<u8>, LangError>::decode(&mut &response[..])
+            .context("Failed to decode Result::<Vec<u8>, LangError>")?
+            .map_err(|err| anyhow!("LangError({err})"))?;
+        Ok(quote)
+    }
+
     pub async fn main_loop() {
+        #[cfg(feature = "js-secret")]
+        match get_master_secret().await {
+            Ok(secret) => crate::host_functions::set_master_secret(&secret),
+            Err(err) => warn!("Failed to get master secret, deriveSecret is unavailable: {err}"),
+        }
+        match get_worker_pubkey().await {
+            Ok(pubkey) => {
+                let _ = WORKER_PUBKEY.set(pubkey);
+            }
+            Err(err) => warn!("Failed to get worker pubkey: {err}"),
+        }
         info!("Getting init script...");
         match get_init_script().await {
             Err(err) => {
@@ -130,7 +358,7 @@ pub mod runtime {
             }
             Ok(script) => {
                 info!("Executing init script...");
-                crate::ServiceKeeper::exec_script("_main", &script);
+                crate::ServiceKeeper::exec_script(crate::service_keeper::MAIN_SERVICE, &script);
             }
         }
         info!("Listening for incoming queries...");
@@ -141,8 +369,11 @@ pub mod runtime {
                         info!("Host dropped the channel, exiting...");
                         break;
                     };
-                    let reply = crate::ServiceKeeper::handle_query(query.origin, &query.payload);
-                    _ = query.reply_tx.send(&reply);
+                    // Handlers may reply asynchronously, don't block the other queries.
+                    spawn(async move {
+                        let reply = crate::ServiceKeeper::handle_query(query.origin, query.payload).await;
+                        _ = query.reply_tx.send(&reply);
+                    });
                 }
                 message = sidevm::channel::input_messages().next() => {
                     let Some(message) = message else {
@@ -232,10 +463,23 @@ pub mod runtime {
         });
     }
 
+    /// Time elapsed since the page was loaded. `std::time::Instant` is not available on the web.
+    pub fn monotonic_now() -> core::time::Duration {
+        let now = web_sys::window()
+            .and_then(|window| window.performance())
+            .map(|performance| performance.now())
+            .unwrap_or_default();
+        core::time::Duration::from_secs_f64(now / 1000.0)
+    }
+
     pub fn getrandom(buf: &mut [u8]) -> Result<(), WebJsValue> {
-        buf.iter_mut().for_each(|byte| {
-            *byte = (js_sys::Math::random() * 256.0) as u8;
-        });
+        let crypto = web_sys::window()
+            .ok_or_else(|| WebJsValue::from_str("No window"))?
+            .crypto()?;
+        // The browser refuses to fill more than 65536 bytes at a time.
+        for chunk in buf.chunks_mut(65536) {
+            crypto.get_random_values_with_u8_array(chunk)?;
+        }
         Ok(())
     }
 
@@ -257,6 +501,16 @@ pub mod runtime {
         }
     }
 
+    pub fn instance_id() -> Option<Vec<u8>> {
+        None
+    }
+    pub fn worker_pubkey() -> Option<Vec<u8>> {
+        None
+    }
+    pub async fn attestation_quote(_report_data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!("Attestation quotes are only available inside the TEE worker")
+    }
+
     #[no_mangle]
     extern "C" fn __pink_clock_time_get(_id: u32, _precision: u64, _retptr0: *mut u64) -> u16 {
         0
@@ -8,18 +8,29 @@ use sidevm_quickjs::{js_eval, runtime};
 async fn main() {
     use pink_types::js::JsValue;
     runtime::init_logger();
-    runtime::run_local(async {
-        let output = match js_eval::run(std::env::args()).await {
+    let exit_code = runtime::run_local(async {
+        let (result, exit_code) = js_eval::run_with_exit_code(std::env::args()).await;
+        let output = match result {
             Ok(value) => value,
             Err(err) => JsValue::Exception(err.to_string()),
         };
+        let failed = matches!(output, JsValue::Exception(_));
         #[cfg(feature = "native")]
         log::info!("Script output: {:?}", output);
         #[cfg(not(feature = "native"))]
         sidevm::ocall::emit_program_output(&scale::Encode::encode(&output))
             .expect("Failed to emit program output");
+        exit_code.unwrap_or(if failed { 1 } else { 0 })
     })
     .await;
+    #[cfg(feature = "native")]
+    if exit_code != 0 {
+        use std::io::Write;
+        _ = std::io::stdout().flush();
+        std::process::exit(exit_code);
+    }
+    #[cfg(not(feature = "native"))]
+    let _ = exit_code;
 }
 
 #[cfg(feature = "web")]
@@ -1,19 +1,44 @@
 use alloc::{
+    borrow::Cow,
     boxed::Box,
     collections::BTreeMap,
     rc::{Rc, Weak},
 };
-use core::{any::Any, cell::RefCell, ops::Deref};
-use log::{debug, error, info, warn};
+use core::{
+    any::Any,
+    cell::{Cell, RefCell},
+    ops::Deref,
+    time::Duration,
+};
+use log::{debug, error, warn};
 use std::{future::Future, sync::Mutex};
 
-use crate::host_functions::setup_host_functions;
+use crate::host_functions::{
+    setup_host_functions, EgressFilter, HttpInterceptor, Permissions, WorkerMessage,
+};
 use anyhow::Result;
 use js::{c, Code, Error as ValueError, ToArgs};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc::UnboundedSender};
 
+mod builder;
+mod interrupt;
+mod memory;
+mod metrics;
+mod module;
+mod packages;
+mod profiler;
+mod rejection;
 mod resource;
+mod snapshot;
+mod source_map;
 
+pub use builder::ServiceBuilder;
+pub(crate) use interrupt::ExecLimits;
+pub use memory::MemoryUsage;
+pub use metrics::Metrics;
+pub use module::{normalize_path, ModuleResolver, ModuleSource};
+pub use packages::ModuleDir;
+pub use resource::TaskInfo;
 pub(crate) use resource::{OwnedJsValue, Resource};
 
 #[derive(Clone)]
@@ -54,11 +79,55 @@ impl TryFrom<js::Context> for ServiceRef {
     }
 }
 
+/// The estimated host memory held by a pending task, used to cap the number of tasks under a
+/// memory limit.
+const RESOURCE_MEMORY_COST: usize = 16 * 1024;
+
+/// Error returned by script evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecError {
+    /// The execution was interrupted because the gas set by `Service::set_gas_limit` ran out.
+    OutOfGas,
+    /// The execution was interrupted by the deadline of `Service::run_with_deadline`.
+    Timeout,
+    /// The execution was interrupted by the watchdog set by `Service::set_watchdog`.
+    Stalled,
+    /// The script threw or failed to compile.
+    Script(String),
+}
+
+impl core::fmt::Display for ExecError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ExecError::OutOfGas => write!(f, "OutOfGas"),
+            ExecError::Timeout => write!(f, "Timeout"),
+            ExecError::Stalled => write!(f, "Stalled"),
+            ExecError::Script(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+impl From<String> for ExecError {
+    fn from(err: String) -> Self {
+        ExecError::Script(err)
+    }
+}
+
+impl From<&str> for ExecError {
+    fn from(err: &str) -> Self {
+        ExecError::Script(err.into())
+    }
+}
+
 pub struct JsEngine {
     pub ctx: js::Context,
     runtime: js::Runtime,
     weak_self: Weak<JsEngine>,
     last_error: Mutex<Option<String>>,
+    /// Referenced by the interrupt handler of `runtime`, so it must be dropped after it.
+    interrupter: interrupt::Interrupter,
 }
 
 impl JsEngine {
@@ -104,13 +173,97 @@ impl JsEngine {
 pub struct Service {
     runtime: Rc<JsEngine>,
     state: RefCell<ServiceState>,
+    /// Shared by all http requests of the service so that keep-alive connections are reused.
+    #[cfg(not(feature = "web"))]
+    http_client: core::cell::OnceCell<crate::runtime::HttpClient>,
 }
 
+pub(crate) type LogSink = Rc<dyn Fn(log::Level, &str)>;
+
 struct ServiceState {
     next_resource_id: u64,
     recources: BTreeMap<u64, Resource>,
     http_listener: Option<OwnedJsValue>,
+    query_handler: Option<OwnedJsValue>,
+    message_handler: Option<OwnedJsValue>,
+    /// Runs the `beforeunload` listeners of the script, see `Service::shutdown`.
+    shutdown_handler: Option<OwnedJsValue>,
     done_tx: broadcast::Sender<()>,
+    modules: BTreeMap<String, ModuleSource>,
+    module_resolver: Option<Box<dyn ModuleResolver>>,
+    /// Set by `process.exit(code)`.
+    exit_code: Option<i32>,
+    /// The entry modules of the packages registered by `add_package`, by package name.
+    packages: BTreeMap<String, String>,
+    /// Where the verified remote modules are kept across runs.
+    #[cfg(feature = "js-remote-module")]
+    module_cache_dir: Option<std::path::PathBuf>,
+    /// The nesting level of the timer whose callback is running, 0 outside of timer callbacks.
+    timer_nesting_level: u32,
+    /// Named SCALE type definitions registered by `scaleRegisterTypes`, parsed once.
+    #[cfg(feature = "js-scale")]
+    scale_types: BTreeMap<String, Rc<crate::host_functions::ScaleType>>,
+    memory_limit: Option<usize>,
+    /// Headers added to every outgoing http request that doesn't set them itself.
+    default_headers: Vec<(String, String)>,
+    /// The proxy url for outgoing http requests that don't set their own.
+    http_proxy: Option<String>,
+    /// Whether the scripts can choose the proxy of their requests.
+    script_proxy: bool,
+    /// The Unix domain sockets the http requests of the scripts may be sent to.
+    unix_sockets: Vec<std::path::PathBuf>,
+    /// The clients of the proxies in use by url, see `http_request::proxy`.
+    #[cfg(feature = "js-http-proxy")]
+    proxy_clients: BTreeMap<String, crate::host_functions::ProxyClient>,
+    #[cfg(feature = "js-dns")]
+    dns_resolver: Option<crate::host_functions::DnsResolver>,
+    /// The system resolver of `dnsResolve` when none is configured.
+    #[cfg(feature = "js-dns")]
+    system_dns_resolver: Option<crate::host_functions::SharedResolver>,
+    /// Set once the script enables the cookie jar.
+    #[cfg(not(feature = "web"))]
+    cookie_jar: Option<crate::host_functions::CookieJar>,
+    unhandled_rejections: Vec<rejection::UnhandledRejection>,
+    rejection_handler: Option<rejection::RejectionHandler>,
+    /// Set if the service runs as a worker, carries the messages posted to the parent.
+    parent_port: Option<UnboundedSender<WorkerMessage>>,
+    /// The memory limits of the running workers, taken out of the memory limit of the service.
+    worker_memory: Rc<Cell<usize>>,
+    /// The linear memories of all the WebAssembly instances, counted against the memory limit.
+    #[cfg(feature = "js-wasm")]
+    wasm_memory: Rc<Cell<usize>>,
+    log_sink: Option<LogSink>,
+    /// How many scripts, modules and callbacks are running, nested in host functions.
+    call_depth: u32,
+    /// Inline source maps of the evaluated scripts and modules, by file name.
+    source_maps: source_map::SourceMaps,
+    /// Numbers the file names of the evaluated scripts.
+    scripts_evaluated: u32,
+    /// The functions defined by the embedder, indexed by the magic number of their JS function.
+    host_fns: Vec<builder::HostFn>,
+    permissions: Permissions,
+    /// Restricts the destinations of the network host functions.
+    egress_filter: crate::host_functions::SharedEgressFilter,
+    /// Hooks of the embedder on the http traffic of the scripts.
+    http_interceptor: Option<HttpInterceptor>,
+    /// Limits the rate of the outgoing http requests.
+    rate_limit: Option<crate::RateLimit>,
+    /// Whether the GET requests go through the http cache.
+    http_cache: bool,
+    /// Backs the `fs` namespace, in memory unless the embedder sets one.
+    file_system: Rc<dyn crate::FileSystem>,
+    metrics: metrics::Counters,
+    /// When the service was created, in `monotonic_now` time, the zero of `performance.now()`.
+    time_origin: Duration,
+    /// `performance.now()` is rounded down to a multiple of this if set.
+    clock_resolution: Option<Duration>,
+    /// The evaluations since `start_recording`, replayed by snapshots.
+    recorded: Option<Vec<snapshot::Recorded>>,
+    /// The hash of the entry code set by the embedder, reported by `sideVmInfo`.
+    code_hash: Option<Vec<u8>>,
+    /// Transpile all the sources as TypeScript, not only the modules named `*.ts`.
+    #[cfg(feature = "typescript")]
+    typescript: bool,
 }
 
 impl ServiceState {
@@ -124,14 +277,68 @@ impl ServiceState {
 impl Default for ServiceState {
     fn default() -> Self {
         Self {
-            next_resource_id: Default::default(),
+            // Start from 1 so that ids are always truthy in JS, e.g. `if (timer) clearTimeout(timer)`
+            next_resource_id: 1,
             recources: Default::default(),
             http_listener: Default::default(),
+            query_handler: None,
+            message_handler: None,
+            shutdown_handler: None,
             done_tx: broadcast::channel(1).0,
+            modules: Default::default(),
+            module_resolver: None,
+            exit_code: None,
+            packages: Default::default(),
+            #[cfg(feature = "js-remote-module")]
+            module_cache_dir: None,
+            timer_nesting_level: 0,
+            #[cfg(feature = "js-scale")]
+            scale_types: Default::default(),
+            memory_limit: None,
+            default_headers: vec![("User-Agent".into(), DEFAULT_USER_AGENT.into())],
+            http_proxy: None,
+            script_proxy: false,
+            unix_sockets: vec![],
+            #[cfg(feature = "js-http-proxy")]
+            proxy_clients: Default::default(),
+            #[cfg(feature = "js-dns")]
+            dns_resolver: None,
+            #[cfg(feature = "js-dns")]
+            system_dns_resolver: None,
+            #[cfg(not(feature = "web"))]
+            cookie_jar: None,
+            unhandled_rejections: Default::default(),
+            rejection_handler: None,
+            parent_port: None,
+            worker_memory: Default::default(),
+            #[cfg(feature = "js-wasm")]
+            wasm_memory: Default::default(),
+            log_sink: None,
+            call_depth: 0,
+            source_maps: Default::default(),
+            scripts_evaluated: 0,
+            host_fns: Default::default(),
+            permissions: Default::default(),
+            egress_filter: Default::default(),
+            http_interceptor: None,
+            rate_limit: None,
+            http_cache: false,
+            file_system: Rc::new(crate::MemoryFs::new(
+                crate::host_functions::DEFAULT_FS_QUOTA,
+            )),
+            metrics: Default::default(),
+            time_origin: crate::runtime::monotonic_now(),
+            clock_resolution: None,
+            recorded: None,
+            code_hash: None,
+            #[cfg(feature = "typescript")]
+            typescript: false,
         }
     }
 }
 
+const DEFAULT_USER_AGENT: &str = "PhatContract/0.1.0";
+
 pub fn ctx_init(ctx: &js::Context) {
     unsafe {
         let ctx = ctx.as_ptr();
@@ -142,34 +349,77 @@ pub fn ctx_init(ctx: &js::Context) {
     };
 }
 
+/// Counts a running script, module or callback until dropped, see [`Service::pump_jobs`].
+pub(crate) struct CallGuard<'a> {
+    service: &'a Service,
+}
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.service.state.borrow_mut().call_depth -= 1;
+    }
+}
+
 impl Service {
-    pub(crate) fn new(weak_self: ServiceWeakRef) -> Self {
+    pub(crate) fn new(weak_self: ServiceWeakRef, permissions: Permissions, metrics: bool) -> Self {
         let runtime = js::Runtime::new();
         let ctx = runtime.new_context();
         let boxed_self = Box::into_raw(Box::new(weak_self));
         unsafe { c::JS_SetContextOpaque(ctx.as_ptr(), boxed_self as *mut _) };
         ctx_init(&ctx);
-        setup_host_functions(&ctx).expect("Failed to setup host functions");
+        module::install_module_loader(&ctx);
+        rejection::install_rejection_tracker(&ctx);
+        setup_host_functions(&ctx, &permissions).expect("Failed to setup host functions");
+        let host_fn_names = if metrics {
+            metrics::count_host_calls(&ctx).expect("Failed to count host calls")
+        } else {
+            vec![]
+        };
         let bootcode = Code::Bytecode(bootcode::BOOT_CODE);
         ctx.eval(&bootcode).expect("Failed to eval bootcode");
-        let state = RefCell::new(ServiceState::default());
+        let state = RefCell::new(ServiceState {
+            permissions,
+            metrics: metrics::Counters::new(metrics, host_fn_names),
+            ..Default::default()
+        });
+        let engine = Rc::new_cyclic(|weak_self| JsEngine {
+            runtime,
+            ctx,
+            weak_self: weak_self.clone(),
+            last_error: Default::default(),
+            interrupter: Default::default(),
+        });
+        interrupt::install_interrupt_handler(&engine);
         Self {
-            runtime: Rc::new_cyclic(|weak_self| JsEngine {
-                runtime,
-                ctx,
-                weak_self: weak_self.clone(),
-                last_error: Default::default(),
-            }),
+            runtime: engine,
             state,
+            #[cfg(not(feature = "web"))]
+            http_client: Default::default(),
         }
     }
 
     pub fn new_ref() -> ServiceRef {
+        Self::new_ref_with_permissions(Permissions::default())
+    }
+
+    /// Create a service whose scripts can only use the host functions of the allowed capabilities.
+    pub fn new_ref_with_permissions(permissions: Permissions) -> ServiceRef {
+        Self::new_ref_with_metrics(permissions, false)
+    }
+
+    /// Create a service counting the calls of its host functions if `metrics` is set, and
+    /// defining `Sidevm.metrics()` for the scripts. Every host function is wrapped for counting,
+    /// so it is off by default.
+    pub fn new_ref_with_metrics(permissions: Permissions, metrics: bool) -> ServiceRef {
         ServiceRef(Rc::new_cyclic(|weak_self| {
-            Service::new(ServiceWeakRef(weak_self.clone()))
+            Service::new(ServiceWeakRef(weak_self.clone()), permissions, metrics)
         }))
     }
 
+    pub fn permissions(&self) -> Permissions {
+        self.state.borrow().permissions.clone()
+    }
+
     pub(crate) fn weak_self(&self) -> ServiceWeakRef {
         unsafe {
             let ptr = c::JS_GetContextOpaque(self.context().as_ptr()) as *mut ServiceWeakRef;
@@ -186,22 +436,105 @@ impl Service {
         self.runtime.clone()
     }
 
-    pub fn exec_script(&self, script: &str) -> Result<OwnedJsValue, String> {
+    #[cfg(not(feature = "web"))]
+    pub(crate) fn http_client(&self) -> crate::runtime::HttpClient {
+        self.http_client
+            .get_or_init(|| crate::runtime::http_client(self.shared_egress_filter()))
+            .clone()
+    }
+
+    pub fn exec_script(&self, script: &str) -> Result<OwnedJsValue, ExecError> {
         self.eval(Code::Source(script))
     }
 
-    pub fn exec_bytecode(&self, script: &[u8]) -> Result<OwnedJsValue, String> {
+    /// Execute bytecode produced by `bytecode::compile`, rejecting bytecode of other engine builds.
+    pub fn exec_bytecode(&self, script: &[u8]) -> Result<OwnedJsValue, ExecError> {
+        let script = crate::bytecode::check(script)?;
         self.eval(Code::Bytecode(script))
     }
 
-    pub fn eval(&self, code: Code) -> Result<OwnedJsValue, String> {
-        let result = js::eval(self.context(), &code)
-            .map(|value| value.try_into().map_err(|err: ValueError| err.to_string()))?;
-        self.runtime.exec_pending_jobs();
+    pub fn eval(&self, code: Code) -> Result<OwnedJsValue, ExecError> {
+        let _span = tracing::info_span!("eval").entered();
+        let _call = self.enter_call();
+        let _timer = self.exec_timer();
+        let result = self.eval_code(code);
+        self.pump_jobs();
         result
     }
 
+    fn eval_code(&self, code: Code) -> Result<OwnedJsValue, ExecError> {
+        let value = match code {
+            Code::Source(source) => {
+                let name = self.next_script_name();
+                let source = &*self.prepare_source(source, &name)?;
+                self.register_source_map(&name, source);
+                let result = source_map::eval_script(self.context(), &name, source);
+                let value = self.check_interrupted(result)?;
+                self.record(|| snapshot::Recorded::Script(source.into()));
+                value
+            }
+            code => {
+                let value = self.check_interrupted(js::eval(self.context(), &code))?;
+                if let Code::Bytecode(bytecode) = code {
+                    self.record(|| snapshot::Recorded::Bytecode(bytecode.to_vec()));
+                }
+                value
+            }
+        };
+        value
+            .try_into()
+            .map_err(|err: ValueError| ExecError::Script(err.to_string()))
+    }
+
+    /// Run the pending promise jobs, including the jobs they queue, then report the promise
+    /// rejections left unhandled.
+    ///
+    /// The service pumps the jobs after every script, module and callback it runs, whether it
+    /// threw or not, so the `.then` handlers queued by a host event always run before the next
+    /// event is delivered. Calls nested in a host function don't pump, the jobs would otherwise
+    /// run in the middle of the caller; the outermost call does when it returns.
+    pub fn pump_jobs(&self) {
+        if self.state.borrow().call_depth > 1 {
+            return;
+        }
+        self.runtime.exec_pending_jobs();
+        self.report_unhandled_rejections();
+    }
+
+    /// Count a script, module or callback as running until the guard is dropped.
+    pub(crate) fn enter_call(&self) -> CallGuard<'_> {
+        self.state.borrow_mut().call_depth += 1;
+        CallGuard { service: self }
+    }
+
+    /// Treat the scripts and modules as TypeScript, stripping their types before evaluation.
+    #[cfg(feature = "typescript")]
+    pub fn set_typescript(&self, enabled: bool) {
+        self.state.borrow_mut().typescript = enabled;
+    }
+
+    /// Transpile `source` if it is TypeScript.
+    pub(crate) fn prepare_source<'a>(
+        &self,
+        source: &'a str,
+        name: &str,
+    ) -> Result<Cow<'a, str>, String> {
+        #[cfg(feature = "typescript")]
+        if self.state.borrow().typescript || crate::typescript::is_typescript(name) {
+            return crate::typescript::transpile(source, name).map(Cow::Owned);
+        }
+        let _ = name;
+        Ok(Cow::Borrowed(source))
+    }
+
+    /// Call `func` with `args`.
+    ///
+    /// A call interrupted by the gas limit, the deadline or the watchdog fails with the
+    /// `ExecError` as the error, like `exec_script`.
     pub fn call_function(&self, func: js::Value, args: impl ToArgs) -> Result<js::Value> {
+        let _span = tracing::debug_span!("call_function").entered();
+        let _call = self.enter_call();
+        let _timer = self.exec_timer();
         let ctx = self.context();
         let mut args = args.to_raw_args(ctx)?;
         let func = *func.raw_value();
@@ -212,12 +545,18 @@ impl Service {
             let args = args.as_mut_ptr();
             c::JS_Call(ctx.as_ptr(), func, this, args_len, args)
         };
-        if c::is_exception(ret) {
-            let err = self.context().get_exception_str();
-            anyhow::bail!("Failed to call function: {err}");
-        }
-        self.runtime.exec_pending_jobs();
-        Ok(js::Value::new_moved(self.context(), ret))
+        let result = if c::is_exception(ret) {
+            Err(self.context().get_exception_str())
+        } else {
+            Ok(js::Value::new_moved(self.context(), ret))
+        };
+        // An interruption is returned as the `ExecError` itself, for callers to downcast
+        let result = self.check_interrupted(result).map_err(|err| match err {
+            ExecError::Script(err) => anyhow::anyhow!("Failed to call function: {err}"),
+            err => err.into(),
+        });
+        self.pump_jobs();
+        result
     }
 
     pub fn push_resource(&self, resource: Resource) -> u64 {
@@ -233,6 +572,28 @@ impl Service {
         Some(self.to_js_value(&state.recources.get(&id)?.js_value))
     }
 
+    /// Attach host side data to the resource `id`. Returns false if the resource does not exist.
+    pub fn set_resource_data(&self, id: u64, data: impl Any) -> bool {
+        let mut state = self.state.borrow_mut();
+        let Some(res) = state.recources.get_mut(&id) else {
+            return false;
+        };
+        res.data = Some(Box::new(data));
+        true
+    }
+
+    /// Get a clone of the host side data attached to the resource `id`.
+    pub fn resource_data<T: Any + Clone>(&self, id: u64) -> Option<T> {
+        let state = self.state.borrow();
+        state
+            .recources
+            .get(&id)?
+            .data
+            .as_ref()?
+            .downcast_ref::<T>()
+            .cloned()
+    }
+
     pub fn close_all(&self) {
         debug!("Destroying all resources");
         let mut state = self.state.borrow_mut();
@@ -243,6 +604,15 @@ impl Service {
         let _ = state.done_tx.send(());
     }
 
+    pub(crate) fn set_exit_code(&self, code: i32) {
+        self.state.borrow_mut().exit_code = Some(code);
+    }
+
+    /// The code the script passed to `process.exit`, if any.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.state.borrow().exit_code
+    }
+
     pub fn remove_resource(&self, id: u64) -> Option<Resource> {
         debug!("Destroying resource {id}");
         let mut state = self.state.borrow_mut();
@@ -259,37 +629,63 @@ impl Service {
         js_callback: OwnedJsValue,
         fut_gen: FutGen,
         args: Args,
-    ) -> u64
+    ) -> Result<u64>
     where
         Fut: Future<Output = ()> + 'static,
         Args: 'static,
         FutGen: FnOnce(ServiceWeakRef, u64, Args) -> Fut + 'static,
     {
+        self.check_resource_quota()?;
         let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel::<()>();
-        let res = Resource::new(js_callback, Some(Box::new(cancel_tx)));
+        let kind = resource::task_kind::<FutGen>();
+        let res = Resource::new(js_callback, Some(Box::new(cancel_tx))).with_kind(kind);
         let id = self.push_resource(res);
         let weak_service = self.weak_self();
-        let _handle = crate::runtime::spawn(async move {
+        let started_at = crate::runtime::monotonic_now();
+        let task = async move {
             tokio::select! {
                 _ = fut_gen(weak_service.clone(), id, args) => {
                 }
                 _ = cancel_rx => {
+                    tracing::debug!("cancelled");
                 }
             }
-            debug!("Task {id} finished");
+            tracing::debug!("finished");
+            if let Some(service) = weak_service.upgrade() {
+                service.count_task(crate::runtime::monotonic_now().saturating_sub(started_at));
+            }
             close(weak_service, id);
-        });
-        id
+        };
+        let span = tracing::info_span!("task", id, kind);
+        let _handle = crate::runtime::spawn(tracing::Instrument::instrument(task, span));
+        Ok(id)
     }
+    /// Log a message of the script. Levels 0 to 4 are trace, debug, info, warn and error.
     pub fn js_log(&self, level: u32, msg: &str) {
-        match level {
-            1 => debug!("JS: {}", msg),
-            2 => info!("JS: {}", msg),
-            3 => warn!("JS: {}", msg),
-            _ => error!("JS: {}", msg),
+        let level = match level {
+            0 => log::Level::Trace,
+            1 => log::Level::Debug,
+            2 => log::Level::Info,
+            3 => log::Level::Warn,
+            _ => log::Level::Error,
+        };
+        let sink = self.state.borrow().log_sink.clone();
+        match sink {
+            Some(sink) => sink(level, msg),
+            None => log::log!(level, "JS: {}", msg),
         }
     }
 
+    /// Capture the logs of the script instead of passing them to the `log` crate.
+    pub fn set_log_sink(&self, sink: impl Fn(log::Level, &str) + 'static) {
+        self.state.borrow_mut().log_sink = Some(Rc::new(sink));
+    }
+
+    /// Replace the log sink, returning the previous one so that it can be restored.
+    pub(crate) fn replace_log_sink(&self, sink: Option<LogSink>) -> Option<LogSink> {
+        core::mem::replace(&mut self.state.borrow_mut().log_sink, sink)
+    }
+
     pub async fn wait_for_tasks(&self) {
         if self.state.borrow().recources.len() == 0 {
             return;
@@ -298,10 +694,344 @@ impl Service {
         let _ = rx.recv().await;
     }
 
-    pub fn number_of_tasks(&self) -> usize {
+    /// Shut the service down gracefully.
+    ///
+    /// The `beforeunload` listeners of the script are called first, then the tasks they started,
+    /// e.g. flushing a cache or closing a WebSocket, get up to `budget` to finish before all the
+    /// remaining tasks are cancelled.
+    pub async fn shutdown(&self, budget: Duration) {
+        if let Some(Ok(handler)) = self.shutdown_handler().map(TryInto::try_into) {
+            if let Err(err) = self.call_function(handler, ()) {
+                warn!("Shutdown handler failed: {err}");
+            }
+        }
+        tokio::select! {
+            _ = self.wait_for_tasks() => {}
+            _ = crate::runtime::time::sleep(budget) => {
+                debug!("Shutdown budget exhausted, {} tasks left", self.resource_count());
+            }
+        }
+        self.close_all();
+    }
+
+    /// The number of live resources, each pinning its JS callback until closed.
+    ///
+    /// The callback of a fetch response is only held weakly until its body is consumed, a response
+    /// dropped unread gets its request closed once collected, see `BodyReceiver` in
+    /// polyfill-fetch.js. Embedders can poll this to spot scripts that keep opening resources
+    /// without closing them.
+    pub fn resource_count(&self) -> usize {
         self.state.borrow().recources.len()
     }
 
+    /// Same as [`Service::resource_count`].
+    pub fn number_of_tasks(&self) -> usize {
+        self.resource_count()
+    }
+
+    pub(crate) fn timer_nesting_level(&self) -> u32 {
+        self.state.borrow().timer_nesting_level
+    }
+
+    pub(crate) fn set_timer_nesting_level(&self, level: u32) {
+        self.state.borrow_mut().timer_nesting_level = level;
+    }
+
+    /// Limit the memory used by the service.
+    ///
+    /// The limit applies to the QuickJS heap, and also bounds host side buffers: http responses are
+    /// rejected once they exceed the limit and the number of pending tasks is capped accordingly.
+    pub fn set_memory_limit(&self, bytes: usize) {
+        unsafe {
+            let rt = c::JS_GetRuntime(self.context().as_ptr());
+            c::JS_SetMemoryLimit(rt, bytes as _);
+        }
+        self.state.borrow_mut().memory_limit = Some(bytes);
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.state.borrow().memory_limit
+    }
+
+    /// The hash of the entry code set by `set_entry_code`, None if not set.
+    pub fn code_hash(&self) -> Option<Vec<u8>> {
+        self.state.borrow().code_hash.clone()
+    }
+
+    /// Record `code` as the entry code of the service, the code the embedder runs it with.
+    ///
+    /// Its hash is reported to the scripts as `codeHash` and bound into the attestation quotes.
+    /// The service doesn't infer it from the code it evaluates, which includes the REPL input,
+    /// helpers of the embedder and the modules.
+    pub fn set_entry_code(&self, code: &[u8]) {
+        self.state.borrow_mut().code_hash = crate::host_functions::code_hash(code);
+    }
+
+    /// Record `code` as run into the service after its entry code, for the embedders running
+    /// several scripts in a live service.
+    ///
+    /// The hash becomes the hash of the previous one followed by `code`, so it covers every script
+    /// that ran in the service, in order. Without a previous hash, `code` is the entry code.
+    pub fn append_entry_code(&self, code: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        state.code_hash = match state.code_hash.take() {
+            Some(previous) => crate::host_functions::code_hash(&[&previous[..], code].concat()),
+            None => crate::host_functions::code_hash(code),
+        };
+    }
+
+    /// Replace the headers added to every outgoing http request.
+    ///
+    /// A header set by the request itself takes precedence over the default of the same name.
+    /// Defaults to `User-Agent: PhatContract/0.1.0`, pass an empty list to send no default header.
+    pub fn set_default_headers(&self, headers: Vec<(String, String)>) {
+        self.state.borrow_mut().default_headers = headers;
+    }
+
+    /// Set a header added to every outgoing http request, replacing the default of the same name.
+    pub fn set_default_header(&self, name: &str, value: &str) {
+        let mut state = self.state.borrow_mut();
+        state
+            .default_headers
+            .retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        state.default_headers.push((name.into(), value.into()));
+    }
+
+    pub fn default_headers(&self) -> Vec<(String, String)> {
+        self.state.borrow().default_headers.clone()
+    }
+
+    /// Send the outgoing http requests through a proxy, e.g. `socks5://127.0.0.1:9050`.
+    ///
+    /// Requests can override it with the `proxy` option if [`Service::set_script_proxy`] allows.
+    pub fn set_http_proxy(&self, proxy: Option<String>) {
+        self.state.borrow_mut().http_proxy = proxy;
+    }
+
+    pub fn http_proxy(&self) -> Option<String> {
+        self.state.borrow().http_proxy.clone()
+    }
+
+    /// Let the scripts choose the proxy of their requests with the `proxy` option, or connect
+    /// directly, overriding [`Service::set_http_proxy`]. Denied by default.
+    pub fn set_script_proxy(&self, allowed: bool) {
+        self.state.borrow_mut().script_proxy = allowed;
+    }
+
+    pub fn script_proxy_allowed(&self) -> bool {
+        self.state.borrow().script_proxy
+    }
+
+    /// Let the scripts send http requests to the Unix domain sockets at `paths` with the
+    /// `unixSocketPath` option. None by default.
+    pub fn set_unix_sockets(&self, paths: Vec<std::path::PathBuf>) {
+        self.state.borrow_mut().unix_sockets = paths;
+    }
+
+    pub fn unix_sockets(&self) -> Vec<std::path::PathBuf> {
+        self.state.borrow().unix_sockets.clone()
+    }
+
+    /// Whether `path` is one of the sockets allowed by [`Service::set_unix_sockets`], following
+    /// the symlinks.
+    pub fn unix_socket_allowed(&self, path: &str) -> bool {
+        let canonical = |path: &std::path::Path| path.canonicalize().ok();
+        let Some(path) = canonical(path.as_ref()) else {
+            return false;
+        };
+        self.state
+            .borrow()
+            .unix_sockets
+            .iter()
+            .any(|allowed| canonical(allowed).as_ref() == Some(&path))
+    }
+
+    #[cfg(feature = "js-http-proxy")]
+    pub(crate) fn with_proxy_clients<R>(
+        &self,
+        f: impl FnOnce(&mut BTreeMap<String, crate::host_functions::ProxyClient>) -> R,
+    ) -> R {
+        f(&mut self.state.borrow_mut().proxy_clients)
+    }
+
+    /// Restrict the hosts the scripts can connect to, None to allow all.
+    pub fn set_egress_filter(&self, filter: Option<EgressFilter>) {
+        self.state.borrow().egress_filter.set(filter);
+    }
+
+    pub fn egress_filter(&self) -> Option<EgressFilter> {
+        self.state.borrow().egress_filter.get()
+    }
+
+    /// The egress filter handle, following later changes, for the connectors of the http clients.
+    pub(crate) fn shared_egress_filter(&self) -> crate::host_functions::SharedEgressFilter {
+        self.state.borrow().egress_filter.clone()
+    }
+
+    /// Limit the rate of the outgoing http requests, None to remove the limit.
+    ///
+    /// Each attempt and each redirect hop of a request counts as one request.
+    pub fn set_rate_limit(&self, limit: Option<crate::RateLimit>) {
+        self.state.borrow_mut().rate_limit = limit;
+    }
+
+    pub fn rate_limit(&self) -> Option<crate::RateLimit> {
+        self.state.borrow().rate_limit.clone()
+    }
+
+    /// Cache the responses of the GET requests in the worker cache as the `Cache-Control`,
+    /// `Expires` and `ETag` headers allow, sharing them with the other scripts of the worker.
+    ///
+    /// Takes effect only in builds with the `js-cache` feature.
+    pub fn set_http_cache(&self, enabled: bool) {
+        self.state.borrow_mut().http_cache = enabled;
+    }
+
+    pub fn http_cache_enabled(&self) -> bool {
+        self.state.borrow().http_cache
+    }
+
+    /// Coarsen `performance.now()` to multiples of `resolution`, e.g. to keep the scripts
+    /// deterministic or to blunt timing side channels. None for the full resolution.
+    pub fn set_clock_resolution(&self, resolution: Option<Duration>) {
+        self.state.borrow_mut().clock_resolution = resolution.filter(|r| !r.is_zero());
+    }
+
+    pub fn clock_resolution(&self) -> Option<Duration> {
+        self.state.borrow().clock_resolution
+    }
+
+    /// The milliseconds since the service was created, coarsened by `set_clock_resolution`.
+    pub(crate) fn performance_now(&self) -> f64 {
+        let state = self.state.borrow();
+        let elapsed = crate::runtime::monotonic_now().saturating_sub(state.time_origin);
+        let elapsed = match state.clock_resolution {
+            Some(resolution) => {
+                let nanos = elapsed.as_nanos() / resolution.as_nanos() * resolution.as_nanos();
+                Duration::from_nanos(nanos as u64)
+            }
+            None => elapsed,
+        };
+        elapsed.as_secs_f64() * 1000.0
+    }
+
+    /// Inspect and modify the outgoing http requests and their response heads before they cross
+    /// into JS, None to remove the interceptor.
+    pub fn set_http_interceptor(&self, interceptor: Option<HttpInterceptor>) {
+        self.state.borrow_mut().http_interceptor = interceptor;
+    }
+
+    pub fn http_interceptor(&self) -> Option<HttpInterceptor> {
+        self.state.borrow().http_interceptor.clone()
+    }
+
+    /// Back the `fs` namespace with `fs` instead of the default in-memory filesystem.
+    pub fn set_file_system(&self, fs: impl crate::FileSystem + 'static) {
+        self.set_shared_file_system(Rc::new(fs));
+    }
+
+    pub(crate) fn set_shared_file_system(&self, fs: Rc<dyn crate::FileSystem>) {
+        self.state.borrow_mut().file_system = fs;
+    }
+
+    pub(crate) fn file_system(&self) -> Rc<dyn crate::FileSystem> {
+        self.state.borrow().file_system.clone()
+    }
+
+    /// Resolve host names of outgoing http requests and `dnsResolve` with the given servers.
+    #[cfg(feature = "js-dns")]
+    pub fn set_dns_config(&self, config: &crate::DnsConfig) -> Result<()> {
+        let resolver =
+            crate::host_functions::DnsResolver::new(config, self.shared_egress_filter())?;
+        self.set_dns_resolver(resolver);
+        Ok(())
+    }
+
+    #[cfg(feature = "js-dns")]
+    pub(crate) fn set_dns_resolver(&self, resolver: crate::host_functions::DnsResolver) {
+        self.state.borrow_mut().dns_resolver = Some(resolver);
+    }
+
+    #[cfg(feature = "js-dns")]
+    pub(crate) fn dns_resolver(&self) -> Option<crate::host_functions::DnsResolver> {
+        self.state.borrow().dns_resolver.clone()
+    }
+
+    #[cfg(feature = "js-dns")]
+    pub(crate) fn set_system_dns_resolver(&self, resolver: crate::host_functions::SharedResolver) {
+        self.state.borrow_mut().system_dns_resolver = Some(resolver);
+    }
+
+    #[cfg(feature = "js-dns")]
+    pub(crate) fn system_dns_resolver(&self) -> Option<crate::host_functions::SharedResolver> {
+        self.state.borrow().system_dns_resolver.clone()
+    }
+
+    /// Store the cookies set by http responses and send them with the following requests.
+    #[cfg(not(feature = "web"))]
+    pub fn enable_cookie_jar(&self) {
+        self.state
+            .borrow_mut()
+            .cookie_jar
+            .get_or_insert_with(Default::default);
+    }
+
+    /// Run `f` on the cookie jar, None if the jar is not enabled.
+    #[cfg(not(feature = "web"))]
+    pub(crate) fn with_cookie_jar<R>(
+        &self,
+        f: impl FnOnce(&mut crate::host_functions::CookieJar) -> R,
+    ) -> Option<R> {
+        self.state.borrow_mut().cookie_jar.as_mut().map(f)
+    }
+
+    fn check_resource_quota(&self) -> Result<()> {
+        let state = self.state.borrow();
+        let Some(limit) = state.memory_limit else {
+            return Ok(());
+        };
+        if state.recources.len() >= limit / RESOURCE_MEMORY_COST {
+            anyhow::bail!("Too many pending tasks for the memory limit of {limit} bytes");
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "js-scale")]
+    pub(crate) fn register_scale_type(&self, name: String, ty: crate::host_functions::ScaleType) {
+        self.state
+            .borrow_mut()
+            .scale_types
+            .insert(name, Rc::new(ty));
+    }
+
+    #[cfg(feature = "js-scale")]
+    pub(crate) fn scale_type(&self, name: &str) -> Option<Rc<crate::host_functions::ScaleType>> {
+        self.state.borrow().scale_types.get(name).cloned()
+    }
+
+    pub(crate) fn set_parent_port(&self, port: UnboundedSender<WorkerMessage>) {
+        self.state.borrow_mut().parent_port = Some(port);
+    }
+
+    pub(crate) fn parent_port(&self) -> Option<UnboundedSender<WorkerMessage>> {
+        self.state.borrow().parent_port.clone()
+    }
+
+    pub(crate) fn take_parent_port(&self) -> Option<UnboundedSender<WorkerMessage>> {
+        self.state.borrow_mut().parent_port.take()
+    }
+
+    /// The memory reserved by the running workers of the service.
+    pub(crate) fn worker_memory(&self) -> Rc<Cell<usize>> {
+        self.state.borrow().worker_memory.clone()
+    }
+
+    /// The memory used by the WebAssembly instances of the service.
+    #[cfg(feature = "js-wasm")]
+    pub(crate) fn wasm_memory(&self) -> Rc<Cell<usize>> {
+        self.state.borrow().wasm_memory.clone()
+    }
+
     pub fn set_http_listener(&self, listener: OwnedJsValue) {
         self.state.borrow_mut().http_listener = Some(listener);
     }
@@ -310,6 +1040,30 @@ impl Service {
         self.state.borrow().http_listener.as_ref()?.dup()
     }
 
+    pub fn set_query_handler(&self, handler: OwnedJsValue) {
+        self.state.borrow_mut().query_handler = Some(handler);
+    }
+
+    pub fn query_handler(&self) -> Option<OwnedJsValue> {
+        self.state.borrow().query_handler.as_ref()?.dup()
+    }
+
+    pub fn set_message_handler(&self, handler: OwnedJsValue) {
+        self.state.borrow_mut().message_handler = Some(handler);
+    }
+
+    pub fn message_handler(&self) -> Option<OwnedJsValue> {
+        self.state.borrow().message_handler.as_ref()?.dup()
+    }
+
+    pub fn set_shutdown_handler(&self, handler: OwnedJsValue) {
+        self.state.borrow_mut().shutdown_handler = Some(handler);
+    }
+
+    pub fn shutdown_handler(&self) -> Option<OwnedJsValue> {
+        self.state.borrow().shutdown_handler.as_ref()?.dup()
+    }
+
     pub fn to_js_value(&self, owned: &OwnedJsValue) -> js::Value {
         self.runtime.to_js_value(owned)
     }
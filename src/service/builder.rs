@@ -0,0 +1,255 @@
+use super::*;
+use anyhow::Context as _;
+use core::ffi::{c_char, c_int};
+use js::ToJsValue;
+use std::ffi::CString;
+
+/// A host function registered by the embedder, called with the JS arguments.
+pub(crate) type HostFn = Rc<dyn Fn(&Service, &[js::Value]) -> Result<js::Value>>;
+
+/// Configures and creates a [`Service`], for crates embedding the JS runtime.
+///
+/// ```ignore
+/// let service = ServiceBuilder::new()
+///     .memory_limit(64 << 20)
+///     .global("appName", "demo".to_string())
+///     .define_fn("add", |_service, args: &[js::Value]| {
+///         let a: f64 = js::FromJsValue::from_js_value(args[0].clone())?;
+///         let b: f64 = js::FromJsValue::from_js_value(args[1].clone())?;
+///         Ok(a + b)
+///     })
+///     .build()?;
+/// service.exec_script("console.log(appName, add(1, 2))")?;
+/// ```
+#[derive(Default)]
+pub struct ServiceBuilder {
+    memory_limit: Option<usize>,
+    default_headers: Vec<(String, String)>,
+    http_proxy: Option<String>,
+    script_proxy: bool,
+    metrics: bool,
+    unix_sockets: Vec<std::path::PathBuf>,
+    globals: Vec<(String, Box<dyn ToJsValue>)>,
+    host_fns: Vec<(String, HostFn)>,
+    permissions: Permissions,
+    egress_filter: Option<EgressFilter>,
+    http_interceptor: Option<HttpInterceptor>,
+    rate_limit: Option<crate::RateLimit>,
+    http_cache: bool,
+    clock_resolution: Option<Duration>,
+    file_system: Option<Rc<dyn crate::FileSystem>>,
+}
+
+impl ServiceBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [`Service::set_memory_limit`].
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Add a header to every outgoing http request, see [`Service::set_default_header`].
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        self.default_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// See [`Service::set_http_proxy`].
+    pub fn http_proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.http_proxy = Some(proxy.into());
+        self
+    }
+
+    /// See [`Service::set_script_proxy`].
+    pub fn script_proxy(mut self, allowed: bool) -> Self {
+        self.script_proxy = allowed;
+        self
+    }
+
+    /// Count the host calls and define `Sidevm.metrics`, see [`Service::new_ref_with_metrics`].
+    pub fn metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled;
+        self
+    }
+
+    /// Allow the scripts to send requests to the Unix domain socket at `path`, see
+    /// [`Service::set_unix_sockets`].
+    pub fn unix_socket(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.unix_sockets.push(path.into());
+        self
+    }
+
+    /// Restrict the host functions available to the scripts, all are allowed by default.
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// See [`Service::set_egress_filter`].
+    pub fn egress_filter(mut self, filter: EgressFilter) -> Self {
+        self.egress_filter = Some(filter);
+        self
+    }
+
+    /// See [`Service::set_http_interceptor`].
+    pub fn http_interceptor(mut self, interceptor: HttpInterceptor) -> Self {
+        self.http_interceptor = Some(interceptor);
+        self
+    }
+
+    /// See [`Service::set_rate_limit`].
+    pub fn rate_limit(mut self, limit: crate::RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// See [`Service::set_http_cache`].
+    pub fn http_cache(mut self, enabled: bool) -> Self {
+        self.http_cache = enabled;
+        self
+    }
+
+    /// See [`Service::set_clock_resolution`].
+    pub fn clock_resolution(mut self, resolution: Duration) -> Self {
+        self.clock_resolution = Some(resolution);
+        self
+    }
+
+    /// See [`Service::set_file_system`].
+    pub fn file_system(mut self, fs: impl crate::FileSystem + 'static) -> Self {
+        self.file_system = Some(Rc::new(fs));
+        self
+    }
+
+    /// Set the global variable `name` to `value`, before any script runs.
+    pub fn global(mut self, name: &str, value: impl ToJsValue + 'static) -> Self {
+        self.globals.push((name.into(), Box::new(value)));
+        self
+    }
+
+    /// Define the global function `name`, see [`Service::define_fn`].
+    pub fn define_fn<F, R>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn(&Service, &[js::Value]) -> Result<R> + 'static,
+        R: ToJsValue,
+    {
+        self.host_fns.push((name.into(), wrap_host_fn(f)));
+        self
+    }
+
+    pub fn build(self) -> Result<ServiceRef> {
+        let service = Service::new_ref_with_metrics(self.permissions, self.metrics);
+        if let Some(bytes) = self.memory_limit {
+            service.set_memory_limit(bytes);
+        }
+        for (name, value) in &self.default_headers {
+            service.set_default_header(name, value);
+        }
+        if self.http_proxy.is_some() {
+            service.set_http_proxy(self.http_proxy);
+        }
+        service.set_script_proxy(self.script_proxy);
+        service.set_unix_sockets(self.unix_sockets);
+        service.set_egress_filter(self.egress_filter);
+        service.set_http_interceptor(self.http_interceptor);
+        service.set_rate_limit(self.rate_limit);
+        service.set_http_cache(self.http_cache);
+        service.set_clock_resolution(self.clock_resolution);
+        if let Some(fs) = self.file_system {
+            service.set_shared_file_system(fs);
+        }
+        let global = js::get_global(service.context());
+        for (name, value) in &self.globals {
+            let value = value
+                .to_js_value(service.context())
+                .with_context(|| format!("Failed to convert global {name}"))?;
+            global.set_property(name, &value)?;
+        }
+        for (name, f) in self.host_fns {
+            service.define_host_fn(&name, f)?;
+        }
+        Ok(service)
+    }
+}
+
+fn wrap_host_fn<F, R>(f: F) -> HostFn
+where
+    F: Fn(&Service, &[js::Value]) -> Result<R> + 'static,
+    R: ToJsValue,
+{
+    Rc::new(move |service, args| Ok(f(service, args)?.to_js_value(service.context())?))
+}
+
+impl Service {
+    /// Define the global function `name` calling `f` with the JS arguments.
+    ///
+    /// An error returned by `f` is thrown to JS as an `InternalError`.
+    pub fn define_fn<F, R>(&self, name: &str, f: F) -> Result<()>
+    where
+        F: Fn(&Service, &[js::Value]) -> Result<R> + 'static,
+        R: ToJsValue,
+    {
+        self.define_host_fn(name, wrap_host_fn(f))
+    }
+
+    fn define_host_fn(&self, name: &str, f: HostFn) -> Result<()> {
+        let index = {
+            let mut state = self.state.borrow_mut();
+            state.host_fns.push(f);
+            state.host_fns.len() - 1
+        };
+        let ctx = self.context();
+        // The index of the function is passed back as the magic number
+        let func = unsafe {
+            c::JS_NewCFunctionData(
+                ctx.as_ptr(),
+                Some(call_host_fn),
+                0,
+                index as c_int,
+                0,
+                core::ptr::null_mut(),
+            )
+        };
+        if c::is_exception(func) {
+            anyhow::bail!("Failed to define {name}: {}", ctx.get_exception_str());
+        }
+        let func = js::Value::new_moved(ctx, func);
+        js::get_global(ctx).set_property(name, &func)?;
+        Ok(())
+    }
+
+    fn host_fn(&self, index: usize) -> Option<HostFn> {
+        self.state.borrow().host_fns.get(index).cloned()
+    }
+}
+
+unsafe extern "C" fn call_host_fn(
+    ctx: *mut c::JSContext,
+    _this: c::JSValue,
+    argc: c_int,
+    argv: *mut c::JSValue,
+    magic: c_int,
+    _data: *mut c::JSValue,
+) -> c::JSValue {
+    let Some(service) = module::ctx_service(ctx) else {
+        return throw_error(ctx, "Service not found");
+    };
+    let Some(f) = service.host_fn(magic as usize) else {
+        return throw_error(ctx, "Host function not found");
+    };
+    let args: Vec<_> = (0..argc as usize)
+        .map(|i| js::Value::new_cloned(service.context(), *argv.add(i)))
+        .collect();
+    match f(&service, &args) {
+        Ok(value) => c::JS_DupValue(ctx, *value.raw_value()),
+        Err(err) => throw_error(ctx, &format!("{err:#}")),
+    }
+}
+
+unsafe fn throw_error(ctx: *mut c::JSContext, message: &str) -> c::JSValue {
+    let message = CString::new(message).unwrap_or_default();
+    c::JS_ThrowInternalError(ctx, b"%s\0".as_ptr() as *const c_char, message.as_ptr())
+}
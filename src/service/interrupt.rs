@@ -0,0 +1,281 @@
+use core::{
+    cell::{Cell, RefCell},
+    ffi::{c_int, c_void},
+    time::Duration,
+};
+
+use super::*;
+use crate::runtime::{monotonic_now, time::sleep};
+use log::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interruption {
+    OutOfGas,
+    Timeout,
+    Stalled,
+}
+
+/// WebAssembly fuel worth one tick of gas, about the instructions QuickJS runs between two polls.
+#[cfg(feature = "js-wasm")]
+const WASM_FUEL_PER_TICK: u64 = 10_000;
+
+/// WebAssembly fuel granted per millisecond left before the deadline, a conservative estimate of
+/// the speed of the interpreter.
+#[cfg(feature = "js-wasm")]
+const WASM_FUEL_PER_MS: u64 = 100_000;
+
+/// Settings of the watchdog, see [`Service::set_watchdog`].
+#[derive(Debug, Clone, Copy)]
+struct Watchdog {
+    threshold: Duration,
+    interrupt: bool,
+}
+
+/// The limits a worker inherits from the service creating it, see [`Service::worker_limits`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ExecLimits {
+    gas: Option<u64>,
+    deadline: Option<Duration>,
+    watchdog: Option<Watchdog>,
+}
+
+/// Decides whether running JS code should be interrupted, polled by QuickJS.
+///
+/// QuickJS polls the interrupt handler roughly every 10000 bytecode branches and calls. Each poll
+/// costs one tick of gas.
+#[derive(Default)]
+pub(crate) struct Interrupter {
+    gas: Cell<Option<u64>>,
+    /// Deadline in `monotonic_now` time.
+    deadline: Cell<Option<Duration>>,
+    interruption: Cell<Option<Interruption>>,
+    watchdog: Cell<Option<Watchdog>>,
+    /// When the outermost running script or callback started, in `monotonic_now` time.
+    exec_start: Cell<Option<Duration>>,
+    /// Whether the watchdog already fired for the running script or callback.
+    stall_reported: Cell<bool>,
+    pub(super) profiler: RefCell<Option<profiler::Profiler>>,
+}
+
+impl Interrupter {
+    fn poll(&self, ctx: &js::Context) -> bool {
+        // Busy if the stack capture runs JS code polling again
+        if let Ok(mut profiler) = self.profiler.try_borrow_mut() {
+            if let Some(profiler) = profiler.as_mut() {
+                profiler.poll(ctx);
+            }
+        }
+        if self.check_stalled(ctx) {
+            self.interruption.set(Some(Interruption::Stalled));
+            return true;
+        }
+        if let Some(deadline) = self.deadline.get() {
+            if monotonic_now() >= deadline {
+                self.interruption.set(Some(Interruption::Timeout));
+                return true;
+            }
+        }
+        match self.gas.get() {
+            None => false,
+            Some(0) => {
+                self.interruption.set(Some(Interruption::OutOfGas));
+                true
+            }
+            Some(n) => {
+                self.gas.set(Some(n - 1));
+                false
+            }
+        }
+    }
+
+    /// Report the running script or callback once it runs past the watchdog threshold. Returns
+    /// true if it should be interrupted.
+    fn check_stalled(&self, ctx: &js::Context) -> bool {
+        let (Some(watchdog), Some(start)) = (self.watchdog.get(), self.exec_start.get()) else {
+            return false;
+        };
+        let elapsed = monotonic_now().saturating_sub(start);
+        if elapsed < watchdog.threshold {
+            return false;
+        }
+        if !self.stall_reported.replace(true) {
+            // Capturing the stack runs JS code polling again, which sees the stall reported
+            let stack = profiler::capture_stack(ctx).unwrap_or_default();
+            warn!(
+                "JS has been running for {}ms without yielding{}\n{}",
+                elapsed.as_millis(),
+                if watchdog.interrupt {
+                    ", interrupting"
+                } else {
+                    ""
+                },
+                stack.trim_end()
+            );
+        }
+        watchdog.interrupt
+    }
+
+    /// Called when the outermost script or callback starts running.
+    pub(super) fn exec_started(&self) {
+        self.exec_start.set(Some(monotonic_now()));
+        self.stall_reported.set(false);
+    }
+
+    /// Called when the outermost script or callback returns.
+    pub(super) fn exec_finished(&self) {
+        self.exec_start.set(None);
+    }
+
+    /// Returns the reason if the execution was interrupted since the last call.
+    pub(crate) fn take_interruption(&self) -> Option<Interruption> {
+        self.interruption.take()
+    }
+}
+
+/// Install the interrupt handler polling the interrupter of `engine`, which must not move.
+pub(crate) fn install_interrupt_handler(engine: &JsEngine) {
+    unsafe {
+        let rt = c::JS_GetRuntime(engine.ctx.as_ptr());
+        c::JS_SetInterruptHandler(
+            rt,
+            Some(js_interrupt_handler),
+            engine as *const JsEngine as *mut c_void,
+        );
+    }
+}
+
+unsafe extern "C" fn js_interrupt_handler(_rt: *mut c::JSRuntime, opaque: *mut c_void) -> c_int {
+    let engine = &*(opaque as *const JsEngine);
+    engine.interrupter.poll(&engine.ctx) as c_int
+}
+
+impl Service {
+    /// Limit the JS execution to `ticks` ticks, or remove the limit if None.
+    ///
+    /// Once the gas runs out, any running JS code is interrupted and `exec_script` returns
+    /// `ExecError::OutOfGas` until a new limit is set.
+    pub fn set_gas_limit(&self, ticks: Option<u64>) {
+        let interrupter = &self.runtime.interrupter;
+        interrupter.gas.set(ticks);
+        interrupter.interruption.set(None);
+    }
+
+    /// The remaining gas, None if unlimited.
+    pub fn gas_remaining(&self) -> Option<u64> {
+        self.runtime.interrupter.gas.get()
+    }
+
+    /// The fuel a WebAssembly call may burn, None if unlimited.
+    ///
+    /// WebAssembly code doesn't poll the interrupt handler, so the gas and the deadline are turned
+    /// into fuel up front, whichever runs out first.
+    #[cfg(feature = "js-wasm")]
+    pub(crate) fn wasm_fuel(&self) -> Option<u64> {
+        let interrupter = &self.runtime.interrupter;
+        let by_gas = interrupter
+            .gas
+            .get()
+            .map(|gas| gas.saturating_mul(WASM_FUEL_PER_TICK));
+        let by_deadline = interrupter.deadline.get().map(|deadline| {
+            let left = deadline.saturating_sub(monotonic_now()).as_millis();
+            u64::try_from(left)
+                .unwrap_or(u64::MAX)
+                .saturating_mul(WASM_FUEL_PER_MS)
+        });
+        match (by_gas, by_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Charge the fuel burnt by a WebAssembly call to the gas. If the call ran out of fuel, the
+    /// execution is marked interrupted by whichever limit the fuel came from.
+    #[cfg(feature = "js-wasm")]
+    pub(crate) fn charge_wasm_fuel(&self, consumed: u64, exhausted: bool) {
+        let interrupter = &self.runtime.interrupter;
+        if let Some(gas) = interrupter.gas.get() {
+            interrupter
+                .gas
+                .set(Some(gas.saturating_sub(consumed / WASM_FUEL_PER_TICK)));
+        }
+        if exhausted {
+            let interruption = if interrupter.gas.get() == Some(0) {
+                Interruption::OutOfGas
+            } else {
+                Interruption::Timeout
+            };
+            interrupter.interruption.set(Some(interruption));
+        }
+    }
+
+    /// The limits of a worker created by this service: the same deadline and watchdog, and half of
+    /// the remaining gas, which this service gives up so that spawning workers doesn't add gas.
+    pub(crate) fn worker_limits(&self) -> ExecLimits {
+        let interrupter = &self.runtime.interrupter;
+        let gas = interrupter.gas.get().map(|gas| {
+            let share = gas / 2;
+            interrupter.gas.set(Some(gas - share));
+            share
+        });
+        ExecLimits {
+            gas,
+            deadline: interrupter.deadline.get(),
+            watchdog: interrupter.watchdog.get(),
+        }
+    }
+
+    /// Apply the limits inherited from the parent, before the worker runs any code.
+    pub(crate) fn set_exec_limits(&self, limits: ExecLimits) {
+        let interrupter = &self.runtime.interrupter;
+        interrupter.gas.set(limits.gas);
+        interrupter.deadline.set(limits.deadline);
+        interrupter.watchdog.set(limits.watchdog);
+    }
+
+    /// Watch for a script or callback running longer than `threshold` without yielding to the
+    /// event loop, or stop watching if None.
+    ///
+    /// A stalled script is logged with its stack once. With `interrupt`, it's also interrupted,
+    /// failing with `ExecError::Stalled`, so a busy loop can't wedge the whole service.
+    pub fn set_watchdog(&self, threshold: Option<Duration>, interrupt: bool) {
+        let watchdog = threshold.map(|threshold| Watchdog {
+            threshold,
+            interrupt,
+        });
+        self.runtime.interrupter.watchdog.set(watchdog);
+    }
+
+    /// Run `fut` with a wall-clock deadline.
+    ///
+    /// JS code running past the deadline is interrupted with `ExecError::Timeout`. If `fut` is
+    /// still pending at the deadline, all pending tasks of the service are cancelled and
+    /// `ExecError::Timeout` is returned.
+    pub async fn run_with_deadline<F: Future>(
+        &self,
+        timeout: Duration,
+        fut: F,
+    ) -> Result<F::Output, ExecError> {
+        let interrupter = &self.runtime.interrupter;
+        interrupter.deadline.set(Some(monotonic_now() + timeout));
+        let result = tokio::select! {
+            output = fut => Ok(output),
+            _ = sleep(timeout) => Err(ExecError::Timeout),
+        };
+        interrupter.deadline.set(None);
+        if result.is_err() {
+            self.close_all();
+        }
+        result
+    }
+
+    /// Turn the error of an interrupted execution into the corresponding `ExecError`.
+    pub(crate) fn check_interrupted<T>(&self, result: Result<T, String>) -> Result<T, ExecError> {
+        let interruption = self.runtime.interrupter.take_interruption();
+        match (result, interruption) {
+            (Err(_), Some(Interruption::OutOfGas)) => Err(ExecError::OutOfGas),
+            (Err(_), Some(Interruption::Timeout)) => Err(ExecError::Timeout),
+            (Err(_), Some(Interruption::Stalled)) => Err(ExecError::Stalled),
+            (result, _) => Ok(result.map_err(|err| self.map_error_stack(err))?),
+        }
+    }
+}
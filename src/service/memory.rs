@@ -0,0 +1,96 @@
+use super::*;
+use js::ToJsValue;
+
+/// QuickJS heap statistics, sizes are in bytes.
+#[derive(Debug, Clone, Default, ToJsValue)]
+#[qjsbind(rename_all = "camelCase")]
+pub struct MemoryUsage {
+    /// Bytes currently allocated by the QuickJS heap.
+    pub malloc_size: i64,
+    /// The memory limit of the runtime, -1 if unlimited.
+    pub malloc_limit: i64,
+    pub malloc_count: i64,
+    /// Bytes used by the JS objects, strings and functions.
+    pub memory_used_size: i64,
+    pub memory_used_count: i64,
+    pub atom_count: i64,
+    pub atom_size: i64,
+    pub str_count: i64,
+    pub str_size: i64,
+    pub obj_count: i64,
+    pub obj_size: i64,
+    pub prop_count: i64,
+    pub prop_size: i64,
+    pub shape_count: i64,
+    pub shape_size: i64,
+    pub js_func_count: i64,
+    pub js_func_size: i64,
+    pub js_func_code_size: i64,
+    pub c_func_count: i64,
+    pub array_count: i64,
+    pub fast_array_count: i64,
+    pub fast_array_elements: i64,
+    pub binary_object_count: i64,
+    pub binary_object_size: i64,
+}
+
+impl From<&c::JSMemoryUsage> for MemoryUsage {
+    fn from(usage: &c::JSMemoryUsage) -> Self {
+        Self {
+            malloc_size: usage.malloc_size as _,
+            malloc_limit: usage.malloc_limit as _,
+            malloc_count: usage.malloc_count as _,
+            memory_used_size: usage.memory_used_size as _,
+            memory_used_count: usage.memory_used_count as _,
+            atom_count: usage.atom_count as _,
+            atom_size: usage.atom_size as _,
+            str_count: usage.str_count as _,
+            str_size: usage.str_size as _,
+            obj_count: usage.obj_count as _,
+            obj_size: usage.obj_size as _,
+            prop_count: usage.prop_count as _,
+            prop_size: usage.prop_size as _,
+            shape_count: usage.shape_count as _,
+            shape_size: usage.shape_size as _,
+            js_func_count: usage.js_func_count as _,
+            js_func_size: usage.js_func_size as _,
+            js_func_code_size: usage.js_func_code_size as _,
+            c_func_count: usage.c_func_count as _,
+            array_count: usage.array_count as _,
+            fast_array_count: usage.fast_array_count as _,
+            fast_array_elements: usage.fast_array_elements as _,
+            binary_object_count: usage.binary_object_count as _,
+            binary_object_size: usage.binary_object_size as _,
+        }
+    }
+}
+
+impl Service {
+    /// Compute the heap statistics of the QuickJS runtime.
+    ///
+    /// This walks the whole heap, so avoid calling it in hot paths.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage: c::JSMemoryUsage = unsafe { core::mem::zeroed() };
+        unsafe {
+            let rt = c::JS_GetRuntime(self.context().as_ptr());
+            c::JS_ComputeMemoryUsage(rt, &mut usage);
+        }
+        (&usage).into()
+    }
+
+    /// Run a full garbage collection, freeing the unreachable objects including cycles.
+    pub fn run_gc(&self) {
+        unsafe { c::JS_RunGC(c::JS_GetRuntime(self.context().as_ptr())) };
+    }
+
+    /// Define `globalThis.gc()` and `Sidevm.runGc()` running a garbage collection, like node's
+    /// `--expose-gc`. The scripts can't trigger collections otherwise.
+    pub fn expose_gc(&self) -> Result<()> {
+        let global = js::get_global(self.context());
+        let ns = global.get_property("Sidevm")?;
+        crate::host_functions::setup_gc(&ns)?;
+        let run_gc = ns.get_property("runGc")?;
+        global.set_property("gc", &run_gc)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use super::*;
+use crate::runtime::monotonic_now;
+use core::ffi::c_int;
+use js::ToJsValue;
+
+/// Counters of what the scripts of a service did, see [`Service::metrics`].
+#[derive(Debug, Clone, Default, ToJsValue)]
+#[qjsbind(rename_all = "camelCase")]
+pub struct Metrics {
+    /// How many times each function of the `Sidevm` namespace was called, by name. Functions
+    /// never called are left out.
+    pub host_calls: Vec<(String, u64)>,
+    /// Request body bytes sent by http requests.
+    pub bytes_sent: u64,
+    /// Response body bytes received by http requests.
+    pub bytes_received: u64,
+    /// The number of spawned tasks that finished or were cancelled.
+    pub tasks_finished: u64,
+    /// The total time the finished tasks were pending, in milliseconds.
+    pub task_time_ms: f64,
+    /// The time spent running scripts, modules and callbacks, in milliseconds.
+    pub exec_time_ms: f64,
+}
+
+/// The counters behind [`Metrics`].
+#[derive(Default)]
+pub(super) struct Counters {
+    /// Whether the host calls are counted and `Sidevm.metrics` is defined.
+    enabled: bool,
+    host_fn_names: Vec<String>,
+    host_calls: Vec<u64>,
+    bytes_sent: u64,
+    bytes_received: u64,
+    tasks_finished: u64,
+    task_time: Duration,
+    exec_time: Duration,
+    /// Nesting depth of the exec timers, only the outermost one counts.
+    exec_depth: u32,
+}
+
+impl Counters {
+    pub(super) fn new(enabled: bool, host_fn_names: Vec<String>) -> Self {
+        Self {
+            enabled,
+            host_calls: vec![0; host_fn_names.len()],
+            host_fn_names,
+            ..Default::default()
+        }
+    }
+}
+
+/// Counts the JS execution time until dropped.
+pub(crate) struct ExecTimer<'a> {
+    service: &'a Service,
+    start: Duration,
+}
+
+impl Drop for ExecTimer<'_> {
+    fn drop(&mut self) {
+        let mut state = self.service.state.borrow_mut();
+        let counters = &mut state.metrics;
+        counters.exec_depth -= 1;
+        if counters.exec_depth == 0 {
+            counters.exec_time += monotonic_now().saturating_sub(self.start);
+            self.service.runtime.interrupter.exec_finished();
+        }
+    }
+}
+
+impl Service {
+    /// Whether the service was created with [`Service::new_ref_with_metrics`] enabled.
+    pub fn metrics_enabled(&self) -> bool {
+        self.state.borrow().metrics.enabled
+    }
+
+    /// A snapshot of the counters of the service. The host calls are only counted if
+    /// [`Service::metrics_enabled`].
+    pub fn metrics(&self) -> Metrics {
+        let state = self.state.borrow();
+        let counters = &state.metrics;
+        Metrics {
+            host_calls: counters
+                .host_fn_names
+                .iter()
+                .zip(&counters.host_calls)
+                .filter(|(_, calls)| **calls > 0)
+                .map(|(name, calls)| (name.clone(), *calls))
+                .collect(),
+            bytes_sent: counters.bytes_sent,
+            bytes_received: counters.bytes_received,
+            tasks_finished: counters.tasks_finished,
+            task_time_ms: counters.task_time.as_secs_f64() * 1000.0,
+            exec_time_ms: counters.exec_time.as_secs_f64() * 1000.0,
+        }
+    }
+
+    /// Define `globalThis.__metrics()` returning the [`Metrics`] of the service, which must have
+    /// metrics enabled.
+    pub fn expose_metrics(&self) -> Result<()> {
+        if !self.metrics_enabled() {
+            anyhow::bail!("The metrics of the service are not enabled");
+        }
+        let global = js::get_global(self.context());
+        let metrics = global.get_property("Sidevm")?.get_property("metrics")?;
+        global.set_property("__metrics", &metrics)?;
+        Ok(())
+    }
+
+    pub(crate) fn exec_timer(&self) -> ExecTimer<'_> {
+        let mut state = self.state.borrow_mut();
+        state.metrics.exec_depth += 1;
+        if state.metrics.exec_depth == 1 {
+            self.runtime.interrupter.exec_started();
+        }
+        drop(state);
+        ExecTimer {
+            service: self,
+            start: monotonic_now(),
+        }
+    }
+
+    pub(crate) fn count_http_bytes(&self, sent: u64, received: u64) {
+        let mut state = self.state.borrow_mut();
+        state.metrics.bytes_sent += sent;
+        state.metrics.bytes_received += received;
+    }
+
+    pub(crate) fn count_task(&self, pending: Duration) {
+        let mut state = self.state.borrow_mut();
+        state.metrics.tasks_finished += 1;
+        state.metrics.task_time += pending;
+    }
+
+    /// Count a call of the host function `index`, returning the span to run it in.
+    fn count_host_call(&self, index: usize) -> tracing::Span {
+        let mut state = self.state.borrow_mut();
+        let counters = &mut state.metrics;
+        if let Some(calls) = counters.host_calls.get_mut(index) {
+            *calls += 1;
+        }
+        let name = counters
+            .host_fn_names
+            .get(index)
+            .map_or("", |name| name.as_str());
+        tracing::debug_span!("host_call", name)
+    }
+}
+
+/// Define `Sidevm.metrics` and replace the functions of the `Sidevm` namespace with wrappers
+/// counting their calls.
+///
+/// Must run before the bootcode, which keeps references to the host functions. Returns the names
+/// of the wrapped functions, indexed by the magic number of their wrapper.
+pub(super) fn count_host_calls(ctx: &js::Context) -> Result<Vec<String>> {
+    let ns = js::get_global(ctx).get_property("Sidevm")?;
+    crate::host_functions::setup_metrics(&ns)?;
+    let mut names = vec![];
+    for name in crate::host_functions::own_property_names(&ns, ctx)? {
+        let func = ns.get_property(&name)?;
+        if !func.is_function() {
+            continue;
+        }
+        ns.set_property(&name, &counted_fn(ctx, names.len(), &func)?)?;
+        names.push(name);
+    }
+    Ok(names)
+}
+
+fn counted_fn(ctx: &js::Context, index: usize, func: &js::Value) -> Result<js::Value> {
+    // The index of the name is passed back as the magic number, the wrapped function as the data
+    let mut data = [*func.raw_value()];
+    let counted = unsafe {
+        c::JS_NewCFunctionData(
+            ctx.as_ptr(),
+            Some(call_counted),
+            0,
+            index as c_int,
+            1,
+            data.as_mut_ptr(),
+        )
+    };
+    if c::is_exception(counted) {
+        anyhow::bail!("Failed to create function: {}", ctx.get_exception_str());
+    }
+    Ok(js::Value::new_moved(ctx, counted))
+}
+
+unsafe extern "C" fn call_counted(
+    ctx: *mut c::JSContext,
+    this: c::JSValue,
+    argc: c_int,
+    argv: *mut c::JSValue,
+    magic: c_int,
+    data: *mut c::JSValue,
+) -> c::JSValue {
+    // The service is not reachable yet while the bootcode runs
+    let span = match module::ctx_service(ctx) {
+        Some(service) => service.count_host_call(magic as usize),
+        None => tracing::Span::none(),
+    };
+    let _span = span.entered();
+    c::JS_Call(ctx, *data, this, argc, argv)
+}
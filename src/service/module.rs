@@ -0,0 +1,275 @@
+use alloc::ffi::CString;
+use core::ffi::{c_char, c_void, CStr};
+
+use super::*;
+
+/// The code of an ES module.
+pub enum ModuleSource {
+    Source(String),
+    /// Module bytecode prefixed with the header written by `bytecode::compile`.
+    Bytecode(Vec<u8>),
+}
+
+/// Resolves and loads the modules imported by `import` statements and dynamic `import()`.
+///
+/// Loading is synchronous, so a resolver backed by a remote fetcher has to prefetch the modules.
+pub trait ModuleResolver {
+    /// Map the `specifier` imported from the module `base` to a module name.
+    fn normalize(&self, base: &str, specifier: &str) -> String {
+        normalize_path(base, specifier)
+    }
+
+    /// Load the module named `name`. Returns None if the module is unknown.
+    fn load(&self, name: &str) -> Option<ModuleSource>;
+}
+
+/// Resolve relative specifiers (`./` and `../`) against the directory of `base`. Other specifiers
+/// are returned as is.
+pub fn normalize_path(base: &str, specifier: &str) -> String {
+    if !specifier.starts_with("./") && !specifier.starts_with("../") {
+        return specifier.into();
+    }
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for part in specifier.split('/') {
+        match part {
+            "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+pub(crate) fn install_module_loader(ctx: &js::Context) {
+    unsafe {
+        let rt = c::JS_GetRuntime(ctx.as_ptr());
+        c::JS_SetModuleLoaderFunc(
+            rt,
+            Some(js_module_normalize),
+            Some(js_module_loader),
+            core::ptr::null_mut(),
+        );
+    }
+}
+
+impl Service {
+    /// Register an in-memory module that can be imported by `name`.
+    pub fn add_module(&self, name: impl Into<String>, source: ModuleSource) {
+        self.state.borrow_mut().modules.insert(name.into(), source);
+    }
+
+    /// Set the resolver used for modules not registered by `add_module`.
+    pub fn set_module_resolver(&self, resolver: impl ModuleResolver + 'static) {
+        self.state.borrow_mut().module_resolver = Some(Box::new(resolver));
+    }
+
+    /// Evaluate `source` as an ES module named `name`.
+    pub fn exec_module(&self, name: &str, source: &str) -> Result<OwnedJsValue, ExecError> {
+        let _span = tracing::info_span!("exec_module", name).entered();
+        let _call = self.enter_call();
+        let _timer = self.exec_timer();
+        let source = &*self.prepare_source(source, name)?;
+        self.register_source_map(name, source);
+        let ctx = self.context();
+        let c_source = CString::new(source).map_err(|_| "Module source contains NUL")?;
+        let c_name = CString::new(name).map_err(|_| "Module name contains NUL")?;
+        let ret = unsafe {
+            c::JS_Eval(
+                ctx.as_ptr(),
+                c_source.as_ptr(),
+                source.len() as _,
+                c_name.as_ptr(),
+                c::JS_EVAL_TYPE_MODULE as _,
+            )
+        };
+        if c::is_exception(ret) {
+            let result = self.check_interrupted(Err(ctx.get_exception_str()));
+            self.pump_jobs();
+            return result;
+        }
+        self.record(|| super::snapshot::Recorded::Module {
+            name: name.into(),
+            source: source.into(),
+        });
+        let result = js::Value::new_moved(ctx, ret)
+            .try_into()
+            .map_err(|err: ValueError| ExecError::Script(err.to_string()));
+        self.pump_jobs();
+        result
+    }
+
+    /// Keep the remote modules fetched by `import_remote` in `dir`, reusing them while they match
+    /// the integrity they are imported with.
+    #[cfg(feature = "js-remote-module")]
+    pub fn set_module_cache_dir(&self, dir: Option<std::path::PathBuf>) {
+        self.state.borrow_mut().module_cache_dir = dir;
+    }
+
+    #[cfg(feature = "js-remote-module")]
+    pub fn module_cache_dir(&self) -> Option<std::path::PathBuf> {
+        self.state.borrow().module_cache_dir.clone()
+    }
+
+    /// Fetch the module at the https `url`, verify it against the subresource `integrity`, e.g.
+    /// `sha384-<base64>`, and register it so that `import "<url>"` loads it.
+    ///
+    /// Module loading is synchronous, so remote modules have to be imported this way, or with
+    /// `Sidevm.importRemote` from JS, before the scripts importing them run.
+    /// The relative imports of a remote module resolve against its url, the modules they name
+    /// have to be imported this way too, each with its own integrity.
+    #[cfg(feature = "js-remote-module")]
+    pub async fn import_remote(&self, url: &str, integrity: &str) -> anyhow::Result<()> {
+        let fetch = crate::host_functions::RemoteFetch::new(self);
+        let source = fetch.fetch(url, integrity).await?;
+        self.add_module(url, ModuleSource::Source(source));
+        Ok(())
+    }
+
+    fn normalize_module_name(&self, base: &str, specifier: &str) -> String {
+        if let Some(entry) = self.package_entry(specifier) {
+            return entry;
+        }
+        #[cfg(feature = "js-remote-module")]
+        if let Some(url) = resolve_remote(base, specifier) {
+            return url;
+        }
+        match &self.state.borrow().module_resolver {
+            Some(resolver) => resolver.normalize(base, specifier),
+            None => normalize_path(base, specifier),
+        }
+    }
+
+    fn load_module(&self, name: &str) -> Option<ModuleSource> {
+        let state = self.state.borrow();
+        if let Some(source) = state.modules.get(name) {
+            return Some(match source {
+                ModuleSource::Source(src) => ModuleSource::Source(src.clone()),
+                ModuleSource::Bytecode(bytes) => ModuleSource::Bytecode(bytes.clone()),
+            });
+        }
+        state.module_resolver.as_ref()?.load(name)
+    }
+}
+
+/// Resolve the relative `specifier` imported by the remote module `base` against its url.
+#[cfg(feature = "js-remote-module")]
+fn resolve_remote(base: &str, specifier: &str) -> Option<String> {
+    if !["./", "../", "/"]
+        .iter()
+        .any(|prefix| specifier.starts_with(prefix))
+    {
+        return None;
+    }
+    let base = ::url::Url::parse(base).ok()?;
+    if base.scheme() != "https" {
+        return None;
+    }
+    base.join(specifier).ok().map(String::from)
+}
+
+pub(super) fn ctx_service(ctx: *mut c::JSContext) -> Option<ServiceRef> {
+    let ptr = unsafe { c::JS_GetContextOpaque(ctx) } as *mut ServiceWeakRef;
+    if ptr.is_null() {
+        return None;
+    }
+    unsafe { (*ptr).upgrade() }
+}
+
+unsafe extern "C" fn js_module_normalize(
+    ctx: *mut c::JSContext,
+    base: *const c_char,
+    name: *const c_char,
+    _opaque: *mut c_void,
+) -> *mut c_char {
+    let base = CStr::from_ptr(base).to_string_lossy();
+    let name = CStr::from_ptr(name).to_string_lossy();
+    let normalized = match ctx_service(ctx) {
+        Some(service) => service.normalize_module_name(&base, &name),
+        None => normalize_path(&base, &name),
+    };
+    c::js_strndup(ctx, normalized.as_ptr() as _, normalized.len() as _)
+}
+
+unsafe extern "C" fn js_module_loader(
+    ctx: *mut c::JSContext,
+    name: *const c_char,
+    _opaque: *mut c_void,
+) -> *mut c::JSModuleDef {
+    let module_name = CStr::from_ptr(name).to_string_lossy();
+    let service = ctx_service(ctx);
+    let source = service
+        .as_ref()
+        .and_then(|service| service.load_module(&module_name));
+    let value = match source {
+        Some(ModuleSource::Source(src)) => {
+            let src = match &service {
+                Some(service) => match service.prepare_source(&src, &module_name) {
+                    Ok(src) => src.into_owned(),
+                    Err(err) => return throw_type_error(ctx, &err),
+                },
+                None => src,
+            };
+            if let Some(service) = &service {
+                service.register_source_map(&module_name, &src);
+            }
+            let Ok(c_src) = CString::new(src.as_str()) else {
+                return throw_load_error(ctx, name);
+            };
+            c::JS_Eval(
+                ctx,
+                c_src.as_ptr(),
+                src.len() as _,
+                name,
+                (c::JS_EVAL_TYPE_MODULE | c::JS_EVAL_FLAG_COMPILE_ONLY) as _,
+            )
+        }
+        Some(ModuleSource::Bytecode(bytes)) => {
+            let bytes = match crate::bytecode::check(&bytes) {
+                Ok(bytes) => bytes,
+                Err(err) => return throw_type_error(ctx, &err),
+            };
+            c::JS_ReadObject(
+                ctx,
+                bytes.as_ptr(),
+                bytes.len() as _,
+                c::JS_READ_OBJ_BYTECODE as _,
+            )
+        }
+        None => return throw_load_error(ctx, name),
+    };
+    if c::is_exception(value) {
+        return core::ptr::null_mut();
+    }
+    // The compiled module is kept alive by the runtime, see js_module_loader in quickjs-libc.
+    let module = c::JS_VALUE_GET_PTR(value) as *mut c::JSModuleDef;
+    c::JS_FreeValue(ctx, value);
+    module
+}
+
+unsafe fn throw_load_error(ctx: *mut c::JSContext, name: *const c_char) -> *mut c::JSModuleDef {
+    let is_remote = CStr::from_ptr(name).to_bytes().starts_with(b"https://");
+    if is_remote {
+        c::JS_ThrowReferenceError(
+            ctx,
+            b"remote module '%s' must be fetched first with Sidevm.importRemote\0".as_ptr()
+                as *const c_char,
+            name,
+        );
+        return core::ptr::null_mut();
+    }
+    c::JS_ThrowReferenceError(
+        ctx,
+        b"could not load module '%s'\0".as_ptr() as *const c_char,
+        name,
+    );
+    core::ptr::null_mut()
+}
+
+unsafe fn throw_type_error(ctx: *mut c::JSContext, err: &str) -> *mut c::JSModuleDef {
+    let err = CString::new(err).unwrap_or_default();
+    c::JS_ThrowTypeError(ctx, b"%s\0".as_ptr() as *const c_char, err.as_ptr());
+    core::ptr::null_mut()
+}
@@ -0,0 +1,128 @@
+use super::*;
+use std::path::{Path, PathBuf};
+
+/// Resolves bare specifiers like `lodash-es` or `@scure/base` against the packages in a directory
+/// laid out like `node_modules`. The modules are named by their canonical file paths, only the
+/// files inside the directory can be loaded.
+pub struct ModuleDir {
+    root: PathBuf,
+}
+
+impl ModuleDir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let root = root.canonicalize().unwrap_or(root);
+        Self { root }
+    }
+
+    /// The canonical path of `file` if it is inside the root once the symlinks are followed.
+    fn contained(&self, file: &Path) -> Option<PathBuf> {
+        let file = file.canonicalize().ok()?;
+        file.starts_with(&self.root).then_some(file)
+    }
+
+    /// The module name of `file`, left as is if it is outside the root for `load` to reject it.
+    fn module_name(&self, file: PathBuf) -> String {
+        self.contained(&file)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The entry file of the package in `dir`, from the `module` or `main` field of its
+    /// package.json.
+    fn package_entry(dir: &Path) -> PathBuf {
+        let manifest = std::fs::read_to_string(dir.join("package.json"))
+            .ok()
+            .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok());
+        let entry = manifest.as_ref().and_then(|manifest| {
+            ["module", "main"]
+                .iter()
+                .find_map(|field| manifest.get(field)?.as_str())
+        });
+        dir.join(entry.unwrap_or("index.js"))
+    }
+}
+
+/// Find the file of `path`, trying the `.js` and `.mjs` extensions and `index.js`.
+fn resolve_file(path: PathBuf) -> PathBuf {
+    if path.is_file() {
+        return path;
+    }
+    for ext in ["js", "mjs"] {
+        let mut file = path.clone().into_os_string();
+        file.push(".");
+        file.push(ext);
+        let file = PathBuf::from(file);
+        if file.is_file() {
+            return file;
+        }
+    }
+    let index = path.join("index.js");
+    if index.is_file() {
+        return index;
+    }
+    path
+}
+
+/// Split `@scope/name/sub/path` or `name/sub/path` into the package name and the subpath.
+fn split_package(specifier: &str) -> (&str, &str) {
+    let name_end = if specifier.starts_with('@') {
+        specifier
+            .match_indices('/')
+            .nth(1)
+            .map_or(specifier.len(), |(i, _)| i)
+    } else {
+        specifier.find('/').unwrap_or(specifier.len())
+    };
+    let (name, subpath) = specifier.split_at(name_end);
+    (name, subpath.trim_start_matches('/'))
+}
+
+impl ModuleResolver for ModuleDir {
+    fn normalize(&self, base: &str, specifier: &str) -> String {
+        if specifier.starts_with("./") || specifier.starts_with("../") {
+            let path = normalize_path(base, specifier);
+            return self.module_name(resolve_file(path.into()));
+        }
+        if specifier.starts_with('/') {
+            return self.module_name(specifier.into());
+        }
+        let (name, subpath) = split_package(specifier);
+        let dir = self.root.join(name);
+        if !dir.is_dir() {
+            // Left as is for the modules registered by `add_module`
+            return specifier.into();
+        }
+        let file = if subpath.is_empty() {
+            Self::package_entry(&dir)
+        } else {
+            dir.join(subpath)
+        };
+        self.module_name(resolve_file(file))
+    }
+
+    fn load(&self, name: &str) -> Option<ModuleSource> {
+        let file = Path::new(name).canonicalize().ok()?;
+        if !file.starts_with(&self.root) {
+            warn!("Module {name} is outside of {}", self.root.display());
+            return None;
+        }
+        std::fs::read_to_string(file).ok().map(ModuleSource::Source)
+    }
+}
+
+impl Service {
+    /// Make `import "<name>"` load the module registered as `entry`, e.g. `lodash-es` as
+    /// `lodash-es/lodash.js`, so that the relative imports of the package resolve under its name.
+    pub fn add_package(&self, name: impl Into<String>, entry: impl Into<String>) {
+        self.state
+            .borrow_mut()
+            .packages
+            .insert(name.into(), entry.into());
+    }
+
+    pub(crate) fn package_entry(&self, specifier: &str) -> Option<String> {
+        self.state.borrow().packages.get(specifier).cloned()
+    }
+}
@@ -0,0 +1,187 @@
+use super::*;
+use crate::runtime::monotonic_now;
+use js::FromJsValue;
+use serde_json::json;
+
+/// A function on the JS stack, as in the `stack` of an Error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Frame {
+    function_name: String,
+    url: String,
+    /// 1-based, 0 if unknown.
+    line: u32,
+}
+
+impl Frame {
+    /// Parse a line of the `stack` of an Error, `    at name (file:line)` in QuickJS.
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim().strip_prefix("at ")?;
+        let (name, location) = match line.strip_suffix(')').and_then(|l| l.split_once(" (")) {
+            Some((name, location)) => (name, location),
+            None => ("", line),
+        };
+        let (url, line) = match location.split_once(':') {
+            Some((url, rest)) => {
+                let line = rest.split(':').next().unwrap_or_default();
+                (url, line.parse().unwrap_or_default())
+            }
+            None => (location, 0),
+        };
+        let function_name = match name {
+            "" | "<anonymous>" => "(anonymous)",
+            name => name,
+        };
+        Some(Self {
+            function_name: function_name.into(),
+            url: url.into(),
+            line,
+        })
+    }
+}
+
+struct Node {
+    frame: Frame,
+    children: Vec<usize>,
+    hit_count: u64,
+}
+
+/// Samples the JS stack while the scripts run, see [`Service::start_profiling`].
+pub(crate) struct Profiler {
+    interval: Duration,
+    start_time: Duration,
+    last_sample: Duration,
+    /// The call tree, the root node first.
+    nodes: Vec<Node>,
+    /// The leaf node of each sample and the time since the previous sample.
+    samples: Vec<(usize, Duration)>,
+}
+
+impl Profiler {
+    fn new(interval: Duration) -> Self {
+        let now = monotonic_now();
+        let root = Node {
+            frame: Frame {
+                function_name: "(root)".into(),
+                url: String::new(),
+                line: 0,
+            },
+            children: vec![],
+            hit_count: 0,
+        };
+        Self {
+            interval,
+            start_time: now,
+            last_sample: now,
+            nodes: vec![root],
+            samples: vec![],
+        }
+    }
+
+    /// Record the stack if the interval has passed since the last sample.
+    pub(crate) fn poll(&mut self, ctx: &js::Context) {
+        let now = monotonic_now();
+        if now < self.last_sample + self.interval {
+            return;
+        }
+        let Some(stack) = capture_stack(ctx) else {
+            return;
+        };
+        // The outermost frame comes last
+        let mut node = 0;
+        for frame in stack.lines().rev().filter_map(Frame::parse) {
+            node = self.child(node, frame);
+        }
+        self.nodes[node].hit_count += 1;
+        self.samples.push((node, now - self.last_sample));
+        self.last_sample = now;
+    }
+
+    fn child(&mut self, parent: usize, frame: Frame) -> usize {
+        let existing = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].frame == frame);
+        if let Some(child) = existing {
+            return child;
+        }
+        let child = self.nodes.len();
+        self.nodes.push(Node {
+            frame,
+            children: vec![],
+            hit_count: 0,
+        });
+        self.nodes[parent].children.push(child);
+        child
+    }
+
+    /// The profile in the `.cpuprofile` format of Chrome DevTools.
+    fn to_cpuprofile(&self) -> String {
+        let micros = |d: Duration| d.as_micros() as u64;
+        // Node ids start from 1
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(id, node)| {
+                json!({
+                    "id": id + 1,
+                    "callFrame": {
+                        "functionName": node.frame.function_name,
+                        "scriptId": "0",
+                        "url": node.frame.url,
+                        "lineNumber": node.frame.line as i64 - 1,
+                        "columnNumber": -1,
+                    },
+                    "hitCount": node.hit_count,
+                    "children": node.children.iter().map(|child| child + 1).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        let samples: Vec<_> = self.samples.iter().map(|(node, _)| node + 1).collect();
+        let time_deltas: Vec<_> = self
+            .samples
+            .iter()
+            .map(|(_, delta)| micros(*delta))
+            .collect();
+        json!({
+            "nodes": nodes,
+            "startTime": micros(self.start_time),
+            "endTime": micros(monotonic_now()),
+            "samples": samples,
+            "timeDeltas": time_deltas,
+        })
+        .to_string()
+    }
+}
+
+/// The `stack` of a new error, listing the JS frames currently running.
+///
+/// The error is created by the engine rather than by calling the global `Error`, which the
+/// scripts can replace.
+pub(super) fn capture_stack(ctx: &js::Context) -> Option<String> {
+    let err = unsafe {
+        c::JS_ThrowInternalError(ctx.as_ptr(), b"\0".as_ptr() as *const core::ffi::c_char);
+        // Take the exception at once, it must not be left to the interrupted code
+        c::JS_GetException(ctx.as_ptr())
+    };
+    let err = js::Value::new_moved(ctx, err);
+    String::from_js_value(err.get_property("stack").ok()?).ok()
+}
+
+impl Service {
+    /// Start sampling the JS stack every `interval` while the scripts run.
+    ///
+    /// The stack can only be sampled when QuickJS polls the interrupt handler, so the actual
+    /// interval is coarser for code with few branches and calls.
+    pub fn start_profiling(&self, interval: Duration) {
+        *self.runtime.interrupter.profiler.borrow_mut() = Some(Profiler::new(interval));
+    }
+
+    /// Stop profiling, returning the profile in the `.cpuprofile` JSON format that Chrome
+    /// DevTools and VS Code open. None if not profiling.
+    pub fn stop_profiling(&self) -> Option<String> {
+        let profiler = self.runtime.interrupter.profiler.borrow_mut().take()?;
+        Some(profiler.to_cpuprofile())
+    }
+}
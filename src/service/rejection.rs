@@ -0,0 +1,79 @@
+use core::ffi::{c_int, c_void};
+
+use super::*;
+
+/// A rejected promise without a handler attached yet.
+pub(crate) struct UnhandledRejection {
+    promise: OwnedJsValue,
+    reason: String,
+}
+
+pub(crate) type RejectionHandler = Rc<dyn Fn(&str)>;
+
+pub(crate) fn install_rejection_tracker(ctx: &js::Context) {
+    unsafe {
+        let rt = c::JS_GetRuntime(ctx.as_ptr());
+        c::JS_SetHostPromiseRejectionTracker(
+            rt,
+            Some(js_promise_rejection_tracker),
+            core::ptr::null_mut(),
+        );
+    }
+}
+
+unsafe extern "C" fn js_promise_rejection_tracker(
+    ctx: *mut c::JSContext,
+    promise: c::JSValue,
+    reason: c::JSValue,
+    is_handled: c_int,
+    _opaque: *mut c_void,
+) {
+    let Some(service) = module::ctx_service(ctx) else {
+        return;
+    };
+    service.track_rejection(promise, reason, is_handled != 0);
+}
+
+impl Service {
+    /// Set the handler for promise rejections still unhandled once the job queue is drained.
+    ///
+    /// By default they are logged as errors.
+    pub fn set_unhandled_rejection_handler(&self, handler: impl Fn(&str) + 'static) {
+        self.state.borrow_mut().rejection_handler = Some(Rc::new(handler));
+    }
+
+    fn track_rejection(&self, promise: c::JSValue, reason: c::JSValue, handled: bool) {
+        let is_same = |value: &OwnedJsValue| {
+            c::JS_VALUE_GET_PTR(*value.value()) == c::JS_VALUE_GET_PTR(promise)
+        };
+        if handled {
+            self.state
+                .borrow_mut()
+                .unhandled_rejections
+                .retain(|rejection| !is_same(&rejection.promise));
+            return;
+        }
+        let reason = js::Value::new_cloned(self.context(), reason);
+        let reason = qjs_extensions::repr::print(&[reason], &Default::default());
+        let reason = self.map_error_stack(reason);
+        let promise = self.runtime.dup_value(promise);
+        self.state
+            .borrow_mut()
+            .unhandled_rejections
+            .push(UnhandledRejection { promise, reason });
+    }
+
+    pub(crate) fn report_unhandled_rejections(&self) {
+        let rejections = core::mem::take(&mut self.state.borrow_mut().unhandled_rejections);
+        if rejections.is_empty() {
+            return;
+        }
+        let handler = self.state.borrow().rejection_handler.clone();
+        for rejection in rejections {
+            match &handler {
+                Some(handler) => handler(&rejection.reason),
+                None => error!("Unhandled promise rejection: {}", rejection.reason),
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use core::time::Duration;
 use js::{Error as ValueError, FromJsValue};
 
 use super::*;
@@ -117,14 +118,82 @@ impl OwnedJsValue {
 
 pub struct Resource {
     pub js_value: OwnedJsValue,
+    /// Arbitrary host side data attached to the resource, e.g. a channel to the spawned task.
+    pub data: Option<Box<dyn Any>>,
     _cancel_token: Option<Box<dyn Any>>,
+    kind: &'static str,
+    created_at: Duration,
 }
 
 impl Resource {
     pub fn new(js_value: OwnedJsValue, cancel_token: Option<Box<dyn Any>>) -> Self {
         Self {
             js_value,
+            data: None,
             _cancel_token: cancel_token,
+            kind: "resource",
+            created_at: crate::runtime::monotonic_now(),
         }
     }
+
+    pub fn with_kind(self, kind: &'static str) -> Self {
+        Self { kind, ..self }
+    }
+
+    fn info(&self, id: u64) -> TaskInfo {
+        TaskInfo {
+            id,
+            kind: self.kind,
+            age: crate::runtime::monotonic_now().saturating_sub(self.created_at),
+        }
+    }
+}
+
+/// A pending task of a service, see [`Service::tasks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: u64,
+    /// What the task does, e.g. `http_request` or `timer`.
+    pub kind: &'static str,
+    /// The time since the task was started.
+    pub age: Duration,
+}
+
+/// The kind of the tasks run by `fut_gen`, from its name without the `do_` prefix.
+pub(crate) fn task_kind<F>() -> &'static str {
+    let name = core::any::type_name::<F>();
+    let name = name.rsplit("::").next().unwrap_or(name);
+    name.strip_prefix("do_").unwrap_or(name)
+}
+
+impl Service {
+    /// The pending tasks, ordered by id.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        let state = self.state.borrow();
+        state
+            .recources
+            .iter()
+            .map(|(id, resource)| resource.info(*id))
+            .collect()
+    }
+
+    /// The kind of the resource `id`, e.g. `http_request` for the task of `do_http_request`.
+    pub(crate) fn resource_kind(&self, id: u64) -> Option<&'static str> {
+        self.state
+            .borrow()
+            .recources
+            .get(&id)
+            .map(|resource| resource.kind)
+    }
+
+    /// Cancel the task `id`, its callback won't be called anymore. Returns false if there is no
+    /// such task.
+    pub fn cancel_task(&self, id: u64) -> bool {
+        self.remove_resource(id).is_some()
+    }
+
+    /// Cancel all the pending tasks.
+    pub fn cancel_all(&self) {
+        self.close_all();
+    }
 }
@@ -0,0 +1,121 @@
+//! Snapshots of the scripts that warmed up a service.
+//!
+//! QuickJS can't serialize closures, so a snapshot doesn't hold the object graph. It records the
+//! scripts and modules evaluated since [`Service::start_recording`], with the scripts compiled to
+//! bytecode, and [`Service::restore`] replays them without parsing. State created by tasks, e.g.
+//! timer callbacks or http responses, is not part of the snapshot.
+
+use super::*;
+use scale::{Decode, Encode};
+
+const MAGIC: &[u8; 4] = b"QJSS";
+const VERSION: u8 = 1;
+
+const KIND_SCRIPT: u8 = 0;
+const KIND_MODULE: u8 = 1;
+const KIND_MODULE_SOURCE: u8 = 2;
+const KIND_MODULE_BYTECODE: u8 = 3;
+
+/// A successful evaluation recorded for the snapshot.
+pub(crate) enum Recorded {
+    Script(String),
+    /// Bytecode without the version header.
+    Bytecode(Vec<u8>),
+    Module {
+        name: String,
+        source: String,
+    },
+}
+
+/// A snapshot entry as `(kind, name, data)`.
+type Entry = (u8, String, Vec<u8>);
+
+impl Service {
+    /// Record the scripts and modules evaluated from now on, for [`Service::snapshot`].
+    pub fn start_recording(&self) {
+        let mut state = self.state.borrow_mut();
+        if state.recorded.is_none() {
+            state.recorded = Some(Vec::new());
+        }
+    }
+
+    pub(crate) fn record(&self, recorded: impl FnOnce() -> Recorded) {
+        if let Some(list) = &mut self.state.borrow_mut().recorded {
+            list.push(recorded());
+        }
+    }
+
+    /// Serialize the registered modules and the evaluations recorded since `start_recording`.
+    ///
+    /// The snapshot can only be restored by the same engine build.
+    pub fn snapshot(&self) -> Result<Vec<u8>, ExecError> {
+        let state = self.state.borrow();
+        let Some(recorded) = &state.recorded else {
+            return Err("Snapshot requires start_recording to be called first".into());
+        };
+        let mut entries: Vec<Entry> = Vec::new();
+        for (name, source) in &state.modules {
+            entries.push(match source {
+                ModuleSource::Source(src) => {
+                    (KIND_MODULE_SOURCE, name.clone(), src.clone().into_bytes())
+                }
+                ModuleSource::Bytecode(bytes) => {
+                    (KIND_MODULE_BYTECODE, name.clone(), bytes.clone())
+                }
+            });
+        }
+        for item in recorded {
+            entries.push(match item {
+                Recorded::Script(src) => {
+                    let bytecode = crate::bytecode::compile(src, source_map::SCRIPT_NAME)?;
+                    (KIND_SCRIPT, String::new(), bytecode)
+                }
+                Recorded::Bytecode(bytecode) => (
+                    KIND_SCRIPT,
+                    String::new(),
+                    crate::bytecode::with_header(bytecode),
+                ),
+                Recorded::Module { name, source } => {
+                    (KIND_MODULE, name.clone(), source.clone().into_bytes())
+                }
+            });
+        }
+        let mut output = MAGIC.to_vec();
+        output.push(VERSION);
+        entries.encode_to(&mut output);
+        Ok(output)
+    }
+
+    /// Replay a snapshot taken by [`Service::snapshot`], usually on a fresh service.
+    pub fn restore(&self, snapshot: &[u8]) -> Result<(), ExecError> {
+        let Some(rest) = snapshot.strip_prefix(MAGIC) else {
+            return Err("Not a snapshot".into());
+        };
+        let [version, ref body @ ..] = *rest else {
+            return Err("Truncated snapshot".into());
+        };
+        if version != VERSION {
+            return Err(format!("Unsupported snapshot version {version}").into());
+        }
+        let entries = Vec::<Entry>::decode(&mut &body[..])
+            .map_err(|err| format!("Invalid snapshot: {err}"))?;
+        for (kind, name, data) in entries {
+            match kind {
+                KIND_SCRIPT => {
+                    self.exec_bytecode(&data)?;
+                }
+                KIND_MODULE => {
+                    self.exec_module(&name, &utf8(data)?)?;
+                }
+                KIND_MODULE_SOURCE => self.add_module(name, ModuleSource::Source(utf8(data)?)),
+                KIND_MODULE_BYTECODE => self.add_module(name, ModuleSource::Bytecode(data)),
+                _ => return Err(format!("Invalid snapshot: unknown entry kind {kind}").into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn utf8(data: Vec<u8>) -> Result<String, ExecError> {
+    String::from_utf8(data).map_err(|_| "Invalid snapshot: source is not UTF-8".into())
+}
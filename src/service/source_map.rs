@@ -0,0 +1,227 @@
+//! Inline source maps, used to point the locations in error stacks at the original sources.
+
+use alloc::{collections::VecDeque, ffi::CString};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use serde::Deserialize;
+
+use super::*;
+
+/// The file name of the scripts compiled into snapshots. The scripts evaluated by `Service::eval`
+/// are numbered instead, see [`Service::next_script_name`].
+pub(crate) const SCRIPT_NAME: &str = "<script>";
+/// The source maps kept for the stacks, the oldest are dropped beyond.
+const MAX_SOURCE_MAPS: usize = 64;
+
+const INLINE_PREFIX: &str = "//# sourceMappingURL=data:application/json;";
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSourceMap {
+    #[serde(default)]
+    source_root: String,
+    sources: Vec<String>,
+    mappings: String,
+}
+
+#[derive(Clone, Copy)]
+struct Segment {
+    column: u32,
+    source: u32,
+    line: u32,
+    source_column: u32,
+}
+
+pub(crate) struct SourceMap {
+    sources: Vec<String>,
+    /// The segments of each generated line, sorted by column.
+    lines: Vec<Vec<Segment>>,
+}
+
+impl SourceMap {
+    /// Parse the inline source map at the end of `source`, if any.
+    pub(crate) fn from_inline(source: &str) -> Option<Self> {
+        let start = source.rfind(INLINE_PREFIX)? + INLINE_PREFIX.len();
+        let data = source[start..].lines().next()?.trim();
+        // Skip the optional parameters, e.g. `charset=utf-8;base64,`
+        let (_, data) = data.split_once("base64,")?;
+        let json = STANDARD_NO_PAD.decode(data.trim_end_matches('=')).ok()?;
+        let raw: RawSourceMap = serde_json::from_slice(&json).ok()?;
+        Self::parse(raw)
+    }
+
+    fn parse(raw: RawSourceMap) -> Option<Self> {
+        let mut lines = vec![];
+        let (mut source, mut line, mut source_column) = (0i64, 0i64, 0i64);
+        for mappings in raw.mappings.split(';') {
+            let mut segments = vec![];
+            let mut column = 0i64;
+            for segment in mappings.split(',').filter(|s| !s.is_empty()) {
+                let fields = decode_vlq(segment)?;
+                column += fields.first()?;
+                if fields.len() < 4 {
+                    // Generated code without an original location
+                    continue;
+                }
+                source += fields[1];
+                line += fields[2];
+                source_column += fields[3];
+                segments.push(Segment {
+                    column: column.try_into().ok()?,
+                    source: source.try_into().ok()?,
+                    line: line.try_into().ok()?,
+                    source_column: source_column.try_into().ok()?,
+                });
+            }
+            segments.sort_by_key(|s| s.column);
+            lines.push(segments);
+        }
+        let sources = raw
+            .sources
+            .into_iter()
+            .map(|source| {
+                if raw.source_root.is_empty() {
+                    source
+                } else {
+                    format!("{}/{source}", raw.source_root.trim_end_matches('/'))
+                }
+            })
+            .collect();
+        Some(Self { sources, lines })
+    }
+
+    /// Map a generated location to the original `(source, line, column)`, all 1-based.
+    ///
+    /// Without a column the first mapping of the line is used, as old QuickJS only reports lines.
+    fn lookup(&self, line: u32, column: Option<u32>) -> Option<(&str, u32, u32)> {
+        let segments = self.lines.get(line.checked_sub(1)? as usize)?;
+        let segment = match column {
+            Some(column) => {
+                let index = segments.partition_point(|s| s.column < column);
+                segments.get(index.checked_sub(1)?)?
+            }
+            None => segments.first()?,
+        };
+        let source = self.sources.get(segment.source as usize)?;
+        Some((source, segment.line + 1, segment.source_column + 1))
+    }
+}
+
+/// Decode a segment of base64 VLQ encoded signed integers.
+fn decode_vlq(segment: &str) -> Option<Vec<i64>> {
+    let mut values = vec![];
+    let (mut value, mut shift) = (0i64, 0u32);
+    for c in segment.bytes() {
+        let digit = BASE64_ALPHABET.iter().position(|&b| b == c)? as i64;
+        value += (digit & 31) << shift;
+        if digit & 32 != 0 {
+            shift += 5;
+            if shift > 60 {
+                return None;
+            }
+            continue;
+        }
+        let negative = value & 1 != 0;
+        value >>= 1;
+        values.push(if negative { -value } else { value });
+        (value, shift) = (0, 0);
+    }
+    (shift == 0).then_some(values)
+}
+
+/// Split a location like `file:12:5` or `file:12` into its parts.
+fn parse_location(location: &str) -> Option<(&str, u32, Option<u32>)> {
+    let (head, last) = location.rsplit_once(':')?;
+    let last = last.parse().ok()?;
+    if let Some((file, line)) = head.rsplit_once(':') {
+        if let Ok(line) = line.parse() {
+            return Some((file, line, Some(last)));
+        }
+    }
+    Some((head, last, None))
+}
+
+/// Rewrite the location of a stack frame line such as `    at f (file:1:400000)`.
+fn translate_frame(frame: &str, maps: &SourceMaps) -> Option<String> {
+    let rest = frame.trim_start().strip_prefix("at ")?;
+    let (start, end) = if rest.ends_with(')') {
+        (frame.rfind('(')? + 1, frame.len() - 1)
+    } else {
+        (frame.len() - rest.len(), frame.len())
+    };
+    let (file, line, column) = parse_location(&frame[start..end])?;
+    let (_, map) = maps.iter().find(|(name, _)| name == file)?;
+    let (source, line, column) = map.lookup(line, column)?;
+    Some(format!(
+        "{}{source}:{line}:{column}{}",
+        &frame[..start],
+        &frame[end..]
+    ))
+}
+
+/// Evaluate `source` as a global script named `name`.
+pub(crate) fn eval_script(
+    ctx: &js::Context,
+    name: &str,
+    source: &str,
+) -> Result<js::Value, String> {
+    let c_source = CString::new(source).map_err(|_| "Script source contains NUL")?;
+    let c_name = CString::new(name).map_err(|_| "Script name contains NUL")?;
+    let ret = unsafe {
+        c::JS_Eval(
+            ctx.as_ptr(),
+            c_source.as_ptr(),
+            source.len() as _,
+            c_name.as_ptr(),
+            c::JS_EVAL_TYPE_GLOBAL as _,
+        )
+    };
+    if c::is_exception(ret) {
+        return Err(ctx.get_exception_str());
+    }
+    Ok(js::Value::new_moved(ctx, ret))
+}
+
+/// The source maps of the evaluated scripts and modules by file name, oldest first.
+pub(crate) type SourceMaps = VecDeque<(String, SourceMap)>;
+
+impl Service {
+    /// A unique file name for the next script evaluated by `Service::eval`, so each script maps
+    /// its stack frames with its own source map.
+    pub(crate) fn next_script_name(&self) -> String {
+        let mut state = self.state.borrow_mut();
+        state.scripts_evaluated += 1;
+        format!("<script-{}>", state.scripts_evaluated)
+    }
+
+    /// Remember the inline source map of the script or module `name`, if it has one, replacing
+    /// the map of a previous source of the same name.
+    pub(crate) fn register_source_map(&self, name: &str, source: &str) {
+        let mut state = self.state.borrow_mut();
+        let maps = &mut state.source_maps;
+        maps.retain(|(file, _)| file != name);
+        let Some(map) = SourceMap::from_inline(source) else {
+            return;
+        };
+        if maps.len() >= MAX_SOURCE_MAPS {
+            maps.pop_front();
+        }
+        maps.push_back((name.into(), map));
+    }
+
+    /// Point the stack frames in `error` at the original sources of bundled scripts.
+    pub(crate) fn map_error_stack(&self, error: String) -> String {
+        let state = self.state.borrow();
+        if state.source_maps.is_empty() {
+            return error;
+        }
+        error
+            .lines()
+            .map(|line| {
+                translate_frame(line, &state.source_maps).unwrap_or_else(|| line.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
@@ -1,7 +1,9 @@
 use anyhow::{anyhow, Result};
 use log::error;
+use pink_types::js::JsValue;
+use scale::Encode;
 use serde::{Deserialize, Serialize};
-use std::{cell::RefCell, collections::BTreeMap};
+use std::{cell::RefCell, collections::BTreeMap, time::Duration};
 
 use crate::runtime::AccountId;
 use crate::service::{Service, ServiceRef};
@@ -19,6 +21,12 @@ enum Message {
     },
 }
 
+/// The service running the init script, which receives the queries and messages.
+pub const MAIN_SERVICE: &str = "_main";
+
+/// How long the `beforeunload` listeners of a service being reset may keep it alive.
+const SHUTDOWN_BUDGET: Duration = Duration::from_secs(1);
+
 thread_local! {
     static KEEPER: RefCell<ServiceKeeper>  = RefCell::new(ServiceKeeper::new());
 }
@@ -46,9 +54,11 @@ impl ServiceKeeper {
     /// Exceute a script in the service named `name`.
     ///
     /// If the service does not exist, it will be created. If the service already exists, the state
-    /// of the service will keep until the service is reset.
+    /// of the service will keep until the service is reset, and its code hash chains the scripts
+    /// it ran, see `Service::append_entry_code`.
     pub fn exec_script(name: &str, source: &str) {
         let service = KEEPER.with(|keeper| keeper.borrow_mut().get_service_or_default(name));
+        service.append_entry_code(source.as_bytes());
         match service.exec_script(source) {
             Ok(_) => {}
             Err(err) => {
@@ -57,18 +67,34 @@ impl ServiceKeeper {
         }
     }
 
-    pub fn handle_query(_from: Option<AccountId>, query: &[u8]) -> Vec<u8> {
+    /// Handle an incoming query.
+    ///
+    /// Queries other than `ping` are passed to the query handler registered by the `_main`
+    /// service, and the reply is the SCALE encoded `JsValue` it returns.
+    pub async fn handle_query(from: Option<AccountId>, query: Vec<u8>) -> Vec<u8> {
         if query == b"ping" {
             return "pong".into();
         }
-        Vec::new()
+        let reply = match KEEPER.with(|keeper| keeper.borrow().get_service(MAIN_SERVICE)) {
+            Some(service) => crate::host_functions::dispatch_query(service, from, query).await,
+            None => JsValue::Exception("No script is running".into()),
+        };
+        reply.encode()
     }
 
     pub fn handle_message(message: Vec<u8>) {
         let message = match serde_json::from_slice::<Message>(&message) {
             Ok(message) => message,
             Err(err) => {
-                error!("Failed to parse incoming message: {err}");
+                // Not a control message, pass it to the script.
+                let service = KEEPER.with(|keeper| keeper.borrow().get_service(MAIN_SERVICE));
+                match service
+                    .map(|service| crate::host_functions::dispatch_message(service, message))
+                {
+                    Some(Ok(true)) => {}
+                    Some(Err(err)) => error!("{err}"),
+                    _ => error!("Failed to parse incoming message: {err}"),
+                }
                 return;
             }
         };
@@ -134,6 +160,9 @@ impl ServiceKeeper {
     }
 
     fn remove_service(&mut self, name: &str) {
-        self.services.remove(name);
+        let Some(service) = self.services.remove(name) else {
+            return;
+        };
+        crate::runtime::spawn(async move { service.shutdown(SHUTDOWN_BUDGET).await });
     }
 }
@@ -0,0 +1,89 @@
+//! Strip the type annotations of TypeScript sources so that QuickJS can run them.
+//!
+//! Only the syntax is transformed, the types are not checked. Enums and namespaces are compiled
+//! to plain objects.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use swc_core::{
+    common::{sync::Lrc, FileName, Globals, Mark, SourceMap, GLOBALS},
+    ecma::{
+        ast::EsVersion,
+        codegen::{text_writer::JsWriter, Emitter},
+        parser::{error::Error, parse_file_as_program, Syntax, TsConfig},
+        transforms::{
+            base::{fixer::fixer, hygiene::hygiene, resolver},
+            typescript::strip,
+        },
+        visit::FoldWith,
+    },
+};
+
+/// Whether `name` has a TypeScript file extension.
+pub fn is_typescript(name: &str) -> bool {
+    [".ts", ".mts", ".cts"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+        && !name.ends_with(".d.ts")
+}
+
+/// Transpile the TypeScript `source` to JavaScript. `name` is used in the error messages and in
+/// the inline source map appended to the output, which points the error stacks at `source`.
+pub fn transpile(source: &str, name: &str) -> Result<String, String> {
+    let cm: Lrc<SourceMap> = Default::default();
+    let file = cm.new_source_file(FileName::Custom(name.into()), source.into());
+    let syntax = Syntax::Typescript(TsConfig {
+        decorators: true,
+        ..Default::default()
+    });
+    let describe = |err: &Error| {
+        let pos = cm.lookup_char_pos(err.span().lo);
+        format!(
+            "{name}:{}:{}: {}",
+            pos.line,
+            pos.col_display + 1,
+            err.kind().msg()
+        )
+    };
+    let mut recovered = vec![];
+    let program = parse_file_as_program(&file, syntax, EsVersion::latest(), None, &mut recovered)
+        .map_err(|err| describe(&err))?;
+    // The parser recovers from some syntax errors, running the output would hide them.
+    if !recovered.is_empty() {
+        return Err(recovered
+            .iter()
+            .map(describe)
+            .collect::<Vec<_>>()
+            .join("\n"));
+    }
+    GLOBALS.set(&Globals::new(), || {
+        let unresolved_mark = Mark::new();
+        let top_level_mark = Mark::new();
+        let program = program
+            .fold_with(&mut resolver(unresolved_mark, top_level_mark, true))
+            .fold_with(&mut strip(top_level_mark))
+            .fold_with(&mut hygiene())
+            .fold_with(&mut fixer(None));
+        let mut output = vec![];
+        let mut mappings = vec![];
+        let mut emitter = Emitter {
+            cfg: Default::default(),
+            cm: cm.clone(),
+            comments: None,
+            wr: JsWriter::new(cm.clone(), "\n", &mut output, Some(&mut mappings)),
+        };
+        emitter
+            .emit_program(&program)
+            .map_err(|err| format!("Failed to emit {name}: {err}"))?;
+        drop(emitter);
+        let mut map = vec![];
+        cm.build_source_map(&mappings)
+            .to_writer(&mut map)
+            .map_err(|err| format!("Failed to write the source map of {name}: {err}"))?;
+        let mut output =
+            String::from_utf8(output).map_err(|_| format!("Invalid UTF-8 emitted for {name}"))?;
+        output.push_str("\n//# sourceMappingURL=data:application/json;base64,");
+        output.push_str(&STANDARD.encode(map));
+        output.push('\n');
+        Ok(output)
+    })
+}